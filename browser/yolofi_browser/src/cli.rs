@@ -0,0 +1,248 @@
+// Command Line Options
+// Kept dependency-free: flags are few and the parsing rules are simple.
+
+use std::path::PathBuf;
+use std::time::Duration;
+use yolofi_net::emulation::NetworkConditions;
+use yolofi_net::http_cache::CacheMode;
+use yolofi_net::identity::Identity;
+use yolofi_net::profile::Profile;
+
+#[derive(Debug, Default)]
+pub struct Options {
+    pub command: Command,
+    /// Profile directory override (defaults to `Profile::default_location`).
+    pub profile: Option<PathBuf>,
+    /// DNS server override, "ip:port".
+    pub dns: Option<String>,
+    /// How `fetch` uses the profile's HTTP cache.
+    pub cache_mode: CacheMode,
+    /// Write `fetch` bodies to stdout unchanged instead of through a content handler.
+    pub raw: bool,
+    /// Upgrade every http URL to https, and refuse sites that lack it.
+    pub https_only: bool,
+    /// What outgoing requests say about us.
+    pub identity: Identity,
+    /// Emulated network conditions, if any flag asked for them.
+    pub network: Option<NetworkConditions>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub enum Command {
+    /// Run the phase verification pipeline.
+    #[default]
+    Demo,
+    Trust(TrustCommand),
+    Fetch(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TrustCommand {
+    List,
+    Import(PathBuf),
+    Export(Option<PathBuf>),
+    Remove(String),
+    Distrust(String),
+    Restore(String),
+}
+
+pub const USAGE: &str = "\
+Usage: yolofi_browser [OPTIONS] [COMMAND]
+
+Commands:
+  (none)                   Run the phase verification pipeline
+  fetch <url>              Fetch a URL and print the response
+  trust list               List trusted roots and their status
+  trust import <file.pem>  Add the certificates in a PEM file as roots
+  trust export [file.pem]  Write user-added roots as PEM (stdout by default)
+  trust remove <sha256>    Delete a user-added root
+  trust distrust <sha256>  Stop trusting a root (bundled or user)
+  trust restore <sha256>   Undo a distrust
+
+Options:
+  --profile <dir>          Profile directory
+  --dns <ip:port>          DNS server to resolve through
+  --offline                Answer only from the HTTP cache
+  --reload                 Bypass the HTTP cache, then refresh it
+  --raw                    Print fetched bodies as-is, without content handlers
+  --https-only             Upgrade http URLs; never fall back to plain http
+  --identity <preset>      sovereign (default) | blend-in
+
+Network emulation:
+  --network <preset>       slow-3g | fast-3g | flaky | none
+  --net-seed <n>           Seed for emulated loss/jitter (default 0)
+  --latency-ms <ms>        One-way latency
+  --jitter-ms <ms>         Random extra latency, 0..=ms
+  --bandwidth-kbps <kbps>  Throughput cap in kilobits/s
+  --loss <rate>            Packet loss probability, 0.0..=1.0
+  --reset <rate>           Connection reset probability, 0.0..=1.0";
+
+/// Network flags as given. The preset is applied first so explicit values win,
+/// whatever order the flags came in.
+#[derive(Default)]
+struct NetworkFlags {
+    preset: Option<NetworkConditions>,
+    seed: Option<u64>,
+    latency: Option<Duration>,
+    jitter: Option<Duration>,
+    bandwidth: Option<u64>,
+    loss_rate: Option<f64>,
+    reset_rate: Option<f64>,
+}
+
+impl NetworkFlags {
+    /// `None` when no network flag was given.
+    fn conditions(self) -> Option<NetworkConditions> {
+        let any = self.preset.is_some()
+            || self.seed.is_some()
+            || self.latency.is_some()
+            || self.jitter.is_some()
+            || self.bandwidth.is_some()
+            || self.loss_rate.is_some()
+            || self.reset_rate.is_some();
+        if !any {
+            return None;
+        }
+        let mut conditions = self.preset.unwrap_or_default();
+        conditions.seed = self.seed.unwrap_or(conditions.seed);
+        conditions.latency = self.latency.unwrap_or(conditions.latency);
+        conditions.jitter = self.jitter.unwrap_or(conditions.jitter);
+        conditions.bandwidth = self.bandwidth.or(conditions.bandwidth);
+        conditions.loss_rate = self.loss_rate.unwrap_or(conditions.loss_rate);
+        conditions.reset_rate = self.reset_rate.unwrap_or(conditions.reset_rate);
+        Some(conditions)
+    }
+}
+
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut network = NetworkFlags::default();
+    let mut positional = Vec::new();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+
+        match arg.as_str() {
+            "--profile" => options.profile = Some(PathBuf::from(value(&arg)?)),
+            "--dns" => options.dns = Some(value(&arg)?),
+            "--offline" => options.cache_mode = CacheMode::OnlyIfCached,
+            "--reload" => options.cache_mode = CacheMode::Reload,
+            "--https-only" => options.https_only = true,
+            "--raw" => options.raw = true,
+            "--identity" => {
+                let name = value(&arg)?;
+                options.identity = Identity::preset(&name).ok_or_else(|| format!("Unknown identity preset '{}'", name))?;
+            }
+            "--network" => {
+                let name = value(&arg)?;
+                let preset = NetworkConditions::preset(&name).ok_or_else(|| format!("Unknown network preset '{}'", name))?;
+                network.preset = Some(preset);
+            }
+            "--net-seed" => network.seed = Some(number(&arg, &value(&arg)?)?),
+            "--latency-ms" => network.latency = Some(Duration::from_millis(number(&arg, &value(&arg)?)?)),
+            "--jitter-ms" => network.jitter = Some(Duration::from_millis(number(&arg, &value(&arg)?)?)),
+            "--bandwidth-kbps" => {
+                // Kilobits, as network tools quote rates; the shaper counts bytes.
+                let kbps = number::<u64>(&arg, &value(&arg)?)?;
+                if kbps == 0 {
+                    return Err(format!("{} must be at least 1", arg));
+                }
+                let bits = kbps.checked_mul(1000).ok_or_else(|| format!("{} value {} is too large", arg, kbps))?;
+                network.bandwidth = Some(bits / 8);
+            }
+            "--loss" => network.loss_rate = Some(rate(&arg, &value(&arg)?)?),
+            "--reset" => network.reset_rate = Some(rate(&arg, &value(&arg)?)?),
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            flag if flag.starts_with("--") => return Err(format!("Unknown argument '{}'\n\n{}", flag, USAGE)),
+            _ => positional.push(arg),
+        }
+    }
+
+    options.network = network.conditions();
+    options.command = parse_command(&positional)?;
+    Ok(options)
+}
+
+fn parse_command(words: &[String]) -> Result<Command, String> {
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+    let command = match words.as_slice() {
+        [] => Command::Demo,
+        ["fetch", url] => Command::Fetch(url.to_string()),
+        ["trust"] | ["trust", "list"] => Command::Trust(TrustCommand::List),
+        ["trust", "import", file] => Command::Trust(TrustCommand::Import(PathBuf::from(file))),
+        ["trust", "export"] => Command::Trust(TrustCommand::Export(None)),
+        ["trust", "export", file] => Command::Trust(TrustCommand::Export(Some(PathBuf::from(file)))),
+        ["trust", "remove", fp] => Command::Trust(TrustCommand::Remove(fp.to_string())),
+        ["trust", "distrust", fp] => Command::Trust(TrustCommand::Distrust(fp.to_string())),
+        ["trust", "restore", fp] => Command::Trust(TrustCommand::Restore(fp.to_string())),
+        _ => return Err(format!("Unknown command '{}'\n\n{}", words.join(" "), USAGE)),
+    };
+    Ok(command)
+}
+
+impl Options {
+    pub fn open_profile(&self) -> Result<Profile, String> {
+        match &self.profile {
+            Some(dir) => Profile::open(dir),
+            None => Profile::open_default(),
+        }
+        .map_err(|e| format!("Cannot open profile: {}", e))
+    }
+}
+
+fn number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("{} expects a number, got '{}'", flag, value))
+}
+
+fn rate(flag: &str, value: &str) -> Result<f64, String> {
+    let rate: f64 = number(flag, value)?;
+    if (0.0..=1.0).contains(&rate) {
+        Ok(rate)
+    } else {
+        Err(format!("{} must be between 0.0 and 1.0", flag))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Options, String> {
+        parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn test_network_flags() {
+        assert!(parse_args(&["fetch", "http://a.test/"]).unwrap().network.is_none());
+
+        // Explicit values win over the preset, before or after it.
+        let slow = NetworkConditions::slow_3g();
+        for args in [
+            ["--latency-ms", "5", "--network", "slow-3g", "--net-seed", "7"],
+            ["--network", "slow-3g", "--latency-ms", "5", "--net-seed", "7"],
+        ] {
+            let network = parse_args(&args).unwrap().network.unwrap();
+            assert_eq!(network.latency, Duration::from_millis(5));
+            assert_eq!(network.seed, 7);
+            assert_eq!(network.bandwidth, slow.bandwidth);
+            assert_eq!(network.jitter, slow.jitter);
+        }
+
+        let network = parse_args(&["--bandwidth-kbps", "64", "--jitter-ms", "3", "--loss", "0.5", "--reset", "1"])
+            .unwrap()
+            .network
+            .unwrap();
+        assert_eq!(network.bandwidth, Some(8000));
+        assert_eq!(network.jitter, Duration::from_millis(3));
+        assert_eq!((network.loss_rate, network.reset_rate), (0.5, 1.0));
+        assert_eq!(network.latency, Duration::ZERO);
+
+        assert!(parse_args(&["--bandwidth-kbps", "0"]).is_err());
+        assert!(parse_args(&["--bandwidth-kbps", "18446744073709551615"]).is_err());
+        assert!(parse_args(&["--loss", "1.5"]).is_err());
+        assert!(parse_args(&["--latency-ms", "soon"]).is_err());
+        assert!(parse_args(&["--network", "dial-up"]).is_err());
+        assert!(parse_args(&["--jitter-ms"]).is_err());
+    }
+}
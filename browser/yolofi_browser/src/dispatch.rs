@@ -0,0 +1,344 @@
+// Content Dispatch
+// Decides what the browser does with a response, from its computed MIME type
+// (yolofi_net::mime_sniff, which honours X-Content-Type-Options: nosniff) and any
+// Content-Disposition: attachment. Each handler renders for the terminal:
+//   html      parsed into a DOM and printed as a tree
+//   text      decoded with the document's charset and printed
+//   css       parsed and printed rule by rule
+//   image     described (type, size, dimensions); bytes are never dumped
+//   json      pretty-printed, or shown as text if it does not parse
+//   download  saved under <profile>/downloads, never overwriting a file
+
+use std::io::Write;
+use std::path::PathBuf;
+use yolofi_html::encoding;
+use yolofi_net::http::HttpResponse;
+use yolofi_net::mime_sniff::{self, computed_mime_type};
+use yolofi_net::profile::Profile;
+use yolofi_net::Url;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Handler {
+    Html,
+    Text,
+    Css,
+    Image,
+    Json,
+    Download,
+}
+
+#[derive(Debug)]
+pub struct Dispatch {
+    /// The computed MIME type essence.
+    pub mime: String,
+    pub handler: Handler,
+}
+
+pub fn route(response: &HttpResponse) -> Dispatch {
+    let mime = computed_mime_type(&response.headers, &response.body);
+    let attachment = response
+        .headers
+        .get("Content-Disposition")
+        .is_some_and(|value| value.split(';').next().is_some_and(|t| t.trim().eq_ignore_ascii_case("attachment")));
+    let handler = match mime.as_str() {
+        _ if attachment => Handler::Download,
+        "text/html" | "application/xhtml+xml" => Handler::Html,
+        "text/css" => Handler::Css,
+        m if m.starts_with("image/") => Handler::Image,
+        m if mime_sniff::is_json(m) => Handler::Json,
+        m if m.starts_with("text/") || mime_sniff::is_xml(m) || mime_sniff::is_javascript(m) => Handler::Text,
+        _ => Handler::Download,
+    };
+    Dispatch { mime, handler }
+}
+
+/// Hands the response to its handler. Output goes to stdout; notes go to stderr.
+pub fn open(url: &Url, response: &HttpResponse, profile: &Profile) -> Result<(), String> {
+    let dispatch = route(response);
+    eprintln!("content: {} -> {:?}", dispatch.mime, dispatch.handler);
+    let charset = response.headers.charset();
+    let text = || encoding::decode(&response.body, charset.as_deref()).text;
+    let output = match dispatch.handler {
+        Handler::Html => yolofi_html::parse_bytes(&response.body, charset.as_deref()).to_string(),
+        Handler::Text => text(),
+        Handler::Css => yolofi_css::parse(&text()).to_string(),
+        Handler::Image => describe_image(&dispatch.mime, &response.body),
+        Handler::Json => {
+            let text = text();
+            pretty_json(&text).unwrap_or_else(|| {
+                eprintln!("content: not valid JSON, showing it as text");
+                text
+            })
+        }
+        Handler::Download => {
+            let path = save_download(profile, &download_name(url, response), &response.body)?;
+            format!("Saved {} bytes to {}", response.body.len(), path.display())
+        }
+    };
+    let mut stdout = std::io::stdout().lock();
+    writeln!(stdout, "{}", output.trim_end()).and_then(|_| stdout.flush()).map_err(|e| e.to_string())
+}
+
+fn describe_image(mime: &str, body: &[u8]) -> String {
+    let size = match mime {
+        "image/png" if body.len() >= 24 => Some((be32(&body[16..20]), be32(&body[20..24]))),
+        "image/gif" if body.len() >= 10 => {
+            Some((u16::from_le_bytes([body[6], body[7]]) as u32, u16::from_le_bytes([body[8], body[9]]) as u32))
+        }
+        _ => None,
+    };
+    match size {
+        Some((width, height)) => format!("[{} image, {}x{}, {} bytes]", mime, width, height, body.len()),
+        None => format!("[{} image, {} bytes]", mime, body.len()),
+    }
+}
+
+fn be32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// The filename from Content-Disposition, else the last path segment, made safe to save.
+fn download_name(url: &Url, response: &HttpResponse) -> String {
+    let from_header = response.headers.get("Content-Disposition").and_then(|value| {
+        value.split(';').skip(1).find_map(|param| {
+            let (name, value) = param.split_once('=')?;
+            name.trim().eq_ignore_ascii_case("filename").then(|| value.trim().trim_matches('"').to_string())
+        })
+    });
+    let from_url = || url.path_segments().and_then(|s| s.last()).map(|s| yolofi_url::percent::decode_utf8(s));
+    let name = from_header.or_else(from_url).unwrap_or_default();
+    // No directories, no hidden files, nothing the filesystem would choke on.
+    let name: String =
+        name.chars().map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) { '_' } else { c }).collect();
+    let name = name.trim().trim_start_matches('.').to_string();
+    if name.is_empty() {
+        "download".to_string()
+    } else {
+        name
+    }
+}
+
+fn save_download(profile: &Profile, name: &str, body: &[u8]) -> Result<PathBuf, String> {
+    let dir = profile.path("downloads");
+    std::fs::create_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{}", ext)),
+        _ => (name, String::new()),
+    };
+    for n in 0..1000 {
+        let candidate = if n == 0 { name.to_string() } else { format!("{} ({}){}", stem, n, extension) };
+        let path = dir.join(candidate);
+        match std::fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(body).map_err(|e| format!("{}: {}", path.display(), e))?;
+                return Ok(path);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+    Err(format!("Too many downloads named {}", name))
+}
+
+/// What may come next while walking a JSON text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Expect {
+    Value,
+    /// Right after `[`: a value, or `]` for an empty array.
+    FirstValue,
+    Key,
+    /// Right after `{`: a key, or `}` for an empty object.
+    FirstKey,
+    Colon,
+    CommaOrClose,
+    End,
+}
+
+/// Re-indents JSON two spaces per level. `None` unless `text` is exactly one well-formed value.
+fn pretty_json(text: &str) -> Option<String> {
+    let mut out = String::with_capacity(text.len() * 2);
+    let mut open: Vec<char> = Vec::new();
+    let mut expect = Expect::Value;
+    let mut chars = text.trim().chars().peekable();
+    let newline = |out: &mut String, depth: usize| {
+        out.push('\n');
+        out.push_str(&"  ".repeat(depth));
+    };
+    let after_value = |open: &[char]| if open.is_empty() { Expect::End } else { Expect::CommaOrClose };
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '"' => {
+                out.push(c);
+                let mut escaped = false;
+                loop {
+                    let c = chars.next()?;
+                    out.push(c);
+                    match c {
+                        _ if escaped => escaped = false,
+                        '\\' => escaped = true,
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+                expect = match expect {
+                    Expect::Key | Expect::FirstKey => Expect::Colon,
+                    Expect::Value | Expect::FirstValue => after_value(&open),
+                    _ => return None,
+                };
+            }
+            '{' | '[' if matches!(expect, Expect::Value | Expect::FirstValue) => {
+                out.push(c);
+                open.push(c);
+                expect = if c == '{' { Expect::FirstKey } else { Expect::FirstValue };
+                // Keep empty containers on one line.
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+                if chars.peek() != Some(&closing(c)) {
+                    newline(&mut out, open.len());
+                }
+            }
+            '}' | ']' => {
+                let empty = if c == '}' { Expect::FirstKey } else { Expect::FirstValue };
+                if open.pop().map(closing) != Some(c) || !(expect == empty || expect == Expect::CommaOrClose) {
+                    return None;
+                }
+                if expect == Expect::CommaOrClose {
+                    newline(&mut out, open.len());
+                }
+                out.push(c);
+                expect = after_value(&open);
+            }
+            ',' if expect == Expect::CommaOrClose => {
+                out.push(c);
+                newline(&mut out, open.len());
+                expect = if open.last() == Some(&'{') { Expect::Key } else { Expect::Value };
+            }
+            ':' if expect == Expect::Colon => {
+                out.push_str(": ");
+                expect = Expect::Value;
+            }
+            '{' | '[' | ',' | ':' => return None,
+            c => {
+                let mut token = c.to_string();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !"{}[],:\"".contains(*c)) {
+                    token.push(c);
+                }
+                if !matches!(expect, Expect::Value | Expect::FirstValue) || !is_json_literal(&token) {
+                    return None;
+                }
+                out.push_str(&token);
+                expect = after_value(&open);
+            }
+        }
+    }
+    (expect == Expect::End).then_some(out)
+}
+
+fn closing(open: char) -> char {
+    if open == '{' {
+        '}'
+    } else {
+        ']'
+    }
+}
+
+/// `true`, `false`, `null` or a number.
+fn is_json_literal(token: &str) -> bool {
+    matches!(token, "true" | "false" | "null") || is_json_number(token)
+}
+
+/// RFC 8259 section 6: `-? (0 | [1-9][0-9]*) (. [0-9]+)? ([eE] [+-]? [0-9]+)?`.
+fn is_json_number(token: &str) -> bool {
+    let digits = |s: &str| s.len() - s.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let mut rest = token.strip_prefix('-').unwrap_or(token);
+    match digits(rest) {
+        0 => return false,
+        n if n > 1 && rest.starts_with('0') => return false,
+        n => rest = &rest[n..],
+    }
+    if let Some(fraction) = rest.strip_prefix('.') {
+        match digits(fraction) {
+            0 => return false,
+            n => rest = &fraction[n..],
+        }
+    }
+    if let Some(exponent) = rest.strip_prefix(['e', 'E']) {
+        let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        match digits(exponent) {
+            0 => return false,
+            n => rest = &exponent[n..],
+        }
+    }
+    rest.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yolofi_net::headers::HeaderMap;
+
+    fn response(headers: &[(&str, &str)], body: &[u8]) -> HttpResponse {
+        HttpResponse {
+            status: 200,
+            headers: headers.iter().copied().collect(),
+            body: body.to_vec(),
+            trailers: HeaderMap::new(),
+            security: None,
+        }
+    }
+
+    #[test]
+    fn test_route_honours_nosniff_and_attachment() {
+        // Apache's default text/plain is sniffed for binary content, unless the server says nosniff.
+        let binary = b"\x00\x01\x02 not text";
+        let plain = [("Content-Type", "text/plain; charset=UTF-8")];
+        assert_eq!(route(&response(&plain, binary)).handler, Handler::Download);
+        let nosniff = [plain[0], ("X-Content-Type-Options", "nosniff")];
+        assert_eq!(route(&response(&nosniff, binary)).handler, Handler::Text);
+
+        assert_eq!(route(&response(&[], b"<!DOCTYPE html><p>hi")).handler, Handler::Html);
+        let attachment = [("Content-Type", "text/html"), ("Content-Disposition", "Attachment; filename=page.html")];
+        let dispatch = route(&response(&attachment, b"<p>hi"));
+        assert_eq!((dispatch.mime.as_str(), dispatch.handler), ("text/html", Handler::Download));
+    }
+
+    #[test]
+    fn test_download_names_are_sanitised_and_never_overwrite() {
+        let url = Url::parse("https://a.test/files/report%20v2.pdf").unwrap();
+        let named = |value: &str| download_name(&url, &response(&[("Content-Disposition", value)], b""));
+        assert_eq!(named("attachment; filename=\"../x\""), "_x");
+        assert_eq!(named("attachment; filename=a/b"), "a_b");
+        assert_eq!(named("attachment; filename=\".\""), "download");
+        assert_eq!(download_name(&url, &response(&[], b"")), "report v2.pdf");
+
+        let dir = std::env::temp_dir().join(format!("yolofi_downloads_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let profile = Profile::open(&dir).unwrap();
+        let saved: Vec<String> = (0..3)
+            .map(|_| save_download(&profile, "notes.txt", b"hi").unwrap())
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(saved, ["notes.txt", "notes (1).txt", "notes (2).txt"]);
+        assert_eq!(std::fs::read(profile.path("downloads/notes.txt")).unwrap(), b"hi");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_pretty_json_accepts_only_well_formed_json() {
+        let pretty = pretty_json(r#" {"a": [1, {"b": null}], "e": {}, "s": "x,{]\"y"} "#).unwrap();
+        let expected = "{\n  \"a\": [\n    1,\n    {\n      \"b\": null\n    }\n  ],\n  \"e\": {},\n";
+        assert_eq!(pretty, format!("{}  \"s\": \"x,{{]\\\"y\"\n}}", expected));
+        assert_eq!(pretty_json("-1.5e3").as_deref(), Some("-1.5e3"));
+
+        for bad in ["{]", "[}", "{a b}", "{\"a\" 1}", "[1,]", "[1 2]", "{", "\"open", "{} []", "[tru]"] {
+            assert_eq!(pretty_json(bad), None, "{:?}", bad);
+        }
+        for number in ["0", "-0", "10", "0.5", "1E+2", "2e-07"] {
+            assert!(is_json_number(number), "{:?}", number);
+        }
+        for bad in ["01", "1.", "+1", "inf", "NaN", "-", ".5", "1e", "1e+", "-01.0", "1.2.3", "0x10"] {
+            assert!(!is_json_number(bad), "{:?}", bad);
+            assert_eq!(pretty_json(&format!("[{}]", bad)), None, "{:?}", bad);
+        }
+    }
+}
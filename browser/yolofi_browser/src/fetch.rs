@@ -0,0 +1,110 @@
+// `fetch` Command
+// Fetches one URL through the full stack (DNS, TCP, TLS, HTTP) and prints the result:
+// status and headers on stderr, then the body on stdout as its content handler renders
+// it (see dispatch.rs), or byte for byte with `--raw`. Responses go through the
+// profile's HTTP cache, so a later `--offline` fetch can replay them.
+//
+// http URLs to HSTS hosts are upgraded; with `--https-only` every http URL is, and a
+// site without working https gets an interstitial explaining why nothing was loaded.
+
+use std::io::Write;
+use std::time::Duration;
+use yolofi_net::cookie_jar::CookieJar;
+use yolofi_net::dns::DnsResolver;
+use yolofi_net::emulation::{ShapedConnector, ShapedDatagram};
+use yolofi_net::fetch::{FetchClient, FetchError, FetchResponse};
+use yolofi_net::hsts::HstsStore;
+use yolofi_net::http_cache::{HttpCache, DEFAULT_CACHE_SIZE};
+use yolofi_net::pin_store::PinStore;
+use yolofi_net::profile::Profile;
+use yolofi_net::tls::TlsConfig;
+use yolofi_net::transport::{Connector, DatagramTransport, TcpConnector, UdpTransport};
+use yolofi_net::trust_store::TrustStore;
+
+use crate::cli::Options;
+use crate::dispatch;
+
+pub fn run(url: &str, options: &Options) -> Result<(), String> {
+    let profile = options.open_profile()?;
+    let udp = UdpTransport::new(Duration::from_secs(2));
+    let result = match &options.network {
+        Some(conditions) => fetch_with(
+            ShapedConnector::new(TcpConnector::new(), conditions.clone()),
+            ShapedDatagram::new(udp, conditions.clone()),
+            &profile,
+            options,
+            url,
+        ),
+        None => fetch_with(TcpConnector::new(), udp, &profile, options, url),
+    }?;
+    print_response(&result, &profile, options.raw)
+}
+
+fn fetch_with<C: Connector, D: DatagramTransport>(
+    connector: C,
+    dns: D,
+    profile: &Profile,
+    options: &Options,
+    url: &str,
+) -> Result<FetchResponse, String> {
+    let trust = TrustStore::load(profile).map_err(|e| e.to_string())?;
+    let pins = PinStore::load(profile).map_err(|e| e.to_string())?;
+    let tls = TlsConfig::from_trust_store(&trust).with_pins(pins);
+    let cookies = CookieJar::load(profile).map_err(|e| e.to_string())?;
+    let cache = HttpCache::open(profile, DEFAULT_CACHE_SIZE).map_err(|e| e.to_string())?;
+    let hsts = HstsStore::load(profile).map_err(|e| e.to_string())?;
+
+    let mut resolver = DnsResolver::with_transport(dns);
+    if let Some(server) = &options.dns {
+        resolver = resolver.with_server(server);
+    }
+    FetchClient::with_parts(connector, Some(resolver))
+        .with_tls(tls)
+        .with_cookies(cookies)
+        .with_cache(cache)
+        .with_cache_mode(options.cache_mode)
+        .with_identity(options.identity.clone())
+        .with_hsts(hsts)
+        .with_https_only(options.https_only)
+        .fetch(url)
+        .map_err(|e| match e {
+            FetchError::HttpsOnly { url, source } => interstitial(&url, &source),
+            e => e.to_string(),
+        })
+}
+
+/// What HTTPS-only mode shows in place of a page it refused to load insecurely.
+fn interstitial(url: &str, error: &FetchError) -> String {
+    format!(
+        "\
+Secure connection not available
+
+{} could not be loaded over HTTPS:
+  {}
+
+HTTPS-only mode does not fall back to plain http, where anyone on the network
+can read and change the page. If you trust this site and network, run the
+command again without --https-only.",
+        url, error
+    )
+}
+
+fn print_response(result: &FetchResponse, profile: &Profile, raw: bool) -> Result<(), String> {
+    for hop in &result.redirects {
+        eprintln!("redirected from {}", hop);
+    }
+    eprintln!("{} {} (cache: {:?})", result.response.status, result.url, result.cache);
+    for (name, value) in result.response.headers.iter() {
+        eprintln!("{}: {}", name, value);
+    }
+    if let Some(security) = &result.response.security {
+        eprintln!("tls: {:?} {:?} alpn={:?}", security.protocol_version, security.cipher_suite, security.alpn);
+    }
+    eprintln!();
+
+    if !raw {
+        return dispatch::open(&result.url, &result.response, profile);
+    }
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(&result.response.body).and_then(|_| stdout.flush()).map_err(|e| e.to_string())
+}
@@ -1,7 +1,38 @@
+mod cli;
+mod dispatch;
+mod fetch;
+mod trust;
+
+use std::time::Duration;
+use yolofi_net::dns::DnsResolver;
+use yolofi_net::emulation::ShapedDatagram;
+use yolofi_net::transport::UdpTransport;
+
 fn main() {
     // Initialize logging
     tracing_subscriber::fmt::init();
 
+    let options = match cli::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(2);
+        }
+    };
+
+    let result = match &options.command {
+        cli::Command::Demo => Ok(()),
+        cli::Command::Trust(command) => options.open_profile().and_then(|profile| trust::run(command, &profile)),
+        cli::Command::Fetch(url) => fetch::run(url, &options),
+    };
+    if options.command != cli::Command::Demo {
+        if let Err(message) = result {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     tracing::info!("YoloFi Browser Engine initializing...");
     tracing::info!("Core Laws: Determinism, Autonomy, Decentralization.");
 
@@ -11,20 +42,29 @@ fn main() {
     // 1. DNS
     let domain = "google.com";
     tracing::info!("Attempting to resolve {} via raw UDP...", domain);
-    let ip = yolofi_net::dns::resolve(domain);
-    tracing::info!("Result: {} -> {}", domain, ip);
+    let udp = UdpTransport::new(Duration::from_secs(2));
+    let ip = match &options.network {
+        Some(conditions) => {
+            tracing::info!("Network emulation active: {:?}", conditions);
+            DnsResolver::with_transport(ShapedDatagram::new(udp, conditions.clone())).resolve(domain)
+        }
+        None => DnsResolver::with_transport(udp).resolve(domain),
+    };
+    match ip {
+        Ok(ip) => tracing::info!("Result: {} -> {}", domain, ip),
+        Err(e) => tracing::warn!("DNS resolution failed: {}", e),
+    }
 
     // 2. HTTP Parsing (Manual test)
     tracing::info!("Testing HTTP request...");
     let raw_response = b"HTTP/1.1 200 OK\r\nServer: Custom\r\n\r\n<html><head><title>Verify</title></head><body><h1>Hello Phase 2</h1></body></html>";
     
-    if let Some(res) = yolofi_net::http::HttpResponse::parse(raw_response) {
+    if let Ok(res) = yolofi_net::http::HttpResponse::parse(raw_response) {
         tracing::info!("Parsed Response status: {}", res.status);
         
         // Phase 2 Verification: HTML Engine
         tracing::info!("--- PHASE 2 VERIFICATION ---");
-        let html_string = String::from_utf8_lossy(&res.body);
-        let dom = yolofi_html::parse(&html_string);
+        let dom = yolofi_html::parse_bytes(&res.body, res.headers.charset().as_deref());
         
         tracing::info!("Generated DOM Tree:\n{}", dom);
 
@@ -0,0 +1,44 @@
+// `trust` Command
+// Lists and edits the browser-owned trust store kept in the profile.
+
+use yolofi_net::profile::Profile;
+use yolofi_net::trust_store::{RootOrigin, TrustStore};
+
+use crate::cli::TrustCommand;
+
+pub fn run(command: &TrustCommand, profile: &Profile) -> Result<(), String> {
+    let mut store = TrustStore::load(profile).map_err(|e| e.to_string())?;
+
+    match command {
+        TrustCommand::List => {
+            for root in store.roots() {
+                let origin = match root.origin {
+                    RootOrigin::Bundled => "bundled",
+                    RootOrigin::User => "user",
+                };
+                let status = if root.distrusted { "distrusted" } else { "trusted" };
+                println!("{}  {:<7}  {:<10}  {}", root.fingerprint, origin, status, root.subject);
+            }
+            return Ok(());
+        }
+        TrustCommand::Export(path) => {
+            let pem = store.export_pem();
+            match path {
+                Some(path) => std::fs::write(path, pem).map_err(|e| format!("{}: {}", path.display(), e))?,
+                None => print!("{}", pem),
+            }
+            return Ok(());
+        }
+        TrustCommand::Import(path) => {
+            let pem = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            for fingerprint in store.import_pem(&pem).map_err(|e| e.to_string())? {
+                println!("Imported {}", fingerprint);
+            }
+        }
+        TrustCommand::Remove(fingerprint) => store.remove_root(fingerprint).map_err(|e| e.to_string())?,
+        TrustCommand::Distrust(fingerprint) => store.distrust(fingerprint).map_err(|e| e.to_string())?,
+        TrustCommand::Restore(fingerprint) => store.restore(fingerprint).map_err(|e| e.to_string())?,
+    }
+
+    store.save(profile).map_err(|e| e.to_string())
+}
@@ -12,3 +12,8 @@ pub const PRIVATE_DNS_SERVER: &str = "127.0.0.1:5353";
 
 // Identity & Sovereignty Strings
 pub const USER_AGENT: &str = "YoloFi/1.0 (Sovereign; Privacy-First)";
+
+// Browser Profile (trust store, pins, cookies, cache...)
+// Resolved as: $YOLOFI_PROFILE, else $HOME/.yolofi, else ./.yolofi
+pub const PROFILE_ENV_VAR: &str = "YOLOFI_PROFILE";
+pub const PROFILE_DIR_NAME: &str = ".yolofi";
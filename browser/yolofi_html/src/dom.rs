@@ -3,6 +3,8 @@ use std::fmt;
 #[derive(Debug)]
 pub struct Document {
     pub root: Node,
+    /// Name of the encoding the source bytes were decoded from.
+    pub character_set: &'static str,
 }
 
 #[derive(Debug, Clone)]
@@ -0,0 +1,268 @@
+// Character Encoding Detection
+// Bytes off the network become text here, before tokenizing. The encoding is chosen
+// by the HTML standard's sniffing algorithm (section 13.2.3):
+//   1. a byte order mark (UTF-8, UTF-16LE/BE)           -> certain
+//   2. the charset parameter of Content-Type            -> certain
+//   3. a <meta charset> / <meta http-equiv> in the first 1024 bytes -> tentative
+//   4. UTF-8 if the bytes are valid UTF-8, else windows-1252          -> tentative
+// Decoding uses encoding_rs, which covers every encoding in the Encoding standard
+// (windows-125x, ISO-8859-x, Shift_JIS, EUC-JP, GBK/gb18030, Big5, EUC-KR, ...).
+
+pub use encoding_rs::Encoding;
+use encoding_rs::{UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252, X_USER_DEFINED};
+
+/// How many bytes the meta prescan looks at.
+const PRESCAN_LIMIT: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confidence {
+    /// From a BOM or the transport layer; a later <meta> cannot change it.
+    Certain,
+    /// A guess from the prescan or the fallback.
+    Tentative,
+}
+
+#[derive(Debug)]
+pub struct Decoded {
+    pub text: String,
+    pub encoding: &'static Encoding,
+    pub confidence: Confidence,
+    /// Some bytes were invalid in `encoding` and became U+FFFD.
+    pub had_errors: bool,
+}
+
+/// Picks the encoding for a document. `transport_charset` is the Content-Type charset, if any.
+pub fn sniff(bytes: &[u8], transport_charset: Option<&str>) -> (&'static Encoding, Confidence) {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return (encoding, Confidence::Certain);
+    }
+    if let Some(encoding) = transport_charset.and_then(|label| Encoding::for_label(label.as_bytes())) {
+        return (encoding, Confidence::Certain);
+    }
+    if let Some(encoding) = prescan(&bytes[..bytes.len().min(PRESCAN_LIMIT)]) {
+        return (encoding, Confidence::Tentative);
+    }
+    let fallback = if std::str::from_utf8(bytes).is_ok() { UTF_8 } else { WINDOWS_1252 };
+    (fallback, Confidence::Tentative)
+}
+
+/// Sniffs and decodes. A BOM is stripped from the text.
+pub fn decode(bytes: &[u8], transport_charset: Option<&str>) -> Decoded {
+    let (encoding, confidence) = sniff(bytes, transport_charset);
+    let (text, encoding, had_errors) = encoding.decode(bytes);
+    Decoded { text: text.into_owned(), encoding, confidence, had_errors }
+}
+
+/// The prescan for a meta-declared encoding (section 13.2.3.2).
+fn prescan(bytes: &[u8]) -> Option<&'static Encoding> {
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let rest = &bytes[pos..];
+        if rest.starts_with(b"<!--") {
+            // The "--" of the opener may also close it, so "<!-->" is a whole comment.
+            pos += 2 + find(&rest[2..], b"-->").map_or(rest.len() - 2, |end| end + 3);
+            continue;
+        }
+        if starts_with_ignore_case(rest, b"<meta") && rest.get(5).is_some_and(|&b| is_space(b) || b == b'/') {
+            pos += 5;
+            if let Some(encoding) = meta_encoding(bytes, &mut pos) {
+                return Some(encoding);
+            }
+            continue;
+        }
+        let tag_start = match rest {
+            [b'<', b'/', c, ..] if c.is_ascii_alphabetic() => Some(2),
+            [b'<', c, ..] if c.is_ascii_alphabetic() => Some(1),
+            _ => None,
+        };
+        if let Some(skip) = tag_start {
+            pos += skip;
+            while pos < bytes.len() && !is_space(bytes[pos]) && bytes[pos] != b'>' {
+                pos += 1;
+            }
+            while get_attribute(bytes, &mut pos).is_some() {}
+            continue;
+        }
+        if rest.starts_with(b"<!") || rest.starts_with(b"</") || rest.starts_with(b"<?") {
+            pos += find(rest, b">").map_or(rest.len(), |end| end + 1);
+            continue;
+        }
+        pos += 1;
+    }
+    None
+}
+
+/// The attributes of one <meta>, deciding whether it declares an encoding.
+fn meta_encoding(bytes: &[u8], pos: &mut usize) -> Option<&'static Encoding> {
+    let mut seen: Vec<Vec<u8>> = Vec::new();
+    let mut got_pragma = false;
+    let mut need_pragma = None;
+    let mut charset = None;
+    while let Some((name, value)) = get_attribute(bytes, pos) {
+        if seen.contains(&name) {
+            continue;
+        }
+        seen.push(name.clone());
+        match name.as_slice() {
+            b"http-equiv" if value.eq_ignore_ascii_case(b"content-type") => got_pragma = true,
+            b"content" if charset.is_none() => {
+                if let Some(found) = charset_from_content(&value) {
+                    charset = Encoding::for_label(&found);
+                    need_pragma = Some(true);
+                }
+            }
+            b"charset" => {
+                charset = Encoding::for_label(&value);
+                need_pragma = Some(false);
+            }
+            _ => {}
+        }
+    }
+    let charset = match need_pragma? {
+        true if !got_pragma => return None,
+        _ => charset?,
+    };
+    // A document that says UTF-16 in ASCII bytes cannot be UTF-16.
+    Some(if charset == UTF_16BE || charset == UTF_16LE {
+        UTF_8
+    } else if charset == X_USER_DEFINED {
+        WINDOWS_1252
+    } else {
+        charset
+    })
+}
+
+/// Reads one attribute at `pos`, name and value lowercased; `None` at the end of the tag.
+fn get_attribute(bytes: &[u8], pos: &mut usize) -> Option<(Vec<u8>, Vec<u8>)> {
+    let at = |i: usize| bytes.get(i).copied();
+    while at(*pos).is_some_and(|b| is_space(b) || b == b'/') {
+        *pos += 1;
+    }
+    if at(*pos).is_none_or(|b| b == b'>') {
+        return None;
+    }
+    let mut name = Vec::new();
+    loop {
+        match at(*pos)? {
+            b'=' if !name.is_empty() => break,
+            b if is_space(b) => break,
+            b'/' | b'>' => return Some((name, Vec::new())),
+            b => name.push(b.to_ascii_lowercase()),
+        }
+        *pos += 1;
+    }
+    while at(*pos).is_some_and(is_space) {
+        *pos += 1;
+    }
+    if at(*pos)? != b'=' {
+        return Some((name, Vec::new()));
+    }
+    *pos += 1;
+    while at(*pos).is_some_and(is_space) {
+        *pos += 1;
+    }
+    let mut value = Vec::new();
+    match at(*pos)? {
+        quote @ (b'"' | b'\'') => {
+            *pos += 1;
+            loop {
+                let b = at(*pos)?;
+                *pos += 1;
+                if b == quote {
+                    return Some((name, value));
+                }
+                value.push(b.to_ascii_lowercase());
+            }
+        }
+        b'>' => Some((name, value)),
+        _ => {
+            while let Some(b) = at(*pos).filter(|&b| !is_space(b) && b != b'>') {
+                value.push(b.to_ascii_lowercase());
+                *pos += 1;
+            }
+            Some((name, value))
+        }
+    }
+}
+
+/// The encoding label in a `content="text/html; charset=..."` value (section 2.6.5).
+fn charset_from_content(content: &[u8]) -> Option<Vec<u8>> {
+    let mut rest = content;
+    loop {
+        let start = find_ignore_case(rest, b"charset")?;
+        rest = &rest[start + 7..];
+        let trimmed = trim_start(rest);
+        if let Some(after) = trimmed.strip_prefix(b"=") {
+            rest = trim_start(after);
+            break;
+        }
+        rest = trimmed;
+    }
+    match rest.first()? {
+        &quote @ (b'"' | b'\'') => {
+            let end = rest[1..].iter().position(|&b| b == quote)?;
+            Some(rest[1..1 + end].to_vec())
+        }
+        _ => {
+            let end = rest.iter().position(|&b| is_space(b) || b == b';').unwrap_or(rest.len());
+            (end > 0).then(|| rest[..end].to_vec())
+        }
+    }
+}
+
+fn is_space(b: u8) -> bool {
+    matches!(b, b'\t' | b'\n' | b'\x0c' | b'\r' | b' ')
+}
+
+fn trim_start(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|&b| !is_space(b)).unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn find_ignore_case(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w.eq_ignore_ascii_case(needle))
+}
+
+fn starts_with_ignore_case(bytes: &[u8], prefix: &[u8]) -> bool {
+    bytes.len() >= prefix.len() && bytes[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{GBK, SHIFT_JIS, WINDOWS_1251};
+
+    #[test]
+    fn test_sniffing_order() {
+        let page = b"<html><head><meta charset=\"shift_jis\"></head>";
+        assert_eq!(sniff(page, None), (SHIFT_JIS, Confidence::Tentative));
+        assert_eq!(sniff(page, Some("GBK")), (GBK, Confidence::Certain));
+        assert_eq!(sniff(&[b"\xEF\xBB\xBF".as_slice(), page].concat(), Some("gbk")), (UTF_8, Confidence::Certain));
+        assert_eq!(sniff(page, Some("no-such-charset")), (SHIFT_JIS, Confidence::Tentative));
+
+        // http-equiv needs both attributes; a bare content= is ignored.
+        let pragma = b"<!-- <meta charset=koi8-r> -->\
+<META HTTP-EQUIV='Content-Type' CONTENT='text/html; Charset = \"windows-1251\"'>";
+        assert_eq!(sniff(pragma, None).0, WINDOWS_1251);
+        assert_eq!(sniff(b"<meta content=\"text/html; charset=gbk\">", None).0, UTF_8);
+        // Attributes of other tags are skipped, not mistaken for a meta.
+        assert_eq!(sniff(b"<div title='<meta charset=gbk>'>\xE9t\xE9</div>", None).0, WINDOWS_1252);
+        assert_eq!(sniff(b"<meta charset=utf-16le>", None).0, UTF_8);
+    }
+
+    #[test]
+    fn test_decodes_legacy_encodings() {
+        let decoded = decode(b"<p>caf\xE9 \x80</p>", None);
+        assert_eq!((decoded.text.as_str(), decoded.encoding), ("<p>café €</p>", WINDOWS_1252));
+        let decoded = decode(b"<meta charset=shift_jis>\x93\xFA\x96\x7B", None);
+        assert!(decoded.text.ends_with("日本"), "{}", decoded.text);
+        let decoded = decode(b"\xC4\xE3\xBA\xC3", Some("gb2312"));
+        assert_eq!((decoded.text.as_str(), decoded.encoding), ("你好", GBK));
+        let decoded = decode(b"\xFF\xFEh\x00i\x00", None);
+        assert_eq!((decoded.text.as_str(), decoded.encoding, decoded.had_errors), ("hi", UTF_16LE, false));
+    }
+}
@@ -1,6 +1,7 @@
 pub mod tokenizer;
 pub mod tree_builder;
 pub mod dom;
+pub mod encoding;
 
 pub fn parse(html: &str) -> dom::Document {
     tracing::info!("Starting HTML parse for {} bytes...", html.len());
@@ -13,3 +14,18 @@ pub fn parse(html: &str) -> dom::Document {
     
     tree_builder.finish()
 }
+
+/// Parses a document as it came off the network. `charset` is the Content-Type charset, if any.
+pub fn parse_bytes(bytes: &[u8], charset: Option<&str>) -> dom::Document {
+    let decoded = encoding::decode(bytes, charset);
+    tracing::info!(
+        "Decoded {} bytes as {} ({:?}{})",
+        bytes.len(),
+        decoded.encoding.name(),
+        decoded.confidence,
+        if decoded.had_errors { ", with replacement characters" } else { "" }
+    );
+    let mut document = parse(&decoded.text);
+    document.character_set = decoded.encoding.name();
+    document
+}
@@ -6,6 +6,12 @@ pub struct TreeBuilder {
     // Stack of open elements to be implemented
 }
 
+impl Default for TreeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TreeBuilder {
     pub fn new() -> Self {
         Self {
@@ -22,11 +28,9 @@ impl TreeBuilder {
                     children: Vec::new(),
                 }));
             }
-            Token::Character(c) => {
+            Token::Character(c) if !c.is_whitespace() => {
                 // Simplified text handling
-                if !c.is_whitespace() {
-                     // Logic to append to text node
-                }
+                // Logic to append to text node
             }
             _ => {}
         }
@@ -38,6 +42,7 @@ impl TreeBuilder {
                 tag_name: "html".to_string(),
                 children: self.root_children,
             }),
+            character_set: "UTF-8",
         }
     }
 }
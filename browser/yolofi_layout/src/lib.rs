@@ -5,7 +5,7 @@ use yolofi_html::dom::Document;
 use yolofi_css::stylesheet::Stylesheet;
 use layout_tree::LayoutBox;
 
-pub fn layout(dom: &Document, style: &Stylesheet, width: f32) -> LayoutBox {
+pub fn layout(dom: &Document, _style: &Stylesheet, width: f32) -> LayoutBox {
     tracing::info!("Starting Layout Calculation...");
     // 1. Build the Layout Tree (DOM + Style)
     let mut root_box = layout_tree::build_layout_tree(&dom.root);
@@ -0,0 +1,249 @@
+// Content-Encoding
+// Decodes gzip, deflate, br and zstd bodies before they reach the HTML/CSS parsers.
+// Decoders are pure Rust and pull-based: `DecodingReader` wraps any `Read`, so a body
+// can be decoded as it streams in, or all at once with `decode`.
+//
+// Zip-bomb guard: decoding stops once the output exceeds `max_output`, or once it is
+// past `ratio_grace` bytes and more than `max_ratio` times the compressed input.
+
+use std::cell::Cell;
+use std::io::{self, BufRead, BufReader, Read};
+use std::rc::Rc;
+use tracing::{debug, warn};
+
+use crate::http_parser::DEFAULT_MAX_BODY_SIZE;
+
+/// Sent on every request.
+pub const ACCEPT_ENCODING: &str = "gzip, deflate, br, zstd";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentCoding {
+    Gzip,
+    Deflate,
+    Brotli,
+    Zstd,
+}
+
+impl ContentCoding {
+    /// Parses one Content-Encoding token. `identity` is not a coding and returns `None`.
+    pub fn from_token(token: &str) -> Result<Option<Self>, DecodeError> {
+        match token.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Ok(Some(ContentCoding::Gzip)),
+            "deflate" => Ok(Some(ContentCoding::Deflate)),
+            "br" => Ok(Some(ContentCoding::Brotli)),
+            "zstd" => Ok(Some(ContentCoding::Zstd)),
+            "identity" | "" => Ok(None),
+            other => Err(DecodeError::Unsupported(other.to_string())),
+        }
+    }
+}
+
+/// Codings in the order they were applied, as listed in a Content-Encoding header.
+pub fn parse_codings(header: &str) -> Result<Vec<ContentCoding>, DecodeError> {
+    header.split(',').filter_map(|token| ContentCoding::from_token(token).transpose()).collect()
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DecodeError {
+    #[error("unsupported content coding {0:?}")]
+    Unsupported(String),
+    #[error("corrupt compressed body: {0}")]
+    Corrupt(io::Error),
+    #[error("decoded body exceeds {limit} bytes")]
+    OutputTooLarge { limit: u64 },
+    #[error("decompression ratio exceeds {limit}:1")]
+    RatioExceeded { limit: u64 },
+}
+
+impl From<io::Error> for DecodeError {
+    fn from(e: io::Error) -> Self {
+        // Guard failures travel through the decoder chain as io::Error; unwrap them.
+        if e.get_ref().and_then(|inner| inner.downcast_ref::<DecodeError>()).is_some() {
+            let inner = e.into_inner().expect("checked above");
+            return *inner.downcast::<DecodeError>().expect("checked above");
+        }
+        DecodeError::Corrupt(e)
+    }
+}
+
+impl From<DecodeError> for io::Error {
+    fn from(e: DecodeError) -> Self {
+        match e {
+            DecodeError::Corrupt(inner) => inner,
+            other => io::Error::new(io::ErrorKind::InvalidData, other),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    pub max_output: u64,
+    pub max_ratio: u64,
+    /// Output size below which the ratio is not checked (small, very repetitive bodies are fine).
+    pub ratio_grace: u64,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self { max_output: DEFAULT_MAX_BODY_SIZE as u64, max_ratio: 100, ratio_grace: 1024 * 1024 }
+    }
+}
+
+/// Counts compressed bytes as the decoders pull them.
+struct CountingReader<R> {
+    inner: R,
+    count: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+}
+
+/// Streams decoded bytes out of a compressed source, enforcing `DecodeLimits`.
+pub struct DecodingReader<'a> {
+    inner: Box<dyn Read + 'a>,
+    input: Rc<Cell<u64>>,
+    output: u64,
+    limits: DecodeLimits,
+}
+
+impl<'a> DecodingReader<'a> {
+    /// `codings` are in applied order; they are undone last to first.
+    pub fn new<R: Read + 'a>(source: R, codings: &[ContentCoding], limits: DecodeLimits) -> Result<Self, DecodeError> {
+        let input = Rc::new(Cell::new(0));
+        let mut reader: Box<dyn Read + 'a> = Box::new(CountingReader { inner: source, count: input.clone() });
+        for coding in codings.iter().rev() {
+            reader = match coding {
+                ContentCoding::Gzip => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+                ContentCoding::Deflate => deflate_reader(reader)?,
+                ContentCoding::Brotli => Box::new(brotli_decompressor::Decompressor::new(reader, 4096)),
+                ContentCoding::Zstd => Box::new(
+                    ruzstd::decoding::StreamingDecoder::new(reader)
+                        .map_err(|e| DecodeError::Corrupt(io::Error::new(io::ErrorKind::InvalidData, e.to_string())))?,
+                ),
+            };
+        }
+        Ok(Self { inner: reader, input, output: 0, limits })
+    }
+}
+
+impl Read for DecodingReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.output += n as u64;
+
+        let limits = &self.limits;
+        if self.output > limits.max_output {
+            warn!(target: "net::http", "Decoded body passed {} bytes, aborting", limits.max_output);
+            return Err(DecodeError::OutputTooLarge { limit: limits.max_output }.into());
+        }
+        if self.output > limits.ratio_grace && self.output / self.input.get().max(1) > limits.max_ratio {
+            warn!(
+                target: "net::http",
+                "Decompression ratio {}:{} looks like a zip bomb, aborting",
+                self.output,
+                self.input.get()
+            );
+            return Err(DecodeError::RatioExceeded { limit: limits.max_ratio }.into());
+        }
+        Ok(n)
+    }
+}
+
+/// "deflate" means zlib-wrapped (RFC 9110), but some servers send raw deflate. Sniff the header.
+fn deflate_reader<'a>(source: Box<dyn Read + 'a>) -> Result<Box<dyn Read + 'a>, DecodeError> {
+    let mut source = BufReader::new(source);
+    let head = source.fill_buf()?;
+    let zlib = head.len() >= 2 && head[0] & 0x0f == 8 && (u16::from(head[0]) << 8 | u16::from(head[1])) % 31 == 0;
+    Ok(if zlib {
+        Box::new(flate2::read::ZlibDecoder::new(source))
+    } else {
+        Box::new(flate2::read::DeflateDecoder::new(source))
+    })
+}
+
+/// Decodes a complete body.
+pub fn decode(body: &[u8], codings: &[ContentCoding], limits: DecodeLimits) -> Result<Vec<u8>, DecodeError> {
+    let mut decoded = Vec::new();
+    DecodingReader::new(body, codings, limits)?.read_to_end(&mut decoded)?;
+    debug!(target: "net::http", "Decoded {:?}: {} -> {} bytes", codings, body.len(), decoded.len());
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn brotli(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ::brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+        encoder.write_all(data).unwrap();
+        encoder.into_inner()
+    }
+
+    #[test]
+    fn test_every_coding_round_trips() {
+        let page = b"<html><body>".repeat(50);
+
+        let mut zlib = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        zlib.write_all(&page).unwrap();
+        let mut raw = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        raw.write_all(&page).unwrap();
+        let zstd = ruzstd::encoding::compress_to_vec(&page[..], ruzstd::encoding::CompressionLevel::Fastest);
+
+        let cases = [
+            ("gzip", gzip(&page)),
+            ("deflate", zlib.finish().unwrap()),
+            ("deflate", raw.finish().unwrap()),
+            ("br", brotli(&page)),
+            ("zstd", zstd),
+            // Applied gzip first, then br: undone in reverse.
+            ("gzip, br", brotli(&gzip(&page))),
+            ("identity", page.clone()),
+        ];
+        for (header, body) in cases {
+            let codings = parse_codings(header).unwrap();
+            assert_eq!(decode(&body, &codings, DecodeLimits::default()).unwrap(), page, "{}", header);
+        }
+
+        assert!(matches!(parse_codings("compress"), Err(DecodeError::Unsupported(_))));
+        assert!(matches!(
+            decode(b"not gzip", &[ContentCoding::Gzip], DecodeLimits::default()),
+            Err(DecodeError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn test_ratio_guard_stops_zip_bombs() {
+        let bomb = gzip(&vec![0u8; 8 * 1024 * 1024]);
+        let err = decode(&bomb, &[ContentCoding::Gzip], DecodeLimits::default()).unwrap_err();
+        assert!(matches!(err, DecodeError::RatioExceeded { limit: 100 }), "{:?}", err);
+
+        let limits = DecodeLimits { max_output: 1000, ..DecodeLimits::default() };
+        let err = decode(&gzip(&[b'a'; 2000]), &[ContentCoding::Gzip], limits).unwrap_err();
+        assert!(matches!(err, DecodeError::OutputTooLarge { limit: 1000 }), "{:?}", err);
+
+        // Streaming: the guard trips part-way, without decoding the whole bomb.
+        let mut reader = DecodingReader::new(&bomb[..], &[ContentCoding::Gzip], DecodeLimits::default()).unwrap();
+        let mut buf = [0u8; 64 * 1024];
+        let mut decoded = 0;
+        let err = loop {
+            match reader.read(&mut buf) {
+                Ok(n) => decoded += n,
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(decoded < 2 * 1024 * 1024);
+    }
+}
@@ -0,0 +1,812 @@
+// Cookie Jar
+// RFC 6265 storage and retrieval, with the RFC 6265bis additions browsers ship:
+// SameSite (Lax when unspecified), Secure-only from secure origins, the __Secure- and
+// __Host- prefixes, and no Domain attribute that names a public suffix.
+//
+// Third-party cookies (a request whose site differs from the top-level site) are
+// neither sent nor stored by default. Sites can be given an exception, which
+// lets cookies flow for requests made while that site is the top-level one.
+//
+// Profile file `cookies.txt`; session cookies are never written:
+//   policy third-party=block|allow
+//   exception <site>
+//   cookie <domain> <host-only> <path> <secure> <http-only> <same-site> <expires> <created> <name>=<value>
+
+use std::collections::BTreeSet;
+use std::io;
+use std::sync::{Arc, Mutex};
+use tracing::{debug, info, warn};
+use yolofi_url::{Host, Url};
+
+use crate::http_date::{parse_http_date, unix_now};
+use crate::profile::Profile;
+use crate::public_suffix::PublicSuffixList;
+
+const COOKIES_FILE: &str = "cookies.txt";
+
+/// Name plus value, per cookie (RFC 6265bis section 5.6).
+pub const MAX_COOKIE_SIZE: usize = 4096;
+pub const MAX_COOKIES_PER_DOMAIN: usize = 50;
+pub const MAX_COOKIES: usize = 3000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    fn as_str(self) -> &'static str {
+        match self {
+            SameSite::Strict => "strict",
+            SameSite::Lax => "lax",
+            SameSite::None => "none",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "strict" => Some(SameSite::Strict),
+            "lax" => Some(SameSite::Lax),
+            "none" => Some(SameSite::None),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    /// Canonical (ASCII, lowercase) host or domain.
+    pub domain: String,
+    /// Set without a Domain attribute: sent to `domain` exactly, not its subdomains.
+    pub host_only: bool,
+    pub path: String,
+    /// Seconds since the Unix epoch. `None` for session cookies.
+    pub expires: Option<i64>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: SameSite,
+    pub created: i64,
+    pub last_access: i64,
+}
+
+impl Cookie {
+    fn is_expired(&self, now: i64) -> bool {
+        self.expires.is_some_and(|at| at <= now)
+    }
+
+    fn matches_host(&self, host: &str) -> bool {
+        if self.host_only {
+            host == self.domain
+        } else {
+            domain_match(host, &self.domain)
+        }
+    }
+}
+
+/// Where a request comes from, for SameSite and third-party decisions.
+#[derive(Debug, Clone)]
+pub struct CookieContext {
+    /// The top-level page's URL. `None` when the user navigated directly (address bar).
+    pub top_level: Option<Url>,
+    /// The request loads a new top-level document (link click, redirect, address bar).
+    pub top_level_navigation: bool,
+    /// GET or HEAD: Lax cookies travel on cross-site top-level navigations only with these.
+    pub safe_method: bool,
+    /// False for document.cookie, which must not see or set HttpOnly cookies.
+    pub http: bool,
+}
+
+impl CookieContext {
+    /// A navigation the user started, or one continued from `from` (a link or redirect).
+    pub fn navigation(from: Option<&Url>, method: &str) -> Self {
+        Self {
+            top_level: from.cloned(),
+            top_level_navigation: true,
+            safe_method: matches!(method, "GET" | "HEAD"),
+            http: true,
+        }
+    }
+
+    /// A subresource (image, script, fetch()) loaded by the page at `top_level`.
+    pub fn subresource(top_level: &Url, method: &str) -> Self {
+        Self {
+            top_level: Some(top_level.clone()),
+            top_level_navigation: false,
+            safe_method: matches!(method, "GET" | "HEAD"),
+            http: true,
+        }
+    }
+
+    /// document.cookie on the page at `page`.
+    pub fn script(page: &Url) -> Self {
+        Self { top_level: Some(page.clone()), top_level_navigation: false, safe_method: true, http: false }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CookiePolicy {
+    pub block_third_party: bool,
+    /// Registrable domains on which third-party cookies are allowed.
+    pub exceptions: BTreeSet<String>,
+}
+
+impl Default for CookiePolicy {
+    fn default() -> Self {
+        Self { block_third_party: true, exceptions: BTreeSet::new() }
+    }
+}
+
+#[derive(Debug, Default)]
+struct JarState {
+    cookies: Vec<Cookie>,
+    policy: CookiePolicy,
+}
+
+/// Shared handle to the cookie jar; clones see the same cookies.
+#[derive(Debug, Clone)]
+pub struct CookieJar {
+    state: Arc<Mutex<JarState>>,
+    suffixes: Arc<PublicSuffixList>,
+    profile: Option<Profile>,
+}
+
+impl Default for CookieJar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CookieJar {
+    /// An in-memory jar with the built-in public suffix list. Nothing is persisted.
+    pub fn new() -> Self {
+        Self {
+            state: Arc::default(),
+            suffixes: Arc::new(PublicSuffixList::builtin().clone()),
+            profile: None,
+        }
+    }
+
+    /// Loads cookies and policy from `profile`. Every later change is written back to it.
+    pub fn load(profile: &Profile) -> io::Result<Self> {
+        let mut state = JarState::default();
+        let now = unix_now();
+        if let Some(text) = profile.read_optional(COOKIES_FILE)? {
+            for (index, line) in text.lines().enumerate() {
+                parse_line(&mut state, line).map_err(|e| {
+                    io::Error::new(io::ErrorKind::InvalidData, format!("{} line {}: {}", COOKIES_FILE, index + 1, e))
+                })?;
+            }
+        }
+        state.cookies.retain(|c| !c.is_expired(now));
+        info!(
+            target: "net::cookies",
+            "Cookie jar loaded: {} cookies, third-party {}, {} exceptions",
+            state.cookies.len(),
+            if state.policy.block_third_party { "blocked" } else { "allowed" },
+            state.policy.exceptions.len()
+        );
+        Ok(Self {
+            state: Arc::new(Mutex::new(state)),
+            suffixes: Arc::new(PublicSuffixList::load(profile)?),
+            profile: Some(profile.clone()),
+        })
+    }
+
+    pub fn policy(&self) -> CookiePolicy {
+        self.lock().policy.clone()
+    }
+
+    pub fn set_block_third_party(&self, block: bool) -> io::Result<()> {
+        self.update(|state| state.policy.block_third_party = block)
+    }
+
+    /// Allows third-party cookies while `site` (any host on it) is the top-level page.
+    pub fn add_exception(&self, site: &str) -> io::Result<()> {
+        let site = self.site_of_host(site)?;
+        self.update(|state| {
+            state.policy.exceptions.insert(site);
+        })
+    }
+
+    pub fn remove_exception(&self, site: &str) -> io::Result<bool> {
+        let site = self.site_of_host(site)?;
+        let mut removed = false;
+        self.update(|state| removed = state.policy.exceptions.remove(&site))?;
+        Ok(removed)
+    }
+
+    /// Unexpired cookies, in storage order.
+    pub fn cookies(&self) -> Vec<Cookie> {
+        let now = unix_now();
+        self.lock().cookies.iter().filter(|c| !c.is_expired(now)).cloned().collect()
+    }
+
+    /// Drops every cookie whose domain is `host` or below it. Returns how many were removed.
+    pub fn clear_site(&self, host: &str) -> io::Result<usize> {
+        let host = self.site_of_host(host).unwrap_or_else(|_| host.to_ascii_lowercase());
+        let mut removed = 0;
+        self.update(|state| {
+            let before = state.cookies.len();
+            state.cookies.retain(|c| !domain_match(&c.domain, &host));
+            removed = before - state.cookies.len();
+        })?;
+        Ok(removed)
+    }
+
+    /// Stores the Set-Cookie values of a response to `url`.
+    pub fn store_response_cookies(&self, url: &Url, set_cookies: &[&str], context: &CookieContext) {
+        if set_cookies.is_empty() {
+            return;
+        }
+        self.store_at(url, set_cookies, context, unix_now());
+    }
+
+    /// The Cookie header value for a request to `url`, if any cookies apply.
+    pub fn cookie_header(&self, url: &Url, context: &CookieContext) -> Option<String> {
+        self.cookie_header_at(url, context, unix_now())
+    }
+
+    /// The registrable domain for `host`, or the host itself when it has none (IPs, "localhost").
+    fn site_of_host(&self, host: &str) -> io::Result<String> {
+        let host = Host::parse(host, false).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        Ok(self.site(&host))
+    }
+
+    fn site(&self, host: &Host) -> String {
+        match host.domain() {
+            Some(domain) => self.suffixes.registrable_domain(domain).unwrap_or(domain).to_string(),
+            None => host.to_string(),
+        }
+    }
+
+    /// Whether a request to `url` is cross-site, and if so whether the policy lets its cookies through.
+    fn third_party(&self, url: &Url, context: &CookieContext, policy: &CookiePolicy) -> (bool, bool) {
+        let (Some(top), Some(host)) = (&context.top_level, url.host()) else {
+            return (false, true);
+        };
+        let Some(top_host) = top.host() else {
+            return (true, !policy.block_third_party);
+        };
+        let top_site = self.site(top_host);
+        let cross_site = top_site != self.site(host);
+        let allowed = !cross_site
+            || context.top_level_navigation
+            || !policy.block_third_party
+            || policy.exceptions.contains(&top_site);
+        (cross_site, allowed)
+    }
+
+    pub(crate) fn store_at(&self, url: &Url, set_cookies: &[&str], context: &CookieContext, now: i64) {
+        let Some(host) = url.host().filter(|h| **h != Host::Empty) else {
+            return;
+        };
+        let host_name = host.to_socket_host();
+        let mut state = self.lock();
+        let (cross_site, allowed) = self.third_party(url, context, &state.policy);
+        if !allowed {
+            debug!(target: "net::cookies", "Blocked {} third-party cookie(s) from {}", set_cookies.len(), host_name);
+            return;
+        }
+
+        let mut changed = false;
+        for header in set_cookies {
+            let Some(parsed) = parse_set_cookie(header, now) else {
+                continue;
+            };
+            match self.build_cookie(parsed, url, host, cross_site, context, now) {
+                Ok(cookie) => changed |= insert(&mut state.cookies, cookie, is_secure_origin(url), context.http, now),
+                Err(reason) => debug!(target: "net::cookies", "Ignored cookie from {}: {}", host_name, reason),
+            }
+        }
+        if changed {
+            self.persist(&state);
+        }
+    }
+
+    fn build_cookie(
+        &self,
+        parsed: SetCookie,
+        url: &Url,
+        host: &Host,
+        cross_site: bool,
+        context: &CookieContext,
+        now: i64,
+    ) -> Result<Cookie, &'static str> {
+        let host_name = host.to_socket_host();
+        let secure_origin = is_secure_origin(url);
+        if parsed.secure && !secure_origin {
+            return Err("Secure cookie from an insecure origin");
+        }
+
+        let (domain, host_only) = match parsed.domain {
+            Some(domain) => {
+                let domain = match Host::parse(&domain, false) {
+                    Ok(Host::Domain(domain)) => domain,
+                    Ok(other) => other.to_socket_host(),
+                    Err(_) => return Err("invalid Domain attribute"),
+                };
+                if host.domain().is_some() && self.suffixes.is_public_suffix(&domain) {
+                    if domain != host_name {
+                        return Err("Domain attribute is a public suffix");
+                    }
+                    (domain, true)
+                } else if host.domain().is_none() && domain != host_name {
+                    return Err("Domain attribute on an IP address host");
+                } else if !domain_match(&host_name, &domain) {
+                    return Err("Domain attribute does not match the host");
+                } else {
+                    // For IP hosts the attribute can only repeat the host, which keeps it host-only.
+                    (domain, host.domain().is_none())
+                }
+            }
+            None => (host_name.to_string(), true),
+        };
+
+        let path = match parsed.path {
+            Some(path) => path,
+            None => default_path(url),
+        };
+
+        if parsed.http_only && !context.http {
+            return Err("HttpOnly cookie from script");
+        }
+        let same_site = parsed.same_site.unwrap_or(SameSite::Lax);
+        if same_site == SameSite::None && !parsed.secure {
+            return Err("SameSite=None without Secure");
+        }
+        if same_site != SameSite::None && cross_site && !context.top_level_navigation {
+            return Err("SameSite cookie set from a cross-site subresource");
+        }
+
+        let lower_name = parsed.name.to_ascii_lowercase();
+        if lower_name.starts_with("__secure-") && !parsed.secure {
+            return Err("__Secure- prefix without Secure");
+        }
+        if lower_name.starts_with("__host-") && !(parsed.secure && host_only && path == "/" && !parsed.had_domain) {
+            return Err("__Host- prefix requirements not met");
+        }
+
+        Ok(Cookie {
+            name: parsed.name,
+            value: parsed.value,
+            domain,
+            host_only,
+            path,
+            expires: parsed.expires,
+            secure: parsed.secure,
+            http_only: parsed.http_only,
+            same_site,
+            created: now,
+            last_access: now,
+        })
+    }
+
+    pub(crate) fn cookie_header_at(&self, url: &Url, context: &CookieContext, now: i64) -> Option<String> {
+        let host = url.host().filter(|h| **h != Host::Empty)?;
+        let host_name = host.to_socket_host();
+        let path = url.path();
+        let secure = is_secure_origin(url);
+
+        let mut state = self.lock();
+        let (cross_site, allowed) = self.third_party(url, context, &state.policy);
+        if !allowed {
+            return None;
+        }
+
+        let mut selected: Vec<&mut Cookie> = state
+            .cookies
+            .iter_mut()
+            .filter(|c| {
+                !c.is_expired(now)
+                    && c.matches_host(&host_name)
+                    && path_match(&path, &c.path)
+                    && (secure || !c.secure)
+                    && (context.http || !c.http_only)
+                    && (!cross_site
+                        || match c.same_site {
+                            SameSite::None => true,
+                            SameSite::Lax => context.top_level_navigation && context.safe_method,
+                            SameSite::Strict => false,
+                        })
+            })
+            .collect();
+        if selected.is_empty() {
+            return None;
+        }
+        // Longer paths first; equal paths by creation time.
+        selected.sort_by(|a, b| b.path.len().cmp(&a.path.len()).then(a.created.cmp(&b.created)));
+        let header = selected
+            .iter_mut()
+            .map(|c| {
+                c.last_access = now;
+                if c.name.is_empty() {
+                    c.value.clone()
+                } else {
+                    format!("{}={}", c.name, c.value)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("; ");
+        Some(header)
+    }
+
+    fn update(&self, change: impl FnOnce(&mut JarState)) -> io::Result<()> {
+        let mut state = self.lock();
+        change(&mut state);
+        match &self.profile {
+            Some(profile) => profile.write_atomic(COOKIES_FILE, serialize(&state).as_bytes()),
+            None => Ok(()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, JarState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Saves after a response stored cookies, where there is no caller to report to.
+    fn persist(&self, state: &JarState) {
+        if let Some(profile) = &self.profile {
+            if let Err(e) = profile.write_atomic(COOKIES_FILE, serialize(state).as_bytes()) {
+                warn!(target: "net::cookies", "Failed to save {}: {}", COOKIES_FILE, e);
+            }
+        }
+    }
+}
+
+/// https, or plain http to the loopback interface (potentially trustworthy origins).
+pub(crate) fn is_secure_origin(url: &Url) -> bool {
+    match (url.scheme(), url.host()) {
+        ("https" | "wss", _) => true,
+        (_, Some(Host::Domain(d))) => d == "localhost" || d.ends_with(".localhost"),
+        (_, Some(Host::Ipv4(ip))) => ip.is_loopback(),
+        (_, Some(Host::Ipv6(ip))) => ip.is_loopback(),
+        _ => false,
+    }
+}
+
+/// RFC 6265 section 5.1.3.
+fn domain_match(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.ends_with(domain)
+            && host[..host.len() - domain.len()].ends_with('.')
+            && host.parse::<std::net::IpAddr>().is_err())
+}
+
+/// RFC 6265 section 5.1.4.
+fn path_match(request_path: &str, cookie_path: &str) -> bool {
+    let request_path = if request_path.is_empty() { "/" } else { request_path };
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/')))
+}
+
+/// The directory of the request path: "/a/b/c" -> "/a/b".
+fn default_path(url: &Url) -> String {
+    let path = url.path();
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(i) => path[..i].to_string(),
+    }
+}
+
+/// Adds or replaces a cookie. Returns whether a persistent cookie changed.
+fn insert(cookies: &mut Vec<Cookie>, mut cookie: Cookie, secure_origin: bool, http: bool, now: i64) -> bool {
+    // An insecure origin may not shadow a Secure cookie (RFC 6265bis "leave secure cookies alone").
+    if !cookie.secure
+        && !secure_origin
+        && cookies.iter().any(|c| {
+            c.secure
+                && c.name == cookie.name
+                && (domain_match(&c.domain, &cookie.domain) || domain_match(&cookie.domain, &c.domain))
+                && path_match(&cookie.path, &c.path)
+        })
+    {
+        debug!(target: "net::cookies", "Insecure origin tried to overwrite Secure cookie {}", cookie.name);
+        return false;
+    }
+
+    let mut changed = cookie.expires.is_some();
+    if let Some(index) = cookies
+        .iter()
+        .position(|c| c.name == cookie.name && c.domain == cookie.domain && c.host_only == cookie.host_only && c.path == cookie.path)
+    {
+        if cookies[index].http_only && !http {
+            debug!(target: "net::cookies", "Script tried to overwrite HttpOnly cookie {}", cookie.name);
+            return false;
+        }
+        let old = cookies.remove(index);
+        cookie.created = old.created;
+        changed |= old.expires.is_some();
+    }
+    if cookie.is_expired(now) {
+        // An expiry in the past is how servers delete cookies.
+        return changed;
+    }
+    cookies.push(cookie);
+    evict(cookies, now);
+    changed
+}
+
+/// Enforces the per-domain and total limits, dropping expired then least recently used cookies.
+fn evict(cookies: &mut Vec<Cookie>, now: i64) {
+    cookies.retain(|c| !c.is_expired(now));
+    let domain = cookies.last().map(|c| c.domain.clone()).unwrap_or_default();
+    while cookies.iter().filter(|c| c.domain == domain).count() > MAX_COOKIES_PER_DOMAIN {
+        remove_oldest(cookies, |c| c.domain == domain);
+    }
+    while cookies.len() > MAX_COOKIES {
+        remove_oldest(cookies, |_| true);
+    }
+}
+
+fn remove_oldest(cookies: &mut Vec<Cookie>, filter: impl Fn(&Cookie) -> bool) {
+    let oldest = cookies
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| filter(c))
+        .min_by_key(|(_, c)| c.last_access)
+        .map(|(i, _)| i);
+    if let Some(index) = oldest {
+        let evicted = cookies.remove(index);
+        debug!(target: "net::cookies", "Evicted cookie {} for {}", evicted.name, evicted.domain);
+    }
+}
+
+/// A parsed Set-Cookie header, before the storage checks.
+#[derive(Debug, Default)]
+struct SetCookie {
+    name: String,
+    value: String,
+    expires: Option<i64>,
+    domain: Option<String>,
+    had_domain: bool,
+    path: Option<String>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+/// RFC 6265bis section 5.6. `None` when the header must be ignored.
+fn parse_set_cookie(header: &str, now: i64) -> Option<SetCookie> {
+    if header.chars().any(|c| c.is_control() && c != '\t') {
+        return None;
+    }
+    let mut parts = header.split(';');
+    let pair = parts.next()?;
+    let (name, value) = match pair.split_once('=') {
+        Some((name, value)) => (name.trim(), value.trim()),
+        None => ("", pair.trim()),
+    };
+    if (name.is_empty() && value.is_empty()) || name.len() + value.len() > MAX_COOKIE_SIZE {
+        return None;
+    }
+
+    let mut cookie = SetCookie { name: name.to_string(), value: value.to_string(), ..SetCookie::default() };
+    let (mut max_age, mut expires) = (None, None);
+    for attribute in parts {
+        let (key, value) = match attribute.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => (attribute.trim(), ""),
+        };
+        if value.len() > 1024 {
+            continue;
+        }
+        match key.to_ascii_lowercase().as_str() {
+            "expires" => expires = parse_http_date(value).or(expires),
+            "max-age" => {
+                let digits = value.strip_prefix('-').unwrap_or(value);
+                if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+                    let seconds = value.parse::<i64>().unwrap_or(if value.starts_with('-') { i64::MIN } else { i64::MAX });
+                    max_age = Some(if seconds <= 0 { i64::MIN } else { now.saturating_add(seconds) });
+                }
+            }
+            "domain" => {
+                cookie.had_domain = true;
+                let domain = value.strip_prefix('.').unwrap_or(value).to_ascii_lowercase();
+                cookie.domain = (!domain.is_empty()).then_some(domain);
+            }
+            "path" => cookie.path = value.starts_with('/').then(|| value.to_string()),
+            "secure" => cookie.secure = true,
+            "httponly" => cookie.http_only = true,
+            "samesite" => cookie.same_site = SameSite::parse(value).or(cookie.same_site),
+            _ => {}
+        }
+    }
+    // Max-Age wins over Expires.
+    cookie.expires = max_age.or(expires);
+    Some(cookie)
+}
+
+fn parse_line(state: &mut JarState, line: &str) -> Result<(), String> {
+    let mut words = line.split(' ');
+    match words.next() {
+        None | Some("") => Ok(()),
+        Some(word) if word.starts_with('#') => Ok(()),
+        Some("policy") => match words.next() {
+            Some("third-party=block") => {
+                state.policy.block_third_party = true;
+                Ok(())
+            }
+            Some("third-party=allow") => {
+                state.policy.block_third_party = false;
+                Ok(())
+            }
+            other => Err(format!("unrecognised policy {:?}", other)),
+        },
+        Some("exception") => {
+            let site = words.next().ok_or("exception without a site")?;
+            state.policy.exceptions.insert(site.to_ascii_lowercase());
+            Ok(())
+        }
+        Some("cookie") => {
+            let fields: Vec<&str> = words.collect();
+            let [domain, host_only, path, secure, http_only, same_site, expires, created, pair] = fields[..] else {
+                return Err(format!("expected 9 cookie fields, found {}", fields.len()));
+            };
+            let flag = |value: &str| match value {
+                "1" => Ok(true),
+                "0" => Ok(false),
+                other => Err(format!("bad flag {:?}", other)),
+            };
+            let number = |value: &str| value.parse::<i64>().map_err(|_| format!("bad number {:?}", value));
+            let (name, value) = pair.split_once('=').ok_or("cookie without '='")?;
+            let created = number(created)?;
+            state.cookies.push(Cookie {
+                name: name.to_string(),
+                value: value.to_string(),
+                domain: domain.to_string(),
+                host_only: flag(host_only)?,
+                path: path.to_string(),
+                expires: Some(number(expires)?),
+                secure: flag(secure)?,
+                http_only: flag(http_only)?,
+                same_site: SameSite::parse(same_site).ok_or_else(|| format!("bad SameSite {:?}", same_site))?,
+                created,
+                last_access: created,
+            });
+            Ok(())
+        }
+        Some(other) => Err(format!("unrecognised entry {:?}", other)),
+    }
+}
+
+fn serialize(state: &JarState) -> String {
+    let mut out = format!(
+        "policy third-party={}\n",
+        if state.policy.block_third_party { "block" } else { "allow" }
+    );
+    for site in &state.policy.exceptions {
+        out.push_str(&format!("exception {}\n", site));
+    }
+    let flag = |b: bool| if b { "1" } else { "0" };
+    for cookie in &state.cookies {
+        // Session cookies die with the browser; spaces cannot appear in stored names or values.
+        let Some(expires) = cookie.expires else { continue };
+        if cookie.name.contains([' ', '=']) || cookie.value.contains(' ') || cookie.path.contains(' ') {
+            continue;
+        }
+        out.push_str(&format!(
+            "cookie {} {} {} {} {} {} {} {} {}={}\n",
+            cookie.domain,
+            flag(cookie.host_only),
+            cookie.path,
+            flag(cookie.secure),
+            flag(cookie.http_only),
+            cookie.same_site.as_str(),
+            expires,
+            cookie.created,
+            cookie.name,
+            cookie.value
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    fn store(jar: &CookieJar, at: &str, headers: &[&str]) {
+        let target = url(at);
+        jar.store_at(&target, headers, &CookieContext::navigation(None, "GET"), NOW);
+    }
+
+    fn header(jar: &CookieJar, at: &str) -> Option<String> {
+        jar.cookie_header_at(&url(at), &CookieContext::navigation(None, "GET"), NOW)
+    }
+
+    #[test]
+    fn test_domain_path_and_attribute_rules() {
+        let jar = CookieJar::new();
+        store(&jar, "https://www.example.co.uk/account/login", &[
+            "sid=1; Secure; HttpOnly",
+            "wide=2; Domain=.Example.co.uk; Path=/",
+            "tld=3; Domain=co.uk",
+            "other=4; Domain=example.com",
+            "late=5; Max-Age=60; Expires=Thu, 01 Jan 1970 00:00:00 GMT",
+            "__Host-h=6; Secure; Path=/",
+            "__Host-bad=7; Secure; Domain=www.example.co.uk; Path=/",
+            "none=8; SameSite=None",
+        ]);
+        let names: Vec<String> = jar.lock().cookies.iter().map(|c| c.name.clone()).collect();
+        assert_eq!(names, vec!["sid", "wide", "late", "__Host-h"]);
+
+        // Default path is the directory: "/account".
+        assert_eq!(header(&jar, "https://www.example.co.uk/account/x").as_deref(), Some("sid=1; late=5; wide=2; __Host-h=6"));
+        assert_eq!(header(&jar, "https://www.example.co.uk/").as_deref(), Some("wide=2; __Host-h=6"));
+        assert_eq!(header(&jar, "https://shop.example.co.uk/accounts").as_deref(), Some("wide=2"));
+        assert_eq!(header(&jar, "http://www.example.co.uk/account/").as_deref(), Some("late=5; wide=2"));
+        assert_eq!(header(&jar, "https://example.com/"), None);
+
+        // Plain http cannot overwrite the Secure cookie; deleting via past expiry works over https.
+        store(&jar, "http://www.example.co.uk/account/", &["sid=evil"]);
+        store(&jar, "https://www.example.co.uk/", &["wide=; Domain=example.co.uk; Max-Age=0"]);
+        assert_eq!(header(&jar, "https://www.example.co.uk/account/x").as_deref(), Some("sid=1; late=5; __Host-h=6"));
+        let later = jar.cookie_header_at(&url("https://www.example.co.uk/"), &CookieContext::navigation(None, "GET"), NOW + 61);
+        assert_eq!(later.as_deref(), Some("__Host-h=6"));
+    }
+
+    #[test]
+    fn test_same_site_and_third_party_policy() {
+        let jar = CookieJar::new();
+        store(&jar, "https://shop.test/", &[
+            "strict=1; SameSite=Strict",
+            "lax=2",
+            "tracker=3; SameSite=None; Secure",
+        ]);
+        let news = url("https://news.test/article");
+        let shop = url("https://shop.test/cart");
+
+        // Same-site subresource: everything. Cross-site top-level GET: Lax and None.
+        assert_eq!(
+            jar.cookie_header_at(&shop, &CookieContext::subresource(&url("https://www.shop.test/"), "GET"), NOW).as_deref(),
+            Some("strict=1; lax=2; tracker=3")
+        );
+        assert_eq!(jar.cookie_header_at(&shop, &CookieContext::navigation(Some(&news), "GET"), NOW).as_deref(), Some("lax=2; tracker=3"));
+        assert_eq!(jar.cookie_header_at(&shop, &CookieContext::navigation(Some(&news), "POST"), NOW).as_deref(), Some("tracker=3"));
+
+        // Cross-site subresource: blocked as third-party, unless news.test has an exception.
+        let embedded = CookieContext::subresource(&news, "GET");
+        assert_eq!(jar.cookie_header_at(&shop, &embedded, NOW), None);
+        jar.add_exception("www.news.test").unwrap();
+        assert_eq!(jar.cookie_header_at(&shop, &embedded, NOW).as_deref(), Some("tracker=3"));
+        jar.store_at(&shop, &["embedded=4; SameSite=None; Secure", "laxish=5"], &embedded, NOW);
+        assert!(jar.cookies().iter().any(|c| c.name == "embedded"));
+        assert!(!jar.cookies().iter().any(|c| c.name == "laxish"));
+
+        // document.cookie never sees HttpOnly.
+        store(&jar, "https://shop.test/", &["secret=6; HttpOnly"]);
+        let script = jar.cookie_header_at(&shop, &CookieContext::script(&shop), NOW).unwrap();
+        assert!(!script.contains("secret"));
+    }
+
+    #[test]
+    fn test_persists_only_persistent_cookies_and_policy() {
+        let dir = std::env::temp_dir().join(format!("yolofi_cookies_{}", std::process::id()));
+        let profile = Profile::open(&dir).unwrap();
+
+        let jar = CookieJar::load(&profile).unwrap();
+        jar.add_exception("news.test").unwrap();
+        let far = "Expires=Fri, 01 Jan 2100 00:00:00 GMT";
+        jar.store_response_cookies(&url("https://a.test/"), &[&format!("keep=1; {}", far), "session=2"], &CookieContext::navigation(None, "GET"));
+
+        let reloaded = CookieJar::load(&profile).unwrap();
+        let names: Vec<String> = reloaded.cookies().into_iter().map(|c| c.name).collect();
+        assert_eq!(names, vec!["keep"]);
+        assert!(reloaded.policy().block_third_party);
+        assert!(reloaded.policy().exceptions.contains("news.test"));
+        assert_eq!(reloaded.clear_site("a.test").unwrap(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,40 +1,127 @@
-use std::net::UdpSocket;
 use std::time::Duration;
 use tracing::{info, warn};
 
 use yolofi_config::PRIVATE_DNS_SERVER;
 
+use crate::transport::{DatagramTransport, UdpTransport};
+use yolofi_url::Host;
+
 const CURRENT_DNS_SERVER: &str = PRIVATE_DNS_SERVER;
 
-pub struct DnsResolver {
-    socket: UdpSocket,
+/// CNAMEs followed from the queried name before giving up.
+const MAX_CNAME_HOPS: usize = 8;
+
+/// Longest name on the wire, length bytes and root label included (RFC 1035 section 2.3.4).
+const MAX_NAME_LENGTH: usize = 255;
+
+pub const DEFAULT_MAX_POINTER_DEPTH: usize = 16;
+pub const DEFAULT_MAX_LABELS: usize = 127;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DnsLimits {
+    /// Compression pointers followed while reading one name.
+    pub max_pointer_depth: usize,
+    /// Labels in one name, after following pointers.
+    pub max_labels: usize,
+}
+
+impl Default for DnsLimits {
+    fn default() -> Self {
+        Self { max_pointer_depth: DEFAULT_MAX_POINTER_DEPTH, max_labels: DEFAULT_MAX_LABELS }
+    }
+}
+
+/// A name as its lowercased labels, so "a.b" in one label never equals "a" + "b".
+type Labels = Vec<Vec<u8>>;
+
+/// Why a reply could not be used.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DnsError {
+    #[error("reply ends inside a record")]
+    Truncated,
+    #[error("reserved label type {0:#04x}")]
+    BadLabel(u8),
+    #[error("compression pointer to offset {0} does not point backwards")]
+    BadPointer(usize),
+    #[error("more than {limit} compression pointers in one name")]
+    PointerTooDeep { limit: usize },
+    #[error("more than {limit} labels in one name")]
+    TooManyLabels { limit: usize },
+    #[error("name longer than 255 bytes")]
+    NameTooLong,
+    #[error("reply ID {got:#06x} does not match query ID {expected:#06x}")]
+    WrongId { expected: u16, got: u16 },
+    #[error("message is a query, not a reply")]
+    NotAReply,
+    #[error("server answered with RCODE {0}")]
+    ServerError(u8),
+    #[error("no A record in the reply")]
+    NoAddress,
+}
+
+impl From<DnsError> for std::io::Error {
+    fn from(e: DnsError) -> Self {
+        let kind = match e {
+            DnsError::NoAddress | DnsError::ServerError(3) => std::io::ErrorKind::NotFound,
+            _ => std::io::ErrorKind::InvalidData,
+        };
+        std::io::Error::new(kind, e)
+    }
+}
+
+pub struct DnsResolver<D: DatagramTransport = UdpTransport> {
+    transport: D,
+    server: String,
+    limits: DnsLimits,
 }
 
 impl DnsResolver {
     pub fn new() -> std::io::Result<Self> {
-        let socket = UdpSocket::bind("0.0.0.0:0")?;
-        socket.set_read_timeout(Some(Duration::from_secs(2)))?;
-        Ok(Self { socket })
+        Ok(Self::with_transport(UdpTransport::new(Duration::from_secs(2))))
     }
+}
 
-    pub fn resolve(&self, domain: &str) -> std::io::Result<String> {
-        info!(target: "net::dns", "Resolving {} via {}", domain, CURRENT_DNS_SERVER);
+impl<D: DatagramTransport> DnsResolver<D> {
+    pub fn with_transport(transport: D) -> Self {
+        Self { transport, server: CURRENT_DNS_SERVER.to_string(), limits: DnsLimits::default() }
+    }
 
-        let query = self.build_query(domain);
-        self.socket.send_to(&query, CURRENT_DNS_SERVER)?;
+    pub fn with_server(mut self, server: &str) -> Self {
+        self.server = server.to_string();
+        self
+    }
 
-        let mut buffer = [0u8; 512];
-        let (amt, _src) = self.socket.recv_from(&mut buffer)?;
+    pub fn with_limits(mut self, limits: DnsLimits) -> Self {
+        self.limits = limits;
+        self
+    }
 
-        let response = &buffer[..amt];
-        self.parse_response(response)
+    /// Names go through URL host parsing first, so "Bücher.example" is asked for as
+    /// "xn--bcher-kva.example". IP literals are returned without a query.
+    pub fn resolve(&self, domain: &str) -> std::io::Result<String> {
+        let host = Host::parse(domain, false)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let Some(domain) = host.domain() else {
+            return Ok(host.to_socket_host());
+        };
+        info!(target: "net::dns", "Resolving {} via {}", domain, self.server);
+
+        // A random ID, so an off-path attacker has to guess it to forge a reply.
+        let random = rustls::crypto::ring::default_provider().secure_random;
+        let mut id = [0u8; 2];
+        random.fill(&mut id).map_err(|_| std::io::Error::other("no system randomness"))?;
+        let id = u16::from_be_bytes(id);
+
+        let query = self.build_query(id, domain)?;
+        let response = self.transport.exchange(&self.server, &query)?;
+        let name = domain.strip_suffix('.').unwrap_or(domain);
+        Ok(self.parse_response(&response, id, name)?)
     }
 
-    fn build_query(&self, domain: &str) -> Vec<u8> {
+    fn build_query(&self, id: u16, domain: &str) -> std::io::Result<Vec<u8>> {
         let mut packet = Vec::with_capacity(512);
 
         // Header
-        let id = 0x1234u16; // Fixed ID for now (Todo: randomize)
         packet.extend_from_slice(&id.to_be_bytes());
         packet.extend_from_slice(&0x0100u16.to_be_bytes()); // Flags: Standard Query, Recursion Desired
         packet.extend_from_slice(&0x0001u16.to_be_bytes()); // QDCOUNT: 1
@@ -42,70 +129,122 @@ impl DnsResolver {
         packet.extend_from_slice(&0x0000u16.to_be_bytes()); // NSCOUNT: 0
         packet.extend_from_slice(&0x0000u16.to_be_bytes()); // ARCOUNT: 0
 
-        // Question Section
-        for part in domain.split('.') {
-            packet.push(part.len() as u8);
-            packet.extend_from_slice(part.as_bytes());
+        // Question Section: length-prefixed labels, 1-63 bytes each, 253 in total.
+        let name = domain.strip_suffix('.').unwrap_or(domain);
+        if name.len() > 253 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Domain name too long"));
+        }
+        for label in name.split('.') {
+            if label.is_empty() || label.len() > 63 {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Invalid label in {:?}", domain)));
+            }
+            packet.push(label.len() as u8);
+            packet.extend_from_slice(label.as_bytes());
         }
         packet.push(0); // Root null byte
 
         packet.extend_from_slice(&0x0001u16.to_be_bytes()); // QTYPE: A (Host Address)
         packet.extend_from_slice(&0x0001u16.to_be_bytes()); // QCLASS: IN
 
-        packet
+        Ok(packet)
     }
 
-    fn parse_response(&self, buffer: &[u8]) -> std::io::Result<String> {
-        // Very minimal parser. Skips header and question to find Answer.
-        // Todo: Full RFC 1035 compliance.
-
+    /// Reads the A record for `name` from the reply to query `id` (RFC 1035 section 4.1).
+    /// Records for other names are ignored unless a CNAME chain from `name` leads to them.
+    /// Every offset is bounds-checked.
+    fn parse_response(&self, buffer: &[u8], id: u16, name: &str) -> Result<String, DnsError> {
+        let field = |pos: usize| -> Result<u16, DnsError> {
+            let bytes = buffer.get(pos..pos + 2).ok_or(DnsError::Truncated)?;
+            Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+        };
         if buffer.len() < 12 {
-            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Packet too short"));
+            return Err(DnsError::Truncated);
         }
+        if field(0)? != id {
+            return Err(DnsError::WrongId { expected: id, got: field(0)? });
+        }
+        let flags = field(2)?;
+        if flags & 0x8000 == 0 {
+            return Err(DnsError::NotAReply);
+        }
+        if flags & 0x000F != 0 {
+            return Err(DnsError::ServerError((flags & 0x000F) as u8));
+        }
+        let questions = field(4)?;
+        let answers = field(6)?;
 
-        // Skip Header (12 bytes)
         let mut pos = 12;
-
-        // Skip Question
-        // Find null byte
-        while pos < buffer.len() {
-            let label_len = buffer[pos];
-            pos += 1;
-            if label_len == 0 {
-                break;
-            }
-            pos += label_len as usize;
+        for _ in 0..questions {
+            pos = self.read_name(buffer, pos)?.1 + 4; // QTYPE, QCLASS
         }
-        pos += 4; // Skip QTYPE and QCLASS
-
-        // Now at Answer section
-        if pos >= buffer.len() {
-             return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "No answer section"));
+        // (owner, TYPE, CLASS, RDATA offset, RDLENGTH)
+        let mut records = Vec::with_capacity(answers as usize);
+        for _ in 0..answers {
+            let (owner, end) = self.read_name(buffer, pos)?;
+            let (rtype, class) = (field(end)?, field(end + 2)?);
+            let rdlength = field(end + 8)? as usize;
+            pos = end + 10;
+            buffer.get(pos..pos + rdlength).ok_or(DnsError::Truncated)?;
+            records.push((owner, rtype, class, pos, rdlength));
+            pos += rdlength;
         }
 
-        // Name ptr (usually 0xc0...)
-        if buffer[pos] & 0xC0 == 0xC0 {
-            pos += 2;
-        } else {
-             // scan labels again... (omitted for brevity in v0.1)
-             // simplified: assume pointer
-             return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "Complex response parsing not impl"));
+        let mut wanted: Labels = name.split('.').map(|label| label.as_bytes().to_ascii_lowercase()).collect();
+        for _ in 0..=MAX_CNAME_HOPS {
+            let owned = |rtype: u16| records.iter().find(|r| r.0 == wanted && (r.1, r.2) == (rtype, 1));
+            if let Some(&(_, _, _, at, 4)) = owned(1) {
+                let ip = format!("{}.{}.{}.{}", buffer[at], buffer[at + 1], buffer[at + 2], buffer[at + 3]);
+                info!(target: "net::dns", "Resolved to {}", ip);
+                return Ok(ip);
+            }
+            match owned(5) {
+                Some(&(_, _, _, at, _)) => wanted = self.read_name(buffer, at)?.0,
+                None => break,
+            }
         }
+        warn!(target: "net::dns", "No A record for {} among {} answers", name, answers);
+        Err(DnsError::NoAddress)
+    }
 
-        pos += 2; // Type
-        pos += 2; // Class
-        pos += 4; // TTL
-        
-        let rdlength = u16::from_be_bytes([buffer[pos], buffer[pos + 1]]) as usize;
-        pos += 2;
-
-        if rdlength == 4 {
-             let ip = format!("{}.{}.{}.{}", buffer[pos], buffer[pos+1], buffer[pos+2], buffer[pos+3]);
-             info!(target: "net::dns", "Resolved to {}", ip);
-             Ok(ip)
-        } else {
-             warn!("Not an A record");
-             Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Not an A record"))
+    /// Reads the name at `pos` (section 4.1.4) and returns it with the offset just past it.
+    /// Compression pointers must point before the labels that led to them, so a name
+    /// cannot loop; how many may be chained is still capped by `DnsLimits`.
+    fn read_name(&self, buffer: &[u8], mut pos: usize) -> Result<(Labels, usize), DnsError> {
+        let mut end = None;
+        let mut segment_start = pos;
+        let (mut jumps, mut labels, mut length) = (0, Labels::new(), 1);
+        loop {
+            let len = *buffer.get(pos).ok_or(DnsError::Truncated)?;
+            match len & 0xC0 {
+                0x00 if len == 0 => return Ok((labels, end.unwrap_or(pos + 1))),
+                0x00 => {
+                    if labels.len() == self.limits.max_labels {
+                        return Err(DnsError::TooManyLabels { limit: self.limits.max_labels });
+                    }
+                    length += len as usize + 1;
+                    if length > MAX_NAME_LENGTH {
+                        return Err(DnsError::NameTooLong);
+                    }
+                    let label = buffer.get(pos + 1..pos + 1 + len as usize).ok_or(DnsError::Truncated)?;
+                    labels.push(label.to_ascii_lowercase());
+                    pos += len as usize + 1;
+                }
+                0xC0 => {
+                    let low = *buffer.get(pos + 1).ok_or(DnsError::Truncated)?;
+                    let target = usize::from(len & 0x3F) << 8 | usize::from(low);
+                    if target >= segment_start {
+                        return Err(DnsError::BadPointer(target));
+                    }
+                    jumps += 1;
+                    if jumps > self.limits.max_pointer_depth {
+                        return Err(DnsError::PointerTooDeep { limit: self.limits.max_pointer_depth });
+                    }
+                    end.get_or_insert(pos + 2);
+                    segment_start = target;
+                    pos = target;
+                }
+                _ => return Err(DnsError::BadLabel(len)),
+            }
         }
     }
 }
@@ -117,3 +256,113 @@ pub fn resolve(host: &str) -> String {
         "0.0.0.0".to_string()
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_over_in_memory_transport() {
+        let transport = |server: &str, query: &[u8]| -> std::io::Result<Vec<u8>> {
+            assert_eq!(server, "10.0.0.53:53");
+            assert_eq!(&query[12..31], b"\x0exn--mnchen-3ya\x02de\x00");
+            let mut reply = query.to_vec();
+            reply[2] = 0x81;
+            reply[3] = 0x80;
+            reply[7] = 1; // ANCOUNT
+            reply.extend_from_slice(&[0xC0, 0x0C, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 93, 184, 216, 34]);
+            Ok(reply)
+        };
+
+        let resolver = DnsResolver::with_transport(transport).with_server("10.0.0.53:53");
+        assert_eq!(resolver.resolve("MÜNCHEN.de.").unwrap(), "93.184.216.34");
+        assert_eq!(resolver.resolve("[::1]").unwrap(), "::1");
+        assert!(resolver.resolve("a..b").is_err());
+        assert!(resolver.resolve(&format!("{}.com", "x".repeat(64))).is_err());
+    }
+
+    #[test]
+    fn test_hostile_replies_fail_with_typed_errors() {
+        let resolver = DnsResolver::with_transport(|_: &str, _: &[u8]| Ok(Vec::new()));
+        // ID, flags, then QDCOUNT and ANCOUNT.
+        let header = |questions: u16, answers: u16| {
+            let mut reply = vec![0x12, 0x34, 0x81, 0x80];
+            reply.extend_from_slice(&questions.to_be_bytes());
+            reply.extend_from_slice(&answers.to_be_bytes());
+            reply.extend_from_slice(&[0, 0, 0, 0]);
+            reply
+        };
+        let answer = [0xC0, 0x0C, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 10, 0, 0, 1];
+        let mut good = header(1, 1);
+        good.extend_from_slice(b"\x01a\x00\x00\x01\x00\x01");
+        good.extend_from_slice(&answer);
+        assert_eq!(resolver.parse_response(&good, 0x1234, "a"), Ok("10.0.0.1".to_string()));
+
+        // Every cut of a valid reply used to index past the end.
+        for cut in 0..good.len() {
+            assert!(resolver.parse_response(&good[..cut], 0x1234, "a").is_err(), "cut at {}", cut);
+        }
+        let parse = |questions: u16, answers: u16, body: &[u8]| {
+            resolver.parse_response(&[header(questions, answers).as_slice(), body].concat(), 0x1234, "a")
+        };
+        assert_eq!(parse(1, 0, b"\xC0\x0C\x00\x01\x00\x01"), Err(DnsError::BadPointer(12)));
+        assert_eq!(parse(1, 0, b"\x01a\xC0\x0C\x00\x01\x00\x01"), Err(DnsError::BadPointer(12)));
+        assert_eq!(parse(1, 0, b"\x41a\x00\x00\x01\x00\x01"), Err(DnsError::BadLabel(0x41)));
+        assert_eq!(parse(1, 0, &b"\x3f".repeat(300)), Err(DnsError::NameTooLong));
+        assert_eq!(parse(1, 1, &good[12..good.len() - 2]), Err(DnsError::Truncated));
+        let mut txt = good.clone();
+        txt[good.len() - 13] = 16; // TYPE: TXT
+        assert_eq!(resolver.parse_response(&txt, 0x1234, "a"), Err(DnsError::NoAddress));
+
+        // Each question points at the one before, so the last is 19 pointers deep.
+        let mut chain = b"\x01a\x00\x00\x01\x00\x01".to_vec();
+        let mut previous = 12;
+        for _ in 0..19 {
+            let here = 12 + chain.len();
+            chain.extend_from_slice(&[0xC0 | (previous >> 8) as u8, previous as u8, 0, 1, 0, 1]);
+            previous = here;
+        }
+        assert_eq!(parse(20, 0, &chain), Err(DnsError::PointerTooDeep { limit: DEFAULT_MAX_POINTER_DEPTH }));
+        let lenient = DnsResolver::with_transport(|_: &str, _: &[u8]| Ok(Vec::new()))
+            .with_limits(DnsLimits { max_pointer_depth: 32, max_labels: 4 });
+        let deep = [header(20, 0).as_slice(), &chain].concat();
+        assert_eq!(lenient.parse_response(&deep, 0x1234, "a"), Err(DnsError::NoAddress));
+        let labels = [header(1, 0).as_slice(), &b"\x01a".repeat(5), b"\x00\x00\x01\x00\x01"].concat();
+        assert_eq!(lenient.parse_response(&labels, 0x1234, "a"), Err(DnsError::TooManyLabels { limit: 4 }));
+    }
+
+    #[test]
+    fn test_replies_must_answer_our_query() {
+        let resolver = DnsResolver::with_transport(|_: &str, _: &[u8]| Ok(Vec::new()));
+        // ID 0x1234 and flags, one question for "a", then `answers`.
+        let reply = |flags: [u8; 2], answers: &[&[u8]]| {
+            let mut reply = vec![0x12, 0x34, flags[0], flags[1], 0, 1, 0, answers.len() as u8, 0, 0, 0, 0];
+            reply.extend_from_slice(b"\x01a\x00\x00\x01\x00\x01");
+            answers.iter().for_each(|answer| reply.extend_from_slice(answer));
+            reply
+        };
+        let a_for_a: &[u8] = &[0xC0, 0x0C, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 10, 0, 0, 1];
+        let ok = [0x81, 0x80];
+        assert_eq!(resolver.parse_response(&reply(ok, &[a_for_a]), 0x1234, "A"), Ok("10.0.0.1".to_string()));
+
+        // Forged or misdirected replies.
+        let forged = resolver.parse_response(&reply(ok, &[a_for_a]), 0x4321, "a");
+        assert_eq!(forged, Err(DnsError::WrongId { expected: 0x4321, got: 0x1234 }));
+        assert_eq!(resolver.parse_response(&reply([0x01, 0x00], &[a_for_a]), 0x1234, "a"), Err(DnsError::NotAReply));
+        let nxdomain = resolver.parse_response(&reply([0x81, 0x83], &[a_for_a]), 0x1234, "a");
+        assert_eq!(nxdomain, Err(DnsError::ServerError(3)));
+        assert_eq!(std::io::Error::from(DnsError::ServerError(3)).kind(), std::io::ErrorKind::NotFound);
+
+        // An A record for a name we did not ask about: "b" comes before the real answer.
+        let a_for_b: &[u8] = &[1, b'b', 0, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 6, 6, 6, 6];
+        assert_eq!(resolver.parse_response(&reply(ok, &[a_for_b]), 0x1234, "a"), Err(DnsError::NoAddress));
+        assert_eq!(resolver.parse_response(&reply(ok, &[a_for_b, a_for_a]), 0x1234, "a"), Ok("10.0.0.1".to_string()));
+
+        // "a" is a CNAME for "b", whose A record is listed first; a CNAME loop finds nothing.
+        let a_is_b: &[u8] = &[0xC0, 0x0C, 0, 5, 0, 1, 0, 0, 0, 60, 0, 3, 1, b'b', 0];
+        let cname = resolver.parse_response(&reply(ok, &[a_for_b, a_is_b]), 0x1234, "a");
+        assert_eq!(cname, Ok("6.6.6.6".to_string()));
+        let b_is_a: &[u8] = &[1, b'b', 0, 0, 5, 0, 1, 0, 0, 0, 60, 0, 2, 0xC0, 0x0C];
+        assert_eq!(resolver.parse_response(&reply(ok, &[a_is_b, b_is_a]), 0x1234, "a"), Err(DnsError::NoAddress));
+    }
+}
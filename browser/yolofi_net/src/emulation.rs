@@ -0,0 +1,395 @@
+// Network Condition Emulation
+// Wraps any transport to add latency, jitter, bandwidth caps, packet loss and resets.
+// Every random decision comes from a seeded generator, so a run with the same seed
+// and the same traffic misbehaves in exactly the same way.
+
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use crate::security::ConnectionSecurityInfo;
+use crate::transport::{Connector, DatagramTransport, Transport};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkConditions {
+    /// One-way delay. Connecting and each request/reply turn cost twice this.
+    pub latency: Duration,
+    /// Uniform random extra delay in `0..=jitter`.
+    pub jitter: Duration,
+    /// Throughput cap in bytes per second. `None` is unlimited.
+    pub bandwidth: Option<u64>,
+    /// Chance (0.0..=1.0) that an operation is lost and retransmitted after a timeout.
+    pub loss_rate: f64,
+    /// Chance (0.0..=1.0) that an operation fails with a connection reset.
+    pub reset_rate: f64,
+    pub seed: u64,
+    /// When false, delays are only accounted for instead of slept (for tests and replays).
+    pub realtime: bool,
+}
+
+impl Default for NetworkConditions {
+    fn default() -> Self {
+        Self {
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            bandwidth: None,
+            loss_rate: 0.0,
+            reset_rate: 0.0,
+            seed: 0,
+            realtime: true,
+        }
+    }
+}
+
+impl NetworkConditions {
+    pub fn slow_3g() -> Self {
+        Self {
+            latency: Duration::from_millis(400),
+            jitter: Duration::from_millis(100),
+            bandwidth: Some(50 * 1024),
+            loss_rate: 0.01,
+            ..Self::default()
+        }
+    }
+
+    pub fn fast_3g() -> Self {
+        Self {
+            latency: Duration::from_millis(150),
+            jitter: Duration::from_millis(40),
+            bandwidth: Some(200 * 1024),
+            ..Self::default()
+        }
+    }
+
+    /// A usable but unreliable link: frequent loss and the occasional reset.
+    pub fn flaky() -> Self {
+        Self {
+            latency: Duration::from_millis(80),
+            jitter: Duration::from_millis(120),
+            bandwidth: Some(1024 * 1024),
+            loss_rate: 0.05,
+            reset_rate: 0.02,
+            ..Self::default()
+        }
+    }
+
+    /// Looks up a named preset (`slow-3g`, `fast-3g`, `flaky`, `none`).
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "slow-3g" => Some(Self::slow_3g()),
+            "fast-3g" => Some(Self::fast_3g()),
+            "flaky" => Some(Self::flaky()),
+            "none" => Some(Self::default()),
+            _ => None,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Time a lost operation waits before it is "retransmitted".
+    fn retransmit_timeout(&self) -> Duration {
+        (self.latency * 2).max(Duration::from_millis(200))
+    }
+}
+
+/// SplitMix64. Small, fast, and identical on every platform.
+#[derive(Debug, Clone)]
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.next_f64() < probability
+    }
+}
+
+/// Shared by streams and datagram exchanges: decides what happens to each operation.
+struct Shaper {
+    conditions: NetworkConditions,
+    rng: SeededRng,
+    delayed: Duration,
+}
+
+impl Shaper {
+    fn new(conditions: NetworkConditions, stream_index: u64) -> Self {
+        // Each connection gets its own sub-stream so concurrency does not reorder draws.
+        let rng = SeededRng::new(conditions.seed ^ stream_index.wrapping_mul(0xD6E8_FEB8_6659_FD93));
+        Self { conditions, rng, delayed: Duration::ZERO }
+    }
+
+    fn latency(&mut self) -> Duration {
+        let jitter = self.conditions.jitter.as_nanos() as u64;
+        let extra = if jitter > 0 { self.rng.next_u64() % (jitter + 1) } else { 0 };
+        self.conditions.latency + Duration::from_nanos(extra)
+    }
+
+    fn transfer_time(&self, bytes: usize) -> Duration {
+        match self.conditions.bandwidth {
+            Some(bps) if bps > 0 => Duration::from_nanos((bytes as u128 * 1_000_000_000 / bps as u128) as u64),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Largest single read/write so that bandwidth is enforced in small steps.
+    fn max_chunk(&self) -> usize {
+        match self.conditions.bandwidth {
+            Some(bps) => (bps as usize / 10).max(1),
+            None => usize::MAX,
+        }
+    }
+
+    /// Applies loss and reset for one operation.
+    fn disturb(&mut self, what: &str) -> io::Result<()> {
+        if self.rng.chance(self.conditions.reset_rate) {
+            warn!(target: "net::emulation", "Injected connection reset during {}", what);
+            return Err(io::Error::new(io::ErrorKind::ConnectionReset, "Emulated connection reset"));
+        }
+        if self.rng.chance(self.conditions.loss_rate) {
+            let rto = self.conditions.retransmit_timeout();
+            debug!(target: "net::emulation", "Injected packet loss during {} (+{:?})", what, rto);
+            self.delay(rto);
+        }
+        Ok(())
+    }
+
+    fn delay(&mut self, duration: Duration) {
+        if duration.is_zero() {
+            return;
+        }
+        self.delayed += duration;
+        if self.conditions.realtime {
+            std::thread::sleep(duration);
+        }
+    }
+}
+
+/// Stream wrapper that plays out the configured conditions.
+pub struct ShapedStream<S> {
+    inner: S,
+    shaper: Shaper,
+    awaiting_reply: bool,
+}
+
+impl<S> ShapedStream<S> {
+    pub fn new(inner: S, conditions: NetworkConditions) -> Self {
+        Self::with_index(inner, conditions, 0)
+    }
+
+    fn with_index(inner: S, conditions: NetworkConditions, index: u64) -> Self {
+        Self { inner, shaper: Shaper::new(conditions, index), awaiting_reply: false }
+    }
+
+    /// Total emulated delay so far (slept or merely accounted).
+    pub fn delayed(&self) -> Duration {
+        self.shaper.delayed
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: Read> Read for ShapedStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.shaper.disturb("read")?;
+        if self.awaiting_reply {
+            // First bytes of a reply arrive one round trip after the request left.
+            let rtt = self.shaper.latency() * 2;
+            self.shaper.delay(rtt);
+            self.awaiting_reply = false;
+        }
+
+        let limit = buf.len().min(self.shaper.max_chunk());
+        let n = self.inner.read(&mut buf[..limit])?;
+        let cost = self.shaper.transfer_time(n);
+        self.shaper.delay(cost);
+        Ok(n)
+    }
+}
+
+impl<S: Write> Write for ShapedStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.shaper.disturb("write")?;
+        let limit = buf.len().min(self.shaper.max_chunk());
+        let n = self.inner.write(&buf[..limit])?;
+        let cost = self.shaper.transfer_time(n);
+        self.shaper.delay(cost);
+        self.awaiting_reply = true;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: Transport> Transport for ShapedStream<S> {
+    fn peer(&self) -> &str {
+        self.inner.peer()
+    }
+
+    fn security_info(&self) -> Option<ConnectionSecurityInfo> {
+        self.inner.security_info()
+    }
+}
+
+/// Connector whose every connection runs under `conditions`.
+pub struct ShapedConnector<C: Connector> {
+    inner: C,
+    conditions: NetworkConditions,
+    connections: AtomicU64,
+}
+
+impl<C: Connector> ShapedConnector<C> {
+    pub fn new(inner: C, conditions: NetworkConditions) -> Self {
+        info!(target: "net::emulation", "Emulating network: {:?}", conditions);
+        Self { inner, conditions, connections: AtomicU64::new(0) }
+    }
+
+    fn shape(&self, connect: impl FnOnce() -> io::Result<C::Stream>) -> io::Result<ShapedStream<C::Stream>> {
+        let index = self.connections.fetch_add(1, Ordering::SeqCst);
+        let mut shaper = Shaper::new(self.conditions.clone(), index);
+
+        // TCP handshake: one round trip, and it can be lost or reset like anything else.
+        shaper.disturb("connect")?;
+        let rtt = shaper.latency() * 2;
+        shaper.delay(rtt);
+
+        let inner = connect()?;
+        Ok(ShapedStream { inner, shaper, awaiting_reply: false })
+    }
+}
+
+impl<C: Connector> Connector for ShapedConnector<C> {
+    type Stream = ShapedStream<C::Stream>;
+
+    fn connect(&self, host: &str, port: u16) -> io::Result<Self::Stream> {
+        self.shape(|| self.inner.connect(host, port))
+    }
+
+    fn resolves_remotely(&self, host: &str, port: u16) -> bool {
+        self.inner.resolves_remotely(host, port)
+    }
+
+    fn connect_resolved(&self, host: &str, addr: &str, port: u16) -> io::Result<Self::Stream> {
+        self.shape(|| self.inner.connect_resolved(host, addr, port))
+    }
+}
+
+/// Datagram transport under emulated conditions. A lost datagram surfaces as a timeout.
+pub struct ShapedDatagram<D: DatagramTransport> {
+    inner: D,
+    conditions: NetworkConditions,
+    exchanges: AtomicU64,
+}
+
+impl<D: DatagramTransport> ShapedDatagram<D> {
+    pub fn new(inner: D, conditions: NetworkConditions) -> Self {
+        Self { inner, conditions, exchanges: AtomicU64::new(0) }
+    }
+}
+
+impl<D: DatagramTransport> DatagramTransport for ShapedDatagram<D> {
+    fn exchange(&self, server: &str, query: &[u8]) -> io::Result<Vec<u8>> {
+        let index = self.exchanges.fetch_add(1, Ordering::SeqCst);
+        let mut shaper = Shaper::new(self.conditions.clone(), index);
+
+        if shaper.rng.chance(shaper.conditions.loss_rate) || shaper.rng.chance(shaper.conditions.reset_rate) {
+            warn!(target: "net::emulation", "Dropped datagram to {}", server);
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "Emulated datagram loss"));
+        }
+        let rtt = shaper.latency() * 2;
+        shaper.delay(rtt);
+        self.inner.exchange(server, query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::MemoryConnector;
+
+    fn echo_connector() -> MemoryConnector {
+        MemoryConnector::new(|_, _, mut s| {
+            let mut buf = [0u8; 1024];
+            while let Ok(n) = s.read(&mut buf) {
+                if n == 0 || s.write_all(&buf[..n]).is_err() {
+                    break;
+                }
+            }
+        })
+    }
+
+    fn accounted(conditions: NetworkConditions) -> NetworkConditions {
+        NetworkConditions { realtime: false, ..conditions }
+    }
+
+    #[test]
+    fn test_latency_and_bandwidth_are_accounted() {
+        let conditions = accounted(NetworkConditions {
+            latency: Duration::from_millis(50),
+            bandwidth: Some(1000),
+            ..NetworkConditions::default()
+        });
+        let connector = ShapedConnector::new(echo_connector(), conditions);
+        let mut stream = connector.connect("example.test", 80).unwrap();
+        let after_connect = stream.delayed();
+        assert_eq!(after_connect, Duration::from_millis(100));
+
+        stream.write_all(&[7u8; 500]).unwrap();
+        let mut buf = [0u8; 500];
+        stream.read_exact(&mut buf).unwrap();
+
+        // 500 bytes written and read back at 1000 B/s, plus one reply round trip.
+        // Reads may be split arbitrarily, so allow for per-chunk rounding.
+        let shaped = stream.delayed() - after_connect;
+        let expected = Duration::from_millis(500 + 500 + 100);
+        assert!(expected.abs_diff(shaped) < Duration::from_micros(1), "got {:?}", shaped);
+    }
+
+    #[test]
+    fn test_same_seed_same_failures() {
+        let run = |seed: u64| -> Vec<bool> {
+            let conditions = accounted(NetworkConditions { reset_rate: 0.3, ..NetworkConditions::default() }.with_seed(seed));
+            let connector = ShapedConnector::new(echo_connector(), conditions);
+            (0..20)
+                .map(|_| match connector.connect("example.test", 80) {
+                    Ok(mut s) => s.write_all(b"ping").is_ok(),
+                    Err(_) => false,
+                })
+                .collect()
+        };
+
+        assert_eq!(run(42), run(42));
+        assert_ne!(run(42), run(43));
+        assert!(run(42).contains(&false), "a 30% reset rate should trip at least once in 20 tries");
+    }
+
+    #[test]
+    fn test_presets() {
+        assert_eq!(NetworkConditions::preset("slow-3g"), Some(NetworkConditions::slow_3g()));
+        assert!(NetworkConditions::preset("dial-up").is_none());
+    }
+}
@@ -0,0 +1,946 @@
+// Fetch Client
+// One call from URL to response: DNS (our resolver, not the OS), TCP, TLS for https,
+// then HTTP/1.1 or HTTP/2. Redirects are followed up to a limit, with the method rewriting
+// rules of the Fetch standard:
+//   301/302 + POST -> GET,  303 -> GET (except HEAD),  307/308 keep method and body.
+//
+// https offers "h2" through ALPN; when the server picks it the request goes over
+// HTTP/2 and the connection is kept for later requests to the same origin, shared by
+// every fetch on the client.
+//
+// With an HttpCache attached, every hop consults it first: fresh entries skip the
+// network, stale ones are revalidated, and unsafe methods invalidate the target URL.
+//
+// http URLs are upgraded to https before connecting when the host is HSTS, or for
+// every host in HTTPS-only mode, which fails rather than falling back to http.
+//
+// HTTP/1.1 connections, plain and TLS, go back to a ConnectionPool after a keep-alive
+// response. A reused connection that turns out to be closed is retried on a fresh one
+// when the request is idempotent or never reached it.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::cookie_jar::{CookieContext, CookieJar};
+use crate::dns::DnsResolver;
+use crate::headers::HeaderMap;
+use crate::hsts::{upgrade_to_https, HstsStore};
+use crate::http::{is_idempotent, HttpRequest, HttpResponse};
+use crate::http2::{Http2Connection, Http2Error};
+use crate::http_cache::{CacheMode, CachePlan, CacheStatus, HttpCache};
+use crate::http_date::unix_now;
+use crate::identity::{Identity, ReferrerPolicy};
+use crate::limits::Limits;
+use crate::pool::{ConnectionPool, PoolConfig, PoolExhausted, PoolKey, PooledConnection};
+use crate::request::{RequestBuilder, RequestError};
+use crate::tls::{self, TlsConfig, TlsError, TlsStream, ALPN_H2};
+use crate::security::ConnectionSecurityInfo;
+use crate::transport::{Connector, DatagramTransport, TcpConnector, Transport, UdpTransport};
+use crate::Url;
+use yolofi_url::Host;
+
+pub const DEFAULT_MAX_REDIRECTS: usize = 20;
+
+#[derive(Debug, thiserror::Error)]
+pub enum FetchError {
+    #[error(transparent)]
+    InvalidUrl(#[from] yolofi_url::ParseError),
+    #[error("unsupported URL scheme {0:?}")]
+    UnsupportedScheme(String),
+    #[error("DNS lookup for {host} failed: {source}")]
+    Dns { host: String, source: io::Error },
+    #[error("could not connect to {addr}: {source}")]
+    Connect { addr: String, source: io::Error },
+    #[error(transparent)]
+    Tls(#[from] TlsError),
+    #[error("HTTP exchange failed: {0}")]
+    Http(io::Error),
+    #[error("HTTP/2 exchange failed: {0}")]
+    Http2(#[from] Http2Error),
+    #[error(transparent)]
+    InvalidRequest(#[from] RequestError),
+    #[error("more than {0} redirects")]
+    TooManyRedirects(usize),
+    /// HTTPS-only mode could not reach the site over https and will not fall back to http.
+    #[error("{url} is not available over HTTPS: {source}")]
+    HttpsOnly { url: String, source: Box<FetchError> },
+    #[error("{0} is not in the cache")]
+    NotCached(String),
+    #[error("cache error: {0}")]
+    Cache(io::Error),
+    /// Every connection slot for the origin is in use (`WouldBlock`).
+    #[error(transparent)]
+    Pool(io::Error),
+}
+
+impl From<PoolExhausted> for FetchError {
+    fn from(e: PoolExhausted) -> Self {
+        FetchError::Pool(e.into())
+    }
+}
+
+/// The final response and where it came from.
+#[derive(Debug)]
+pub struct FetchResponse {
+    /// URL of the response actually returned, after redirects.
+    pub url: Url,
+    /// Every URL that answered with a redirect, in order.
+    pub redirects: Vec<Url>,
+    pub response: HttpResponse,
+    /// How the cache took part in producing the final response.
+    pub cache: CacheStatus,
+}
+
+/// Composes DNS, a connector, TLS and HTTP into `fetch(url)`.
+pub struct FetchClient<C: Connector = TcpConnector, D: DatagramTransport = UdpTransport> {
+    pool: ConnectionPool<Dialer<C, D>>,
+    tls: TlsConfig,
+    cookies: Option<CookieJar>,
+    cache: Option<HttpCache>,
+    cache_mode: CacheMode,
+    limits: Limits,
+    http2: bool,
+    identity: Identity,
+    hsts: Option<HstsStore>,
+    https_only: bool,
+    /// Open HTTP/2 connections by "host:port", reused across requests.
+    sessions: Mutex<HashMap<String, Session<C::Stream>>>,
+}
+
+/// A hop's response, how the cache took part, and the fields the server sent for it.
+type Exchanged = (HttpResponse, CacheStatus, Option<HeaderMap>);
+
+/// An HTTP/2 connection shared by concurrent fetches to its origin.
+type Session<S> = Arc<Mutex<Http2Connection<TlsStream<S>>>>;
+
+impl FetchClient {
+    /// Real TCP, the browser's DNS resolver and the bundled trust store.
+    pub fn new() -> Self {
+        let resolver = DnsResolver::with_transport(UdpTransport::new(Duration::from_secs(2)));
+        Self::with_parts(TcpConnector::new(), Some(resolver))
+    }
+}
+
+impl Default for FetchClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Fetches `url` with a default client.
+pub fn fetch(url: &str) -> Result<FetchResponse, FetchError> {
+    FetchClient::new().fetch(url)
+}
+
+impl<C: Connector, D: DatagramTransport> FetchClient<C, D> {
+    /// Without a resolver, host names go to the connector as-is (in-memory tests). Connectors
+    /// that resolve remotely, like a SOCKS5 proxy, get them as-is even with one.
+    pub fn with_parts(connector: C, resolver: Option<DnsResolver<D>>) -> Self {
+        Self {
+            pool: ConnectionPool::new(Dialer { connector, resolver }, PoolConfig::default()),
+            tls: TlsConfig::default(),
+            cookies: None,
+            cache: None,
+            cache_mode: CacheMode::Default,
+            limits: Limits::default(),
+            http2: true,
+            identity: Identity::default(),
+            hsts: None,
+            https_only: false,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// Sends and stores cookies. Requests are navigations from their referrer, or subresources
+    /// of the page given with `RequestBuilder::with_top_level`, whose cookies may be third-party.
+    pub fn with_cookies(mut self, jar: CookieJar) -> Self {
+        self.cookies = Some(jar);
+        self
+    }
+
+    pub fn with_cache(mut self, cache: HttpCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// `CacheMode::OnlyIfCached` turns the client into an offline replay of the cache.
+    pub fn with_cache_mode(mut self, mode: CacheMode) -> Self {
+        self.cache_mode = mode;
+        self
+    }
+
+    /// Offers "h2" in ALPN for https (on by default). Plain http always uses HTTP/1.1.
+    pub fn with_http2(mut self, enabled: bool) -> Self {
+        self.http2 = enabled;
+        self
+    }
+
+    /// The fields every request presents: User-Agent, Accept, field order, referrer policy.
+    pub fn with_identity(mut self, identity: Identity) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    /// Upgrades http requests to hosts the store knows as HSTS, and records new policies.
+    pub fn with_hsts(mut self, store: HstsStore) -> Self {
+        self.hsts = Some(store);
+        self
+    }
+
+    /// Upgrades every http request to https. A site that cannot be reached that way fails
+    /// with `FetchError::HttpsOnly` instead of being fetched over http.
+    pub fn with_https_only(mut self, enabled: bool) -> Self {
+        self.https_only = enabled;
+        self
+    }
+
+    /// Caps on what servers can make the client buffer or follow; DNS limits go to the resolver.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        let dialer = self.pool.connector_mut();
+        dialer.resolver = dialer.resolver.take().map(|resolver| resolver.with_limits(limits.dns));
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_max_redirects(mut self, max_redirects: usize) -> Self {
+        self.limits.max_redirects = max_redirects;
+        self
+    }
+
+    /// Opens connections the way fetches do, for protocols that speak their own framing.
+    pub(crate) fn dialer(&self) -> &Dialer<C, D> {
+        self.pool.connector()
+    }
+
+    pub(crate) fn tls(&self) -> &TlsConfig {
+        &self.tls
+    }
+
+    pub fn fetch(&self, url: &str) -> Result<FetchResponse, FetchError> {
+        self.send(RequestBuilder::get(&Url::parse(url)?))
+    }
+
+    /// Sends `method` to `url`, following redirects.
+    pub fn request(&self, method: &str, url: &Url, body: Vec<u8>) -> Result<FetchResponse, FetchError> {
+        self.send(RequestBuilder::new(method, url).bytes(body))
+    }
+
+    /// Sends a built request, following redirects. Fields the caller did not set get the defaults.
+    pub fn send(&self, mut request: RequestBuilder) -> Result<FetchResponse, FetchError> {
+        let mut target = request.url().clone();
+        target.set_fragment(None);
+        request.set_url(target.clone());
+        let mut redirects = Vec::new();
+
+        loop {
+            // Upgrades happen before any byte goes out over plain http, redirect targets included.
+            let mut insecure = None;
+            if target.scheme() == "http" {
+                let hsts = self.hsts.as_ref().is_some_and(|store| store.should_upgrade(&target));
+                if hsts || self.https_only {
+                    let secure = upgrade_to_https(&target);
+                    info!(target: "net::fetch", "Upgrading {} to https ({})", target, if hsts { "HSTS" } else { "HTTPS-only" });
+                    request.set_url(secure.clone());
+                    // HSTS hosts have promised https, so only HTTPS-only upgrades explain a failure.
+                    let original = std::mem::replace(&mut target, secure);
+                    insecure = (!hsts).then_some(original);
+                }
+            }
+
+            let method = request.method().to_string();
+            let context = match request.top_level() {
+                Some(page) => CookieContext::subresource(page, &method),
+                None => CookieContext::navigation(request.referrer(), &method),
+            };
+            let mut hop = request.clone();
+            if let Some(cookie) = self.cookies.as_ref().and_then(|jar| jar.cookie_header(&target, &context)) {
+                hop = hop.header("Cookie", &cookie);
+            }
+            let (response, cache, received) = match self.cached_exchange(&target, self.identity.apply(hop).build()?) {
+                Err(err @ (FetchError::Connect { .. } | FetchError::Tls(_) | FetchError::Http(_) | FetchError::Http2(_)))
+                    if insecure.is_some() =>
+                {
+                    let url = insecure.expect("checked above").to_string();
+                    warn!(target: "net::fetch", "HTTPS-only: {} is not available over https: {}", url, err);
+                    return Err(FetchError::HttpsOnly { url, source: Box::new(err) });
+                }
+                result => result?,
+            };
+            // Only what the server just sent counts: a 304 carries its own fields, a cache hit none.
+            if let Some(received) = &received {
+                if let Some(jar) = &self.cookies {
+                    jar.store_response_cookies(&target, &received.set_cookies(), &context);
+                }
+                if let Some(hsts) = &self.hsts {
+                    hsts.process_response(&target, received);
+                }
+            }
+            let location = match response.status {
+                301 | 302 | 303 | 307 | 308 => response.headers.location().map(str::to_string),
+                _ => None,
+            };
+            let Some(location) = location else {
+                info!(target: "net::fetch", "{} {} -> {}", method, target, response.status);
+                return Ok(FetchResponse { url: target, redirects, response, cache });
+            };
+
+            let max_redirects = self.limits.max_redirects;
+            if redirects.len() == max_redirects {
+                warn!(target: "net::fetch", "Redirect limit ({}) reached at {}", max_redirects, target);
+                return Err(FetchError::TooManyRedirects(max_redirects));
+            }
+            let mut next = target.join(&location)?;
+            next.set_fragment(None);
+            info!(target: "net::fetch", "{} redirect: {} -> {}", response.status, target, next);
+
+            let to_get = match response.status {
+                301 | 302 => method == "POST",
+                303 => method != "HEAD",
+                _ => false,
+            };
+            if to_get {
+                request.set_method("GET");
+                request.clear_body();
+            }
+            if next.origin() != target.origin() {
+                request.remove_header("Authorization");
+            }
+            // The referrer stays the original page, but the redirect may tighten the policy.
+            let policy = response.headers.get_combined("Referrer-Policy");
+            if let Some(policy) = policy.as_deref().and_then(ReferrerPolicy::parse_header) {
+                request.set_referrer_policy(policy);
+            }
+            request.set_url(next.clone());
+            redirects.push(std::mem::replace(&mut target, next));
+        }
+    }
+
+    /// One hop through the cache, if there is one. Also returns the fields the server sent
+    /// for this hop, `None` when the cache answered alone.
+    fn cached_exchange(&self, target: &Url, mut request: HttpRequest) -> Result<Exchanged, FetchError> {
+        let Some(cache) = &self.cache else {
+            let response = self.exchange(target, &request)?;
+            let received = response.headers.clone();
+            return Ok((response, CacheStatus::Uncached, Some(received)));
+        };
+        let method = request.method.as_str();
+        let store = match cache.plan(self.cache_mode, method, target, &request.headers) {
+            CachePlan::Serve(entry) => {
+                info!(target: "net::fetch", "{} {} served from cache", method, target);
+                return Ok((entry.to_response(), CacheStatus::Hit, None));
+            }
+            CachePlan::Unavailable => return Err(FetchError::NotCached(target.to_string())),
+            CachePlan::Revalidate { entry, conditions } => {
+                // The cache's validators replace any the caller set, so each is sent once.
+                for (name, value) in conditions.iter() {
+                    request.headers.insert(name, value);
+                }
+                let request_time = unix_now();
+                let response = self.exchange(target, &request)?;
+                if response.status == 304 {
+                    let fresh = cache.freshen(entry, &response, request_time, unix_now()).map_err(FetchError::Cache)?;
+                    return Ok((fresh, CacheStatus::Revalidated, Some(response.headers)));
+                }
+                self.store(cache, target, &request.headers, &response, request_time);
+                let received = response.headers.clone();
+                return Ok((response, CacheStatus::Miss, Some(received)));
+            }
+            CachePlan::Network { store } => store,
+        };
+
+        let request_time = unix_now();
+        let response = self.exchange(target, &request)?;
+        if store {
+            self.store(cache, target, &request.headers, &response, request_time);
+        } else if !matches!(method, "GET" | "HEAD" | "OPTIONS" | "TRACE") && response.status < 400 {
+            // RFC 9111 section 4.4: a successful unsafe request invalidates the target URI.
+            cache.invalidate(target).map_err(FetchError::Cache)?;
+        }
+        let received = response.headers.clone();
+        Ok((response, CacheStatus::Miss, Some(received)))
+    }
+
+    fn store(&self, cache: &HttpCache, target: &Url, headers: &HeaderMap, response: &HttpResponse, request_time: i64) {
+        if let Err(err) = cache.store(target, headers, response, request_time, unix_now()) {
+            warn!(target: "net::fetch", "Could not cache {}: {}", target, err);
+        }
+    }
+
+    fn exchange(&self, target: &Url, request: &HttpRequest) -> Result<HttpResponse, FetchError> {
+        let https = match target.scheme() {
+            "http" => false,
+            "https" => true,
+            other => return Err(FetchError::UnsupportedScheme(other.to_string())),
+        };
+        let host = match target.host() {
+            Some(host) if *host != Host::Empty => host,
+            _ => return Err(yolofi_url::ParseError::MissingHost(target.to_string()).into()),
+        };
+        let port = target.port_or_default().expect("http(s) has a default port");
+
+        // An open HTTP/2 connection to the origin skips DNS, TCP and TLS entirely.
+        let origin = format!("{}:{}", host.to_socket_host(), port);
+        let session = if https { self.sessions().get(&origin).cloned() } else { None };
+        if let Some(session) = session {
+            match session_request(&session, request) {
+                Some(Ok(response)) => return Ok(response),
+                Some(Err(err))
+                    if err.is_retryable() || matches!(err, Http2Error::Io(_)) && is_idempotent(&request.method) =>
+                {
+                    info!(target: "net::fetch", "Reused HTTP/2 connection to {} failed ({}), reconnecting", origin, err);
+                }
+                Some(Err(err)) => return Err(err.into()),
+                None => {}
+            }
+            self.forget_session(&origin, &session);
+        }
+
+        let key = PoolKey::new(target.scheme(), &host.to_socket_host(), port);
+        let open = || -> Result<HttpStream<C::Stream>, FetchError> {
+            let stream = self.pool.connector().dial(host, port)?;
+            if !https {
+                return Ok(HttpStream::Plain(stream));
+            }
+            // SNI and certificate checks use the name from the URL, not the resolved address.
+            let mut tls = self.tls.clone();
+            if self.http2 && !tls.alpn.iter().any(|p| p == ALPN_H2) {
+                tls.alpn.insert(0, ALPN_H2.to_vec());
+            }
+            Ok(HttpStream::Tls(Box::new(tls::handshake(stream, &host.to_socket_host(), &tls)?)))
+        };
+
+        let mut conn = self.pool.checkout_with(&key, open)?;
+        if !conn.was_reused() {
+            return self.exchange_fresh(conn, origin, request);
+        }
+        match conn.exchange_with_limits(&request.method, &request.serialize(), &self.limits) {
+            Ok(response) => Ok(response),
+            Err(err) if conn.may_retry(&request.method) => {
+                info!(target: "net::fetch", "Reused connection to {} failed ({}), reconnecting", origin, err);
+                drop(conn);
+                self.exchange_fresh(self.pool.checkout_fresh_with(&key, open)?, origin, request)
+            }
+            Err(err) => Err(FetchError::Http(err)),
+        }
+    }
+
+    /// First request on a new connection: HTTP/2 if ALPN picked it, otherwise HTTP/1.1 through the pool.
+    fn exchange_fresh(
+        &self,
+        mut conn: PooledConnection<'_, Dialer<C, D>>,
+        origin: String,
+        request: &HttpRequest,
+    ) -> Result<HttpResponse, FetchError> {
+        if !conn.stream().get_ref().is_h2() {
+            let response = conn.exchange_with_limits(&request.method, &request.serialize(), &self.limits);
+            return response.map_err(FetchError::Http);
+        }
+        let HttpStream::Tls(stream) = conn.detach() else { unreachable!("only TLS negotiates h2") };
+        let mut connection = Http2Connection::handshake_with_limits(*stream, "https", &self.limits)?;
+        let stream = connection.send_request(request)?;
+        // Shared while the response is outstanding, so fetches that arrive meanwhile use it.
+        let session = Arc::new(Mutex::new(connection));
+        self.sessions().entry(origin.clone()).or_insert_with(|| session.clone());
+        let result = lock_session(&session).read_response(stream);
+        if result.is_err() || !lock_session(&session).is_usable() {
+            self.forget_session(&origin, &session);
+        }
+        Ok(result?)
+    }
+
+    fn sessions(&self) -> MutexGuard<'_, HashMap<String, Session<C::Stream>>> {
+        self.sessions.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Drops `session` from the map, unless another fetch already replaced it.
+    fn forget_session(&self, origin: &str, session: &Session<C::Stream>) {
+        let mut sessions = self.sessions();
+        if sessions.get(origin).is_some_and(|current| Arc::ptr_eq(current, session)) {
+            sessions.remove(origin);
+        }
+    }
+}
+
+fn lock_session<S: Transport>(session: &Session<S>) -> MutexGuard<'_, Http2Connection<TlsStream<S>>> {
+    session.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// One request on a shared HTTP/2 connection; `None` when it takes no new streams. The lock is
+/// let go between sending and reading, so other fetches can open their streams in between.
+fn session_request<S: Transport>(
+    session: &Session<S>,
+    request: &HttpRequest,
+) -> Option<Result<HttpResponse, Http2Error>> {
+    let sent = {
+        let mut connection = lock_session(session);
+        if !connection.is_usable() {
+            return None;
+        }
+        connection.send_request(request)
+    };
+    Some(sent.and_then(|stream| lock_session(session).read_response(stream)))
+}
+
+/// Opens TCP connections, resolving host names through our own resolver when there is one
+/// and the connector does not leave that to a proxy.
+pub(crate) struct Dialer<C: Connector, D: DatagramTransport> {
+    connector: C,
+    resolver: Option<DnsResolver<D>>,
+}
+
+impl<C: Connector, D: DatagramTransport> Dialer<C, D> {
+    pub(crate) fn dial(&self, host: &Host, port: u16) -> Result<C::Stream, FetchError> {
+        let name = host.to_socket_host();
+        let connected = match (&self.resolver, host.domain()) {
+            (Some(resolver), Some(domain)) if !self.connector.resolves_remotely(domain, port) => {
+                let addr = resolver
+                    .resolve(domain)
+                    .map_err(|source| FetchError::Dns { host: domain.to_string(), source })?;
+                self.connector.connect_resolved(&name, &addr, port).map_err(|source| (addr, source))
+            }
+            _ => self.connector.connect(&name, port).map_err(|source| (name.clone(), source)),
+        };
+        connected.map_err(|(addr, source)| FetchError::Connect { addr: format!("{}:{}", addr, port), source })
+    }
+}
+
+impl<C: Connector, D: DatagramTransport> Connector for Dialer<C, D> {
+    type Stream = HttpStream<C::Stream>;
+
+    fn connect(&self, host: &str, port: u16) -> io::Result<Self::Stream> {
+        let host = Host::parse(host, false).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        self.dial(&host, port).map(HttpStream::Plain).map_err(io::Error::other)
+    }
+
+    fn resolves_remotely(&self, host: &str, port: u16) -> bool {
+        self.connector.resolves_remotely(host, port)
+    }
+}
+
+/// A pooled HTTP/1.1 connection: plain for http, TLS for https.
+pub(crate) enum HttpStream<S: Transport> {
+    Plain(S),
+    Tls(Box<TlsStream<S>>),
+}
+
+impl<S: Transport> HttpStream<S> {
+    fn is_h2(&self) -> bool {
+        matches!(self, HttpStream::Tls(stream) if stream.alpn_protocol() == Some(ALPN_H2))
+    }
+}
+
+impl<S: Transport> Read for HttpStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            HttpStream::Plain(stream) => stream.read(buf),
+            HttpStream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl<S: Transport> Write for HttpStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            HttpStream::Plain(stream) => stream.write(buf),
+            HttpStream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            HttpStream::Plain(stream) => stream.flush(),
+            HttpStream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+impl<S: Transport> Transport for HttpStream<S> {
+    fn peer(&self) -> &str {
+        match self {
+            HttpStream::Plain(stream) => stream.peer(),
+            HttpStream::Tls(stream) => stream.peer(),
+        }
+    }
+
+    fn security_info(&self) -> Option<ConnectionSecurityInfo> {
+        match self {
+            HttpStream::Plain(stream) => stream.security_info(),
+            HttpStream::Tls(stream) => stream.security_info(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipe::PipeStream;
+    use crate::test_pki::TestPki;
+    use crate::tls::ALPN_HTTP11;
+    use crate::transport::MemoryConnector;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Reads one request (head and Content-Length body) off the pipe.
+    fn read_request(server: &mut PipeStream) -> (String, Vec<u8>) {
+        next_request(server).expect("a request before end of stream")
+    }
+
+    /// Like `read_request`, but `None` once the client closes the connection between requests.
+    fn next_request(server: &mut PipeStream) -> Option<(String, Vec<u8>)> {
+        let mut data = Vec::new();
+        let mut buf = [0u8; 1024];
+        let head_end = loop {
+            if let Some(end) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+                break end + 4;
+            }
+            let n = server.read(&mut buf).unwrap();
+            if n == 0 && data.is_empty() {
+                return None;
+            }
+            data.extend_from_slice(&buf[..n]);
+        };
+        let head = String::from_utf8(data[..head_end].to_vec()).unwrap();
+        let length = head
+            .lines()
+            .find_map(|l| l.strip_prefix("Content-Length: "))
+            .map_or(0, |v| v.trim().parse::<usize>().unwrap());
+        while data.len() < head_end + length {
+            let n = server.read(&mut buf).unwrap();
+            data.extend_from_slice(&buf[..n]);
+        }
+        Some((head, data[head_end..].to_vec()))
+    }
+
+    #[test]
+    fn test_redirects_rewrite_methods() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        let connector = MemoryConnector::new(move |host, port, mut server| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            while let Some((head, body)) = next_request(&mut server) {
+                let request_line = head.lines().next().unwrap().to_string();
+                let reply = match (host, port, request_line.as_str()) {
+                    ("a.test", 80, "POST /form HTTP/1.1") => {
+                        assert_eq!(body, b"q=1");
+                        "HTTP/1.1 307 Temporary Redirect\r\nLocation: /form2\r\nContent-Length: 0\r\n\r\n"
+                    }
+                    ("a.test", 80, "POST /form2 HTTP/1.1") => {
+                        assert_eq!(body, b"q=1");
+                        "HTTP/1.1 302 Found\r\nLocation: http://b.test:8080/done?x=1#frag\r\nContent-Length: 0\r\n\r\n"
+                    }
+                    ("b.test", 8080, "GET /done?x=1 HTTP/1.1") => {
+                        assert!(head.contains("Host: b.test:8080\r\n"));
+                        assert!(head.contains(&format!("User-Agent: {}\r\n", yolofi_config::USER_AGENT)));
+                        assert!(body.is_empty());
+                        "HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\ndone"
+                    }
+                    ("a.test", 80, "GET /loop HTTP/1.1") => "HTTP/1.1 301 Moved\r\nLocation: loop\r\nContent-Length: 0\r\n\r\n",
+                    other => panic!("unexpected request {:?}", other),
+                };
+                server.write_all(reply.as_bytes()).unwrap();
+            }
+        });
+        let client = FetchClient::<_, UdpTransport>::with_parts(connector, None);
+
+        let url = Url::parse("http://a.test/form").unwrap();
+        let result = client.send(RequestBuilder::post(&url).form([("q", "1")])).unwrap();
+        assert_eq!(result.url.to_string(), "http://b.test:8080/done?x=1");
+        let redirects: Vec<String> = result.redirects.iter().map(Url::to_string).collect();
+        assert_eq!(redirects, vec!["http://a.test/form", "http://a.test/form2"]);
+        assert_eq!(result.response.body, b"done");
+
+        let limits = Limits { max_redirects: 3, ..Limits::default() };
+        let err = client.with_limits(limits).fetch("http://a.test/loop").unwrap_err();
+        assert!(matches!(err, FetchError::TooManyRedirects(3)));
+        assert_eq!(connections.load(Ordering::SeqCst), 2, "one keep-alive connection per origin");
+    }
+
+    #[test]
+    fn test_login_cookie_survives_redirect() {
+        let connector = MemoryConnector::new(|_, _, mut server| {
+            let (head, _) = read_request(&mut server);
+            let reply = match head.lines().next().unwrap() {
+                "POST /login HTTP/1.1" => {
+                    assert!(!head.contains("Cookie:"));
+                    "HTTP/1.1 303 See Other\r\nLocation: /home\r\nSet-Cookie: sid=abc; Path=/; HttpOnly\r\nContent-Length: 0\r\n\r\n"
+                }
+                "GET /home HTTP/1.1" => {
+                    assert!(head.contains("Cookie: sid=abc\r\n"), "{}", head);
+                    "HTTP/1.1 200 OK\r\nContent-Length: 7\r\n\r\nwelcome"
+                }
+                other => panic!("unexpected request {:?}", other),
+            };
+            server.write_all(reply.as_bytes()).unwrap();
+        });
+        let jar = CookieJar::new();
+        let client = FetchClient::<_, UdpTransport>::with_parts(connector, None).with_cookies(jar.clone());
+
+        let result = client.request("POST", &Url::parse("http://app.test/login").unwrap(), b"u=me".to_vec()).unwrap();
+        assert_eq!(result.response.body, b"welcome");
+        assert_eq!(jar.cookies().len(), 1);
+    }
+
+    #[test]
+    fn test_third_party_subresource_gets_no_cookie() {
+        let pki = TestPki::new("tracker.test");
+        let server_config = pki.server_config(&[ALPN_HTTP11]);
+        let connector = MemoryConnector::new(move |_, _, pipe| {
+            let mut tls = TestPki::accept(server_config.clone(), pipe);
+            let mut head = Vec::new();
+            let mut byte = [0u8; 1];
+            while !head.ends_with(b"\r\n\r\n") {
+                tls.read_exact(&mut byte).unwrap();
+                head.push(byte[0]);
+            }
+            let body = if String::from_utf8_lossy(&head).contains("Cookie: id=1\r\n") { "sent" } else { "none" };
+            let reply = format!(
+                "HTTP/1.1 200 OK\r\nSet-Cookie: seen=1; SameSite=None; Secure\r\nConnection: close\r\n\
+Content-Length: 4\r\n\r\n{}",
+                body
+            );
+            tls.write_all(reply.as_bytes()).unwrap();
+            tls.conn.send_close_notify();
+            let _ = tls.flush();
+        });
+        let tracker = Url::parse("https://tracker.test/pixel").unwrap();
+        let page = Url::parse("https://news.test/").unwrap();
+        let jar = CookieJar::new();
+        jar.store_response_cookies(&tracker, &["id=1; SameSite=None; Secure"], &CookieContext::navigation(None, "GET"));
+        let tls = TlsConfig::with_root_certificates(&[pki.ca_der()]).unwrap();
+        let client = FetchClient::<_, UdpTransport>::with_parts(connector, None).with_tls(tls);
+        let client = client.with_cookies(jar.clone());
+
+        let embedded = client.send(RequestBuilder::get(&tracker).with_top_level(&page)).unwrap();
+        assert_eq!(embedded.response.body, b"none");
+        assert_eq!(jar.cookies().len(), 1, "a blocked third party cannot set cookies either");
+
+        let visited = client.send(RequestBuilder::get(&tracker)).unwrap();
+        assert_eq!(visited.response.body, b"sent");
+        assert_eq!(jar.cookies().len(), 2);
+    }
+
+    #[test]
+    fn test_cache_hits_revalidates_and_replays_offline() {
+        use crate::profile::Profile;
+
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let connector = MemoryConnector::new(move |_, _, mut server| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let (head, _) = read_request(&mut server);
+            let reply = match head.lines().next().unwrap() {
+                "GET /logo HTTP/1.1" => "HTTP/1.1 200 OK\r\nCache-Control: max-age=3600\r\nContent-Length: 3\r\n\r\npng",
+                "GET /feed HTTP/1.1" if head.contains("If-None-Match: \"7\"\r\n") => {
+                    assert_eq!(head.matches("If-None-Match").count(), 1, "{}", head);
+                    "HTTP/1.1 304 Not Modified\r\nETag: \"7\"\r\nSet-Cookie: seen=1\r\n\r\n"
+                }
+                "GET /feed HTTP/1.1" => "HTTP/1.1 200 OK\r\nCache-Control: no-cache\r\nETag: \"7\"\r\nContent-Length: 4\r\n\r\nnews",
+                other => panic!("unexpected request {:?}", other),
+            };
+            server.write_all(reply.as_bytes()).unwrap();
+        });
+        let dir = std::env::temp_dir().join(format!("yolofi_fetch_cache_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let cache = HttpCache::open(&Profile::open(&dir).unwrap(), 1 << 20).unwrap();
+        let jar = CookieJar::new();
+        let client = FetchClient::<_, UdpTransport>::with_parts(connector, None).with_cache(cache.clone());
+        let client = client.with_cookies(jar.clone());
+
+        assert_eq!(client.fetch("http://a.test/logo").unwrap().cache, CacheStatus::Miss);
+        let hit = client.fetch("http://a.test/logo").unwrap();
+        assert_eq!((hit.cache, hit.response.body.as_slice()), (CacheStatus::Hit, &b"png"[..]));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        client.fetch("http://a.test/feed").unwrap();
+        let feed = RequestBuilder::get(&Url::parse("http://a.test/feed").unwrap()).header("If-None-Match", "\"6\"");
+        let revalidated = client.send(feed).unwrap();
+        assert_eq!((revalidated.cache, revalidated.response.status), (CacheStatus::Revalidated, 200));
+        assert_eq!(revalidated.response.body, b"news");
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        assert_eq!(jar.cookies().len(), 1, "the 304's own Set-Cookie is stored");
+
+        let offline = client.with_cache_mode(CacheMode::OnlyIfCached);
+        assert_eq!(offline.fetch("http://a.test/feed").unwrap().response.body, b"news");
+        assert!(matches!(offline.fetch("http://a.test/other"), Err(FetchError::NotCached(_))));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_https_negotiates_h2_and_reuses_the_connection() {
+        use crate::http2::tests::TestPeer;
+        use crate::tls::ALPN_H2;
+
+        let pki = TestPki::new("h2.test");
+        let server_config = pki.server_config(&[ALPN_H2, ALPN_HTTP11]);
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        let connector = MemoryConnector::new(move |_, _, pipe| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let mut peer = TestPeer::accept(TestPki::accept(server_config.clone(), pipe), &[]);
+            let first = peer.next_request();
+            assert_eq!((first.field(":path"), first.field(":authority")), (Some("/start"), Some("h2.test")));
+            assert_eq!(first.field("connection"), None);
+            peer.send_response(first.stream, "302", &[("location", "/end")], b"");
+            let second = peer.next_request();
+            assert_eq!((second.stream, second.field(":path")), (3, Some("/end")));
+            peer.send_response(second.stream, "200", &[("content-length", "2")], b"h2");
+        });
+        let tls = TlsConfig::with_root_certificates(&[pki.ca_der()]).unwrap();
+        let client = FetchClient::<_, UdpTransport>::with_parts(connector, None).with_tls(tls);
+
+        let result = client.fetch("https://h2.test/start").unwrap();
+        assert_eq!((result.response.status, result.response.body.as_slice()), (200, &b"h2"[..]));
+        assert_eq!(result.response.security.unwrap().alpn.as_deref(), Some("h2"));
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_concurrent_fetches_share_one_h2_connection() {
+        use crate::http2::tests::TestPeer;
+        use crate::tls::ALPN_H2;
+
+        let pki = TestPki::new("h2.test");
+        let server_config = pki.server_config(&[ALPN_H2]);
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        let connector = MemoryConnector::new(move |_, _, pipe| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let mut peer = TestPeer::accept(TestPki::accept(server_config.clone(), pipe), &[]);
+            for _ in 0..3 {
+                let request = peer.next_request();
+                // Slow answers leave time for the other fetch to look for a connection.
+                std::thread::sleep(Duration::from_millis(50));
+                let path = request.field(":path").unwrap().to_string();
+                peer.send_response(request.stream, "200", &[], path.as_bytes());
+            }
+        });
+        let tls = TlsConfig::with_root_certificates(&[pki.ca_der()]).unwrap();
+        let client = FetchClient::<_, UdpTransport>::with_parts(connector, None).with_tls(tls);
+
+        assert_eq!(client.fetch("https://h2.test/first").unwrap().response.body, b"/first");
+        std::thread::scope(|scope| {
+            let client = &client;
+            let fetch = move |path: &str| client.fetch(&format!("https://h2.test{}", path));
+            let fetches = ["/a", "/b"].map(|path| scope.spawn(move || fetch(path)));
+            let bodies = fetches.map(|fetch| fetch.join().unwrap().unwrap().response.body);
+            assert_eq!(bodies, [b"/a".to_vec(), b"/b".to_vec()]);
+        });
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_hsts_and_https_only_upgrade_before_connecting() {
+        use crate::hsts::HstsStore;
+
+        let pki = TestPki::new("secure.test");
+        let server_config = pki.server_config(&[ALPN_HTTP11]);
+        let connector = MemoryConnector::new(move |host, port, pipe| {
+            assert_eq!(port, 443, "nothing may go out over plain http");
+            if host == "secure.test" {
+                let reply = b"HTTP/1.1 200 OK\r\nStrict-Transport-Security: max-age=600\r\nContent-Length: 2\r\n\r\nok";
+                TestPki::serve_response(server_config.clone(), pipe, reply);
+            }
+        });
+        let tls = TlsConfig::with_root_certificates(&[pki.ca_der()]).unwrap();
+        let hsts = HstsStore::without_preload();
+        let client = FetchClient::<_, UdpTransport>::with_parts(connector, None).with_tls(tls).with_hsts(hsts.clone());
+
+        client.fetch("https://secure.test/").unwrap();
+        assert!(hsts.lookup("secure.test").is_some());
+        let upgraded = client.fetch("http://secure.test/page").unwrap();
+        assert_eq!(upgraded.url.to_string(), "https://secure.test/page");
+
+        // Without HTTPS support there is no quiet fallback: the caller gets the error to show.
+        let client = client.with_https_only(true);
+        match client.fetch("http://plain.test/") {
+            Err(FetchError::HttpsOnly { url, source }) => {
+                assert_eq!(url, "http://plain.test/");
+                assert!(matches!(*source, FetchError::Tls(_)), "{:?}", source);
+            }
+            other => panic!("expected an HTTPS-only failure, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_proxy_receives_the_host_name() {
+        use crate::proxy::{ProxyConfig, ProxyConnector, ProxyKind};
+
+        let upstream = MemoryConnector::new(|host, port, mut proxy| {
+            assert_eq!((host, port), ("127.0.0.1", 9050));
+            let mut greeting = [0u8; 3];
+            proxy.read_exact(&mut greeting).unwrap();
+            proxy.write_all(&[5, 0]).unwrap();
+            let mut connect = [0u8; 5];
+            proxy.read_exact(&mut connect).unwrap();
+            assert_eq!(connect[3], 3, "the proxy must get a domain name, not an address");
+            let mut target = vec![0u8; connect[4] as usize + 2];
+            proxy.read_exact(&mut target).unwrap();
+            assert_eq!(&target[..target.len() - 2], b"onion.test");
+            proxy.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+
+            read_request(&mut proxy);
+            proxy.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nhidden").unwrap();
+        });
+        let dns = |_: &str, _: &[u8]| -> io::Result<Vec<u8>> { panic!("host name leaked to local DNS") };
+        let proxy = ProxyConnector::new(upstream, ProxyConfig::new(ProxyKind::Socks5, "127.0.0.1", 9050));
+        let client = FetchClient::with_parts(proxy, Some(DnsResolver::with_transport(dns)));
+
+        assert_eq!(client.fetch("http://onion.test/").unwrap().response.body, b"hidden");
+
+        // Bypassed hosts connect directly, but still through our resolver rather than the OS.
+        let direct = MemoryConnector::new(|host, port, mut server| {
+            assert_eq!((host, port), ("10.1.2.3", 80));
+            read_request(&mut server);
+            server.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\ndirect").unwrap();
+        });
+        let dns = |_: &str, query: &[u8]| -> io::Result<Vec<u8>> {
+            assert!(query.windows(8).any(|w| w == b"intranet"), "only bypassed names are resolved locally");
+            let mut reply = query.to_vec();
+            reply[2] = 0x81;
+            reply[3] = 0x80;
+            reply[7] = 1;
+            reply.extend_from_slice(&[0xC0, 0x0C, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 10, 1, 2, 3]);
+            Ok(reply)
+        };
+        let config = ProxyConfig::new(ProxyKind::Socks5, "127.0.0.1", 9050).with_bypass_list(".intranet.test");
+        let proxy = ProxyConnector::new(direct, config);
+        let client = FetchClient::with_parts(proxy, Some(DnsResolver::with_transport(dns)));
+        assert_eq!(client.fetch("http://wiki.intranet.test/").unwrap().response.body, b"direct");
+    }
+
+    #[test]
+    fn test_https_fetch_resolves_through_dns() {
+        let pki = TestPki::new("secure.test");
+        let server_config = pki.server_config(&[ALPN_HTTP11]);
+        let connector = MemoryConnector::new(move |host, port, pipe| {
+            assert_eq!((host, port), ("10.1.2.3", 443));
+            TestPki::serve_response(server_config.clone(), pipe, b"HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nsecret");
+        });
+        let dns = |_: &str, query: &[u8]| -> io::Result<Vec<u8>> {
+            let mut reply = query.to_vec();
+            reply[2] = 0x81;
+            reply[3] = 0x80;
+            reply[7] = 1;
+            reply.extend_from_slice(&[0xC0, 0x0C, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 10, 1, 2, 3]);
+            Ok(reply)
+        };
+        let tls = TlsConfig::with_root_certificates(&[pki.ca_der()]).unwrap();
+        let client = FetchClient::with_parts(connector, Some(DnsResolver::with_transport(dns))).with_tls(tls);
+
+        let result = client.fetch("https://Secure.test/").unwrap();
+        assert_eq!(result.url.to_string(), "https://secure.test/");
+        assert_eq!(result.response.body, b"secret");
+        assert!(result.response.security.is_some());
+
+        assert!(matches!(client.fetch("ftp://secure.test/"), Err(FetchError::UnsupportedScheme(_))));
+    }
+}
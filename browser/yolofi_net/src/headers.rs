@@ -0,0 +1,203 @@
+// HTTP Header Map
+// Field names are case-insensitive (RFC 9110 section 5.1) but we keep the casing
+// and order the server used, and every value: fields like Set-Cookie repeat.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderMap {
+    entries: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Adds a field, keeping any existing ones with the same name.
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.entries.push((name.into(), value.into()));
+    }
+
+    /// Sets a field, replacing every existing value. The first occurrence keeps its position.
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let (name, value) = (name.into(), value.into());
+        match self.entries.iter().position(|(n, _)| n.eq_ignore_ascii_case(&name)) {
+            Some(first) => {
+                self.entries[first] = (name.clone(), value);
+                let mut index = 0;
+                self.entries.retain(|(n, _)| {
+                    index += 1;
+                    index - 1 == first || !n.eq_ignore_ascii_case(&name)
+                });
+            }
+            None => self.entries.push((name, value)),
+        }
+    }
+
+    /// Removes every value of `name`. Returns how many were removed.
+    pub fn remove(&mut self, name: &str) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        before - self.entries.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// The first value of `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+
+    /// Every value of `name`, in the order received.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries.iter().filter(move |(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+
+    /// All values joined with ", " — the combined form of a list-valued field.
+    /// Not meaningful for Set-Cookie, whose values may contain commas.
+    pub fn get_combined(&self, name: &str) -> Option<String> {
+        let values: Vec<&str> = self.get_all(name).collect();
+        (!values.is_empty()).then(|| values.join(", "))
+    }
+
+    /// Comma-separated list elements across every value of `name`, trimmed and lowercased.
+    pub fn tokens(&self, name: &str) -> Vec<String> {
+        self.get_all(name)
+            .flat_map(|v| v.split(','))
+            .map(|t| t.trim().to_ascii_lowercase())
+            .filter(|t| !t.is_empty())
+            .collect()
+    }
+
+    /// Fields in received order, with their original casing.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    /// The most recently added value, for obs-fold continuation lines.
+    pub(crate) fn last_value_mut(&mut self) -> Option<(&str, &mut String)> {
+        self.entries.last_mut().map(|(n, v)| (n.as_str(), v))
+    }
+
+    // Typed accessors for the common fields.
+
+    /// Content-Length, if present and a plain decimal number.
+    pub fn content_length(&self) -> Option<u64> {
+        self.get("Content-Length").and_then(|v| v.trim().parse().ok())
+    }
+
+    /// The full Content-Type value, parameters included.
+    pub fn content_type(&self) -> Option<&str> {
+        self.get("Content-Type")
+    }
+
+    /// Content-Type without parameters, lowercased: "text/html".
+    pub fn mime_type(&self) -> Option<String> {
+        let essence = self.content_type()?.split(';').next()?.trim().to_ascii_lowercase();
+        (!essence.is_empty()).then_some(essence)
+    }
+
+    /// The `charset` parameter of Content-Type, lowercased, without quotes.
+    pub fn charset(&self) -> Option<String> {
+        self.content_type()?.split(';').skip(1).find_map(|param| {
+            let (name, value) = param.split_once('=')?;
+            name.trim()
+                .eq_ignore_ascii_case("charset")
+                .then(|| value.trim().trim_matches('"').to_ascii_lowercase())
+        })
+    }
+
+    pub fn location(&self) -> Option<&str> {
+        self.get("Location")
+    }
+
+    /// Every Set-Cookie value, unmerged.
+    pub fn set_cookies(&self) -> Vec<&str> {
+        self.get_all("Set-Cookie").collect()
+    }
+
+    /// Content-Encoding codings in the order they were applied.
+    pub fn content_encoding(&self) -> Vec<String> {
+        self.tokens("Content-Encoding")
+    }
+
+    /// Connection options, e.g. ["close"] or ["keep-alive"].
+    pub fn connection(&self) -> Vec<String> {
+        self.tokens("Connection")
+    }
+}
+
+impl<N: Into<String>, V: Into<String>> FromIterator<(N, V)> for HeaderMap {
+    fn from_iter<I: IntoIterator<Item = (N, V)>>(iter: I) -> Self {
+        let mut map = HeaderMap::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<N: Into<String>, V: Into<String>> Extend<(N, V)> for HeaderMap {
+    fn extend<I: IntoIterator<Item = (N, V)>>(&mut self, iter: I) {
+        for (name, value) in iter {
+            self.append(name, value);
+        }
+    }
+}
+
+/// Wire format: one `Name: value\r\n` line per field.
+impl fmt::Display for HeaderMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in &self.entries {
+            write!(f, "{}: {}\r\n", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_is_case_insensitive_and_multi_valued() {
+        let mut headers: HeaderMap = [
+            ("Content-Type", "text/HTML; Charset=\"UTF-8\""),
+            ("Set-Cookie", "a=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT"),
+            ("set-cookie", "b=2"),
+            ("Cache-Control", "no-cache"),
+            ("cache-control", "max-age=0"),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(headers.get("content-type"), Some("text/HTML; Charset=\"UTF-8\""));
+        assert_eq!(headers.mime_type().as_deref(), Some("text/html"));
+        assert_eq!(headers.charset().as_deref(), Some("utf-8"));
+        assert_eq!(headers.set_cookies(), vec!["a=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT", "b=2"]);
+        assert_eq!(headers.get_combined("CACHE-CONTROL").as_deref(), Some("no-cache, max-age=0"));
+
+        headers.insert("SET-COOKIE", "c=3");
+        assert_eq!(headers.set_cookies(), vec!["c=3"]);
+        let names: Vec<&str> = headers.iter().map(|(n, _)| n).collect();
+        assert_eq!(names, vec!["Content-Type", "SET-COOKIE", "Cache-Control", "cache-control"]);
+
+        assert_eq!(headers.remove("cache-control"), 2);
+        headers.append("Content-Length", "42");
+        assert_eq!(headers.content_length(), Some(42));
+        assert_eq!(headers.to_string(), "Content-Type: text/HTML; Charset=\"UTF-8\"\r\nSET-COOKIE: c=3\r\nContent-Length: 42\r\n");
+    }
+}
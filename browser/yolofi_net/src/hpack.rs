@@ -0,0 +1,586 @@
+// HPACK
+// Header compression for HTTP/2 (RFC 7541): prefix integers, string literals with
+// optional Huffman coding, the static table and a size-bounded dynamic table.
+//
+// The Huffman code of Appendix B is canonical (codes of one length are consecutive
+// and ordered by symbol), so only the code lengths are listed; the codes are derived.
+//
+// The encoder never indexes credentials (Authorization, Cookie): they are sent as
+// never-indexed literals so they cannot be probed through the shared table.
+
+use std::collections::VecDeque;
+use std::sync::OnceLock;
+
+pub const DEFAULT_TABLE_SIZE: usize = 4096;
+/// Per-entry overhead counted by the table size (RFC 7541 section 4.1).
+const ENTRY_OVERHEAD: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum HpackError {
+    #[error("header block ends in the middle of a field")]
+    Truncated,
+    #[error("integer does not fit")]
+    IntegerOverflow,
+    #[error("index {0} is not in the table")]
+    InvalidIndex(usize),
+    #[error("invalid Huffman-coded string")]
+    InvalidHuffman,
+    #[error("table size update to {0} exceeds the allowed maximum or comes after a field")]
+    InvalidTableSizeUpdate(usize),
+    #[error("header list exceeds {0} bytes")]
+    HeaderListTooLarge(usize),
+}
+
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// Fields the encoder sends as never-indexed literals.
+const SENSITIVE: [&str; 3] = ["authorization", "proxy-authorization", "cookie"];
+
+// ---------------------------------------------------------------------------
+// Dynamic table
+// ---------------------------------------------------------------------------
+
+#[derive(Debug)]
+struct DynamicTable {
+    /// Newest first, matching HPACK index order.
+    entries: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl DynamicTable {
+    fn new(max_size: usize) -> Self {
+        Self { entries: VecDeque::new(), size: 0, max_size }
+    }
+
+    fn insert(&mut self, name: String, value: String) {
+        let entry_size = name.len() + value.len() + ENTRY_OVERHEAD;
+        // An entry larger than the table empties it and is not added (section 4.4).
+        self.evict_to(self.max_size.saturating_sub(entry_size));
+        if entry_size <= self.max_size {
+            self.size += entry_size;
+            self.entries.push_front((name, value));
+        }
+    }
+
+    fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.evict_to(max_size);
+    }
+
+    fn evict_to(&mut self, target: usize) {
+        while self.size > target {
+            let (name, value) = self.entries.pop_back().expect("size is nonzero");
+            self.size -= name.len() + value.len() + ENTRY_OVERHEAD;
+        }
+    }
+
+    /// Looks up a 1-based HPACK index across the static and dynamic tables.
+    fn get(&self, index: usize) -> Result<(&str, &str), HpackError> {
+        match index {
+            0 => Err(HpackError::InvalidIndex(0)),
+            1..=61 => Ok(STATIC_TABLE[index - 1]),
+            _ => self
+                .entries
+                .get(index - 62)
+                .map(|(n, v)| (n.as_str(), v.as_str()))
+                .ok_or(HpackError::InvalidIndex(index)),
+        }
+    }
+
+    /// The best index for a field: (index, whether the value matched too).
+    fn find(&self, name: &str, value: &str) -> Option<(usize, bool)> {
+        let all = STATIC_TABLE
+            .iter()
+            .copied()
+            .chain(self.entries.iter().map(|(n, v)| (n.as_str(), v.as_str())))
+            .enumerate();
+        let mut name_match = None;
+        for (i, (n, v)) in all {
+            if n == name {
+                if v == value {
+                    return Some((i + 1, true));
+                }
+                name_match.get_or_insert(i + 1);
+            }
+        }
+        name_match.map(|i| (i, false))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Decoder
+// ---------------------------------------------------------------------------
+
+/// Decodes header blocks from one peer. Keeps the table between blocks.
+#[derive(Debug)]
+pub struct Decoder {
+    table: DynamicTable,
+    /// What we advertised as SETTINGS_HEADER_TABLE_SIZE.
+    max_table_size: usize,
+    max_header_list_size: usize,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new(DEFAULT_TABLE_SIZE)
+    }
+}
+
+impl Decoder {
+    pub fn new(max_table_size: usize) -> Self {
+        Self { table: DynamicTable::new(max_table_size), max_table_size, max_header_list_size: usize::MAX }
+    }
+
+    /// Limits the decoded list, counted as in SETTINGS_MAX_HEADER_LIST_SIZE.
+    pub fn set_max_header_list_size(&mut self, size: usize) {
+        self.max_header_list_size = size;
+    }
+
+    pub fn table_size(&self) -> usize {
+        self.table.size
+    }
+
+    /// Decodes one complete header block (HEADERS plus any CONTINUATION payloads).
+    pub fn decode(&mut self, block: &[u8]) -> Result<Vec<(String, String)>, HpackError> {
+        let mut input = block;
+        let mut fields = Vec::new();
+        let mut list_size = 0;
+        while let Some(&first) = input.first() {
+            let (name, value, index) = if first & 0x80 != 0 {
+                // Indexed field.
+                let index = decode_integer(&mut input, 7)?;
+                let (n, v) = self.table.get(index)?;
+                (n.to_string(), v.to_string(), false)
+            } else if first & 0xE0 == 0x20 {
+                // Dynamic table size update; only allowed before the first field.
+                let size = decode_integer(&mut input, 5)?;
+                if !fields.is_empty() || size > self.max_table_size {
+                    return Err(HpackError::InvalidTableSizeUpdate(size));
+                }
+                self.table.set_max_size(size);
+                continue;
+            } else {
+                // Literal: with incremental indexing (01), without (0000) or never indexed (0001).
+                let (prefix, index) = if first & 0x40 != 0 { (6, true) } else { (4, false) };
+                let name_index = decode_integer(&mut input, prefix)?;
+                let name = match name_index {
+                    0 => decode_string(&mut input)?,
+                    i => self.table.get(i)?.0.to_string(),
+                };
+                (name, decode_string(&mut input)?, index)
+            };
+
+            list_size += name.len() + value.len() + ENTRY_OVERHEAD;
+            if list_size > self.max_header_list_size {
+                return Err(HpackError::HeaderListTooLarge(self.max_header_list_size));
+            }
+            if index {
+                self.table.insert(name.clone(), value.clone());
+            }
+            fields.push((name, value));
+        }
+        Ok(fields)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Encoder
+// ---------------------------------------------------------------------------
+
+/// Encodes header blocks for one peer, mirroring the peer decoder's table.
+#[derive(Debug)]
+pub struct Encoder {
+    table: DynamicTable,
+    /// Smallest size the peer allowed since the last block, if it changed.
+    pending_update: Option<usize>,
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self { table: DynamicTable::new(DEFAULT_TABLE_SIZE), pending_update: None }
+    }
+
+    /// Applies the peer's SETTINGS_HEADER_TABLE_SIZE; signalled at the start of the next block.
+    pub fn set_max_table_size(&mut self, size: usize) {
+        self.pending_update = Some(self.pending_update.map_or(size, |pending| pending.min(size)));
+        self.table.set_max_size(size);
+    }
+
+    /// Encodes fields in order. Names must already be lowercase.
+    pub fn encode<'a>(&mut self, fields: impl IntoIterator<Item = (&'a str, &'a str)>) -> Vec<u8> {
+        let mut out = Vec::new();
+        if let Some(smallest) = self.pending_update.take() {
+            // A shrink-then-grow must be signalled as both steps (section 4.2).
+            if smallest < self.table.max_size {
+                encode_integer(smallest, 5, 0x20, &mut out);
+            }
+            encode_integer(self.table.max_size, 5, 0x20, &mut out);
+        }
+
+        for (name, value) in fields {
+            let found = self.table.find(name, value);
+            if SENSITIVE.contains(&name) {
+                encode_integer(found.map_or(0, |(i, _)| i), 4, 0x10, &mut out);
+            } else if let Some((index, true)) = found {
+                encode_integer(index, 7, 0x80, &mut out);
+                continue;
+            } else if name.len() + value.len() + ENTRY_OVERHEAD > self.table.max_size / 2 {
+                // Large values would flush the table for little gain.
+                encode_integer(found.map_or(0, |(i, _)| i), 4, 0x00, &mut out);
+            } else {
+                encode_integer(found.map_or(0, |(i, _)| i), 6, 0x40, &mut out);
+                self.table.insert(name.to_string(), value.to_string());
+            }
+            if found.is_none() {
+                encode_string(name.as_bytes(), &mut out);
+            }
+            encode_string(value.as_bytes(), &mut out);
+        }
+        out
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Primitives
+// ---------------------------------------------------------------------------
+
+/// Prefix integer (section 5.1); `flags` fills the bits above the prefix.
+fn encode_integer(value: usize, prefix_bits: u8, flags: u8, out: &mut Vec<u8>) {
+    let max_prefix = (1usize << prefix_bits) - 1;
+    if value < max_prefix {
+        out.push(flags | value as u8);
+        return;
+    }
+    out.push(flags | max_prefix as u8);
+    let mut rest = value - max_prefix;
+    while rest >= 0x80 {
+        out.push((rest as u8 & 0x7F) | 0x80);
+        rest >>= 7;
+    }
+    out.push(rest as u8);
+}
+
+fn decode_integer(input: &mut &[u8], prefix_bits: u8) -> Result<usize, HpackError> {
+    let (&first, mut rest) = input.split_first().ok_or(HpackError::Truncated)?;
+    let max_prefix = (1usize << prefix_bits) - 1;
+    let mut value = first as usize & max_prefix;
+    if value == max_prefix {
+        let mut shift = 0;
+        loop {
+            let (&byte, tail) = rest.split_first().ok_or(HpackError::Truncated)?;
+            rest = tail;
+            // Anything past 28 bits is far beyond every limit we enforce.
+            if shift > 21 {
+                return Err(HpackError::IntegerOverflow);
+            }
+            value += (byte as usize & 0x7F) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+    }
+    *input = rest;
+    Ok(value)
+}
+
+fn encode_string(bytes: &[u8], out: &mut Vec<u8>) {
+    let huffman_len = huffman_encoded_len(bytes);
+    if huffman_len < bytes.len() {
+        encode_integer(huffman_len, 7, 0x80, out);
+        huffman_encode(bytes, out);
+    } else {
+        encode_integer(bytes.len(), 7, 0x00, out);
+        out.extend_from_slice(bytes);
+    }
+}
+
+fn decode_string(input: &mut &[u8]) -> Result<String, HpackError> {
+    let huffman = input.first().ok_or(HpackError::Truncated)? & 0x80 != 0;
+    let len = decode_integer(input, 7)?;
+    if input.len() < len {
+        return Err(HpackError::Truncated);
+    }
+    let (raw, rest) = input.split_at(len);
+    *input = rest;
+    let bytes = if huffman { huffman_decode(raw)? } else { raw.to_vec() };
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// ---------------------------------------------------------------------------
+// Huffman code (Appendix B)
+// ---------------------------------------------------------------------------
+
+const EOS: usize = 256;
+
+#[rustfmt::skip]
+const CODE_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28,
+    28, 28, 28, 28, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+     6, 10, 10, 12, 13,  6,  8, 11, 10, 10,  8, 11,  8,  6,  6,  6,
+     5,  5,  5,  6,  6,  6,  6,  6,  6,  6,  7,  8, 15,  6, 12, 10,
+    13,  6,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,
+     7,  7,  7,  7,  7,  7,  7,  7,  8,  7,  8, 13, 19, 13, 14,  6,
+    15,  5,  6,  5,  6,  5,  6,  6,  6,  5,  7,  7,  6,  6,  6,  5,
+     6,  7,  6,  5,  5,  6,  7,  7,  7,  7,  7, 15, 11, 14, 13, 28,
+    20, 22, 20, 20, 22, 22, 22, 23, 22, 23, 23, 23, 23, 23, 24, 23,
+    24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24,
+    22, 21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23,
+    21, 21, 22, 21, 23, 22, 23, 23, 20, 22, 22, 22, 23, 22, 22, 23,
+    26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25,
+    19, 21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27,
+    20, 24, 20, 21, 22, 21, 21, 23, 22, 22, 25, 25, 24, 24, 26, 23,
+    26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26,
+    30,
+];
+
+struct HuffmanCode {
+    /// (code, length) per symbol.
+    codes: [(u32, u8); 257],
+    /// Per length: the first code, how many codes, and where they start in `symbols`.
+    first_code: [u32; 31],
+    count: [u32; 31],
+    offset: [usize; 31],
+    /// Symbols ordered by (length, symbol).
+    symbols: Vec<u16>,
+}
+
+fn huffman() -> &'static HuffmanCode {
+    static CODE: OnceLock<HuffmanCode> = OnceLock::new();
+    CODE.get_or_init(|| {
+        let mut symbols: Vec<u16> = (0..=EOS as u16).collect();
+        symbols.sort_by_key(|&s| (CODE_LENGTHS[s as usize], s));
+        let mut code = HuffmanCode {
+            codes: [(0, 0); 257],
+            first_code: [0; 31],
+            count: [0; 31],
+            offset: [0; 31],
+            symbols,
+        };
+        for &len in &CODE_LENGTHS {
+            code.count[len as usize] += 1;
+        }
+        let (mut next, mut offset) = (0u32, 0usize);
+        for len in 1..31 {
+            next <<= 1;
+            code.first_code[len] = next;
+            code.offset[len] = offset;
+            next += code.count[len];
+            offset += code.count[len] as usize;
+        }
+        for (i, &symbol) in code.symbols.iter().enumerate() {
+            let len = CODE_LENGTHS[symbol as usize] as usize;
+            let value = code.first_code[len] + (i - code.offset[len]) as u32;
+            code.codes[symbol as usize] = (value, len as u8);
+        }
+        code
+    })
+}
+
+fn huffman_encoded_len(bytes: &[u8]) -> usize {
+    let bits: usize = bytes.iter().map(|&b| CODE_LENGTHS[b as usize] as usize).sum();
+    bits.div_ceil(8)
+}
+
+fn huffman_encode(bytes: &[u8], out: &mut Vec<u8>) {
+    let codes = &huffman().codes;
+    let (mut buffer, mut pending) = (0u64, 0u32);
+    for &b in bytes {
+        let (code, len) = codes[b as usize];
+        buffer = (buffer << len) | code as u64;
+        pending += len as u32;
+        while pending >= 8 {
+            pending -= 8;
+            out.push((buffer >> pending) as u8);
+        }
+    }
+    if pending > 0 {
+        // Pad with the most significant bits of EOS, which are all ones.
+        out.push(((buffer << (8 - pending)) as u8) | (0xFF >> pending));
+    }
+}
+
+fn huffman_decode(bytes: &[u8]) -> Result<Vec<u8>, HpackError> {
+    let code = huffman();
+    let mut out = Vec::with_capacity(bytes.len() * 8 / 5);
+    let (mut value, mut len) = (0u32, 0usize);
+    for &byte in bytes {
+        for shift in (0..8).rev() {
+            value = (value << 1) | ((byte >> shift) & 1) as u32;
+            len += 1;
+            if len > 30 {
+                return Err(HpackError::InvalidHuffman);
+            }
+            let index = value.wrapping_sub(code.first_code[len]);
+            if value >= code.first_code[len] && index < code.count[len] {
+                let symbol = code.symbols[code.offset[len] + index as usize] as usize;
+                if symbol == EOS {
+                    return Err(HpackError::InvalidHuffman);
+                }
+                out.push(symbol as u8);
+                value = 0;
+                len = 0;
+            }
+        }
+    }
+    // Padding: fewer than 8 bits, all ones (section 5.2).
+    if len > 7 || value != (1 << len) - 1 {
+        return Err(HpackError::InvalidHuffman);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let digits: Vec<u8> = s.bytes().filter(u8::is_ascii_hexdigit).collect();
+        digits.chunks(2).map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_rfc7541_request_examples_with_huffman() {
+        // Appendix C.4: three requests on one connection.
+        let requests: [(&[(&str, &str)], &str); 3] = [
+            (
+                &[(":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com")],
+                "8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff",
+            ),
+            (
+                &[
+                    (":method", "GET"),
+                    (":scheme", "http"),
+                    (":path", "/"),
+                    (":authority", "www.example.com"),
+                    ("cache-control", "no-cache"),
+                ],
+                "8286 84be 5886 a8eb 1064 9cbf",
+            ),
+            (
+                &[
+                    (":method", "GET"),
+                    (":scheme", "https"),
+                    (":path", "/index.html"),
+                    (":authority", "www.example.com"),
+                    ("custom-key", "custom-value"),
+                ],
+                "8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf",
+            ),
+        ];
+        let mut encoder = Encoder::new();
+        let mut decoder = Decoder::default();
+        for (fields, wire) in requests {
+            let block = encoder.encode(fields.iter().copied());
+            assert_eq!(block, hex(wire));
+            let decoded = decoder.decode(&block).unwrap();
+            let expected: Vec<(String, String)> = fields.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect();
+            assert_eq!(decoded, expected);
+        }
+        assert_eq!(decoder.table_size(), 164);
+
+        // Every byte value survives Huffman coding.
+        let all: Vec<u8> = (0..=255).collect();
+        let mut coded = Vec::new();
+        huffman_encode(&all, &mut coded);
+        assert_eq!(huffman_decode(&coded).unwrap(), all);
+    }
+
+    #[test]
+    fn test_table_limits_sensitive_fields_and_malformed_blocks() {
+        let mut encoder = Encoder::new();
+        let mut decoder = Decoder::new(DEFAULT_TABLE_SIZE);
+        encoder.set_max_table_size(0);
+        encoder.set_max_table_size(100);
+        let block = encoder.encode([("x-a", "1"), ("cookie", "sid=secret"), ("x-b", "22222222222222")]);
+        // Shrink to 0, grow to 100, then fields.
+        assert_eq!(&block[..3], &[0x20, 0x3F, 0x45]);
+        let fields = decoder.decode(&block).unwrap();
+        assert_eq!(fields[1], ("cookie".to_string(), "sid=secret".to_string()));
+        // x-a (36) and x-b (49) fit in 100 bytes; the cookie was never indexed.
+        assert_eq!(decoder.table_size(), 36 + 49);
+        let again = encoder.encode([("x-b", "22222222222222"), ("x-a", "1")]);
+        assert_eq!(again, [0xBE, 0xBF]);
+        assert_eq!(decoder.decode(&again).unwrap().len(), 2);
+
+        assert_eq!(decoder.decode(&[0x80]), Err(HpackError::InvalidIndex(0)));
+        assert_eq!(decoder.decode(&[0xFF, 0x00]), Err(HpackError::InvalidIndex(127)));
+        assert_eq!(decoder.decode(&[0x82, 0x20]), Err(HpackError::InvalidTableSizeUpdate(0)));
+        assert_eq!(decoder.decode(&[0x3F, 0xE1, 0x7F]), Err(HpackError::InvalidTableSizeUpdate(16384)));
+        assert_eq!(decoder.decode(&[0x40, 0x05, b'a']), Err(HpackError::Truncated));
+        assert_eq!(decoder.decode(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F]), Err(HpackError::IntegerOverflow));
+        // "a" Huffman-coded (00011) padded with zeros instead of ones.
+        assert_eq!(decoder.decode(&[0x00, 0x81, 0x18, 0x00]), Err(HpackError::InvalidHuffman));
+        decoder.set_max_header_list_size(40);
+        assert_eq!(decoder.decode(&[0x82, 0x84]), Err(HpackError::HeaderListTooLarge(40)));
+    }
+}
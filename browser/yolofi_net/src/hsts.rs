@@ -0,0 +1,356 @@
+// HTTP Strict Transport Security (RFC 6797)
+// A host that sends Strict-Transport-Security over a valid https connection is
+// only ever contacted over https until max-age runs out: http URLs to it (and to
+// its subdomains, with includeSubDomains) are rewritten before any byte is sent.
+// A built-in preload list covers the first visit, which the header cannot protect.
+//
+// Profile file `hsts.txt`, one host per line:
+//   <host> expires=<unix seconds> [include-subdomains]
+
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+use crate::headers::HeaderMap;
+use crate::http_date::unix_now;
+use crate::profile::Profile;
+use crate::Url;
+use yolofi_url::Host;
+
+const HSTS_FILE: &str = "hsts.txt";
+
+/// Preloaded hosts, as `host [include-subdomains]`. A small subset of the Chromium list:
+/// our own services, the HSTS-only TLDs, and a few high-value sites.
+const PRELOAD: &str = "
+yolofi.in include-subdomains
+dev include-subdomains
+app include-subdomains
+page include-subdomains
+foo include-subdomains
+bank include-subdomains
+insurance include-subdomains
+github.com include-subdomains
+accounts.google.com include-subdomains
+mail.google.com include-subdomains
+paypal.com
+www.paypal.com
+torproject.org include-subdomains
+eff.org include-subdomains
+";
+
+/// A parsed Strict-Transport-Security header (RFC 6797 section 6.1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StsDirective {
+    pub max_age: u64,
+    pub include_subdomains: bool,
+}
+
+impl StsDirective {
+    /// `None` for anything malformed: a missing or non-numeric max-age, or a repeated directive.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut max_age = None;
+        let mut include_subdomains = false;
+        for directive in value.split(';') {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim())),
+                None => (directive.trim(), None),
+            };
+            match name.to_ascii_lowercase().as_str() {
+                "" => {}
+                "max-age" => {
+                    let value = value?;
+                    let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value);
+                    if max_age.is_some() || value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+                        return None;
+                    }
+                    // Absurdly large values mean "forever"; clamp instead of overflowing.
+                    max_age = Some(value.parse().unwrap_or(u64::MAX));
+                }
+                "includesubdomains" => {
+                    if include_subdomains || value.is_some() {
+                        return None;
+                    }
+                    include_subdomains = true;
+                }
+                _ => {}
+            }
+        }
+        Some(Self { max_age: max_age?, include_subdomains })
+    }
+}
+
+/// A host we have been told to reach only over https.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HstsEntry {
+    pub host: String,
+    /// Unix seconds; `None` for preloaded entries, which never expire.
+    pub expires: Option<i64>,
+    pub include_subdomains: bool,
+}
+
+#[derive(Debug, Default)]
+struct HstsState {
+    /// Learned from headers. They can add to a preloaded policy but never weaken it.
+    dynamic: BTreeMap<String, HstsEntry>,
+}
+
+/// Shared handle to the HSTS store; clones see the same entries.
+#[derive(Debug, Clone, Default)]
+pub struct HstsStore {
+    state: Arc<Mutex<HstsState>>,
+    profile: Option<Profile>,
+    /// Consult the built-in preload list (on unless turned off for tests).
+    preload: bool,
+}
+
+impl HstsStore {
+    /// An in-memory store with the preload list. Nothing is persisted.
+    pub fn new() -> Self {
+        Self { preload: true, ..Self::default() }
+    }
+
+    /// An in-memory store that knows only what it is told.
+    pub fn without_preload() -> Self {
+        Self::default()
+    }
+
+    /// Loads learned entries from `profile`, dropping expired ones. Every later change is written back.
+    pub fn load(profile: &Profile) -> io::Result<Self> {
+        let mut state = HstsState::default();
+        let now = unix_now();
+        if let Some(text) = profile.read_optional(HSTS_FILE)? {
+            for (index, line) in text.lines().enumerate() {
+                let entry = parse_line(line)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{} line {}: {}", HSTS_FILE, index + 1, e)))?;
+                if let Some(entry) = entry.filter(|e| e.expires.is_some_and(|t| t > now)) {
+                    state.dynamic.insert(entry.host.clone(), entry);
+                }
+            }
+        }
+        info!(target: "net::hsts", "HSTS store loaded: {} learned hosts", state.dynamic.len());
+        Ok(Self { state: Arc::new(Mutex::new(state)), profile: Some(profile.clone()), preload: true })
+    }
+
+    /// Records the Strict-Transport-Security header of a response to `url`. Only https
+    /// responses count, and only for domain names (section 8.1).
+    pub fn process_response(&self, url: &Url, headers: &HeaderMap) {
+        let Some(host) = url.host().and_then(Host::domain).filter(|_| url.scheme() == "https") else {
+            return;
+        };
+        // Only the first header counts (section 8.1); a malformed one is ignored.
+        let Some(directive) = headers.get("Strict-Transport-Security").and_then(StsDirective::parse) else {
+            return;
+        };
+        let host = normalize_host(host);
+        let mut state = self.lock();
+        if directive.max_age == 0 {
+            if state.dynamic.remove(&host).is_some() {
+                info!(target: "net::hsts", "{} cleared its HSTS policy", host);
+                self.persist(&state);
+            }
+            return;
+        }
+        let expires = unix_now().saturating_add(directive.max_age.min(i64::MAX as u64) as i64);
+        let entry = HstsEntry { host: host.clone(), expires: Some(expires), include_subdomains: directive.include_subdomains };
+        let previous = state.dynamic.insert(host.clone(), entry.clone());
+        // Sites resend the header on every response; only write when something meaningful moved.
+        let stale = |p: &HstsEntry| p.include_subdomains != entry.include_subdomains || p.expires < Some(expires - 86_400);
+        if previous.as_ref().is_none_or(stale) {
+            if previous.is_none() {
+                info!(target: "net::hsts", "{} is now HSTS (includeSubDomains: {})", host, entry.include_subdomains);
+            }
+            self.persist(&state);
+        }
+    }
+
+    /// The entry that forces `host` onto https, if any: the host itself, or a parent
+    /// domain with includeSubDomains.
+    pub fn lookup(&self, host: &str) -> Option<HstsEntry> {
+        let host = normalize_host(host);
+        let state = self.lock();
+        let now = unix_now();
+        let mut candidate = host.as_str();
+        loop {
+            let exact = candidate == host;
+            let learned = state.dynamic.get(candidate).filter(|entry| entry.expires.is_some_and(|t| t > now));
+            let preload = if self.preload { preloaded(candidate) } else { None };
+            // An expired learned entry, or one without the preload's includeSubDomains, leaves the preload in force.
+            let found = match (learned, preload) {
+                (Some(learned), Some(preload)) if preload.include_subdomains && !learned.include_subdomains => {
+                    Some(preload)
+                }
+                (Some(learned), _) => Some(learned.clone()),
+                (None, preload) => preload,
+            };
+            if let Some(entry) = found.filter(|e| exact || e.include_subdomains) {
+                return Some(entry);
+            }
+            candidate = candidate.split_once('.')?.1;
+        }
+    }
+
+    /// Whether an http request to `url` must be sent as https instead.
+    pub fn should_upgrade(&self, url: &Url) -> bool {
+        url.scheme() == "http" && url.host().and_then(Host::domain).is_some_and(|host| self.lookup(host).is_some())
+    }
+
+    /// Every learned entry, preloads excluded.
+    pub fn entries(&self) -> Vec<HstsEntry> {
+        self.lock().dynamic.values().cloned().collect()
+    }
+
+    /// Drops the learned policy for `host`. Preloaded hosts stay HSTS.
+    pub fn forget(&self, host: &str) -> io::Result<bool> {
+        let mut state = self.lock();
+        let removed = state.dynamic.remove(&normalize_host(host)).is_some();
+        if let (true, Some(profile)) = (removed, &self.profile) {
+            profile.write_atomic(HSTS_FILE, serialize(&state).as_bytes())?;
+        }
+        Ok(removed)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HstsState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Saves after a change made while handling a response, where there is no caller to report to.
+    fn persist(&self, state: &HstsState) {
+        if let Some(profile) = &self.profile {
+            if let Err(e) = profile.write_atomic(HSTS_FILE, serialize(state).as_bytes()) {
+                warn!(target: "net::hsts", "Failed to save {}: {}", HSTS_FILE, e);
+            }
+        }
+    }
+}
+
+/// `url` with the https scheme. The default http port becomes 443; any other port is kept.
+pub fn upgrade_to_https(url: &Url) -> Url {
+    let fragment = url.fragment().map(|f| format!("#{}", f)).unwrap_or_default();
+    Url::parse(&format!("https://{}{}{}", url.authority(), url.request_target(), fragment)).expect("parts of a valid URL")
+}
+
+fn preloaded(host: &str) -> Option<HstsEntry> {
+    PRELOAD.lines().find_map(|line| {
+        let mut words = line.split_whitespace();
+        (words.next() == Some(host)).then(|| HstsEntry {
+            host: host.to_string(),
+            expires: None,
+            include_subdomains: words.next() == Some("include-subdomains"),
+        })
+    })
+}
+
+fn normalize_host(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn parse_line(line: &str) -> Result<Option<HstsEntry>, String> {
+    let mut words = line.split_whitespace();
+    let host = match words.next() {
+        None => return Ok(None),
+        Some(word) if word.starts_with('#') => return Ok(None),
+        Some(host) => normalize_host(host),
+    };
+    let mut entry = HstsEntry { host, expires: None, include_subdomains: false };
+    for word in words {
+        match word.split_once('=') {
+            Some(("expires", t)) => entry.expires = Some(t.parse().map_err(|_| format!("bad expiry '{}'", t))?),
+            None if word == "include-subdomains" => entry.include_subdomains = true,
+            _ => return Err(format!("unrecognised field '{}'", word)),
+        }
+    }
+    if entry.expires.is_none() {
+        return Err(format!("no expiry for {}", entry.host));
+    }
+    Ok(Some(entry))
+}
+
+fn serialize(state: &HstsState) -> String {
+    let mut out = String::new();
+    for entry in state.dynamic.values() {
+        out.push_str(&format!("{} expires={}", entry.host, entry.expires.unwrap_or_default()));
+        if entry.include_subdomains {
+            out.push_str(" include-subdomains");
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    fn sts(value: &str) -> HeaderMap {
+        [("Strict-Transport-Security", value)].into_iter().collect()
+    }
+
+    #[test]
+    fn test_header_parsing() {
+        let parsed = StsDirective::parse("max-age=\"31536000\"; INCLUDESUBDOMAINS; preload");
+        assert_eq!(parsed, Some(StsDirective { max_age: 31_536_000, include_subdomains: true }));
+        assert_eq!(StsDirective::parse(" ; max-age=0"), Some(StsDirective { max_age: 0, include_subdomains: false }));
+        assert_eq!(StsDirective::parse("max-age=99999999999999999999999").map(|d| d.max_age), Some(u64::MAX));
+        for bad in ["includeSubDomains", "max-age=1; max-age=2", "max-age=-1", "max-age=", "max-age=1; includeSubDomains=yes"] {
+            assert_eq!(StsDirective::parse(bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_store_learns_matches_and_persists() {
+        let dir = std::env::temp_dir().join(format!("yolofi_hsts_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let profile = Profile::open(&dir).unwrap();
+        let store = HstsStore::load(&profile).unwrap();
+
+        // Ignored over http and for IP addresses.
+        store.process_response(&url("http://a.test/"), &sts("max-age=600"));
+        store.process_response(&url("https://10.0.0.1/"), &sts("max-age=600"));
+        assert!(store.entries().is_empty());
+
+        store.process_response(&url("https://a.test/"), &sts("max-age=600; includeSubDomains"));
+        store.process_response(&url("https://b.test/"), &sts("max-age=600"));
+        assert!(store.should_upgrade(&url("http://deep.sub.a.test/x")));
+        assert!(store.should_upgrade(&url("http://B.test./")));
+        assert!(!store.should_upgrade(&url("http://sub.b.test/")));
+        assert!(!store.should_upgrade(&url("https://a.test/")));
+
+        // Preloaded, including whole TLDs.
+        assert!(store.should_upgrade(&url("http://docs.yolofi.in/")));
+        assert!(store.should_upgrade(&url("http://example.dev/")));
+        assert!(!HstsStore::without_preload().should_upgrade(&url("http://example.dev/")));
+
+        let reloaded = HstsStore::load(&profile).unwrap();
+        assert_eq!(reloaded.entries().len(), 2);
+        reloaded.process_response(&url("https://a.test/"), &sts("max-age=0"));
+        assert!(!reloaded.should_upgrade(&url("http://a.test/")));
+        assert_eq!(HstsStore::load(&profile).unwrap().entries().len(), 1);
+        assert!(HstsStore::load(&profile).unwrap().forget("b.test").unwrap());
+        assert!(HstsStore::load(&profile).unwrap().entries().is_empty());
+
+        assert_eq!(upgrade_to_https(&url("http://a.test/p?q#f")).to_string(), "https://a.test/p?q#f");
+        assert_eq!(upgrade_to_https(&url("http://a.test:8080/")).to_string(), "https://a.test:8080/");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_learned_entries_never_weaken_the_preload() {
+        let store = HstsStore::new();
+        store.process_response(&url("https://github.com/"), &sts("max-age=600"));
+        assert_eq!(store.entries().len(), 1);
+        assert!(store.lookup("github.com").unwrap().include_subdomains);
+        assert!(store.should_upgrade(&url("http://api.github.com/")));
+
+        store.lock().dynamic.insert(
+            "eff.org".to_string(),
+            HstsEntry { host: "eff.org".to_string(), expires: Some(1), include_subdomains: false },
+        );
+        assert_eq!(store.lookup("eff.org").unwrap().expires, None, "the preload outlives an expired entry");
+        assert!(store.should_upgrade(&url("http://www.eff.org/")));
+    }
+}
@@ -1,60 +1,426 @@
-use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read};
 use tracing::{debug, info};
 
+use crate::content_coding::{self, DecodeError, DecodeLimits, DecodingReader};
+use crate::headers::HeaderMap;
+use crate::http_parser::{ParseLimits, ResponseEvent, ResponseParser};
+use crate::identity::Identity;
+use crate::limits::Limits;
+use crate::request::RequestBuilder;
+use crate::security::ConnectionSecurityInfo;
+use crate::transport::{Connector, Transport};
+use crate::Url;
+
+pub use crate::http_parser::BodyLength;
+
 #[derive(Debug)]
 pub struct HttpRequest {
     pub method: String,
     pub path: String,
-    pub headers: HashMap<String, String>,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    /// The HTTP/1.1 wire form. Build requests with `RequestBuilder` so the framing fields are right.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = format!("{} {} HTTP/1.1\r\n{}\r\n", self.method, self.path, self.headers).into_bytes();
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
 }
 
 #[derive(Debug)]
 pub struct HttpResponse {
     pub status: u16,
-    pub headers: HashMap<String, String>,
+    pub headers: HeaderMap,
     pub body: Vec<u8>,
+    /// Fields sent after a chunked body. Kept apart from `headers` on purpose.
+    pub trailers: HeaderMap,
+    /// Set when the response arrived over TLS.
+    pub security: Option<ConnectionSecurityInfo>,
 }
 
 impl HttpResponse {
-    pub fn parse(bytes: &[u8]) -> Option<Self> {
-        let text = String::from_utf8_lossy(bytes);
-        
-        // Find double newline splitting headers and body
-        let split_idx = bytes.windows(4).position(|w| w == b"\r\n\r\n")?;
-        
-        let header_part = &text[..split_idx];
-        let body_part = &bytes[split_idx + 4..];
-
-        let mut lines = header_part.lines();
-        let status_line = lines.next()?;
-        
-        // Parse Status Line: "HTTP/1.1 200 OK"
-        let parts: Vec<&str> = status_line.split_whitespace().collect();
-        if parts.len() < 2 { return None; }
-        let status = parts[1].parse::<u16>().ok()?;
-        
-        let mut headers = HashMap::new();
-        for line in lines {
-            if let Some((key, value)) = line.split_once(':') {
-                headers.insert(key.trim().to_string(), value.trim().to_string());
+    /// Parses a complete response held in memory; the end of `bytes` counts as connection close.
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        Self::parse_with_limits(bytes, &Limits::default())
+    }
+
+    /// `parse`, failing with a `ParseError` or `DecodeError` once the response breaks a limit.
+    pub fn parse_with_limits(bytes: &[u8], limits: &Limits) -> io::Result<Self> {
+        let (response, _) = read_response_with_limits(&mut &bytes[..], "GET", limits)?;
+        info!(target: "net::http", "Parsed response: Status {}", response.status);
+        Ok(response)
+    }
+
+    /// Case-insensitive header lookup (first value).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+}
+
+/// Collects parser events into an `HttpResponse`.
+#[derive(Default)]
+struct ResponseBuilder {
+    status: u16,
+    headers: HeaderMap,
+    body: Vec<u8>,
+    trailers: HeaderMap,
+    length: Option<BodyLength>,
+    reusable: Option<bool>,
+}
+
+impl ResponseBuilder {
+    fn apply(&mut self, events: Vec<ResponseEvent>) {
+        for event in events {
+            match event {
+                // A final response replaces any interim (1xx) one before it.
+                ResponseEvent::Status { code, .. } => {
+                    self.status = code;
+                    self.headers.clear();
+                    self.length = None;
+                }
+                ResponseEvent::Header { name, value } => {
+                    self.headers.append(name, value);
+                }
+                ResponseEvent::HeaderFolded { value, .. } => {
+                    if let Some((_, last)) = self.headers.last_value_mut() {
+                        *last = value;
+                    }
+                }
+                ResponseEvent::HeadersComplete { length } => self.length = Some(length),
+                ResponseEvent::Chunk { .. } => {}
+                ResponseEvent::Body(bytes) => self.body.extend_from_slice(&bytes),
+                ResponseEvent::Trailer { name, value } => {
+                    self.trailers.append(name, value);
+                }
+                ResponseEvent::Complete { reusable } => self.reusable = Some(reusable),
             }
         }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.reusable.is_some()
+    }
+
+    /// Whether the head of the final (not interim) response is in.
+    fn has_final_head(&self) -> bool {
+        self.length.is_some() && (self.status >= 200 || self.status == 101)
+    }
+
+    /// Whether there are body bytes to take, or will never be more.
+    fn has_body_bytes(&self) -> bool {
+        !self.body.is_empty() || self.is_complete()
+    }
+
+    fn build(self) -> (HttpResponse, bool) {
+        debug!(
+            target: "net::http",
+            "Response body {:?}: {} bytes, reusable={:?}",
+            self.length,
+            self.body.len(),
+            self.reusable
+        );
+        let response = HttpResponse {
+            status: self.status,
+            headers: self.headers,
+            body: self.body,
+            trailers: self.trailers,
+            security: None,
+        };
+        (response, self.reusable.unwrap_or(false))
+    }
+}
+
+/// Runs the parser over a stream. As a `Read` it yields the raw body and pulls from the
+/// stream only when asked, so a coded body is decoded while it is still arriving.
+struct ResponseReader<'a, R> {
+    stream: &'a mut R,
+    parser: ResponseParser,
+    builder: ResponseBuilder,
+}
+
+impl<R: BufRead> ResponseReader<'_, R> {
+    /// Feeds whatever the stream has buffered to the parser. False at end of stream.
+    fn pump(&mut self) -> io::Result<bool> {
+        let available = self.stream.fill_buf()?;
+        if available.is_empty() {
+            self.builder.apply(self.parser.finish()?);
+            return Ok(false);
+        }
+        let parsed = self.parser.feed(available)?;
+        self.stream.consume(parsed.consumed);
+        self.builder.apply(parsed.events);
+        Ok(true)
+    }
 
-        info!(target: "net::http", "Parsed response: {} Status {}", parts[0], status);
-        
-        Some(HttpResponse {
-            status,
-            headers,
-            body: body_part.to_vec(),
-        })
+    fn pump_until(&mut self, done: impl Fn(&ResponseBuilder) -> bool) -> io::Result<()> {
+        while !done(&self.builder) && self.pump()? {}
+        Ok(())
     }
 }
 
-pub fn build_get_request(host: &str, path: &str) -> Vec<u8> {
-    let request = format!(
-        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: YolofiBrowser/0.1\r\nConnection: close\r\n\r\n",
-        path, host
-    );
-    debug!(target: "net::http", "Built Request:\n{}", request.trim());
-    request.into_bytes()
+impl<R: BufRead> Read for ResponseReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.pump_until(ResponseBuilder::has_body_bytes)?;
+        let body = &mut self.builder.body;
+        let n = buf.len().min(body.len());
+        buf[..n].copy_from_slice(&body[..n]);
+        body.drain(..n);
+        Ok(n)
+    }
+}
+
+/// Undoes Content-Encoding on a body already held in full (HTTP/2 collects its DATA frames first).
+pub(crate) fn decode_body(
+    headers: &mut HeaderMap,
+    body: Vec<u8>,
+    limits: DecodeLimits,
+) -> Result<Vec<u8>, DecodeError> {
+    let Some(encoding) = headers.get_combined("Content-Encoding").filter(|_| !body.is_empty()) else {
+        return Ok(body);
+    };
+    let codings = content_coding::parse_codings(&encoding)?;
+    if codings.is_empty() {
+        return Ok(body);
+    }
+    let body = content_coding::decode(&body, &codings, limits)?;
+    // The headers now describe bytes we no longer hold.
+    headers.remove("Content-Encoding");
+    headers.remove("Content-Length");
+    Ok(body)
+}
+
+pub fn build_get_request(url: &Url) -> Vec<u8> {
+    let request = Identity::default()
+        .apply(RequestBuilder::get(url).header("Connection", "keep-alive"))
+        .build()
+        .expect("identity fields are valid");
+    debug!(target: "net::http", "Built Request: GET {} ({} fields)", request.path, request.headers.len());
+    request.serialize()
+}
+
+/// RFC 9110 section 9.2.2: methods a client may repeat when a connection fails mid-request.
+pub(crate) fn is_idempotent(method: &str) -> bool {
+    matches!(method, "GET" | "HEAD" | "OPTIONS" | "TRACE" | "PUT" | "DELETE")
+}
+
+/// Reads exactly one response, leaving any later bytes in `stream`.
+/// Also reports whether the connection may carry another request.
+pub fn read_response<R: BufRead>(stream: &mut R, request_method: &str) -> io::Result<(HttpResponse, bool)> {
+    read_response_with_limits(stream, request_method, &Limits::default())
+}
+
+/// Reads the status line and fields of one final response and nothing after them, so a
+/// protocol that takes over the connection (a 101) finds its first bytes still in `stream`.
+/// The body, if there is one, is not waited for.
+pub fn read_response_head<R: BufRead>(
+    stream: &mut R,
+    request_method: &str,
+    limits: ParseLimits,
+) -> io::Result<HttpResponse> {
+    let parser = ResponseParser::with_limits(request_method, limits);
+    let mut reader = ResponseReader { stream, parser, builder: ResponseBuilder::default() };
+    reader.pump_until(ResponseBuilder::has_final_head)?;
+    let (mut response, _) = reader.builder.build();
+    response.body.clear();
+    Ok(response)
+}
+
+pub fn read_response_with_limits<R: BufRead>(
+    stream: &mut R,
+    request_method: &str,
+    limits: &Limits,
+) -> io::Result<(HttpResponse, bool)> {
+    let parser = ResponseParser::with_limits(request_method, limits.response);
+    let mut reader = ResponseReader { stream, parser, builder: ResponseBuilder::default() };
+    reader.pump_until(ResponseBuilder::has_final_head)?;
+
+    // An empty body (HEAD, 204, 304) has nothing to decode, whatever the headers say.
+    reader.pump_until(ResponseBuilder::has_body_bytes)?;
+    let codings = match reader.builder.headers.get_combined("Content-Encoding") {
+        Some(encoding) if !reader.builder.body.is_empty() => {
+            content_coding::parse_codings(&encoding).map_err(io::Error::from)?
+        }
+        _ => Vec::new(),
+    };
+    if codings.is_empty() {
+        reader.pump_until(ResponseBuilder::is_complete)?;
+        return Ok(reader.builder.build());
+    }
+
+    let mut decoded = Vec::new();
+    DecodingReader::new(&mut reader, &codings, limits.decoded)?.read_to_end(&mut decoded)?;
+    // Bytes after the end of the coded stream are dropped, as `decode` would.
+    io::copy(&mut reader, &mut io::sink())?;
+    debug!(target: "net::http", "Decoded {:?} body to {} bytes while reading", codings, decoded.len());
+    let mut builder = reader.builder;
+    builder.body = decoded;
+    // The headers now describe bytes we no longer hold.
+    builder.headers.remove("Content-Encoding");
+    builder.headers.remove("Content-Length");
+    Ok(builder.build())
+}
+
+/// Writes a serialized request and reads one response back.
+pub fn send<T: Transport>(stream: &mut T, request: &[u8]) -> io::Result<HttpResponse> {
+    send_with_limits(stream, request, &Limits::default())
+}
+
+pub fn send_with_limits<T: Transport>(stream: &mut T, request: &[u8], limits: &Limits) -> io::Result<HttpResponse> {
+    stream.write_all(request)?;
+    stream.flush()?;
+
+    let method = request.split(|&b| b == b' ').next().unwrap_or(b"GET");
+    let method = String::from_utf8_lossy(method).into_owned();
+    let (mut response, _reusable) = read_response_with_limits(&mut BufReader::new(&mut *stream), &method, limits)?;
+    response.security = stream.security_info();
+    info!(target: "net::http", "Received {} body bytes from {}", response.body.len(), stream.peer());
+    Ok(response)
+}
+
+pub fn get<C: Connector>(connector: &C, url: &Url) -> io::Result<HttpResponse> {
+    let host = url.host().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "URL has no host"))?;
+    let port = url.port_or_default().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "URL has no port"))?;
+    let mut stream = connector.connect(&host.to_socket_host(), port)?;
+    send(&mut stream, &build_get_request(url))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_parser::ParseError;
+    use crate::transport::MemoryConnector;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_get_over_memory_connector() {
+        let connector = MemoryConnector::new(|host, port, mut server| {
+            assert_eq!((host, port), ("example.test", 80));
+            let mut request = Vec::new();
+            let mut buf = [0u8; 256];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = server.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            assert!(request.starts_with(b"GET /index.html HTTP/1.1\r\nHost: example.test\r\n"));
+            server.write_all(b"HTTP/1.1 200 OK\r\nServer: Memory\r\n\r\n<p>hi</p>").unwrap();
+        });
+
+        let response = get(&connector, &Url::parse("http://example.test/index.html").unwrap()).unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.headers.get("server"), Some("Memory"));
+        assert_eq!(response.body, b"<p>hi</p>");
+    }
+
+    #[test]
+    fn test_compressed_body_is_decoded() {
+        let connector = MemoryConnector::new(|_, _, mut server| {
+            let mut request = Vec::new();
+            let mut buf = [0u8; 256];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = server.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            let request = String::from_utf8(request).unwrap();
+            assert!(request.contains("Accept-Encoding: gzip, deflate, br, zstd\r\n"));
+
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(b"<p>compressed</p>").unwrap();
+            let body = encoder.finish().unwrap();
+            let head = format!("HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n", body.len());
+            server.write_all(head.as_bytes()).unwrap();
+            server.write_all(&body).unwrap();
+        });
+
+        let response = get(&connector, &Url::parse("http://example.test/").unwrap()).unwrap();
+        assert_eq!(response.body, b"<p>compressed</p>");
+        assert!(response.header("Content-Encoding").is_none());
+    }
+
+    #[test]
+    fn test_coded_body_is_decoded_as_it_arrives() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&vec![0u8; 16 << 20]).unwrap();
+        let bomb = encoder.finish().unwrap();
+        let mut wire = format!("HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n", bomb.len())
+            .into_bytes();
+        wire.extend_from_slice(&bomb);
+
+        let mut unread = &wire[..];
+        let mut limits = Limits::default();
+        limits.decoded.max_output = 1 << 20;
+        let mut stream = BufReader::with_capacity(1024, &mut unread);
+        let err = read_response_with_limits(&mut stream, "GET", &limits).unwrap_err();
+        assert!(matches!(err.get_ref().and_then(|e| e.downcast_ref()), Some(DecodeError::OutputTooLarge { .. })));
+        assert!(unread.len() > bomb.len() / 2, "the guard trips before the body is all read");
+    }
+
+    #[test]
+    fn test_read_response_honours_message_length() {
+        let mut wire: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello\
+HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3;x=y\r\nabc\r\n2\r\nde\r\n0\r\nX-T: 1\r\n\r\n\
+HTTP/1.1 304 Not Modified\r\nContent-Length: 99\r\nContent-Encoding: x-unknown\r\n\r\n\
+HTTP/1.1 200 OK\r\nConnection: close\r\n\r\ntail";
+
+        let (first, reusable) = read_response(&mut wire, "GET").unwrap();
+        assert_eq!((first.body.as_slice(), reusable), (&b"hello"[..], true));
+
+        let (second, reusable) = read_response(&mut wire, "GET").unwrap();
+        assert_eq!((second.body.as_slice(), reusable), (&b"abcde"[..], true));
+        assert_eq!(second.trailers.get("x-t"), Some("1"));
+        assert!(second.header("X-T").is_none());
+
+        let (third, _) = read_response(&mut wire, "GET").unwrap();
+        assert_eq!(third.status, 304);
+        assert!(third.body.is_empty());
+
+        let (last, reusable) = read_response(&mut wire, "GET").unwrap();
+        assert_eq!((last.body.as_slice(), reusable), (&b"tail"[..], false));
+
+        // An unknown coding only matters when there is a body to decode.
+        let head: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Encoding: x-unknown\r\nContent-Length: 4\r\n\r\n";
+        assert_eq!(read_response(&mut &head[..], "HEAD").unwrap().0.status, 200);
+        assert!(read_response(&mut &[head, b"body"].concat()[..], "GET").is_err());
+        let mut headers: HeaderMap = [("Content-Encoding", "x-unknown")].into_iter().collect();
+        assert!(decode_body(&mut headers, Vec::new(), DecodeLimits::default()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_folded_field_appears_once() {
+        let wire = b"HTTP/1.1 200 OK\r\nX-A: 1\r\n  folded\r\nSet-Cookie: a=1;\r\n\tPath=/\r\n\
+Content-Length: 0\r\n\r\n";
+        let response = HttpResponse::parse(wire).unwrap();
+        assert_eq!(response.headers.get_all("X-A").collect::<Vec<_>>(), ["1 folded"]);
+        assert_eq!(response.headers.set_cookies(), ["a=1; Path=/"]);
+        assert_eq!(response.headers.len(), 3);
+    }
+
+    #[test]
+    fn test_parse_with_limits_rejects_hostile_responses() {
+        let typed = |err: io::Error| err.into_inner().map(|inner| inner.to_string()).unwrap_or_default();
+        let mut limits = Limits::default();
+        limits.response.max_headers = 2;
+        limits.response.max_body_size = 2048;
+        limits.decoded.max_output = 4096;
+
+        let many = b"HTTP/1.1 200 OK\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n";
+        assert!(HttpResponse::parse(many).is_ok());
+        let err = HttpResponse::parse_with_limits(many, &limits).unwrap_err();
+        assert_eq!(typed(err), ParseError::TooManyHeaders { limit: 2 }.to_string());
+
+        let long = [b"HTTP/1.1 200 OK\r\n\r\n".as_slice(), &[b'x'; 2049]].concat();
+        let err = HttpResponse::parse_with_limits(&long, &limits).unwrap_err();
+        assert_eq!(typed(err), ParseError::BodyTooLarge { limit: 2048 }.to_string());
+
+        // 1 MiB of zeros compresses to about 1 KiB: within the body limit, far past the decoded one.
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(&vec![0u8; 1 << 20]).unwrap();
+        let bomb = encoder.finish().unwrap();
+        assert!(bomb.len() <= 2048, "{}", bomb.len());
+        let head = format!("HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n", bomb.len());
+        let err = HttpResponse::parse_with_limits(&[head.as_bytes(), &bomb].concat(), &limits).unwrap_err();
+        assert_eq!(typed(err), DecodeError::OutputTooLarge { limit: 4096 }.to_string());
+    }
 }
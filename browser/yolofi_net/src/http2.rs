@@ -0,0 +1,1115 @@
+// HTTP/2 Client
+// RFC 9113 over any Transport, used when TLS ALPN settles on "h2". One connection
+// carries many streams: `send_request` opens a stream and returns its id, and
+// `read_response` drives the connection until that stream is complete, buffering
+// frames that belong to the others. Requests and responses are the same
+// `HttpRequest` / `HttpResponse` as on the HTTP/1.1 path, content codings removed.
+//
+// Flow control: we advertise large receive windows and top them up once half has
+// been consumed; sending waits for the peer's WINDOW_UPDATE. After GOAWAY no new
+// streams are opened, and streams above its last-stream-id fail with an error that
+// says the request is safe to retry. Server push is disabled in our SETTINGS.
+//
+// `Limits` apply as on HTTP/1.1: a stream whose DATA passes the body limit is reset
+// with CANCEL, and content codings are removed within the decoded-size limits.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read, Write};
+use tracing::{debug, info, warn};
+
+use crate::content_coding::DecodeError;
+use crate::headers::HeaderMap;
+use crate::hpack::{self, Decoder, Encoder};
+use crate::http::{self, HttpRequest, HttpResponse};
+use crate::limits::Limits;
+use crate::transport::Transport;
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+const FRAME_HEADER_LEN: usize = 9;
+const DEFAULT_MAX_FRAME_SIZE: usize = 16_384;
+const MAX_FRAME_SIZE_LIMIT: usize = 16_777_215;
+const DEFAULT_WINDOW: i64 = 65_535;
+const MAX_WINDOW: i64 = (1 << 31) - 1;
+
+/// Receive windows we advertise, per stream and for the whole connection.
+pub const STREAM_WINDOW: u32 = 1 << 20;
+pub const CONNECTION_WINDOW: u32 = 1 << 24;
+/// Our SETTINGS_MAX_HEADER_LIST_SIZE.
+pub const MAX_HEADER_LIST_SIZE: u32 = 256 * 1024;
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_ACK: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+const FLAG_PADDED: u8 = 0x8;
+const FLAG_PRIORITY: u8 = 0x20;
+
+const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+/// Fields that only make sense for one HTTP/1.1 connection (RFC 9113 section 8.2.2).
+const CONNECTION_SPECIFIC: [&str; 6] = ["connection", "keep-alive", "proxy-connection", "transfer-encoding", "upgrade", "host"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    NoError,
+    ProtocolError,
+    InternalError,
+    FlowControlError,
+    SettingsTimeout,
+    StreamClosed,
+    FrameSizeError,
+    RefusedStream,
+    Cancel,
+    CompressionError,
+    ConnectError,
+    EnhanceYourCalm,
+    InadequateSecurity,
+    Http11Required,
+    Unknown(u32),
+}
+
+const ERROR_CODES: [ErrorCode; 14] = [
+    ErrorCode::NoError,
+    ErrorCode::ProtocolError,
+    ErrorCode::InternalError,
+    ErrorCode::FlowControlError,
+    ErrorCode::SettingsTimeout,
+    ErrorCode::StreamClosed,
+    ErrorCode::FrameSizeError,
+    ErrorCode::RefusedStream,
+    ErrorCode::Cancel,
+    ErrorCode::CompressionError,
+    ErrorCode::ConnectError,
+    ErrorCode::EnhanceYourCalm,
+    ErrorCode::InadequateSecurity,
+    ErrorCode::Http11Required,
+];
+
+impl ErrorCode {
+    fn from_u32(code: u32) -> Self {
+        ERROR_CODES.get(code as usize).copied().unwrap_or(ErrorCode::Unknown(code))
+    }
+
+    fn to_u32(self) -> u32 {
+        match self {
+            ErrorCode::Unknown(code) => code,
+            known => ERROR_CODES.iter().position(|c| *c == known).expect("listed above") as u32,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Http2Error {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("connection error {code:?}: {reason}")]
+    Connection { code: ErrorCode, reason: String },
+    #[error("stream {stream} reset: {code:?}")]
+    StreamReset { stream: u32, code: ErrorCode },
+    #[error("connection is going away ({code:?}, last stream {last_stream_id})")]
+    GoAway { last_stream_id: u32, code: ErrorCode },
+    #[error("malformed response on stream {stream}: {reason}")]
+    Malformed { stream: u32, reason: String },
+    #[error("body on stream {stream} exceeds the {limit} byte limit")]
+    BodyTooLarge { stream: u32, limit: usize },
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+}
+
+impl Http2Error {
+    /// The server did not process the request, so it may be sent again on a new connection.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Http2Error::GoAway { .. } | Http2Error::StreamReset { code: ErrorCode::RefusedStream, .. })
+    }
+}
+
+fn connection_error(code: ErrorCode, reason: impl Into<String>) -> Http2Error {
+    Http2Error::Connection { code, reason: reason.into() }
+}
+
+/// Why a stream ended without a response. Kept until `read_response` collects it.
+#[derive(Debug, Clone)]
+enum StreamFailure {
+    Reset(ErrorCode),
+    GoAway { last_stream_id: u32, code: ErrorCode },
+    Malformed(String),
+    TooLarge(usize),
+}
+
+impl StreamFailure {
+    fn into_error(self, stream: u32) -> Http2Error {
+        match self {
+            StreamFailure::Reset(code) => Http2Error::StreamReset { stream, code },
+            StreamFailure::GoAway { last_stream_id, code } => Http2Error::GoAway { last_stream_id, code },
+            StreamFailure::Malformed(reason) => Http2Error::Malformed { stream, reason },
+            StreamFailure::TooLarge(limit) => Http2Error::BodyTooLarge { stream, limit },
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Frame {
+    kind: u8,
+    flags: u8,
+    stream: u32,
+    payload: Vec<u8>,
+}
+
+fn read_frame<R: Read>(reader: &mut R, max_payload: usize) -> Result<Frame, Http2Error> {
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    reader.read_exact(&mut header)?;
+    let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+    if length > max_payload {
+        return Err(connection_error(ErrorCode::FrameSizeError, format!("{}-byte frame", length)));
+    }
+    let mut payload = vec![0u8; length];
+    reader.read_exact(&mut payload)?;
+    let stream = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7FFF_FFFF;
+    Ok(Frame { kind: header[3], flags: header[4], stream, payload })
+}
+
+fn write_frame<W: Write>(writer: &mut W, kind: u8, flags: u8, stream: u32, payload: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+    frame.push(kind);
+    frame.push(flags);
+    frame.extend_from_slice(&stream.to_be_bytes());
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame)
+}
+
+/// Removes the Pad Length byte and trailing padding of DATA and HEADERS.
+fn strip_padding(flags: u8, payload: &[u8]) -> Result<&[u8], Http2Error> {
+    if flags & FLAG_PADDED == 0 {
+        return Ok(payload);
+    }
+    let (&pad, rest) = payload.split_first().ok_or_else(|| connection_error(ErrorCode::FrameSizeError, "missing pad length"))?;
+    if pad as usize > rest.len() {
+        return Err(connection_error(ErrorCode::ProtocolError, "padding exceeds frame"));
+    }
+    Ok(&rest[..rest.len() - pad as usize])
+}
+
+#[derive(Debug)]
+struct Stream {
+    send_window: i64,
+    /// Received bytes not yet returned to the peer with WINDOW_UPDATE.
+    unacked: u32,
+    head_request: bool,
+    status: Option<u16>,
+    headers: HeaderMap,
+    body: Vec<u8>,
+    trailers: HeaderMap,
+    /// The peer sent END_STREAM (or the stream failed).
+    remote_closed: bool,
+    failure: Option<StreamFailure>,
+}
+
+/// A client HTTP/2 connection.
+pub struct Http2Connection<T: Transport> {
+    io: T,
+    scheme: String,
+    limits: Limits,
+    encoder: Encoder,
+    decoder: Decoder,
+    streams: HashMap<u32, Stream>,
+    /// Streams we reset. Frames the peer sent before it saw RST_STREAM are dropped (section 5.4.2).
+    reset: HashSet<u32>,
+    next_stream_id: u32,
+    max_frame_size: usize,
+    max_concurrent_streams: usize,
+    initial_send_window: i64,
+    send_window: i64,
+    unacked: u32,
+    /// A header block split over HEADERS and CONTINUATION: (stream, END_STREAM, fragments).
+    continuation: Option<(u32, bool, Vec<u8>)>,
+    goaway: Option<(u32, ErrorCode)>,
+    failed: Option<(ErrorCode, String)>,
+}
+
+impl<T: Transport> Http2Connection<T> {
+    /// Sends the client preface and our SETTINGS, then waits for the server's SETTINGS.
+    /// `scheme` is sent as `:scheme` on every request ("https" behind TLS).
+    pub fn handshake(io: T, scheme: &str) -> Result<Self, Http2Error> {
+        Self::handshake_with_limits(io, scheme, &Limits::default())
+    }
+
+    /// `handshake`, with response bodies and their decoding capped by `limits`.
+    pub fn handshake_with_limits(io: T, scheme: &str, limits: &Limits) -> Result<Self, Http2Error> {
+        let mut decoder = Decoder::new(hpack::DEFAULT_TABLE_SIZE);
+        decoder.set_max_header_list_size(MAX_HEADER_LIST_SIZE as usize);
+        let mut connection = Self {
+            io,
+            scheme: scheme.to_string(),
+            limits: *limits,
+            encoder: Encoder::new(),
+            decoder,
+            streams: HashMap::new(),
+            reset: HashSet::new(),
+            next_stream_id: 1,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            max_concurrent_streams: usize::MAX,
+            initial_send_window: DEFAULT_WINDOW,
+            send_window: DEFAULT_WINDOW,
+            unacked: 0,
+            continuation: None,
+            goaway: None,
+            failed: None,
+        };
+
+        let mut settings = Vec::new();
+        for (id, value) in [
+            (SETTINGS_ENABLE_PUSH, 0),
+            (SETTINGS_INITIAL_WINDOW_SIZE, STREAM_WINDOW),
+            (SETTINGS_MAX_HEADER_LIST_SIZE, MAX_HEADER_LIST_SIZE),
+        ] {
+            settings.extend_from_slice(&id.to_be_bytes());
+            settings.extend_from_slice(&value.to_be_bytes());
+        }
+        let mut preface = PREFACE.to_vec();
+        write_frame(&mut preface, SETTINGS, 0, 0, &settings)?;
+        write_frame(&mut preface, WINDOW_UPDATE, 0, 0, &(CONNECTION_WINDOW - DEFAULT_WINDOW as u32).to_be_bytes())?;
+        connection.io.write_all(&preface)?;
+        connection.io.flush()?;
+
+        // The server preface is a SETTINGS frame; anything else is not HTTP/2.
+        let frame = read_frame(&mut connection.io, DEFAULT_MAX_FRAME_SIZE)?;
+        if frame.kind != SETTINGS || frame.flags & FLAG_ACK != 0 {
+            return Err(connection_error(ErrorCode::ProtocolError, "server preface is not SETTINGS"));
+        }
+        connection.process(frame)?;
+        info!(target: "net::http2", "HTTP/2 connection to {} established", connection.io.peer());
+        Ok(connection)
+    }
+
+    /// Whether new requests may be sent on this connection.
+    pub fn is_usable(&self) -> bool {
+        self.goaway.is_none() && self.failed.is_none() && self.next_stream_id < (1 << 31)
+    }
+
+    /// Streams still waiting for the end of their response.
+    pub fn active_streams(&self) -> usize {
+        self.streams.values().filter(|s| !s.remote_closed).count()
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.io
+    }
+
+    /// Sends one request and waits for its response.
+    pub fn request(&mut self, request: &HttpRequest) -> Result<HttpResponse, Http2Error> {
+        let stream = self.send_request(request)?;
+        self.read_response(stream)
+    }
+
+    /// Opens a stream for `request` and sends it, body included. Returns the stream id.
+    pub fn send_request(&mut self, request: &HttpRequest) -> Result<u32, Http2Error> {
+        self.check_usable()?;
+        // A limit of 0 holds back even the first stream until the peer raises it.
+        while self.active_streams() >= self.max_concurrent_streams {
+            self.read_and_process()?;
+            self.check_usable()?;
+        }
+
+        let id = self.next_stream_id;
+        self.next_stream_id += 2;
+        let fields = request_fields(request, &self.scheme);
+        let block = self.encoder.encode(fields.iter().map(|(n, v)| (n.as_str(), v.as_str())));
+        self.streams.insert(
+            id,
+            Stream {
+                send_window: self.initial_send_window,
+                unacked: 0,
+                head_request: request.method.eq_ignore_ascii_case("HEAD"),
+                status: None,
+                headers: HeaderMap::new(),
+                body: Vec::new(),
+                trailers: HeaderMap::new(),
+                remote_closed: false,
+                failure: None,
+            },
+        );
+        debug!(target: "net::http2", "Stream {}: {} {}", id, request.method, request.path);
+
+        // HEADERS then CONTINUATION frames, back to back.
+        let mut chunks = block.chunks(self.max_frame_size).peekable();
+        let mut kind = HEADERS;
+        let end_stream = if request.body.is_empty() { FLAG_END_STREAM } else { 0 };
+        let mut frames = Vec::new();
+        loop {
+            let chunk = chunks.next().unwrap_or(&[]);
+            let last = chunks.peek().is_none();
+            let mut flags = if last { FLAG_END_HEADERS } else { 0 };
+            if kind == HEADERS {
+                flags |= end_stream;
+            }
+            write_frame(&mut frames, kind, flags, id, chunk)?;
+            if last {
+                break;
+            }
+            kind = CONTINUATION;
+        }
+        self.io.write_all(&frames)?;
+        self.io.flush()?;
+
+        if !request.body.is_empty() {
+            self.send_body(id, &request.body)?;
+        }
+        Ok(id)
+    }
+
+    /// Sends DATA within the peer's flow-control windows, reading frames while blocked.
+    fn send_body(&mut self, id: u32, body: &[u8]) -> Result<(), Http2Error> {
+        let mut rest = body;
+        while !rest.is_empty() {
+            let stream = self.streams.get(&id).expect("opened by send_request");
+            if stream.failure.is_some() {
+                return Ok(());
+            }
+            if stream.remote_closed {
+                // The server answered early; it does not want the rest.
+                write_frame(&mut self.io, RST_STREAM, 0, id, &ErrorCode::Cancel.to_u32().to_be_bytes())?;
+                self.reset.insert(id);
+                return Ok(());
+            }
+            let window = self.send_window.min(stream.send_window).min(self.max_frame_size as i64);
+            if window <= 0 {
+                self.io.flush()?;
+                self.read_and_process()?;
+                continue;
+            }
+            let n = rest.len().min(window as usize);
+            let flags = if n == rest.len() { FLAG_END_STREAM } else { 0 };
+            write_frame(&mut self.io, DATA, flags, id, &rest[..n])?;
+            self.send_window -= n as i64;
+            self.streams.get_mut(&id).expect("checked above").send_window -= n as i64;
+            rest = &rest[n..];
+        }
+        self.io.flush()?;
+        Ok(())
+    }
+
+    /// Drives the connection until stream `id` has its complete response.
+    pub fn read_response(&mut self, id: u32) -> Result<HttpResponse, Http2Error> {
+        loop {
+            let stream = self.streams.get(&id).ok_or_else(|| Http2Error::Malformed {
+                stream: id,
+                reason: "no such stream".to_string(),
+            })?;
+            if let Some(failure) = stream.failure.clone() {
+                self.streams.remove(&id);
+                return Err(failure.into_error(id));
+            }
+            if stream.remote_closed {
+                break;
+            }
+            if let Some((code, reason)) = &self.failed {
+                return Err(connection_error(*code, reason.clone()));
+            }
+            self.read_and_process()?;
+        }
+
+        let mut stream = self.streams.remove(&id).expect("checked above");
+        let status = stream.status.expect("END_STREAM only counts after the response head");
+        let declared = stream.headers.content_length();
+        if let Some(length) = declared.filter(|_| !stream.head_request && status != 304) {
+            if length != stream.body.len() as u64 {
+                return Err(Http2Error::Malformed {
+                    stream: id,
+                    reason: format!("content-length {} but {} bytes of DATA", length, stream.body.len()),
+                });
+            }
+        }
+        let body = http::decode_body(&mut stream.headers, stream.body, self.limits.decoded)?;
+        info!(target: "net::http2", "Stream {}: {} with {} body bytes", id, status, body.len());
+        Ok(HttpResponse {
+            status,
+            headers: stream.headers,
+            body,
+            trailers: stream.trailers,
+            security: self.io.security_info(),
+        })
+    }
+
+    /// Sends GOAWAY and closes the connection.
+    pub fn close(mut self) -> io::Result<()> {
+        self.send_goaway(ErrorCode::NoError, "")?;
+        self.io.flush()
+    }
+
+    fn check_usable(&self) -> Result<(), Http2Error> {
+        if let Some((code, reason)) = &self.failed {
+            return Err(connection_error(*code, reason.clone()));
+        }
+        if let Some((last_stream_id, code)) = self.goaway {
+            return Err(Http2Error::GoAway { last_stream_id, code });
+        }
+        if !self.is_usable() {
+            return Err(Http2Error::GoAway { last_stream_id: self.next_stream_id - 2, code: ErrorCode::NoError });
+        }
+        Ok(())
+    }
+
+    fn send_goaway(&mut self, code: ErrorCode, reason: &str) -> io::Result<()> {
+        // We never accept streams, so the last processed stream is always 0.
+        let mut payload = 0u32.to_be_bytes().to_vec();
+        payload.extend_from_slice(&code.to_u32().to_be_bytes());
+        payload.extend_from_slice(reason.as_bytes());
+        write_frame(&mut self.io, GOAWAY, 0, 0, &payload)
+    }
+
+    /// Reads and handles one frame. Connection errors are reported to the peer with GOAWAY.
+    fn read_and_process(&mut self) -> Result<(), Http2Error> {
+        let result = read_frame(&mut self.io, DEFAULT_MAX_FRAME_SIZE).and_then(|frame| self.process(frame));
+        match result {
+            Err(Http2Error::Connection { code, reason }) => {
+                warn!(target: "net::http2", "Connection error {:?}: {}", code, reason);
+                let _ = self.send_goaway(code, &reason).and_then(|_| self.io.flush());
+                self.failed = Some((code, reason.clone()));
+                Err(Http2Error::Connection { code, reason })
+            }
+            Err(Http2Error::Io(err)) => {
+                self.failed = Some((ErrorCode::InternalError, err.to_string()));
+                Err(Http2Error::Io(err))
+            }
+            other => {
+                self.io.flush()?;
+                other
+            }
+        }
+    }
+
+    fn process(&mut self, frame: Frame) -> Result<(), Http2Error> {
+        if let Some((stream, _, _)) = &self.continuation {
+            if frame.kind != CONTINUATION || frame.stream != *stream {
+                return Err(connection_error(ErrorCode::ProtocolError, "header block interrupted"));
+            }
+        }
+        match frame.kind {
+            DATA => self.on_data(frame),
+            HEADERS => {
+                if frame.stream == 0 {
+                    return Err(connection_error(ErrorCode::ProtocolError, "HEADERS on stream 0"));
+                }
+                let mut fragment = strip_padding(frame.flags, &frame.payload)?;
+                if frame.flags & FLAG_PRIORITY != 0 {
+                    fragment = fragment
+                        .get(5..)
+                        .ok_or_else(|| connection_error(ErrorCode::FrameSizeError, "short HEADERS priority"))?;
+                }
+                let end_stream = frame.flags & FLAG_END_STREAM != 0;
+                if frame.flags & FLAG_END_HEADERS != 0 {
+                    self.on_header_block(frame.stream, end_stream, fragment.to_vec())
+                } else {
+                    self.continuation = Some((frame.stream, end_stream, fragment.to_vec()));
+                    Ok(())
+                }
+            }
+            CONTINUATION => {
+                let Some((stream, end_stream, mut block)) = self.continuation.take() else {
+                    return Err(connection_error(ErrorCode::ProtocolError, "unexpected CONTINUATION"));
+                };
+                block.extend_from_slice(&frame.payload);
+                if block.len() > MAX_HEADER_LIST_SIZE as usize {
+                    return Err(connection_error(ErrorCode::EnhanceYourCalm, "header block too large"));
+                }
+                if frame.flags & FLAG_END_HEADERS != 0 {
+                    self.on_header_block(stream, end_stream, block)
+                } else {
+                    self.continuation = Some((stream, end_stream, block));
+                    Ok(())
+                }
+            }
+            PRIORITY => Ok(()),
+            RST_STREAM => {
+                let code = four_bytes(&frame)?;
+                if frame.stream == 0 {
+                    return Err(connection_error(ErrorCode::ProtocolError, "RST_STREAM on stream 0"));
+                }
+                if let Some(stream) = self.streams.get_mut(&frame.stream) {
+                    let code = ErrorCode::from_u32(code);
+                    debug!(target: "net::http2", "Stream {} reset by peer: {:?}", frame.stream, code);
+                    stream.remote_closed = true;
+                    stream.failure.get_or_insert(StreamFailure::Reset(code));
+                }
+                Ok(())
+            }
+            SETTINGS => self.on_settings(frame),
+            PUSH_PROMISE => Err(connection_error(ErrorCode::ProtocolError, "PUSH_PROMISE with push disabled")),
+            PING => {
+                if frame.payload.len() != 8 {
+                    return Err(connection_error(ErrorCode::FrameSizeError, "PING must be 8 bytes"));
+                }
+                if frame.stream != 0 {
+                    return Err(connection_error(ErrorCode::ProtocolError, "PING on a stream"));
+                }
+                if frame.flags & FLAG_ACK == 0 {
+                    write_frame(&mut self.io, PING, FLAG_ACK, 0, &frame.payload)?;
+                }
+                Ok(())
+            }
+            GOAWAY => {
+                if frame.stream != 0 || frame.payload.len() < 8 {
+                    return Err(connection_error(ErrorCode::ProtocolError, "malformed GOAWAY"));
+                }
+                let p = &frame.payload;
+                let last_stream_id = u32::from_be_bytes([p[0], p[1], p[2], p[3]]) & 0x7FFF_FFFF;
+                let code = ErrorCode::from_u32(u32::from_be_bytes([p[4], p[5], p[6], p[7]]));
+                info!(
+                    target: "net::http2",
+                    "GOAWAY from {}: {:?}, last stream {} {:?}",
+                    self.io.peer(),
+                    code,
+                    last_stream_id,
+                    String::from_utf8_lossy(&p[8..])
+                );
+                self.goaway = Some((last_stream_id, code));
+                for (_, stream) in self.streams.iter_mut().filter(|(id, _)| **id > last_stream_id) {
+                    stream.remote_closed = true;
+                    stream.failure.get_or_insert(StreamFailure::GoAway { last_stream_id, code });
+                }
+                Ok(())
+            }
+            WINDOW_UPDATE => {
+                let increment = (four_bytes(&frame)? & 0x7FFF_FFFF) as i64;
+                if frame.stream == 0 {
+                    if increment == 0 {
+                        return Err(connection_error(ErrorCode::ProtocolError, "zero WINDOW_UPDATE"));
+                    }
+                    self.send_window += increment;
+                    if self.send_window > MAX_WINDOW {
+                        return Err(connection_error(ErrorCode::FlowControlError, "connection window overflow"));
+                    }
+                    return Ok(());
+                }
+                let overflow = match self.streams.get_mut(&frame.stream) {
+                    Some(stream) if !stream.remote_closed => {
+                        stream.send_window += increment;
+                        increment == 0 || stream.send_window > MAX_WINDOW
+                    }
+                    _ => false,
+                };
+                if overflow {
+                    let code = if increment == 0 { ErrorCode::ProtocolError } else { ErrorCode::FlowControlError };
+                    self.reset_stream(frame.stream, code, StreamFailure::Reset(code))?;
+                }
+                Ok(())
+            }
+            // Unknown frame types are ignored (section 4.1).
+            _ => Ok(()),
+        }
+    }
+
+    fn on_data(&mut self, frame: Frame) -> Result<(), Http2Error> {
+        if frame.stream == 0 {
+            return Err(connection_error(ErrorCode::ProtocolError, "DATA on stream 0"));
+        }
+        // Flow control counts the whole payload, padding included.
+        let length = frame.payload.len() as u32;
+        if self.unacked + length > CONNECTION_WINDOW {
+            return Err(connection_error(ErrorCode::FlowControlError, "peer overran the connection window"));
+        }
+        self.unacked += length;
+        if self.unacked >= CONNECTION_WINDOW / 2 {
+            write_frame(&mut self.io, WINDOW_UPDATE, 0, 0, &self.unacked.to_be_bytes())?;
+            self.unacked = 0;
+        }
+        let data = strip_padding(frame.flags, &frame.payload)?;
+        if self.reset.contains(&frame.stream) {
+            return Ok(());
+        }
+
+        let Some(stream) = self.streams.get_mut(&frame.stream) else {
+            return self.on_unknown_stream(frame.stream);
+        };
+        let problem = if stream.remote_closed {
+            Some(ErrorCode::StreamClosed)
+        } else if stream.unacked + length > STREAM_WINDOW {
+            Some(ErrorCode::FlowControlError)
+        } else if stream.status.is_none() {
+            Some(ErrorCode::ProtocolError)
+        } else {
+            None
+        };
+        if let Some(code) = problem {
+            let failure = StreamFailure::Malformed(format!("unexpected DATA ({:?})", code));
+            return self.reset_stream(frame.stream, code, failure);
+        }
+        let limit = self.limits.response.max_body_size;
+        if stream.body.len().saturating_add(data.len()) > limit {
+            warn!(target: "net::http2", "Stream {} body would exceed {} bytes", frame.stream, limit);
+            return self.reset_stream(frame.stream, ErrorCode::Cancel, StreamFailure::TooLarge(limit));
+        }
+
+        stream.body.extend_from_slice(data);
+        stream.remote_closed = frame.flags & FLAG_END_STREAM != 0;
+        stream.unacked += length;
+        if !stream.remote_closed && stream.unacked >= STREAM_WINDOW / 2 {
+            let increment = std::mem::take(&mut stream.unacked);
+            write_frame(&mut self.io, WINDOW_UPDATE, 0, frame.stream, &increment.to_be_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn on_header_block(&mut self, id: u32, end_stream: bool, block: Vec<u8>) -> Result<(), Http2Error> {
+        // Decode even for streams we ignore: the table must stay in sync.
+        let fields = self
+            .decoder
+            .decode(&block)
+            .map_err(|e| connection_error(ErrorCode::CompressionError, e.to_string()))?;
+        if self.reset.contains(&id) {
+            return Ok(());
+        }
+        let Some(stream) = self.streams.get_mut(&id) else {
+            return self.on_unknown_stream(id);
+        };
+        if stream.remote_closed {
+            return self.reset_stream(id, ErrorCode::StreamClosed, StreamFailure::Malformed("HEADERS after END_STREAM".into()));
+        }
+
+        let result = if stream.status.is_none() {
+            parse_response_head(fields).and_then(|(status, headers)| match status {
+                // Interim responses are dropped; the final one follows.
+                100..=199 if end_stream => Err("interim response ends the stream".to_string()),
+                100..=199 => Ok(()),
+                _ => {
+                    stream.status = Some(status);
+                    stream.headers = headers;
+                    Ok(())
+                }
+            })
+        } else if end_stream {
+            parse_trailers(fields).map(|trailers| stream.trailers = trailers)
+        } else {
+            Err("second header block without END_STREAM".to_string())
+        };
+        match result {
+            Ok(()) => {
+                stream.remote_closed = end_stream;
+                Ok(())
+            }
+            Err(reason) => self.reset_stream(id, ErrorCode::ProtocolError, StreamFailure::Malformed(reason)),
+        }
+    }
+
+    /// Frames for streams we never opened are a connection error; for finished ones, a stream error.
+    fn on_unknown_stream(&mut self, id: u32) -> Result<(), Http2Error> {
+        if id.is_multiple_of(2) || id >= self.next_stream_id {
+            return Err(connection_error(ErrorCode::ProtocolError, format!("frame on idle stream {}", id)));
+        }
+        write_frame(&mut self.io, RST_STREAM, 0, id, &ErrorCode::StreamClosed.to_u32().to_be_bytes())?;
+        Ok(())
+    }
+
+    fn reset_stream(&mut self, id: u32, code: ErrorCode, failure: StreamFailure) -> Result<(), Http2Error> {
+        warn!(target: "net::http2", "Resetting stream {}: {:?}", id, failure);
+        write_frame(&mut self.io, RST_STREAM, 0, id, &code.to_u32().to_be_bytes())?;
+        self.reset.insert(id);
+        if let Some(stream) = self.streams.get_mut(&id) {
+            stream.remote_closed = true;
+            stream.failure.get_or_insert(failure);
+        }
+        Ok(())
+    }
+
+    fn on_settings(&mut self, frame: Frame) -> Result<(), Http2Error> {
+        if frame.stream != 0 {
+            return Err(connection_error(ErrorCode::ProtocolError, "SETTINGS on a stream"));
+        }
+        if frame.flags & FLAG_ACK != 0 {
+            return match frame.payload.is_empty() {
+                true => Ok(()),
+                false => Err(connection_error(ErrorCode::FrameSizeError, "SETTINGS ACK with payload")),
+            };
+        }
+        if !frame.payload.len().is_multiple_of(6) {
+            return Err(connection_error(ErrorCode::FrameSizeError, "SETTINGS length not a multiple of 6"));
+        }
+        for setting in frame.payload.chunks(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+            match id {
+                SETTINGS_HEADER_TABLE_SIZE => self.encoder.set_max_table_size((value as usize).min(hpack::DEFAULT_TABLE_SIZE)),
+                SETTINGS_ENABLE_PUSH if value != 0 => {
+                    return Err(connection_error(ErrorCode::ProtocolError, "server sent ENABLE_PUSH"));
+                }
+                SETTINGS_MAX_CONCURRENT_STREAMS => self.max_concurrent_streams = value as usize,
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    if value as i64 > MAX_WINDOW {
+                        return Err(connection_error(ErrorCode::FlowControlError, "initial window too large"));
+                    }
+                    // Changes apply to every open stream (section 6.9.2), and may not overflow any of them.
+                    let delta = value as i64 - self.initial_send_window;
+                    if self.streams.values().any(|stream| stream.send_window + delta > MAX_WINDOW) {
+                        return Err(connection_error(ErrorCode::FlowControlError, "stream window overflow"));
+                    }
+                    self.initial_send_window = value as i64;
+                    for stream in self.streams.values_mut() {
+                        stream.send_window += delta;
+                    }
+                }
+                SETTINGS_MAX_FRAME_SIZE => {
+                    if !(DEFAULT_MAX_FRAME_SIZE..=MAX_FRAME_SIZE_LIMIT).contains(&(value as usize)) {
+                        return Err(connection_error(ErrorCode::ProtocolError, "invalid MAX_FRAME_SIZE"));
+                    }
+                    self.max_frame_size = value as usize;
+                }
+                _ => {}
+            }
+        }
+        debug!(target: "net::http2", "Applied peer SETTINGS ({} entries)", frame.payload.len() / 6);
+        write_frame(&mut self.io, SETTINGS, FLAG_ACK, 0, &[])?;
+        Ok(())
+    }
+}
+
+fn four_bytes(frame: &Frame) -> Result<u32, Http2Error> {
+    let bytes: [u8; 4] = frame.payload.as_slice().try_into().map_err(|_| {
+        connection_error(ErrorCode::FrameSizeError, format!("frame type {} must be 4 bytes", frame.kind))
+    })?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+/// Pseudo-header fields first, then the request's own fields, lowercased and
+/// without HTTP/1.1 connection management.
+fn request_fields(request: &HttpRequest, scheme: &str) -> Vec<(String, String)> {
+    let mut fields = vec![
+        (":method".to_string(), request.method.to_ascii_uppercase()),
+        (":scheme".to_string(), scheme.to_string()),
+    ];
+    if let Some(host) = request.headers.get("Host") {
+        fields.push((":authority".to_string(), host.to_string()));
+    }
+    fields.push((":path".to_string(), request.path.clone()));
+    for (name, value) in request.headers.iter() {
+        let name = name.to_ascii_lowercase();
+        let is_te_other_than_trailers = name == "te" && !value.eq_ignore_ascii_case("trailers");
+        if !CONNECTION_SPECIFIC.contains(&name.as_str()) && !is_te_other_than_trailers {
+            fields.push((name, value.to_string()));
+        }
+    }
+    fields
+}
+
+/// Checks a response header block (RFC 9113 section 8.3.2) and splits off `:status`.
+fn parse_response_head(fields: Vec<(String, String)>) -> Result<(u16, HeaderMap), String> {
+    let mut status = None;
+    let mut headers = HeaderMap::new();
+    for (name, value) in fields {
+        if let Some(pseudo) = name.strip_prefix(':') {
+            if pseudo != "status" || status.is_some() || !headers.is_empty() {
+                return Err(format!("unexpected pseudo-header {}", name));
+            }
+            status = Some(value.parse::<u16>().ok().filter(|s| (100..=999).contains(s)).ok_or("invalid :status")?);
+        } else {
+            check_field_name(&name)?;
+            headers.append(name, value);
+        }
+    }
+    Ok((status.ok_or("missing :status")?, headers))
+}
+
+fn parse_trailers(fields: Vec<(String, String)>) -> Result<HeaderMap, String> {
+    let mut trailers = HeaderMap::new();
+    for (name, value) in fields {
+        if name.starts_with(':') {
+            return Err(format!("pseudo-header {} in trailers", name));
+        }
+        check_field_name(&name)?;
+        trailers.append(name, value);
+    }
+    Ok(trailers)
+}
+
+fn check_field_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.bytes().any(|b| b.is_ascii_uppercase() || b <= b' ' || b == b':' || b >= 0x7F) {
+        return Err(format!("invalid field name {:?}", name));
+    }
+    if CONNECTION_SPECIFIC[..5].contains(&name) {
+        return Err(format!("connection-specific field {}", name));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::pipe;
+
+    /// The server side of a connection, frame by frame, for tests.
+    pub(crate) struct TestPeer<S: Read + Write> {
+        io: S,
+        encoder: Encoder,
+        decoder: Decoder,
+    }
+
+    /// One request as the server saw it.
+    pub(crate) struct ReceivedRequest {
+        pub stream: u32,
+        pub fields: Vec<(String, String)>,
+        pub body: Vec<u8>,
+    }
+
+    impl ReceivedRequest {
+        pub fn field(&self, name: &str) -> Option<&str> {
+            self.fields.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+        }
+    }
+
+    impl<S: Read + Write> TestPeer<S> {
+        /// Reads the client preface and answers with our own SETTINGS.
+        pub fn accept(mut io: S, settings: &[(u16, u32)]) -> Self {
+            let mut preface = [0u8; 24];
+            io.read_exact(&mut preface).unwrap();
+            assert_eq!(preface, PREFACE);
+            let payload: Vec<u8> =
+                settings.iter().flat_map(|(id, value)| id.to_be_bytes().into_iter().chain(value.to_be_bytes())).collect();
+            write_frame(&mut io, SETTINGS, 0, 0, &payload).unwrap();
+            Self { io, encoder: Encoder::new(), decoder: Decoder::default() }
+        }
+
+        fn frame(&mut self) -> Frame {
+            read_frame(&mut self.io, MAX_FRAME_SIZE_LIMIT).unwrap()
+        }
+
+        /// Reads frames until a request is complete, returning window space as DATA arrives.
+        pub fn next_request(&mut self) -> ReceivedRequest {
+            let (mut stream, mut fields, mut block, mut body) = (0, Vec::new(), Vec::new(), Vec::new());
+            loop {
+                let frame = self.frame();
+                match frame.kind {
+                    HEADERS | CONTINUATION => {
+                        stream = frame.stream;
+                        block.extend_from_slice(&frame.payload);
+                        if frame.flags & FLAG_END_HEADERS != 0 {
+                            fields = self.decoder.decode(&std::mem::take(&mut block)).unwrap();
+                        }
+                    }
+                    DATA => {
+                        body.extend_from_slice(&frame.payload);
+                        let increment = (frame.payload.len() as u32).to_be_bytes();
+                        self.send_frame(WINDOW_UPDATE, 0, 0, &increment);
+                        self.send_frame(WINDOW_UPDATE, 0, frame.stream, &increment);
+                    }
+                    _ => continue,
+                }
+                if frame.flags & FLAG_END_STREAM != 0 && frame.kind != CONTINUATION {
+                    return ReceivedRequest { stream, fields, body };
+                }
+            }
+        }
+
+        /// Reads frames until one of `kind` arrives.
+        pub fn expect(&mut self, kind: u8) -> (u8, u32, Vec<u8>) {
+            loop {
+                let frame = self.frame();
+                if frame.kind == kind {
+                    return (frame.flags, frame.stream, frame.payload);
+                }
+            }
+        }
+
+        pub fn send_frame(&mut self, kind: u8, flags: u8, stream: u32, payload: &[u8]) {
+            write_frame(&mut self.io, kind, flags, stream, payload).unwrap();
+            self.io.flush().unwrap();
+        }
+
+        pub fn send_headers(&mut self, stream: u32, fields: &[(&str, &str)], end_stream: bool) {
+            let block = self.encoder.encode(fields.iter().copied());
+            let flags = FLAG_END_HEADERS | if end_stream { FLAG_END_STREAM } else { 0 };
+            self.send_frame(HEADERS, flags, stream, &block);
+        }
+
+        pub fn send_data(&mut self, stream: u32, data: &[u8], end_stream: bool) {
+            self.send_frame(DATA, if end_stream { FLAG_END_STREAM } else { 0 }, stream, data);
+        }
+
+        pub fn send_response(&mut self, stream: u32, status: &str, headers: &[(&str, &str)], body: &[u8]) {
+            let fields: Vec<(&str, &str)> = std::iter::once((":status", status)).chain(headers.iter().copied()).collect();
+            self.send_headers(stream, &fields, body.is_empty());
+            if !body.is_empty() {
+                self.send_data(stream, body, true);
+            }
+        }
+    }
+
+    fn request(method: &str, path: &str, body: &[u8]) -> HttpRequest {
+        let headers = [("Host", "h2.test"), ("Connection", "close"), ("Accept", "*/*")].into_iter().collect();
+        HttpRequest { method: method.to_string(), path: path.to_string(), headers, body: body.to_vec() }
+    }
+
+    #[test]
+    fn test_multiplexing_flow_control_and_trailers() {
+        let (client, server) = pipe::duplex("h2.test:443");
+        let server = std::thread::spawn(move || {
+            // A 16-byte stream window makes the 40-byte upload wait for WINDOW_UPDATE.
+            let mut peer = TestPeer::accept(server, &[(SETTINGS_INITIAL_WINDOW_SIZE, 16), (SETTINGS_MAX_FRAME_SIZE, 16_384)]);
+            let get = peer.next_request();
+            assert_eq!(get.stream, 1);
+            assert_eq!(get.field(":authority"), Some("h2.test"));
+            assert_eq!(get.field(":scheme"), Some("https"));
+            assert_eq!(get.field("connection"), None);
+            let post = peer.next_request();
+            assert_eq!((post.stream, post.body.len()), (3, 40));
+            assert_eq!(post.field(":method"), Some("POST"));
+
+            // Responses interleaved; stream 1's head split over HEADERS and CONTINUATION.
+            peer.send_headers(3, &[(":status", "201"), ("content-type", "text/plain")], false);
+            let block = peer.encoder.encode([(":status", "200"), ("x-split", "yes")]);
+            let (first, rest) = block.split_at(2);
+            peer.send_frame(HEADERS, 0, 1, first);
+            peer.send_frame(CONTINUATION, FLAG_END_HEADERS, 1, rest);
+            peer.send_data(1, b"hel", false);
+            peer.send_data(3, b"pos", false);
+            peer.send_frame(PING, 0, 0, b"12345678");
+            peer.send_data(1, b"lo", true);
+            peer.send_data(3, b"ted", false);
+            peer.send_headers(3, &[("x-checksum", "abc")], true);
+            assert_eq!(peer.expect(PING), (FLAG_ACK, 0, b"12345678".to_vec()));
+        });
+
+        let mut connection = Http2Connection::handshake(client, "https").unwrap();
+        let get = connection.send_request(&request("GET", "/a", b"")).unwrap();
+        let post = connection.send_request(&request("POST", "/b", &[7u8; 40])).unwrap();
+        assert_eq!(connection.active_streams(), 2);
+
+        let posted = connection.read_response(post).unwrap();
+        assert_eq!((posted.status, posted.body.as_slice()), (201, &b"posted"[..]));
+        assert_eq!(posted.trailers.get("x-checksum"), Some("abc"));
+        let got = connection.read_response(get).unwrap();
+        assert_eq!((got.status, got.body.as_slice()), (200, &b"hello"[..]));
+        assert_eq!(got.headers.get("X-Split"), Some("yes"));
+        assert_eq!(connection.active_streams(), 0);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_goaway_resets_and_connection_errors() {
+        let (client, server) = pipe::duplex("h2.test:443");
+        let server = std::thread::spawn(move || {
+            let mut peer = TestPeer::accept(server, &[]);
+            for _ in 0..4 {
+                peer.next_request();
+            }
+            peer.send_frame(RST_STREAM, 0, 3, &ErrorCode::RefusedStream.to_u32().to_be_bytes());
+            peer.send_frame(GOAWAY, 0, 0, &[0, 0, 0, 5, 0, 0, 0, 0]);
+            peer.send_response(1, "200", &[("content-length", "2")], b"ok");
+            // Uppercase names are malformed in HTTP/2.
+            peer.send_response(5, "200", &[("Bad", "1")], b"");
+            peer.send_data(0, b"nope", false);
+            let (_, _, payload) = peer.expect(GOAWAY);
+            assert_eq!(payload[4..8], ErrorCode::ProtocolError.to_u32().to_be_bytes());
+        });
+
+        let mut connection = Http2Connection::handshake(client, "https").unwrap();
+        let ids: Vec<u32> = (0..4).map(|_| connection.send_request(&request("GET", "/", b"")).unwrap()).collect();
+        assert_eq!(ids, [1, 3, 5, 7]);
+
+        assert_eq!(connection.read_response(1).unwrap().body, b"ok");
+        let refused = connection.read_response(3).unwrap_err();
+        assert!(matches!(refused, Http2Error::StreamReset { stream: 3, code: ErrorCode::RefusedStream }));
+        assert!(refused.is_retryable());
+        let beyond = connection.read_response(7).unwrap_err();
+        assert!(matches!(beyond, Http2Error::GoAway { last_stream_id: 5, .. }) && beyond.is_retryable());
+        assert!(!connection.is_usable());
+        assert!(matches!(connection.send_request(&request("GET", "/", b"")), Err(Http2Error::GoAway { .. })));
+
+        let malformed = connection.read_response(5).unwrap_err();
+        assert!(matches!(malformed, Http2Error::Malformed { stream: 5, .. }), "{:?}", malformed);
+
+        // DATA on stream 0 is a connection error, answered with GOAWAY.
+        let error = connection.read_and_process().unwrap_err();
+        assert!(matches!(error, Http2Error::Connection { code: ErrorCode::ProtocolError, .. }));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_peer_settings_hold_back_streams_and_guard_windows() {
+        let setting = |id: u16, value: u32| [id.to_be_bytes().as_slice(), &value.to_be_bytes()].concat();
+        let (client, server) = pipe::duplex("h2.test:443");
+        let server = std::thread::spawn(move || {
+            // No streams at all until the second SETTINGS: the client must wait, not open one.
+            let mut peer = TestPeer::accept(server, &[(SETTINGS_MAX_CONCURRENT_STREAMS, 0)]);
+            peer.send_frame(PING, 0, 0, b"waiting?");
+            loop {
+                let frame = peer.frame();
+                assert_ne!(frame.kind, HEADERS, "stream opened while the limit was 0");
+                if frame.kind == PING {
+                    break;
+                }
+            }
+            peer.send_frame(SETTINGS, 0, 0, &setting(SETTINGS_MAX_CONCURRENT_STREAMS, 1));
+            let get = peer.next_request();
+
+            // Stream window at the maximum, then an INITIAL_WINDOW_SIZE that would push it past.
+            let increment = (MAX_WINDOW - DEFAULT_WINDOW) as u32;
+            peer.send_frame(WINDOW_UPDATE, 0, get.stream, &increment.to_be_bytes());
+            peer.send_frame(SETTINGS, 0, 0, &setting(SETTINGS_INITIAL_WINDOW_SIZE, DEFAULT_WINDOW as u32 + 1));
+            let (_, _, payload) = peer.expect(GOAWAY);
+            assert_eq!(payload[4..8], ErrorCode::FlowControlError.to_u32().to_be_bytes());
+        });
+
+        let mut connection = Http2Connection::handshake(client, "https").unwrap();
+        let id = connection.send_request(&request("GET", "/", b"")).unwrap();
+        let err = connection.read_response(id).unwrap_err();
+        assert!(matches!(err, Http2Error::Connection { code: ErrorCode::FlowControlError, .. }), "{:?}", err);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_limits_cap_bodies_and_decoding() {
+        // 1 MiB of zeros compresses to about 1 KiB: within the body limit, far past the decoded one.
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(&vec![0u8; 1 << 20]).unwrap();
+        let bomb = encoder.finish().unwrap();
+        let (client, server) = pipe::duplex("h2.test:443");
+        let server = std::thread::spawn(move || {
+            let mut peer = TestPeer::accept(server, &[]);
+            let first = peer.next_request();
+            peer.send_headers(first.stream, &[(":status", "200")], false);
+            peer.send_data(first.stream, &[b'x'; 1500], false);
+            peer.send_data(first.stream, &[b'x'; 1500], false);
+            let (_, stream, payload) = peer.expect(RST_STREAM);
+            assert_eq!((stream, payload), (first.stream, ErrorCode::Cancel.to_u32().to_be_bytes().to_vec()));
+            // The server had more in flight; the client drops it without another RST_STREAM.
+            peer.send_data(first.stream, &[b'x'; 1500], true);
+            peer.send_frame(PING, 0, 0, b"drained!");
+
+            let second = peer.next_request();
+            loop {
+                let frame = peer.frame();
+                assert_ne!(frame.kind, RST_STREAM, "a reset stream is not reset again");
+                if frame.kind == PING {
+                    break;
+                }
+            }
+            peer.send_response(second.stream, "200", &[("content-encoding", "gzip")], &bomb);
+        });
+
+        let mut limits = Limits::default();
+        limits.response.max_body_size = 2048;
+        limits.decoded.max_output = 4096;
+        let mut connection = Http2Connection::handshake_with_limits(client, "https", &limits).unwrap();
+        let err = connection.request(&request("GET", "/big", b"")).unwrap_err();
+        assert!(matches!(err, Http2Error::BodyTooLarge { stream: 1, limit: 2048 }), "{:?}", err);
+        let err = connection.request(&request("GET", "/bomb", b"")).unwrap_err();
+        assert!(matches!(err, Http2Error::Decode(DecodeError::OutputTooLarge { limit: 4096 })), "{:?}", err);
+        assert!(connection.is_usable());
+        server.join().unwrap();
+    }
+}
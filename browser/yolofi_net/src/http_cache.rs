@@ -0,0 +1,658 @@
+// HTTP Cache
+// A private RFC 9111 cache kept under `<profile>/cache/`: one file per stored
+// response plus an index holding LRU ticks and sizes. Bodies are stored decoded,
+// exactly as `HttpResponse` carries them.
+//
+// Freshness comes from max-age, then Expires - Date, then the usual 10% of
+// (Date - Last-Modified) heuristic. Stale entries with a validator are revalidated
+// with If-None-Match / If-Modified-Since, and a 304 refreshes the stored headers.
+// Vary is honoured by keeping one entry per set of varying request-header values.
+// Partial (206) and range responses are never stored.
+//
+// Entries keep no TLS details: a hit returns `security: None`, since no connection made it.
+//
+// Entry file: "YFHC1" line, then `url`, `status`, `times <request> <response>`,
+// `vary <name>: <value>` and `header <name>: <value>` lines, a blank line, the body.
+// Index file: `<id> <last-used tick> <size> <url>` per line.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use tracing::{debug, info, warn};
+
+use sha2::{Digest, Sha256};
+use yolofi_url::Url;
+
+use crate::headers::HeaderMap;
+use crate::http::HttpResponse;
+use crate::http_date::{parse_http_date, unix_now};
+use crate::profile::Profile;
+
+const CACHE_DIR: &str = "cache";
+const INDEX_FILE: &str = "cache/index.txt";
+const ENTRY_MAGIC: &str = "YFHC1";
+
+pub const DEFAULT_CACHE_SIZE: u64 = 64 * 1024 * 1024;
+/// Upper bound on heuristic freshness, as most browsers use.
+const MAX_HEURISTIC_LIFETIME: i64 = 24 * 60 * 60;
+/// Status codes that may be cached without explicit freshness (RFC 9110 section 15.1).
+const HEURISTICALLY_CACHEABLE: [u16; 11] = [200, 203, 204, 300, 301, 308, 404, 405, 410, 414, 501];
+/// Final status codes this cache understands, and so may store with explicit freshness (RFC 9111 section 3).
+const UNDERSTOOD: [u16; 15] = [200, 203, 204, 300, 301, 302, 303, 307, 308, 404, 405, 410, 414, 451, 501];
+
+/// How a fetch uses the cache; the `cache` modes of the Fetch standard.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Fresh entries are used, stale ones revalidated.
+    #[default]
+    Default,
+    /// The cache is neither read nor written.
+    NoStore,
+    /// Always go to the network, then store the result.
+    Reload,
+    /// Always revalidate stored entries, however fresh.
+    NoCache,
+    /// Use any stored entry, even stale; the network only on a miss.
+    ForceCache,
+    /// Use any stored entry, even stale, and never touch the network. For offline replays.
+    OnlyIfCached,
+}
+
+/// What the cache contributed to a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheStatus {
+    /// No cache, or the request bypassed it.
+    Uncached,
+    /// Fetched from the network (and stored, if storable).
+    Miss,
+    /// Served from the cache without contacting the server.
+    Hit,
+    /// The server confirmed the stored entry with 304 Not Modified.
+    Revalidated,
+}
+
+/// What to do for a request, decided by `HttpCache::plan`.
+#[derive(Debug)]
+pub enum CachePlan {
+    /// Serve the stored response as-is.
+    Serve(CachedResponse),
+    /// Send the request with these conditional headers set; a 304 means `entry` is still good.
+    Revalidate { entry: CachedResponse, conditions: HeaderMap },
+    /// Go to the network; store the response if allowed.
+    Network { store: bool },
+    /// `OnlyIfCached` and nothing stored.
+    Unavailable,
+}
+
+/// A stored response with the times needed to compute its age.
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    id: String,
+    pub url: String,
+    pub status: u16,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+    /// When the request that produced this response was sent / its response received (Unix seconds).
+    pub request_time: i64,
+    pub response_time: i64,
+    vary: Vec<(String, String)>,
+}
+
+impl CachedResponse {
+    /// Seconds the response stays fresh, measured from its generation at the origin.
+    pub fn freshness_lifetime(&self) -> i64 {
+        let directives = cache_control(&self.headers);
+        if let Some(max_age) = directive_seconds(&directives, "max-age") {
+            return max_age;
+        }
+        let date = self.date();
+        if let Some(expires) = self.headers.get("Expires") {
+            // An invalid Expires ("0", "-1") means already expired.
+            return parse_http_date(expires).map_or(0, |at| (at - date).max(0));
+        }
+        match self.headers.get("Last-Modified").and_then(parse_http_date) {
+            Some(modified) if HEURISTICALLY_CACHEABLE.contains(&self.status) => {
+                ((date - modified).max(0) / 10).min(MAX_HEURISTIC_LIFETIME)
+            }
+            _ => 0,
+        }
+    }
+
+    /// RFC 9111 section 4.2.3.
+    pub fn current_age(&self, now: i64) -> i64 {
+        let age_value = self.headers.get("Age").and_then(|v| v.trim().parse::<i64>().ok()).unwrap_or(0);
+        let apparent_age = (self.response_time - self.date()).max(0);
+        let response_delay = self.response_time - self.request_time;
+        let corrected_initial_age = apparent_age.max(age_value + response_delay);
+        corrected_initial_age + (now - self.response_time).max(0)
+    }
+
+    fn date(&self) -> i64 {
+        self.headers.get("Date").and_then(parse_http_date).unwrap_or(self.response_time)
+    }
+
+    /// Fresh enough for a request carrying `request_directives`.
+    fn satisfies(&self, request_directives: &[(String, Option<String>)], now: i64) -> bool {
+        if has_directive(&cache_control(&self.headers), "no-cache") || has_directive(request_directives, "no-cache") {
+            return false;
+        }
+        let age = self.current_age(now);
+        let mut lifetime = self.freshness_lifetime();
+        if let Some(max_age) = directive_seconds(request_directives, "max-age") {
+            lifetime = lifetime.min(max_age);
+        }
+        if let Some(min_fresh) = directive_seconds(request_directives, "min-fresh") {
+            lifetime -= min_fresh;
+        }
+        if lifetime > age {
+            return true;
+        }
+        // max-stale without a value accepts any staleness.
+        match request_directives.iter().find(|(name, _)| name == "max-stale") {
+            Some((_, None)) => !has_directive(&cache_control(&self.headers), "must-revalidate"),
+            Some((_, Some(value))) => value.parse::<i64>().is_ok_and(|stale| age - lifetime <= stale),
+            None => false,
+        }
+    }
+
+    fn conditions(&self) -> Option<HeaderMap> {
+        let mut conditions = HeaderMap::new();
+        if let Some(etag) = self.headers.get("ETag") {
+            conditions.append("If-None-Match", etag);
+        }
+        if let Some(modified) = self.headers.get("Last-Modified") {
+            conditions.append("If-Modified-Since", modified);
+        }
+        (!conditions.is_empty()).then_some(conditions)
+    }
+
+    /// The stored response, with `security: None` (see the file header).
+    pub fn to_response(&self) -> HttpResponse {
+        HttpResponse {
+            status: self.status,
+            headers: self.headers.clone(),
+            body: self.body.clone(),
+            trailers: HeaderMap::new(),
+            security: None,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = format!(
+            "{}\nurl {}\nstatus {}\ntimes {} {}\n",
+            ENTRY_MAGIC, self.url, self.status, self.request_time, self.response_time
+        );
+        for (name, value) in &self.vary {
+            out.push_str(&format!("vary {}: {}\n", name, value));
+        }
+        for (name, value) in self.headers.iter() {
+            out.push_str(&format!("header {}: {}\n", name, value));
+        }
+        out.push('\n');
+        let mut bytes = out.into_bytes();
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+
+    fn decode(id: &str, bytes: &[u8]) -> Option<Self> {
+        let split = bytes.windows(2).position(|w| w == b"\n\n")?;
+        let head = std::str::from_utf8(&bytes[..split]).ok()?;
+        let mut lines = head.lines();
+        if lines.next()? != ENTRY_MAGIC {
+            return None;
+        }
+        let mut entry = CachedResponse {
+            id: id.to_string(),
+            url: String::new(),
+            status: 0,
+            headers: HeaderMap::new(),
+            body: bytes[split + 2..].to_vec(),
+            request_time: 0,
+            response_time: 0,
+            vary: Vec::new(),
+        };
+        for line in lines {
+            let (kind, rest) = line.split_once(' ')?;
+            match kind {
+                "url" => entry.url = rest.to_string(),
+                "status" => entry.status = rest.parse().ok()?,
+                "times" => {
+                    let (request, response) = rest.split_once(' ')?;
+                    entry.request_time = request.parse().ok()?;
+                    entry.response_time = response.parse().ok()?;
+                }
+                "vary" | "header" => {
+                    let (name, value) = rest.split_once(": ").unwrap_or((rest.trim_end_matches(':'), ""));
+                    if kind == "vary" {
+                        entry.vary.push((name.to_string(), value.to_string()));
+                    } else {
+                        entry.headers.append(name, value);
+                    }
+                }
+                _ => return None,
+            }
+        }
+        Some(entry)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct IndexEntry {
+    url: String,
+    size: u64,
+    last_used: u64,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    entries: BTreeMap<String, IndexEntry>,
+    tick: u64,
+    total: u64,
+}
+
+/// Shared handle to the on-disk cache; clones see the same entries.
+#[derive(Debug, Clone)]
+pub struct HttpCache {
+    state: Arc<Mutex<CacheState>>,
+    profile: Profile,
+    max_bytes: u64,
+}
+
+impl HttpCache {
+    /// Opens the cache in `profile`, evicting down to `max_bytes` if it shrank.
+    pub fn open(profile: &Profile, max_bytes: u64) -> io::Result<Self> {
+        std::fs::create_dir_all(profile.path(CACHE_DIR))?;
+        let mut state = CacheState::default();
+        if let Some(text) = profile.read_optional(INDEX_FILE)? {
+            for line in text.lines() {
+                let mut fields = line.splitn(4, ' ');
+                let parsed = (|| {
+                    let id = fields.next()?.to_string();
+                    let last_used = fields.next()?.parse().ok()?;
+                    let size = fields.next()?.parse().ok()?;
+                    Some((id, IndexEntry { last_used, size, url: fields.next()?.to_string() }))
+                })();
+                match parsed {
+                    Some((id, entry)) if profile.path(&entry_file(&id)).exists() => {
+                        state.tick = state.tick.max(entry.last_used);
+                        state.total += entry.size;
+                        state.entries.insert(id, entry);
+                    }
+                    Some(_) => {}
+                    None => warn!(target: "net::cache", "Skipping bad cache index line {:?}", line),
+                }
+            }
+        }
+        info!(target: "net::cache", "HTTP cache: {} entries, {} of {} bytes", state.entries.len(), state.total, max_bytes);
+        let cache = Self { state: Arc::new(Mutex::new(state)), profile: profile.clone(), max_bytes };
+        cache.update(|_| {})?;
+        Ok(cache)
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes on disk, entry files only.
+    pub fn size(&self) -> u64 {
+        self.lock().total
+    }
+
+    /// Decides how to satisfy `method url` with these request headers under `mode`.
+    pub fn plan(&self, mode: CacheMode, method: &str, url: &Url, request_headers: &HeaderMap) -> CachePlan {
+        let request_directives = cache_control(request_headers);
+        let cacheable_method = method == "GET";
+        match mode {
+            CacheMode::NoStore => return CachePlan::Network { store: false },
+            _ if !cacheable_method || has_directive(&request_directives, "no-store") => {
+                return if mode == CacheMode::OnlyIfCached { CachePlan::Unavailable } else { CachePlan::Network { store: false } };
+            }
+            CacheMode::Reload => return CachePlan::Network { store: true },
+            _ => {}
+        }
+
+        let Some(entry) = self.lookup(url, request_headers) else {
+            return if mode == CacheMode::OnlyIfCached || has_directive(&request_directives, "only-if-cached") {
+                CachePlan::Unavailable
+            } else {
+                CachePlan::Network { store: true }
+            };
+        };
+        let now = unix_now();
+        match mode {
+            CacheMode::ForceCache | CacheMode::OnlyIfCached => CachePlan::Serve(entry),
+            CacheMode::Default if entry.satisfies(&request_directives, now) => {
+                debug!(target: "net::cache", "Fresh hit for {} (age {}s)", url, entry.current_age(now));
+                CachePlan::Serve(entry)
+            }
+            _ => match entry.conditions() {
+                Some(conditions) => CachePlan::Revalidate { entry, conditions },
+                None => CachePlan::Network { store: true },
+            },
+        }
+    }
+
+    /// The stored response for `url` whose Vary'd request headers match `request_headers`.
+    pub fn lookup(&self, url: &Url, request_headers: &HeaderMap) -> Option<CachedResponse> {
+        let key = url.to_string();
+        let ids: Vec<String> = {
+            let state = self.lock();
+            state.entries.iter().filter(|(_, e)| e.url == key).map(|(id, _)| id.clone()).collect()
+        };
+        let entry = ids
+            .iter()
+            .filter_map(|id| self.read_entry(id))
+            .find(|entry| entry.vary.iter().all(|(name, value)| vary_value(request_headers, name) == *value))?;
+        let _ = self.update(|state| {
+            state.tick += 1;
+            let tick = state.tick;
+            if let Some(index) = state.entries.get_mut(&entry.id) {
+                index.last_used = tick;
+            }
+        });
+        Some(entry)
+    }
+
+    /// Stores `response` if RFC 9111 allows it and it is a whole representation (no ranges).
+    /// Returns whether it was stored.
+    pub fn store(
+        &self,
+        url: &Url,
+        request_headers: &HeaderMap,
+        response: &HttpResponse,
+        request_time: i64,
+        response_time: i64,
+    ) -> io::Result<bool> {
+        let directives = cache_control(&response.headers);
+        let explicit = has_directive(&directives, "max-age")
+            || has_directive(&directives, "public")
+            || has_directive(&directives, "private")
+            || response.headers.contains("Expires");
+        let vary_names: Vec<String> = response.headers.tokens("Vary");
+        if has_directive(&directives, "no-store")
+            || has_directive(&cache_control(request_headers), "no-store")
+            || vary_names.iter().any(|v| v == "*")
+            || !UNDERSTOOD.contains(&response.status)
+            || !(explicit || HEURISTICALLY_CACHEABLE.contains(&response.status))
+            || request_headers.contains("Range")
+            || response.headers.contains("Content-Range")
+            || response.body.len() as u64 > self.max_bytes
+        {
+            return Ok(false);
+        }
+
+        let vary: Vec<(String, String)> =
+            vary_names.iter().map(|name| (name.clone(), vary_value(request_headers, name))).collect();
+        let key = url.to_string();
+        let mut hasher = Sha256::new();
+        hasher.update(key.as_bytes());
+        for (name, value) in &vary {
+            hasher.update(format!("\n{}: {}", name, value).as_bytes());
+        }
+        let id: String = hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect();
+
+        let entry = CachedResponse {
+            id: id.clone(),
+            url: key.clone(),
+            status: response.status,
+            headers: response.headers.clone(),
+            body: response.body.clone(),
+            request_time,
+            response_time,
+            vary,
+        };
+        self.write_entry(&entry)?;
+        debug!(target: "net::cache", "Stored {} ({} bytes)", key, entry.body.len());
+        Ok(true)
+    }
+
+    /// Applies a 304 to the stored entry and returns the refreshed response.
+    pub fn freshen(
+        &self,
+        mut entry: CachedResponse,
+        not_modified: &HttpResponse,
+        request_time: i64,
+        response_time: i64,
+    ) -> io::Result<HttpResponse> {
+        // RFC 9111 section 3.2: stored fields are replaced by those in the 304, framing excepted.
+        for (name, _) in not_modified.headers.iter() {
+            if !name.eq_ignore_ascii_case("Content-Length") && !name.eq_ignore_ascii_case("Transfer-Encoding") {
+                entry.headers.remove(name);
+            }
+        }
+        for (name, value) in not_modified.headers.iter() {
+            if !name.eq_ignore_ascii_case("Content-Length") && !name.eq_ignore_ascii_case("Transfer-Encoding") {
+                entry.headers.append(name, value);
+            }
+        }
+        entry.request_time = request_time;
+        entry.response_time = response_time;
+        self.write_entry(&entry)?;
+        let mut response = entry.to_response();
+        response.security = not_modified.security.clone();
+        Ok(response)
+    }
+
+    /// Drops every entry for `url`, after an unsafe method succeeded against it.
+    pub fn invalidate(&self, url: &Url) -> io::Result<usize> {
+        let key = url.to_string();
+        let mut removed = Vec::new();
+        self.update(|state| {
+            let ids: Vec<String> = state.entries.iter().filter(|(_, e)| e.url == key).map(|(id, _)| id.clone()).collect();
+            for id in ids {
+                if let Some(entry) = state.entries.remove(&id) {
+                    state.total -= entry.size;
+                    removed.push(id);
+                }
+            }
+        })?;
+        for id in &removed {
+            let _ = std::fs::remove_file(self.profile.path(&entry_file(id)));
+        }
+        Ok(removed.len())
+    }
+
+    pub fn clear(&self) -> io::Result<()> {
+        let ids: Vec<String> = self.lock().entries.keys().cloned().collect();
+        self.update(|state| {
+            state.entries.clear();
+            state.total = 0;
+        })?;
+        for id in ids {
+            let _ = std::fs::remove_file(self.profile.path(&entry_file(&id)));
+        }
+        Ok(())
+    }
+
+    fn read_entry(&self, id: &str) -> Option<CachedResponse> {
+        let bytes = std::fs::read(self.profile.path(&entry_file(id))).ok()?;
+        let entry = CachedResponse::decode(id, &bytes);
+        if entry.is_none() {
+            warn!(target: "net::cache", "Corrupt cache entry {}", id);
+        }
+        entry
+    }
+
+    fn write_entry(&self, entry: &CachedResponse) -> io::Result<()> {
+        let bytes = entry.encode();
+        self.profile.write_atomic(&entry_file(&entry.id), &bytes)?;
+        self.update(|state| {
+            state.tick += 1;
+            let index = IndexEntry { url: entry.url.clone(), size: bytes.len() as u64, last_used: state.tick };
+            if let Some(old) = state.entries.insert(entry.id.clone(), index) {
+                state.total -= old.size;
+            }
+            state.total += bytes.len() as u64;
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CacheState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Applies `change`, evicts least recently used entries past the budget, and saves the index.
+    fn update(&self, change: impl FnOnce(&mut CacheState)) -> io::Result<()> {
+        let mut state = self.lock();
+        change(&mut state);
+        while state.total > self.max_bytes {
+            let Some(id) = state.entries.iter().min_by_key(|(_, e)| e.last_used).map(|(id, _)| id.clone()) else {
+                break;
+            };
+            let evicted = state.entries.remove(&id).expect("found above");
+            state.total -= evicted.size;
+            debug!(target: "net::cache", "Evicted {} ({} bytes)", evicted.url, evicted.size);
+            let _ = std::fs::remove_file(self.profile.path(&entry_file(&id)));
+        }
+        let index: String = state
+            .entries
+            .iter()
+            .map(|(id, e)| format!("{} {} {} {}\n", id, e.last_used, e.size, e.url))
+            .collect();
+        self.profile.write_atomic(INDEX_FILE, index.as_bytes())
+    }
+}
+
+fn entry_file(id: &str) -> String {
+    format!("{}/{}.entry", CACHE_DIR, id)
+}
+
+/// Cache-Control directives, lowercased, with unquoted values.
+fn cache_control(headers: &HeaderMap) -> Vec<(String, Option<String>)> {
+    headers
+        .tokens("Cache-Control")
+        .into_iter()
+        .map(|token| match token.split_once('=') {
+            Some((name, value)) => (name.trim().to_string(), Some(value.trim().trim_matches('"').to_string())),
+            None => (token, None),
+        })
+        .collect()
+}
+
+fn has_directive(directives: &[(String, Option<String>)], name: &str) -> bool {
+    directives.iter().any(|(n, _)| n == name)
+}
+
+fn directive_seconds(directives: &[(String, Option<String>)], name: &str) -> Option<i64> {
+    directives.iter().find(|(n, _)| n == name).and_then(|(_, v)| v.as_deref()?.parse::<i64>().ok())
+}
+
+/// A request header's value as Vary compares it: combined and whitespace-normalised.
+fn vary_value(headers: &HeaderMap, name: &str) -> String {
+    headers
+        .get_combined(name)
+        .map(|v| v.split(',').map(str::trim).collect::<Vec<_>>().join(","))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(status: u16, headers: &[(&str, &str)], body: &str) -> HttpResponse {
+        HttpResponse {
+            status,
+            headers: headers.iter().copied().collect(),
+            body: body.as_bytes().to_vec(),
+            trailers: HeaderMap::new(),
+            security: None,
+        }
+    }
+
+    fn temp_profile(name: &str) -> Profile {
+        let dir = std::env::temp_dir().join(format!("yolofi_cache_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        Profile::open(dir).unwrap()
+    }
+
+    #[test]
+    fn test_freshness_and_revalidation() {
+        let profile = temp_profile("fresh");
+        let cache = HttpCache::open(&profile, DEFAULT_CACHE_SIZE).unwrap();
+        let url = Url::parse("https://a.test/app.js").unwrap();
+        let headers = HeaderMap::new();
+        let t = unix_now();
+
+        let fresh = response(200, &[("Cache-Control", "max-age=600"), ("ETag", "\"v1\"")], "js");
+        assert!(cache.store(&url, &headers, &fresh, t, t).unwrap());
+        assert!(matches!(cache.plan(CacheMode::Default, "GET", &url, &headers), CachePlan::Serve(_)));
+        assert!(matches!(cache.plan(CacheMode::Default, "POST", &url, &headers), CachePlan::Network { store: false }));
+        let no_cache: HeaderMap = [("Cache-Control", "no-cache")].into_iter().collect();
+        assert!(matches!(cache.plan(CacheMode::Default, "GET", &url, &no_cache), CachePlan::Revalidate { .. }));
+
+        // Age beyond max-age: revalidate with the validator, then a 304 refreshes it.
+        let stale = response(200, &[("Cache-Control", "max-age=60"), ("Age", "120"), ("ETag", "\"v1\"")], "js");
+        cache.store(&url, &headers, &stale, t, t).unwrap();
+        let CachePlan::Revalidate { entry, conditions } = cache.plan(CacheMode::Default, "GET", &url, &headers) else {
+            panic!("expected revalidation");
+        };
+        assert_eq!(conditions.get("If-None-Match"), Some("\"v1\""));
+        let not_modified = response(304, &[("Cache-Control", "max-age=300"), ("ETag", "\"v1\"")], "");
+        let refreshed = cache.freshen(entry, &not_modified, t, t).unwrap();
+        assert_eq!((refreshed.status, refreshed.body.as_slice()), (200, &b"js"[..]));
+        assert!(matches!(cache.plan(CacheMode::Default, "GET", &url, &headers), CachePlan::Serve(_)));
+
+        // Heuristic freshness from Last-Modified; Expires in the past; no-store.
+        let heuristic = CachedResponse::decode(
+            "x",
+            b"YFHC1\nurl u\nstatus 200\ntimes 0 0\nheader Date: Thu, 11 Jan 1970 00:00:00 GMT\nheader Last-Modified: Thu, 01 Jan 1970 00:00:00 GMT\n\n",
+        )
+        .unwrap();
+        assert_eq!(heuristic.freshness_lifetime(), 86400);
+        let expired = response(200, &[("Expires", "0")], "");
+        assert!(cache.store(&url, &headers, &expired, t, t).unwrap());
+        assert!(matches!(cache.plan(CacheMode::Default, "GET", &url, &headers), CachePlan::Network { store: true }));
+        assert!(!cache.store(&url, &headers, &response(200, &[("Cache-Control", "no-store")], ""), t, t).unwrap());
+        assert!(!cache.store(&url, &headers, &response(500, &[], ""), t, t).unwrap());
+        assert!(!cache.store(&url, &headers, &response(500, &[("Cache-Control", "max-age=60")], ""), t, t).unwrap());
+
+        // Partial content never replaces the whole representation, whatever its freshness.
+        let partial = response(206, &[("Cache-Control", "max-age=60"), ("Content-Range", "bytes 0-1/10")], "js");
+        assert!(!cache.store(&url, &headers, &partial, t, t).unwrap());
+        let range: HeaderMap = [("Range", "bytes=0-1")].into_iter().collect();
+        assert!(!cache.store(&url, &range, &response(200, &[("Cache-Control", "max-age=60")], "js"), t, t).unwrap());
+
+        std::fs::remove_dir_all(profile.root()).unwrap();
+    }
+
+    #[test]
+    fn test_vary_offline_mode_and_lru_eviction() {
+        let profile = temp_profile("vary");
+        let cache = HttpCache::open(&profile, 600).unwrap();
+        let url = Url::parse("https://a.test/page").unwrap();
+        let english: HeaderMap = [("Accept-Language", "en")].into_iter().collect();
+        let french: HeaderMap = [("Accept-Language", "fr")].into_iter().collect();
+        let vary = [("Cache-Control", "max-age=0"), ("Vary", "Accept-Language")];
+
+        cache.store(&url, &english, &response(200, &vary, "hello"), 0, 0).unwrap();
+        cache.store(&url, &french, &response(200, &vary, "bonjour"), 0, 0).unwrap();
+        assert_eq!(cache.lookup(&url, &french).unwrap().body, b"bonjour");
+        assert!(cache.lookup(&url, &HeaderMap::new()).is_none());
+
+        // Stale and without validators, but offline replay serves it anyway.
+        let CachePlan::Serve(entry) = cache.plan(CacheMode::OnlyIfCached, "GET", &url, &english) else {
+            panic!("expected the stored entry");
+        };
+        assert_eq!(entry.body, b"hello");
+        let other = Url::parse("https://a.test/missing").unwrap();
+        assert!(matches!(cache.plan(CacheMode::OnlyIfCached, "GET", &other, &english), CachePlan::Unavailable));
+
+        // Survives a reopen; a third entry pushes out the least recently used (English).
+        let reopened = HttpCache::open(&profile, 600).unwrap();
+        assert_eq!(reopened.len(), 2);
+        reopened.lookup(&url, &french).unwrap();
+        let big = "x".repeat(300);
+        reopened.store(&other, &english, &response(200, &[("Cache-Control", "max-age=60")], &big), 0, 0).unwrap();
+        assert!(reopened.lookup(&url, &english).is_none());
+        assert!(reopened.lookup(&url, &french).is_some());
+        assert!(reopened.size() <= 600);
+
+        assert_eq!(reopened.invalidate(&url).unwrap(), 1);
+        reopened.clear().unwrap();
+        assert!(reopened.is_empty());
+        std::fs::remove_dir_all(profile.root()).unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+// HTTP Dates
+// Parses Date, Expires, Last-Modified and cookie Expires values. One lenient parser
+// serves all of them: the cookie-date algorithm of RFC 6265 section 5.1.1 accepts the
+// IMF-fixdate, RFC 850 and asctime forms that RFC 9110 requires recipients to read.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The current time in the same unit as `parse_http_date`: seconds since the epoch.
+pub fn unix_now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64)
+}
+
+/// Seconds since the epoch, or `None` when no complete, valid date is found.
+pub fn parse_http_date(input: &str) -> Option<i64> {
+    let is_delimiter = |c: char| matches!(c, '\t' | ' '..='/' | ';'..='@' | '['..='`' | '{'..='~');
+    let (mut time, mut day, mut month, mut year) = (None, None, None, None);
+
+    // 1*max DIGIT followed by nothing or a non-digit.
+    let leading_number = |token: &str, min: usize, max: usize| -> Option<u32> {
+        let digits = token.bytes().take_while(u8::is_ascii_digit).count();
+        (min..=max).contains(&digits).then(|| token[..digits].parse().ok()).flatten()
+    };
+
+    for token in input.split(is_delimiter).filter(|t| !t.is_empty()) {
+        if time.is_none() {
+            let mut fields = token.splitn(3, ':');
+            if let (Some(h), Some(m), Some(s)) = (fields.next(), fields.next(), fields.next()) {
+                if let (Some(h), Some(m), Some(s)) = (
+                    leading_number(h, 1, 2).filter(|_| h.len() <= 2),
+                    leading_number(m, 1, 2).filter(|_| m.len() <= 2),
+                    leading_number(s, 1, 2),
+                ) {
+                    time = Some((h, m, s));
+                    continue;
+                }
+            }
+        }
+        if day.is_none() {
+            if let Some(d) = leading_number(token, 1, 2) {
+                day = Some(d);
+                continue;
+            }
+        }
+        if month.is_none() && token.len() >= 3 {
+            const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+            let prefix = token.get(..3).map(str::to_ascii_lowercase);
+            if let Some(m) = MONTHS.iter().position(|m| Some(*m) == prefix.as_deref()) {
+                month = Some(m as u32 + 1);
+                continue;
+            }
+        }
+        if year.is_none() {
+            if let Some(y) = leading_number(token, 2, 4) {
+                year = Some(y);
+                continue;
+            }
+        }
+    }
+
+    let (hour, minute, second) = time?;
+    let (day, month, mut year) = (day?, month?, year?);
+    if (70..=99).contains(&year) {
+        year += 1900;
+    } else if year <= 69 {
+        year += 2000;
+    }
+    if !(1..=31).contains(&day) || year < 1601 || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    let days_in_month = match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    if day > days_in_month {
+        return None;
+    }
+    let days = days_from_civil(year as i64, month as i64, day as i64);
+    Some(days * 86400 + (hour * 3600 + minute * 60 + second) as i64)
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_all_three_formats_and_validation() {
+        assert_eq!(parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT"), Some(1445412480));
+        assert_eq!(parse_http_date("Wednesday, 21-Oct-15 07:28:00 GMT"), Some(1445412480));
+        assert_eq!(parse_http_date("Wed Oct 21 07:28:00 2015"), Some(1445412480));
+        assert_eq!(parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+        assert_eq!(parse_http_date("Sat, 29 Feb 2020 12:00:00 GMT"), Some(1582977600));
+        assert_eq!(parse_http_date("Fri, 30 Feb 2021 12:00:00 GMT"), None);
+        assert_eq!(parse_http_date("21 Oct 2015"), None);
+        assert_eq!(parse_http_date("Wed, 21 Oct 1500 07:28:00 GMT"), None);
+    }
+}
@@ -0,0 +1,689 @@
+// Incremental HTTP/1.1 Response Parser
+// A resumable state machine: feed it bytes as they arrive, get events back.
+// Framing follows RFC 9112 section 6.3 exactly; the body is never treated as text.
+//
+//   StatusLine -> Headers -> (Fixed | Chunk* | UntilClose) -> Done
+//
+// Interim 1xx responses (other than 101) are reported and then parsing
+// continues with the final response on the same stream.
+//
+// Chunked bodies are decoded here: size lines, extensions and trailers never reach
+// the body. Chunk sizes and the total body are capped by `ParseLimits`.
+//
+// Heads are capped too: the status line, the number of fields and the bytes of every
+// head line (interim heads and trailers included). A line is checked while it is
+// still being buffered, so a peer that never sends a newline cannot grow it forever.
+
+use std::io;
+use tracing::{debug, warn};
+
+use crate::headers::HeaderMap;
+
+pub const DEFAULT_MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+pub const DEFAULT_MAX_BODY_SIZE: usize = 64 * 1024 * 1024;
+pub const DEFAULT_MAX_STATUS_LINE: usize = 8 * 1024;
+pub const DEFAULT_MAX_HEADERS: usize = 256;
+pub const DEFAULT_MAX_HEADER_BYTES: usize = 256 * 1024;
+
+/// Longest chunk-size line, extensions included.
+const MAX_CHUNK_LINE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseLimits {
+    /// Largest single chunk a chunked body may declare.
+    pub max_chunk_size: usize,
+    /// Largest body, after chunked decoding, in any framing.
+    pub max_body_size: usize,
+    /// Longest status line, without its line ending.
+    pub max_status_line: usize,
+    /// Most header and trailer fields in one response, interim heads included.
+    pub max_headers: usize,
+    /// Most bytes of status lines, header lines and trailer lines in one response.
+    pub max_header_bytes: usize,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        Self {
+            max_chunk_size: DEFAULT_MAX_CHUNK_SIZE,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            max_status_line: DEFAULT_MAX_STATUS_LINE,
+            max_headers: DEFAULT_MAX_HEADERS,
+            max_header_bytes: DEFAULT_MAX_HEADER_BYTES,
+        }
+    }
+}
+
+/// How the end of a response body is found on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyLength {
+    Empty,
+    Fixed(usize),
+    Chunked,
+    UntilClose,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpVersion {
+    Http10,
+    Http11,
+}
+
+/// `name` and optional value of a chunk extension.
+pub type ChunkExtension = (String, Option<String>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResponseEvent {
+    Status { version: HttpVersion, code: u16, reason: String },
+    Header { name: String, value: String },
+    /// An obs-fold continuation line: `value` replaces that of the last `Header`.
+    HeaderFolded { name: String, value: String },
+    /// End of the head. For interim (1xx) responses another `Status` follows.
+    HeadersComplete { length: BodyLength },
+    /// Start of a chunk (size 0 is the last chunk). Extensions are `name[=value]`.
+    Chunk { size: usize, extensions: Vec<ChunkExtension> },
+    /// Decoded body bytes.
+    Body(Vec<u8>),
+    /// A trailer field sent after the last chunk.
+    Trailer { name: String, value: String },
+    /// The message is over; `reusable` says whether the connection may carry another request.
+    Complete { reusable: bool },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ParseError {
+    #[error("malformed status line: {0:?}")]
+    InvalidStatusLine(String),
+    #[error("unsupported HTTP version: {0:?}")]
+    UnsupportedVersion(String),
+    #[error("malformed header line: {0:?}")]
+    InvalidHeader(String),
+    #[error("invalid Content-Length: {0:?}")]
+    InvalidContentLength(String),
+    #[error("invalid chunk size line: {0:?}")]
+    InvalidChunkSize(String),
+    #[error("invalid chunk extension: {0:?}")]
+    InvalidChunkExtension(String),
+    #[error("chunk exceeds the {limit} byte limit")]
+    ChunkTooLarge { limit: usize },
+    #[error("body exceeds the {limit} byte limit")]
+    BodyTooLarge { limit: usize },
+    #[error("status line exceeds the {limit} byte limit")]
+    StatusLineTooLong { limit: usize },
+    #[error("more than {limit} header fields")]
+    TooManyHeaders { limit: usize },
+    #[error("response head exceeds the {limit} byte limit")]
+    HeadersTooLarge { limit: usize },
+    #[error("chunk size line exceeds the {limit} byte limit")]
+    ChunkLineTooLong { limit: usize },
+    #[error("chunk data not followed by CRLF")]
+    MissingChunkTerminator,
+    #[error("connection closed before the response was complete")]
+    IncompleteMessage,
+}
+
+impl From<ParseError> for io::Error {
+    fn from(e: ParseError) -> Self {
+        let kind = match e {
+            ParseError::IncompleteMessage => io::ErrorKind::UnexpectedEof,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+/// The result of one `feed` call.
+#[derive(Debug, Default)]
+pub struct Parsed {
+    /// Bytes of the input that belong to this response. Anything after it is left alone.
+    pub consumed: usize,
+    pub events: Vec<ResponseEvent>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    StatusLine,
+    Headers,
+    Fixed(usize),
+    ChunkSize,
+    ChunkData(usize),
+    ChunkDataEnd,
+    Trailers,
+    UntilClose,
+    Done,
+}
+
+pub struct ResponseParser {
+    state: State,
+    request_method: String,
+    limits: ParseLimits,
+    /// Decoded body bytes seen so far.
+    body_received: usize,
+    /// Partial line carried over between `feed` calls.
+    line: Vec<u8>,
+    /// Head lines and fields seen so far, across interim heads and trailers.
+    head_bytes: usize,
+    fields: usize,
+    version: HttpVersion,
+    status: u16,
+    headers: HeaderMap,
+}
+
+impl ResponseParser {
+    /// A parser for the response to a request made with `request_method`.
+    pub fn new(request_method: &str) -> Self {
+        Self::with_limits(request_method, ParseLimits::default())
+    }
+
+    pub fn with_limits(request_method: &str, limits: ParseLimits) -> Self {
+        Self {
+            state: State::StatusLine,
+            request_method: request_method.to_string(),
+            limits,
+            body_received: 0,
+            line: Vec::new(),
+            head_bytes: 0,
+            fields: 0,
+            version: HttpVersion::Http11,
+            status: 0,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// Parses as much of `input` as possible. Stops at the end of the message.
+    pub fn feed(&mut self, input: &[u8]) -> Result<Parsed, ParseError> {
+        let mut parsed = Parsed::default();
+        while parsed.consumed < input.len() && self.state != State::Done {
+            let rest = &input[parsed.consumed..];
+            parsed.consumed += self.step(rest, &mut parsed.events)?;
+        }
+        Ok(parsed)
+    }
+
+    /// Signals that the peer closed the connection.
+    pub fn finish(&mut self) -> Result<Vec<ResponseEvent>, ParseError> {
+        match self.state {
+            State::Done => Ok(Vec::new()),
+            State::UntilClose => {
+                self.state = State::Done;
+                Ok(vec![ResponseEvent::Complete { reusable: false }])
+            }
+            _ => Err(ParseError::IncompleteMessage),
+        }
+    }
+
+    /// Consumes bytes for the current state and returns how many were used.
+    fn step(&mut self, input: &[u8], events: &mut Vec<ResponseEvent>) -> Result<usize, ParseError> {
+        match self.state {
+            State::StatusLine | State::Headers | State::ChunkSize | State::Trailers => {
+                let Some(end) = input.iter().position(|&b| b == b'\n') else {
+                    // One byte of slack for the CR that may end the line.
+                    self.check_line((self.line.len() + input.len()).saturating_sub(1))?;
+                    self.line.extend_from_slice(input);
+                    return Ok(input.len());
+                };
+                self.line.extend_from_slice(&input[..end]);
+                let mut line = std::mem::take(&mut self.line);
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                self.check_line(line.len())?;
+                self.on_line(&line, events)?;
+                Ok(end + 1)
+            }
+            State::Fixed(remaining) | State::ChunkData(remaining) => {
+                let take = remaining.min(input.len());
+                self.body_received += take;
+                events.push(ResponseEvent::Body(input[..take].to_vec()));
+                let left = remaining - take;
+                self.state = match (self.state, left) {
+                    (State::Fixed(_), 0) => self.complete(events),
+                    (State::Fixed(_), _) => State::Fixed(left),
+                    (_, 0) => State::ChunkDataEnd,
+                    (_, _) => State::ChunkData(left),
+                };
+                Ok(take)
+            }
+            State::ChunkDataEnd => {
+                // Expect CRLF (or a bare LF); the CR may arrive in a separate feed.
+                match (self.line.as_slice(), input[0]) {
+                    ([], b'\r') => {
+                        self.line.push(b'\r');
+                        Ok(1)
+                    }
+                    (_, b'\n') => {
+                        self.line.clear();
+                        self.state = State::ChunkSize;
+                        Ok(1)
+                    }
+                    _ => Err(ParseError::MissingChunkTerminator),
+                }
+            }
+            State::UntilClose => {
+                self.count_body(input.len())?;
+                events.push(ResponseEvent::Body(input.to_vec()));
+                Ok(input.len())
+            }
+            State::Done => Ok(0),
+        }
+    }
+
+    /// Fails if a line of `len` bytes would break a limit for the current state.
+    fn check_line(&self, len: usize) -> Result<(), ParseError> {
+        let limits = &self.limits;
+        match self.state {
+            State::StatusLine if len > limits.max_status_line => {
+                Err(ParseError::StatusLineTooLong { limit: limits.max_status_line })
+            }
+            State::StatusLine | State::Headers | State::Trailers if self.head_bytes + len > limits.max_header_bytes => {
+                warn!(target: "net::http", "Response head would exceed {} bytes", limits.max_header_bytes);
+                Err(ParseError::HeadersTooLarge { limit: limits.max_header_bytes })
+            }
+            State::ChunkSize if len > MAX_CHUNK_LINE => Err(ParseError::ChunkLineTooLong { limit: MAX_CHUNK_LINE }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Counts one more header or trailer field.
+    fn count_field(&mut self) -> Result<(), ParseError> {
+        self.fields += 1;
+        if self.fields > self.limits.max_headers {
+            return Err(ParseError::TooManyHeaders { limit: self.limits.max_headers });
+        }
+        Ok(())
+    }
+
+    fn on_line(&mut self, line: &[u8], events: &mut Vec<ResponseEvent>) -> Result<(), ParseError> {
+        if matches!(self.state, State::StatusLine | State::Headers | State::Trailers) {
+            self.head_bytes += line.len();
+        }
+        match self.state {
+            State::StatusLine => {
+                let (version, code, reason) = parse_status_line(line)?;
+                self.version = version;
+                self.status = code;
+                self.headers.clear();
+                events.push(ResponseEvent::Status { version, code, reason });
+                self.state = State::Headers;
+            }
+            State::Headers if line.is_empty() => {
+                let length = self.body_length()?;
+                events.push(ResponseEvent::HeadersComplete { length });
+                self.state = if (100..200).contains(&self.status) && self.status != 101 {
+                    State::StatusLine
+                } else {
+                    match length {
+                        BodyLength::Empty | BodyLength::Fixed(0) => self.complete(events),
+                        BodyLength::Fixed(len) if len > self.limits.max_body_size => {
+                            return Err(ParseError::BodyTooLarge { limit: self.limits.max_body_size });
+                        }
+                        BodyLength::Fixed(len) => State::Fixed(len),
+                        BodyLength::Chunked => State::ChunkSize,
+                        BodyLength::UntilClose => State::UntilClose,
+                    }
+                };
+            }
+            State::Headers if line[0] == b' ' || line[0] == b'\t' => {
+                // obs-fold: a user agent must replace it with a space (RFC 9112 section 5.2).
+                let continuation = String::from_utf8_lossy(line.trim_ascii()).into_owned();
+                let Some((name, value)) = self.headers.last_value_mut() else {
+                    return Err(ParseError::InvalidHeader(continuation));
+                };
+                value.push(' ');
+                value.push_str(&continuation);
+                events.push(ResponseEvent::HeaderFolded { name: name.to_string(), value: value.clone() });
+            }
+            State::Headers => {
+                self.count_field()?;
+                let (name, value) = parse_header_line(line)?;
+                self.headers.append(name.clone(), value.clone());
+                events.push(ResponseEvent::Header { name, value });
+            }
+            State::ChunkSize => {
+                let (size, extensions) = parse_chunk_line(line, self.limits.max_chunk_size)?;
+                // Checked up front so an oversized body fails before its bytes are buffered.
+                if self.body_received.saturating_add(size) > self.limits.max_body_size {
+                    warn!(target: "net::http", "Chunked body would exceed {} bytes", self.limits.max_body_size);
+                    return Err(ParseError::BodyTooLarge { limit: self.limits.max_body_size });
+                }
+                events.push(ResponseEvent::Chunk { size, extensions });
+                self.state = if size == 0 { State::Trailers } else { State::ChunkData(size) };
+            }
+            State::Trailers if line.is_empty() => self.state = self.complete(events),
+            State::Trailers => {
+                self.count_field()?;
+                let (name, value) = parse_header_line(line)?;
+                events.push(ResponseEvent::Trailer { name, value });
+            }
+            _ => unreachable!("line-oriented states only"),
+        }
+        Ok(())
+    }
+
+    fn count_body(&mut self, len: usize) -> Result<(), ParseError> {
+        self.body_received += len;
+        if self.body_received > self.limits.max_body_size {
+            return Err(ParseError::BodyTooLarge { limit: self.limits.max_body_size });
+        }
+        Ok(())
+    }
+
+    fn complete(&self, events: &mut Vec<ResponseEvent>) -> State {
+        // A message carrying both Transfer-Encoding and Content-Length may be a smuggling
+        // attempt; never reuse the connection after one (RFC 9112 section 6.3).
+        let ambiguous = self.headers.contains("Transfer-Encoding") && self.headers.contains("Content-Length");
+        let reusable = self.keep_alive()
+            && !ambiguous
+            && !matches!(self.body_length(), Ok(BodyLength::UntilClose))
+            && self.status != 101;
+        debug!(target: "net::http", "Response {} complete, reusable={}", self.status, reusable);
+        events.push(ResponseEvent::Complete { reusable });
+        State::Done
+    }
+
+    fn keep_alive(&self) -> bool {
+        let connection = self.headers.connection();
+        let has = |token: &str| connection.iter().any(|t| t == token);
+        match self.version {
+            HttpVersion::Http10 => has("keep-alive"),
+            HttpVersion::Http11 => !has("close"),
+        }
+    }
+
+    /// Decides where the body ends (RFC 9112 section 6.3).
+    fn body_length(&self) -> Result<BodyLength, ParseError> {
+        if self.request_method.eq_ignore_ascii_case("HEAD")
+            || (100..200).contains(&self.status)
+            || self.status == 204
+            || self.status == 304
+        {
+            return Ok(BodyLength::Empty);
+        }
+        self.framing()
+    }
+
+    /// Framing as declared by the headers alone.
+    fn framing(&self) -> Result<BodyLength, ParseError> {
+        if self.headers.contains("Transfer-Encoding") {
+            // Transfer-Encoding overrides Content-Length; the body is chunked only if chunked is last.
+            let codings = self.headers.tokens("Transfer-Encoding");
+            let chunked = codings.last().is_some_and(|last| last == "chunked");
+            return Ok(if chunked { BodyLength::Chunked } else { BodyLength::UntilClose });
+        }
+
+        // Repeated Content-Length values are allowed only when they all agree.
+        let mut length = None;
+        for value in self.headers.get_all("Content-Length").flat_map(|v| v.split(',')) {
+            let value = value.trim();
+            if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(ParseError::InvalidContentLength(value.to_string()));
+            }
+            let parsed = value.parse::<usize>().map_err(|_| ParseError::InvalidContentLength(value.to_string()))?;
+            if length.is_some_and(|previous| previous != parsed) {
+                let all = self.headers.get_combined("Content-Length").unwrap_or_default();
+                return Err(ParseError::InvalidContentLength(all));
+            }
+            length = Some(parsed);
+        }
+        Ok(length.map_or(BodyLength::UntilClose, BodyLength::Fixed))
+    }
+}
+
+fn parse_status_line(line: &[u8]) -> Result<(HttpVersion, u16, String), ParseError> {
+    let text = String::from_utf8_lossy(line);
+    let invalid = || ParseError::InvalidStatusLine(text.to_string());
+
+    let mut parts = text.splitn(3, ' ');
+    let version = match parts.next() {
+        Some("HTTP/1.1") => HttpVersion::Http11,
+        Some("HTTP/1.0") => HttpVersion::Http10,
+        Some(other) if other.starts_with("HTTP/") => return Err(ParseError::UnsupportedVersion(other.to_string())),
+        _ => return Err(invalid()),
+    };
+    let code = parts.next().ok_or_else(invalid)?;
+    if code.len() != 3 || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let reason = parts.next().unwrap_or("").to_string();
+    Ok((version, code.parse().map_err(|_| invalid())?, reason))
+}
+
+fn parse_header_line(line: &[u8]) -> Result<(String, String), ParseError> {
+    let invalid = || ParseError::InvalidHeader(String::from_utf8_lossy(line).into_owned());
+    let colon = line.iter().position(|&b| b == b':').ok_or_else(invalid)?;
+    let name = &line[..colon];
+    if name.is_empty() || !name.iter().all(|&b| is_token_char(b)) {
+        return Err(invalid());
+    }
+    let value = line[colon + 1..].trim_ascii();
+    Ok((String::from_utf8_lossy(name).into_owned(), String::from_utf8_lossy(value).into_owned()))
+}
+
+/// chunk-size [ chunk-ext ] (RFC 9112 section 7.1).
+fn parse_chunk_line(line: &[u8], max_chunk_size: usize) -> Result<(usize, Vec<ChunkExtension>), ParseError> {
+    let text = String::from_utf8_lossy(line);
+    let (size, extensions) = text.split_once(';').unwrap_or((&text, ""));
+
+    let size = size.trim_end_matches([' ', '\t']);
+    if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(ParseError::InvalidChunkSize(text.to_string()));
+    }
+    // Leading zeros are legal; anything wider than usize is simply too large.
+    let size = match usize::from_str_radix(size, 16) {
+        Ok(size) if size <= max_chunk_size => size,
+        _ => return Err(ParseError::ChunkTooLarge { limit: max_chunk_size }),
+    };
+
+    let extensions = if text.contains(';') { parse_chunk_extensions(extensions)? } else { Vec::new() };
+    Ok((size, extensions))
+}
+
+/// `name [= token / quoted-string]` pairs separated by `;`.
+fn parse_chunk_extensions(text: &str) -> Result<Vec<ChunkExtension>, ParseError> {
+    let invalid = || ParseError::InvalidChunkExtension(text.to_string());
+    let mut extensions = Vec::new();
+    let mut rest = text;
+    loop {
+        let (name, after) = take_token(rest.trim_start_matches([' ', '\t'])).ok_or_else(invalid)?;
+        rest = after.trim_start_matches([' ', '\t']);
+
+        let mut value = None;
+        if let Some(after_eq) = rest.strip_prefix('=') {
+            let after_eq = after_eq.trim_start_matches([' ', '\t']);
+            let (parsed, after) = match after_eq.strip_prefix('"') {
+                Some(quoted) => take_quoted(quoted).ok_or_else(invalid)?,
+                None => take_token(after_eq).map(|(t, r)| (t.to_string(), r)).ok_or_else(invalid)?,
+            };
+            value = Some(parsed);
+            rest = after.trim_start_matches([' ', '\t']);
+        }
+        extensions.push((name.to_string(), value));
+
+        match rest.strip_prefix(';') {
+            Some(next) => rest = next,
+            None if rest.is_empty() => return Ok(extensions),
+            None => return Err(invalid()),
+        }
+    }
+}
+
+fn take_token(text: &str) -> Option<(&str, &str)> {
+    let end = text.bytes().position(|b| !is_token_char(b)).unwrap_or(text.len());
+    (end > 0).then(|| text.split_at(end))
+}
+
+/// Reads a quoted-string body (opening quote already stripped), handling `\` escapes.
+fn take_quoted(text: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &text[i + 1..])),
+            '\\' => value.push(chars.next()?.1),
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// tchar from RFC 9110 section 5.6.2.
+fn is_token_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `wire` one byte at a time and collects every event.
+    fn parse_bytewise(method: &str, wire: &[u8]) -> Result<Vec<ResponseEvent>, ParseError> {
+        let mut parser = ResponseParser::new(method);
+        let mut events = Vec::new();
+        for byte in wire.chunks(1) {
+            let parsed = parser.feed(byte)?;
+            events.extend(parsed.events);
+        }
+        events.extend(parser.finish()?);
+        Ok(events)
+    }
+
+    fn body(events: &[ResponseEvent]) -> Vec<u8> {
+        events
+            .iter()
+            .filter_map(|e| match e {
+                ResponseEvent::Body(bytes) => Some(bytes.as_slice()),
+                _ => None,
+            })
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    #[test]
+    fn test_events_survive_any_split() {
+        let wire = b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nX-A: 1\r\n  folded\r\nTransfer-Encoding: chunked\r\n\r\n\
+4\r\n\xff\x00\r\n\r\n0\r\nTrailer: x\r\n\r\n";
+        let events = parse_bytewise("GET", wire).unwrap();
+
+        assert_eq!(events[0], ResponseEvent::Status { version: HttpVersion::Http11, code: 100, reason: "Continue".into() });
+        assert!(events.contains(&ResponseEvent::Header { name: "X-A".into(), value: "1".into() }));
+        assert!(events.contains(&ResponseEvent::HeaderFolded { name: "X-A".into(), value: "1 folded".into() }));
+        assert!(events.contains(&ResponseEvent::HeadersComplete { length: BodyLength::Chunked }));
+        assert_eq!(body(&events), b"\xff\x00\r\n");
+        assert!(events.contains(&ResponseEvent::Trailer { name: "Trailer".into(), value: "x".into() }));
+        assert_eq!(events.last(), Some(&ResponseEvent::Complete { reusable: true }));
+
+        let mut parser = ResponseParser::new("GET");
+        let parsed = parser.feed(b"HTTP/1.0 200 OK\r\nContent-Length: 2\r\n\r\nokNEXT").unwrap();
+        assert_eq!(parsed.consumed, 40);
+        assert_eq!(parsed.events.last(), Some(&ResponseEvent::Complete { reusable: false }));
+        assert!(parser.is_complete());
+    }
+
+    #[test]
+    fn test_framing_errors_are_typed() {
+        let cases: &[(&[u8], ParseError)] = &[
+            (b"HTTP/1.1 2000 OK\r\n\r\n", ParseError::InvalidStatusLine("HTTP/1.1 2000 OK".into())),
+            (b"HTTP/2 200\r\n\r\n", ParseError::UnsupportedVersion("HTTP/2".into())),
+            (b"HTTP/1.1 200 OK\r\nBad Header: x\r\n\r\n", ParseError::InvalidHeader("Bad Header: x".into())),
+            (
+                b"HTTP/1.1 200 OK\r\nContent-Length: 3\r\nContent-Length: 4\r\n\r\n",
+                ParseError::InvalidContentLength("3, 4".into()),
+            ),
+            (b"HTTP/1.1 200 OK\r\nContent-Length: -1\r\n\r\n", ParseError::InvalidContentLength("-1".into())),
+            (b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n", ParseError::InvalidChunkSize("zz".into())),
+            (b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n1\r\nab", ParseError::MissingChunkTerminator),
+            (b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort", ParseError::IncompleteMessage),
+        ];
+        for (wire, expected) in cases {
+            assert_eq!(parse_bytewise("GET", wire).as_ref(), Err(expected), "{}", String::from_utf8_lossy(wire));
+        }
+
+        // Agreeing duplicates are fine; HEAD responses have no body whatever the headers say.
+        let events = parse_bytewise("GET", b"HTTP/1.1 200 OK\r\nContent-Length: 2, 2\r\n\r\nok").unwrap();
+        assert_eq!(body(&events), b"ok");
+        let events = parse_bytewise("HEAD", b"HTTP/1.1 200 OK\r\nContent-Length: 99\r\n\r\n").unwrap();
+        assert!(body(&events).is_empty());
+
+        // Bodyless responses end at the head, so the connection stays usable without a length.
+        let bodyless: [(&str, &[u8]); 2] =
+            [("HEAD", b"HTTP/1.1 200 OK\r\n\r\n"), ("GET", b"HTTP/1.1 304 Not Modified\r\n\r\n")];
+        for (method, wire) in bodyless {
+            let events = parse_bytewise(method, wire).unwrap();
+            assert_eq!(events.last(), Some(&ResponseEvent::Complete { reusable: true }), "{}", method);
+        }
+        let events = parse_bytewise("GET", b"HTTP/1.1 200 OK\r\n\r\nrest").unwrap();
+        assert_eq!(events.last(), Some(&ResponseEvent::Complete { reusable: false }));
+    }
+
+    #[test]
+    fn test_chunk_extensions_and_limits() {
+        let wire = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip, chunked\r\n\r\n\
+005 ; name=\"a \\\"b\\\"\" ;flag\r\nhello\r\n0;last=1\r\n\r\n";
+        let events = parse_bytewise("GET", wire).unwrap();
+        let chunks: Vec<_> = events.iter().filter(|e| matches!(e, ResponseEvent::Chunk { .. })).collect();
+        assert_eq!(
+            chunks,
+            [
+                &ResponseEvent::Chunk {
+                    size: 5,
+                    extensions: vec![("name".into(), Some("a \"b\"".into())), ("flag".into(), None)],
+                },
+                &ResponseEvent::Chunk { size: 0, extensions: vec![("last".into(), Some("1".into()))] },
+            ]
+        );
+        assert_eq!(body(&events), b"hello");
+
+        let bad = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n1;=x\r\n";
+        assert!(matches!(parse_bytewise("GET", bad), Err(ParseError::InvalidChunkExtension(_))));
+
+        let limits = ParseLimits { max_chunk_size: 8, max_body_size: 12, ..ParseLimits::default() };
+        let parse = |wire: &[u8]| {
+            let mut parser = ResponseParser::with_limits("GET", limits);
+            parser.feed(wire).map(|_| ())
+        };
+        let chunked = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n";
+        assert_eq!(parse(format!("{}9\r\n", chunked).as_bytes()), Err(ParseError::ChunkTooLarge { limit: 8 }));
+        assert_eq!(
+            parse(format!("{}ffffffffffffffffffff\r\n", chunked).as_bytes()),
+            Err(ParseError::ChunkTooLarge { limit: 8 })
+        );
+        assert_eq!(
+            parse(format!("{}8\r\n12345678\r\n8\r\n", chunked).as_bytes()),
+            Err(ParseError::BodyTooLarge { limit: 12 })
+        );
+        assert_eq!(parse(b"HTTP/1.1 200 OK\r\nContent-Length: 13\r\n\r\n"), Err(ParseError::BodyTooLarge { limit: 12 }));
+        assert_eq!(parse(b"HTTP/1.0 200 OK\r\n\r\n0123456789abc"), Err(ParseError::BodyTooLarge { limit: 12 }));
+    }
+
+    #[test]
+    fn test_head_limits_stop_hostile_responses() {
+        let limits =
+            ParseLimits { max_status_line: 32, max_headers: 4, max_header_bytes: 128, ..ParseLimits::default() };
+        let parse = |wire: &[u8]| {
+            let mut parser = ResponseParser::with_limits("GET", limits);
+            wire.chunks(7).try_for_each(|piece| parser.feed(piece).map(|_| ()))
+        };
+        // A status line that never ends fails once it is too long, not when the newline comes.
+        let endless = [b"HTTP/1.1 200 ".as_slice(), &[b'x'; 64]].concat();
+        assert_eq!(parse(&endless), Err(ParseError::StatusLineTooLong { limit: 32 }));
+        assert!(parse(b"HTTP/1.1 200 0123456789abcdefghi\r\n").is_ok());
+
+        let many = format!("HTTP/1.1 200 OK\r\n{}\r\n", "A: 1\r\n".repeat(5));
+        assert_eq!(parse(many.as_bytes()), Err(ParseError::TooManyHeaders { limit: 4 }));
+        let big = format!("HTTP/1.1 200 OK\r\nCookie: {}", "c".repeat(200));
+        assert_eq!(parse(big.as_bytes()), Err(ParseError::HeadersTooLarge { limit: 128 }));
+        // Endless interim responses and trailers count against the same budget.
+        let interim = "HTTP/1.1 100 Continue\r\n\r\n".repeat(10);
+        assert_eq!(parse(interim.as_bytes()), Err(ParseError::HeadersTooLarge { limit: 128 }));
+        let trailers = format!("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n{}", "T: 1\r\n".repeat(4));
+        assert_eq!(parse(trailers.as_bytes()), Err(ParseError::TooManyHeaders { limit: 4 }));
+
+        let extensions = format!("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n1;{}", "x".repeat(5000));
+        assert_eq!(parse(extensions.as_bytes()), Err(ParseError::ChunkLineTooLong { limit: MAX_CHUNK_LINE }));
+    }
+}
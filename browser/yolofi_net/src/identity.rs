@@ -0,0 +1,322 @@
+// Outgoing Identity
+// Everything a server can learn about us from request fields alone: User-Agent,
+// Accept, Accept-Language, the order the fields arrive in, and how much of the
+// previous page the Referer gives away. Every request goes through `Identity::apply`,
+// so these are decided in one place instead of being scattered as string literals.
+//
+// Presets:
+//   sovereign  our own UA (yolofi_config::USER_AGENT), no Accept-Language, same-origin
+//              referrers only, and Sec-GPC: 1. Honest, but easy to single out.
+//   blend-in   the fields, values and order of the most common desktop browser
+//              configuration, so we look like one face in a large crowd. Navigations
+//              also carry its Upgrade-Insecure-Requests, Sec-Fetch-* and Priority fields.
+
+use yolofi_config::USER_AGENT;
+
+use crate::content_coding::ACCEPT_ENCODING;
+use crate::cookie_jar::is_secure_origin;
+use crate::public_suffix::PublicSuffixList;
+use crate::request::RequestBuilder;
+use crate::Url;
+
+/// Referrer Policy, section 3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReferrerPolicy {
+    NoReferrer,
+    NoReferrerWhenDowngrade,
+    SameOrigin,
+    Origin,
+    StrictOrigin,
+    OriginWhenCrossOrigin,
+    #[default]
+    StrictOriginWhenCrossOrigin,
+    UnsafeUrl,
+}
+
+impl ReferrerPolicy {
+    pub fn from_token(token: &str) -> Option<Self> {
+        let policy = match token.trim().to_ascii_lowercase().as_str() {
+            "no-referrer" => Self::NoReferrer,
+            "no-referrer-when-downgrade" => Self::NoReferrerWhenDowngrade,
+            "same-origin" => Self::SameOrigin,
+            "origin" => Self::Origin,
+            "strict-origin" => Self::StrictOrigin,
+            "origin-when-cross-origin" => Self::OriginWhenCrossOrigin,
+            "strict-origin-when-cross-origin" => Self::StrictOriginWhenCrossOrigin,
+            "unsafe-url" => Self::UnsafeUrl,
+            _ => return None,
+        };
+        Some(policy)
+    }
+
+    /// A Referrer-Policy header value: the last token we understand wins, so sites can
+    /// list a fallback before a newer policy.
+    pub fn parse_header(value: &str) -> Option<Self> {
+        value.split(',').filter_map(Self::from_token).next_back()
+    }
+
+    /// The Referer value for a request from `referrer` to `target`, if any (section 8.3).
+    pub fn referrer(self, referrer: &Url, target: &Url) -> Option<String> {
+        if !matches!(referrer.scheme(), "http" | "https") {
+            return None;
+        }
+        let full = format!("{}{}", referrer.origin(), referrer.request_target());
+        let origin = format!("{}/", referrer.origin());
+        let same_origin = referrer.origin() == target.origin();
+        let downgrade = is_secure_origin(referrer) && !is_secure_origin(target);
+        match self {
+            Self::NoReferrer => None,
+            Self::UnsafeUrl => Some(full),
+            Self::Origin => Some(origin),
+            Self::NoReferrerWhenDowngrade => (!downgrade).then_some(full),
+            Self::SameOrigin => same_origin.then_some(full),
+            Self::StrictOrigin => (!downgrade).then_some(origin),
+            Self::OriginWhenCrossOrigin => Some(if same_origin { full } else { origin }),
+            Self::StrictOriginWhenCrossOrigin if same_origin => Some(full),
+            Self::StrictOriginWhenCrossOrigin => (!downgrade).then_some(origin),
+        }
+    }
+}
+
+/// The request fields we present to servers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    pub user_agent: String,
+    pub accept: String,
+    /// `None` leaves the field out, which says nothing about the user's locale.
+    pub accept_language: Option<String>,
+    /// Used when the request does not carry its own policy.
+    pub referrer_policy: ReferrerPolicy,
+    /// Sends `Sec-GPC: 1`, the Global Privacy Control opt-out signal.
+    pub global_privacy_control: bool,
+    /// Sends the fields Firefox adds to navigations: Upgrade-Insecure-Requests, Priority
+    /// and, to secure origins, Fetch Metadata (Sec-Fetch-Dest/Mode/Site/User).
+    pub navigation_fields: bool,
+    /// Field order after Host; names are matched case-insensitively and anything not
+    /// listed keeps its relative order after the listed ones.
+    pub header_order: Vec<String>,
+}
+
+impl Default for Identity {
+    fn default() -> Self {
+        Self::sovereign()
+    }
+}
+
+impl Identity {
+    pub fn sovereign() -> Self {
+        Self {
+            user_agent: USER_AGENT.to_string(),
+            accept: "*/*".to_string(),
+            accept_language: None,
+            referrer_policy: ReferrerPolicy::SameOrigin,
+            global_privacy_control: true,
+            navigation_fields: false,
+            header_order: order(&[
+                "User-Agent",
+                "Accept",
+                "Accept-Encoding",
+                "Referer",
+                "Sec-GPC",
+                "Connection",
+                "Cookie",
+            ]),
+        }
+    }
+
+    /// Current Firefox on 64-bit Windows in en-US, the largest single bucket of desktop
+    /// fingerprints. Values and field order match what that browser sends.
+    pub fn blend_in() -> Self {
+        Self {
+            user_agent: "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:128.0) Gecko/20100101 Firefox/128.0".to_string(),
+            accept: "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8".to_string(),
+            accept_language: Some("en-US,en;q=0.5".to_string()),
+            referrer_policy: ReferrerPolicy::StrictOriginWhenCrossOrigin,
+            global_privacy_control: false,
+            navigation_fields: true,
+            header_order: order(&[
+                "User-Agent",
+                "Accept",
+                "Accept-Language",
+                "Accept-Encoding",
+                "Referer",
+                "Connection",
+                "Cookie",
+                "Upgrade-Insecure-Requests",
+                "Sec-Fetch-Dest",
+                "Sec-Fetch-Mode",
+                "Sec-Fetch-Site",
+                "Sec-Fetch-User",
+                "Priority",
+            ]),
+        }
+    }
+
+    /// Looks up a named preset (`sovereign`, `blend-in`).
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "sovereign" => Some(Self::sovereign()),
+            "blend-in" => Some(Self::blend_in()),
+            _ => None,
+        }
+    }
+
+    /// Fills in the identity fields the request does not set itself, adds Referer
+    /// according to the referrer policy, then puts the fields in our order.
+    pub fn apply(&self, request: RequestBuilder) -> RequestBuilder {
+        let mut request = request
+            .default_header("User-Agent", &self.user_agent)
+            .default_header("Accept", &self.accept)
+            .default_header("Accept-Encoding", ACCEPT_ENCODING);
+        if let Some(language) = &self.accept_language {
+            request = request.default_header("Accept-Language", language);
+        }
+        if self.global_privacy_control {
+            request = request.default_header("Sec-GPC", "1");
+        }
+        // Subresources and WebSocket upgrades have other destinations we do not model.
+        if self.navigation_fields && request.top_level().is_none() && !request.headers().contains("Upgrade") {
+            request = request.default_header("Upgrade-Insecure-Requests", "1");
+            if is_secure_origin(request.url()) {
+                let site = fetch_site(request.referrer(), request.url());
+                request = request
+                    .default_header("Sec-Fetch-Dest", "document")
+                    .default_header("Sec-Fetch-Mode", "navigate")
+                    .default_header("Sec-Fetch-Site", site)
+                    .default_header("Sec-Fetch-User", "?1");
+            }
+            request = request.default_header("Priority", "u=0, i");
+        }
+        let policy = request.referrer_policy().unwrap_or(self.referrer_policy);
+        let referer = request.referrer().and_then(|from| policy.referrer(from, request.url()));
+        request.remove_header("Referer");
+        if let Some(referer) = referer {
+            request = request.header("Referer", &referer);
+        }
+
+        let mut fields: Vec<(String, String)> =
+            request.headers().iter().map(|(n, v)| (n.to_string(), v.to_string())).collect();
+        fields.sort_by_key(|(name, _)| self.rank(name));
+        *request.headers_mut() = fields.into_iter().collect();
+        request
+    }
+
+    /// Position in `header_order`; unlisted names sort after every listed one.
+    fn rank(&self, name: &str) -> usize {
+        self.header_order.iter().position(|n| n.eq_ignore_ascii_case(name)).unwrap_or(usize::MAX)
+    }
+}
+
+/// Sec-Fetch-Site for a navigation from `from` (Fetch Metadata, section 2.1).
+/// "none" when the user started it, e.g. from the address bar.
+fn fetch_site(from: Option<&Url>, target: &Url) -> &'static str {
+    let Some(from) = from else {
+        return "none";
+    };
+    if from.origin() == target.origin() {
+        return "same-origin";
+    }
+    let site = |url: &Url| {
+        let host = url.host()?;
+        Some(match host.domain() {
+            Some(domain) => PublicSuffixList::builtin().registrable_domain(domain).unwrap_or(domain).to_string(),
+            None => host.to_string(),
+        })
+    };
+    if from.scheme() == target.scheme() && site(from).is_some_and(|s| Some(s) == site(target)) {
+        "same-site"
+    } else {
+        "cross-site"
+    }
+}
+
+fn order(names: &[&str]) -> Vec<String> {
+    names.iter().map(|n| n.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn test_referrer_policies() {
+        const FULL: &str = "https://a.test/page?q=1";
+        let from = url("https://user:pw@a.test/page?q=1#top");
+        let same = url("https://a.test/other");
+        let cross = url("https://b.test/");
+        let insecure = url("http://b.test/");
+        let cases = [
+            (ReferrerPolicy::NoReferrer, None, None, None),
+            (ReferrerPolicy::UnsafeUrl, Some(FULL), Some(FULL), Some(FULL)),
+            (ReferrerPolicy::SameOrigin, Some(FULL), None, None),
+            (ReferrerPolicy::StrictOrigin, Some("https://a.test/"), Some("https://a.test/"), None),
+            (ReferrerPolicy::StrictOriginWhenCrossOrigin, Some(FULL), Some("https://a.test/"), None),
+            (ReferrerPolicy::NoReferrerWhenDowngrade, Some(FULL), Some(FULL), None),
+        ];
+        for (policy, to_same, to_cross, to_insecure) in cases {
+            assert_eq!(policy.referrer(&from, &same).as_deref(), to_same, "{:?}", policy);
+            assert_eq!(policy.referrer(&from, &cross).as_deref(), to_cross, "{:?}", policy);
+            assert_eq!(policy.referrer(&from, &insecure).as_deref(), to_insecure, "{:?}", policy);
+        }
+        assert_eq!(ReferrerPolicy::Origin.referrer(&url("file:///etc/passwd"), &same), None);
+        assert_eq!(ReferrerPolicy::parse_header("no-referrer, bogus, Origin, future-policy"), Some(ReferrerPolicy::Origin));
+        assert_eq!(ReferrerPolicy::parse_header("bogus"), None);
+    }
+
+    #[test]
+    fn test_presets_set_fields_and_order() {
+        let target = url("https://b.test/app");
+        let request = RequestBuilder::get(&target)
+            .header("Cookie", "sid=1")
+            .header("X-Custom", "1")
+            .header("Connection", "keep-alive")
+            .with_referrer(&url("https://a.test/from?secret=1"));
+
+        let sovereign = Identity::sovereign().apply(request.clone()).build().unwrap();
+        let names: Vec<&str> = sovereign.headers.iter().map(|(n, _)| n).collect();
+        assert_eq!(names, ["Host", "User-Agent", "Accept", "Accept-Encoding", "Sec-GPC", "Connection", "Cookie", "X-Custom"]);
+        assert_eq!(sovereign.headers.get("User-Agent"), Some(USER_AGENT));
+
+        let blended = Identity::preset("blend-in").unwrap().apply(request.header("User-Agent", "mine")).build().unwrap();
+        assert_eq!(blended.headers.get("User-Agent"), Some("mine"));
+        assert_eq!(blended.headers.get("Referer"), Some("https://a.test/"));
+        assert_eq!(blended.headers.get("Sec-Fetch-Site"), Some("cross-site"));
+        assert_eq!(blended.headers.iter().last().map(|(n, _)| n), Some("X-Custom"));
+        assert_eq!(Identity::preset("chrome"), None);
+    }
+
+    #[test]
+    fn test_blend_in_navigation_on_the_wire() {
+        let identity = Identity::blend_in();
+        let request = RequestBuilder::get(&url("https://www.example.com/")).header("Connection", "keep-alive");
+        let wire = identity.apply(request).build().unwrap().serialize();
+        let expected = "GET / HTTP/1.1\r\n\
+            Host: www.example.com\r\n\
+            User-Agent: Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:128.0) Gecko/20100101 Firefox/128.0\r\n\
+            Accept: text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8\r\n\
+            Accept-Language: en-US,en;q=0.5\r\n\
+            Accept-Encoding: gzip, deflate, br, zstd\r\n\
+            Connection: keep-alive\r\n\
+            Upgrade-Insecure-Requests: 1\r\n\
+            Sec-Fetch-Dest: document\r\n\
+            Sec-Fetch-Mode: navigate\r\n\
+            Sec-Fetch-Site: none\r\n\
+            Sec-Fetch-User: ?1\r\n\
+            Priority: u=0, i\r\n\r\n";
+        assert_eq!(String::from_utf8(wire).unwrap(), expected);
+
+        // A link within the site; Fetch Metadata is only sent to secure origins.
+        let from = url("https://www.example.com/");
+        let same_site = identity.apply(RequestBuilder::get(&url("https://shop.example.com/")).with_referrer(&from));
+        assert_eq!(same_site.build().unwrap().headers.get("Sec-Fetch-Site"), Some("same-site"));
+        let insecure = identity.apply(RequestBuilder::get(&url("http://www.example.com/"))).build().unwrap();
+        assert_eq!(insecure.headers.get("Upgrade-Insecure-Requests"), Some("1"));
+        assert!(!insecure.headers.contains("Sec-Fetch-Mode"));
+        let image = identity.apply(RequestBuilder::get(&url("https://cdn.test/a.png")).with_top_level(&from));
+        assert!(!image.build().unwrap().headers.contains("Priority"));
+    }
+}
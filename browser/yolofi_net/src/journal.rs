@@ -0,0 +1,237 @@
+// Network Capture Journal
+// Append-only record of every byte the browser exchanges with the network.
+// Phase 1 requirement: "We can record and replay network packets exactly."
+//
+// File layout:
+//   MAGIC (5 bytes)
+//   Record* := kind:u8 | conn_id:u32 | tick:u64 | len:u32 | payload[len]   (big endian)
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::{debug, info};
+
+const MAGIC: &[u8; 5] = b"YFNJ\x01";
+const RECORD_HEADER_LEN: usize = 1 + 4 + 8 + 4;
+
+const KIND_OPEN: u8 = 0;
+const KIND_READ: u8 = 1;
+const KIND_WRITE: u8 = 2;
+
+/// Direction of a data record, seen from the browser.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JournalRecord {
+    /// A new connection to `peer` was opened.
+    Open { conn_id: u32, tick: u64, peer: String },
+    /// Raw bytes crossed the wire. An empty `Read` marks end of stream.
+    Data { conn_id: u32, tick: u64, direction: Direction, bytes: Vec<u8> },
+}
+
+impl JournalRecord {
+    pub fn conn_id(&self) -> u32 {
+        match self {
+            JournalRecord::Open { conn_id, .. } | JournalRecord::Data { conn_id, .. } => *conn_id,
+        }
+    }
+
+    pub fn tick(&self) -> u64 {
+        match self {
+            JournalRecord::Open { tick, .. } | JournalRecord::Data { tick, .. } => *tick,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let (kind, conn_id, tick, payload) = match self {
+            JournalRecord::Open { conn_id, tick, peer } => (KIND_OPEN, *conn_id, *tick, peer.as_bytes()),
+            JournalRecord::Data { conn_id, tick, direction, bytes } => {
+                let kind = match direction {
+                    Direction::Read => KIND_READ,
+                    Direction::Write => KIND_WRITE,
+                };
+                (kind, *conn_id, *tick, bytes.as_slice())
+            }
+        };
+
+        let mut out = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+        out.push(kind);
+        out.extend_from_slice(&conn_id.to_be_bytes());
+        out.extend_from_slice(&tick.to_be_bytes());
+        out.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        out.extend_from_slice(payload);
+        out
+    }
+}
+
+/// Reads every record from a journal file, in the order they were written.
+pub fn read_journal(path: impl AsRef<Path>) -> io::Result<Vec<JournalRecord>> {
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+    decode_journal(&bytes)
+}
+
+fn decode_journal(bytes: &[u8]) -> io::Result<Vec<JournalRecord>> {
+    if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a YoloFi network journal"));
+    }
+
+    let mut records = Vec::new();
+    let mut pos = MAGIC.len();
+
+    while pos < bytes.len() {
+        if bytes.len() - pos < RECORD_HEADER_LEN {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated journal record header"));
+        }
+        let kind = bytes[pos];
+        let conn_id = u32::from_be_bytes(bytes[pos + 1..pos + 5].try_into().unwrap());
+        let tick = u64::from_be_bytes(bytes[pos + 5..pos + 13].try_into().unwrap());
+        let len = u32::from_be_bytes(bytes[pos + 13..pos + 17].try_into().unwrap()) as usize;
+        pos += RECORD_HEADER_LEN;
+
+        if bytes.len() - pos < len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated journal record payload"));
+        }
+        let payload = bytes[pos..pos + len].to_vec();
+        pos += len;
+
+        let record = match kind {
+            KIND_OPEN => JournalRecord::Open {
+                conn_id,
+                tick,
+                peer: String::from_utf8(payload)
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Peer address is not UTF-8"))?,
+            },
+            KIND_READ => JournalRecord::Data { conn_id, tick, direction: Direction::Read, bytes: payload },
+            KIND_WRITE => JournalRecord::Data { conn_id, tick, direction: Direction::Write, bytes: payload },
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unknown journal record kind {}", other),
+                ))
+            }
+        };
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+struct CaptureState {
+    file: File,
+    next_conn_id: u32,
+    next_tick: u64,
+}
+
+/// Shared handle to an append-only capture file.
+/// Cloning the handle shares the same file, connection counter and logical clock.
+#[derive(Clone)]
+pub struct CaptureJournal {
+    path: PathBuf,
+    state: Arc<Mutex<CaptureState>>,
+}
+
+impl CaptureJournal {
+    /// Opens `path` for appending, creating it if needed.
+    /// Connection ids and ticks continue from any records already in the file.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let existing = path.exists() && std::fs::metadata(&path)?.len() > 0;
+
+        let (next_conn_id, next_tick) = if existing {
+            let records = read_journal(&path)?;
+            let conn = records.iter().map(|r| r.conn_id() + 1).max().unwrap_or(0);
+            let tick = records.iter().map(|r| r.tick() + 1).max().unwrap_or(0);
+            (conn, tick)
+        } else {
+            (0, 0)
+        };
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        if !existing {
+            file.write_all(MAGIC)?;
+        }
+
+        info!(target: "net::journal", "Capturing network traffic to {}", path.display());
+        Ok(Self {
+            path,
+            state: Arc::new(Mutex::new(CaptureState { file, next_conn_id, next_tick })),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Registers a new connection and returns its id.
+    pub fn open_connection(&self, peer: &str) -> io::Result<u32> {
+        let mut state = self.lock();
+        let conn_id = state.next_conn_id;
+        state.next_conn_id += 1;
+        let tick = state.advance();
+        state.append(&JournalRecord::Open { conn_id, tick, peer: peer.to_string() })?;
+        debug!(target: "net::journal", "Opened connection #{} to {} at tick {}", conn_id, peer, tick);
+        Ok(conn_id)
+    }
+
+    pub fn record(&self, conn_id: u32, direction: Direction, bytes: &[u8]) -> io::Result<()> {
+        let mut state = self.lock();
+        let tick = state.advance();
+        state.append(&JournalRecord::Data { conn_id, tick, direction, bytes: bytes.to_vec() })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CaptureState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl CaptureState {
+    fn advance(&mut self) -> u64 {
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        tick
+    }
+
+    fn append(&mut self, record: &JournalRecord) -> io::Result<()> {
+        // One write per record keeps the file consistent if we crash mid-session.
+        self.file.write_all(&record.encode())?;
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_round_trip_through_file() {
+        let path = std::env::temp_dir().join(format!("yolofi_journal_{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let journal = CaptureJournal::open(&path).unwrap();
+        let conn = journal.open_connection("127.0.0.1:80").unwrap();
+        journal.record(conn, Direction::Write, b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        journal.record(conn, Direction::Read, &[0, 159, 146, 150]).unwrap();
+        drop(journal);
+
+        // Reopening continues the clock instead of restarting it.
+        let journal = CaptureJournal::open(&path).unwrap();
+        let second = journal.open_connection("127.0.0.1:443").unwrap();
+        assert_eq!(second, 1);
+
+        let records = read_journal(&path).unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(
+            records[2],
+            JournalRecord::Data { conn_id: 0, tick: 2, direction: Direction::Read, bytes: vec![0, 159, 146, 150] }
+        );
+        assert_eq!(records[3].tick(), 3);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -2,6 +2,38 @@ pub mod dns;
 pub mod tcp;
 pub mod tls;
 pub mod http;
+pub mod headers;
+pub mod request;
+pub mod identity;
+pub mod http_parser;
+pub mod http2;
+pub mod hpack;
+pub mod http_date;
+pub mod content_coding;
+pub mod mime_sniff;
+pub mod cookie_jar;
+pub mod public_suffix;
+pub mod http_cache;
+pub mod hsts;
+pub mod websocket;
+pub mod fetch;
+pub mod limits;
+pub mod journal;
+pub mod replay;
+pub mod transport;
+pub mod pipe;
+pub mod pool;
+pub mod proxy;
+pub mod emulation;
+pub mod profile;
+pub mod trust_store;
+pub mod pin_store;
+pub mod security;
+
+pub use yolofi_url::Url;
+
+#[cfg(test)]
+mod test_pki;
 
 pub fn init() {
     tracing::info!("Networking stack initialized.");
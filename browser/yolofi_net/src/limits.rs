@@ -0,0 +1,32 @@
+// Resource Limits
+// Everything a peer can make the network stack read, buffer or follow is capped, and
+// the caps live together here so a client can tighten them in one place:
+//   response  HTTP/1.1 status line, field count, head bytes, chunk and body sizes (body on HTTP/2 too)
+//   decoded   Content-Encoding output size and compression ratio
+//   redirects hops followed by `FetchClient`
+//   dns       compression pointers and labels per name in a DNS reply
+// Each limit fails with a typed error from the layer that enforces it.
+
+use crate::content_coding::DecodeLimits;
+use crate::dns::DnsLimits;
+use crate::fetch::DEFAULT_MAX_REDIRECTS;
+use crate::http_parser::ParseLimits;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub response: ParseLimits,
+    pub decoded: DecodeLimits,
+    pub max_redirects: usize,
+    pub dns: DnsLimits,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            response: ParseLimits::default(),
+            decoded: DecodeLimits::default(),
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            dns: DnsLimits::default(),
+        }
+    }
+}
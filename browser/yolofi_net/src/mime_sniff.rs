@@ -0,0 +1,262 @@
+// MIME Type Sniffing (WHATWG MIME Sniffing standard)
+// Servers often send no Content-Type, a wrong one, or Apache's catch-all
+// "text/plain". The computed MIME type starts from what the server supplied and
+// looks at the first bytes of the body only where the standard allows it:
+//   - no usable Content-Type          -> identify from the bytes (HTML only if allowed)
+//   - X-Content-Type-Options: nosniff -> trust the server, full stop
+//   - Apache's default text/plain     -> only decide between text and binary
+//   - image/*, audio/*, video/*       -> correct the subtype if the bytes say otherwise
+// Sniffing never upgrades a response to HTML unless there was no type at all.
+
+use crate::headers::HeaderMap;
+
+/// The standard looks at no more than this many bytes.
+pub const RESOURCE_HEADER_LIMIT: usize = 1445;
+
+/// A byte signature: `mask` is applied to the input before comparing with `pattern`.
+struct Pattern {
+    pattern: &'static [u8],
+    mask: &'static [u8],
+    mime: &'static str,
+}
+
+const fn exact(pattern: &'static [u8], mime: &'static str) -> Pattern {
+    Pattern { pattern, mask: &[0xFF; 16], mime }
+}
+
+/// The HTML-ish signatures: a case-insensitive tag followed by a space or '>'.
+const SCRIPTABLE_TAGS: [&[u8]; 17] = [
+    b"<!DOCTYPE HTML", b"<HTML", b"<HEAD", b"<SCRIPT", b"<IFRAME", b"<H1", b"<DIV", b"<FONT", b"<TABLE", b"<A",
+    b"<STYLE", b"<TITLE", b"<B", b"<BODY", b"<BR", b"<P", b"<!--",
+];
+
+const IMAGE_PATTERNS: [Pattern; 8] = [
+    exact(b"\x00\x00\x01\x00", "image/x-icon"),
+    exact(b"\x00\x00\x02\x00", "image/x-icon"),
+    exact(b"BM", "image/bmp"),
+    exact(b"GIF87a", "image/gif"),
+    exact(b"GIF89a", "image/gif"),
+    Pattern {
+        pattern: b"RIFF\x00\x00\x00\x00WEBPVP",
+        mask: &[0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+        mime: "image/webp",
+    },
+    exact(b"\x89PNG\r\n\x1A\n", "image/png"),
+    exact(b"\xFF\xD8\xFF", "image/jpeg"),
+];
+
+const MEDIA_PATTERNS: [Pattern; 5] = [
+    Pattern {
+        pattern: b"FORM\x00\x00\x00\x00AIFF",
+        mask: &[0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF],
+        mime: "audio/aiff",
+    },
+    exact(b"ID3", "audio/mpeg"),
+    exact(b"OggS\x00", "application/ogg"),
+    exact(b"MThd\x00\x00\x00\x06", "audio/midi"),
+    Pattern {
+        pattern: b"RIFF\x00\x00\x00\x00WAVE",
+        mask: &[0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF],
+        mime: "audio/wave",
+    },
+];
+
+const OTHER_PATTERNS: [Pattern; 8] = [
+    exact(b"%PDF-", "application/pdf"),
+    exact(b"%!PS-Adobe-", "application/postscript"),
+    exact(b"\xFE\xFF", "text/plain"),
+    exact(b"\xFF\xFE", "text/plain"),
+    exact(b"\xEF\xBB\xBF", "text/plain"),
+    exact(b"\x1F\x8B\x08", "application/x-gzip"),
+    exact(b"PK\x03\x04", "application/zip"),
+    exact(b"Rar!\x1A\x07\x00", "application/x-rar-compressed"),
+];
+
+/// The type a response is handled as, from its fields and the start of its body.
+pub fn computed_mime_type(headers: &HeaderMap, body: &[u8]) -> String {
+    let nosniff = headers.tokens("X-Content-Type-Options").iter().any(|t| t == "nosniff");
+    let apache_bug = headers.content_type().is_some_and(is_apache_default);
+    sniff(headers.mime_type().as_deref(), nosniff, apache_bug, body)
+}
+
+/// MIME Sniffing section 7.1. `supplied` is the essence of the Content-Type, if any.
+pub fn sniff(supplied: Option<&str>, nosniff: bool, apache_bug: bool, body: &[u8]) -> String {
+    let header = &body[..body.len().min(RESOURCE_HEADER_LIMIT)];
+    let supplied = match supplied {
+        Some("unknown/unknown" | "application/unknown" | "*/*") | None => return identify_unknown(header, !nosniff),
+        Some(mime) if !mime.contains('/') => return identify_unknown(header, !nosniff),
+        Some(mime) => mime,
+    };
+    if nosniff {
+        return supplied.to_string();
+    }
+    if apache_bug {
+        return text_or_binary(header).to_string();
+    }
+    if is_xml(supplied) || supplied == "text/html" {
+        return supplied.to_string();
+    }
+    if supplied.starts_with("image/") {
+        if let Some(mime) = match_patterns(&IMAGE_PATTERNS, header) {
+            return mime.to_string();
+        }
+    }
+    if supplied.starts_with("audio/") || supplied.starts_with("video/") {
+        if let Some(mime) = match_media(header) {
+            return mime.to_string();
+        }
+    }
+    supplied.to_string()
+}
+
+pub fn is_xml(mime: &str) -> bool {
+    mime.ends_with("+xml") || mime == "text/xml" || mime == "application/xml"
+}
+
+pub fn is_json(mime: &str) -> bool {
+    mime.ends_with("+json") || mime == "application/json" || mime == "text/json"
+}
+
+pub fn is_javascript(mime: &str) -> bool {
+    matches!(
+        mime,
+        "application/javascript" | "application/ecmascript" | "application/x-javascript" | "text/javascript"
+            | "text/ecmascript" | "text/jscript" | "text/livescript" | "text/x-javascript" | "text/x-ecmascript"
+    )
+}
+
+/// The exact Content-Type values Apache sends for files it knows nothing about.
+fn is_apache_default(content_type: &str) -> bool {
+    matches!(
+        content_type,
+        "text/plain" | "text/plain; charset=ISO-8859-1" | "text/plain; charset=iso-8859-1" | "text/plain; charset=UTF-8"
+    )
+}
+
+/// Section 7.1, "rules for identifying an unknown MIME type".
+fn identify_unknown(header: &[u8], sniff_scriptable: bool) -> String {
+    if sniff_scriptable {
+        let start = header.iter().position(|&b| !is_whitespace(b)).unwrap_or(header.len());
+        let rest = &header[start..];
+        let tag = SCRIPTABLE_TAGS.iter().find(|tag| {
+            rest.len() > tag.len()
+                && rest[..tag.len()].eq_ignore_ascii_case(tag)
+                && matches!(rest[tag.len()], b' ' | b'>')
+        });
+        if tag.is_some() {
+            return "text/html".to_string();
+        }
+        if rest.starts_with(b"<?xml") {
+            return "text/xml".to_string();
+        }
+    }
+    let found = match_patterns(&OTHER_PATTERNS, header)
+        .or_else(|| match_patterns(&IMAGE_PATTERNS, header))
+        .or_else(|| match_media(header));
+    found.unwrap_or_else(|| text_or_binary(header)).to_string()
+}
+
+/// Section 7.2, for responses served with Apache's default type.
+fn text_or_binary(header: &[u8]) -> &'static str {
+    let bom = header.starts_with(b"\xFE\xFF") || header.starts_with(b"\xFF\xFE") || header.starts_with(b"\xEF\xBB\xBF");
+    if bom || !header.iter().any(|&b| is_binary(b)) {
+        "text/plain"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+fn match_patterns(patterns: &[Pattern], header: &[u8]) -> Option<&'static str> {
+    patterns.iter().find(|p| matches_pattern(p, header)).map(|p| p.mime)
+}
+
+fn matches_pattern(pattern: &Pattern, header: &[u8]) -> bool {
+    header.len() >= pattern.pattern.len()
+        && pattern.pattern.iter().zip(pattern.mask).zip(header).all(|((&p, &m), &b)| b & m == p)
+}
+
+fn match_media(header: &[u8]) -> Option<&'static str> {
+    match_patterns(&MEDIA_PATTERNS, header).or_else(|| {
+        if is_mp4(header) {
+            Some("video/mp4")
+        } else if is_webm(header) {
+            Some("video/webm")
+        } else {
+            None
+        }
+    })
+}
+
+/// Section 6.2.1: an "ftyp" box whose major or a compatible brand starts with "mp4".
+fn is_mp4(header: &[u8]) -> bool {
+    let Some(size) = header.get(..4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize) else {
+        return false;
+    };
+    if header.len() < size || size < 12 || !size.is_multiple_of(4) || &header[4..8] != b"ftyp" {
+        return false;
+    }
+    header[8..11] == *b"mp4" || (16..size).step_by(4).any(|i| header.get(i..i + 3) == Some(b"mp4"))
+}
+
+/// Section 6.2.2: an EBML header whose DocType element is "webm".
+fn is_webm(header: &[u8]) -> bool {
+    if !header.starts_with(b"\x1A\x45\xDF\xA3") {
+        return false;
+    }
+    let end = header.len().min(38);
+    (4..end.saturating_sub(1)).any(|i| {
+        if header[i..i + 2] != *b"\x42\x82" {
+            return false;
+        }
+        // The DocType length is a variable-size integer: its leading zero bits give its width.
+        let Some(&first) = header.get(i + 2) else { return false };
+        let width = first.leading_zeros() as usize + 1;
+        width <= 8 && header.get(i + 2 + width..i + 6 + width) == Some(b"webm")
+    })
+}
+
+fn is_whitespace(b: u8) -> bool {
+    matches!(b, b'\t' | b'\n' | b'\x0C' | b'\r' | b' ')
+}
+
+/// Section 3: bytes that never appear in text.
+fn is_binary(b: u8) -> bool {
+    matches!(b, 0x00..=0x08 | 0x0B | 0x0E..=0x1A | 0x1C..=0x1F)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_types_are_identified_from_bytes() {
+        assert_eq!(sniff(None, false, false, b"  \n<!doctype html><p>hi"), "text/html");
+        assert_eq!(sniff(Some("unknown/unknown"), false, false, b"<?xml version='1.0'?>"), "text/xml");
+        assert_eq!(sniff(None, false, false, b"\x89PNG\r\n\x1A\n...."), "image/png");
+        assert_eq!(sniff(None, false, false, b"RIFF\x10\x00\x00\x00WEBPVP8 "), "image/webp");
+        assert_eq!(sniff(None, false, false, b"%PDF-1.7"), "application/pdf");
+        assert_eq!(sniff(None, false, false, b"\x00\x00\x00\x18ftypmp42\x00\x00\x00\x00mp41isom"), "video/mp4");
+        assert_eq!(sniff(None, false, false, b"\x1A\x45\xDF\xA3\x9F\x42\x86\x81\x01\x42\x82\x84webm"), "video/webm");
+        assert_eq!(sniff(None, false, false, b"plain words"), "text/plain");
+        assert_eq!(sniff(None, false, false, b"\x00\x01binary"), "application/octet-stream");
+        // "<a" must be a whole tag name, and nosniff never produces HTML.
+        assert_eq!(sniff(None, false, false, b"<abbr>"), "text/plain");
+        assert_eq!(sniff(None, true, false, b"<html>"), "text/plain");
+    }
+
+    #[test]
+    fn test_supplied_types_and_nosniff() {
+        let headers = |fields: &[(&str, &str)]| -> HeaderMap { fields.iter().copied().collect() };
+        let gif = b"GIF89a\x01\x00";
+        assert_eq!(computed_mime_type(&headers(&[("Content-Type", "image/png")]), gif), "image/gif");
+        // Apache's default type only distinguishes text from binary: no HTML, no images.
+        assert_eq!(computed_mime_type(&headers(&[("Content-Type", "text/plain")]), b"<html>"), "text/plain");
+        assert_eq!(computed_mime_type(&headers(&[("Content-Type", "text/plain")]), gif), "application/octet-stream");
+        assert_eq!(computed_mime_type(&headers(&[("Content-Type", "text/plain; charset=utf-8")]), gif), "text/plain");
+        let nosniff = headers(&[("Content-Type", "text/plain"), ("X-Content-Type-Options", "NoSniff")]);
+        assert_eq!(computed_mime_type(&nosniff, gif), "text/plain");
+        let json = headers(&[("Content-Type", "Application/JSON; charset=utf-8")]);
+        assert_eq!(computed_mime_type(&json, b"<html>"), "application/json");
+        assert!(is_json("application/ld+json") && is_xml("image/svg+xml"));
+    }
+}
@@ -0,0 +1,399 @@
+// Certificate Pinning
+// Per-host SPKI pins, checked after normal chain verification succeeds.
+//   - Static pins are configured up front (our internal services).
+//   - TOFU pins are learned on the first successful connection to a host
+//     inside an opted-in domain, and enforced from then on.
+// Each host has primary pins and optional backup pins. A connection that matches
+// a backup pin is accepted; for TOFU hosts the backup is then promoted (rotation).
+//
+// Profile file `pins.txt`, one entry per line:
+//   static <host> pin=<sha256> ... backup=<sha256> ...
+//   tofu <host> pin=<sha256> ... backup=<sha256> ...
+//   tofu-domain <domain>
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+use crate::profile::Profile;
+
+const PINS_FILE: &str = "pins.txt";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinSource {
+    Static,
+    Tofu,
+}
+
+impl fmt::Display for PinSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PinSource::Static => write!(f, "static"),
+            PinSource::Tofu => write!(f, "trust-on-first-use"),
+        }
+    }
+}
+
+/// The pins recorded for one host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinSet {
+    pub host: String,
+    pub source: PinSource,
+    /// Hex SHA-256 SPKI fingerprints, as produced by `trust_store::spki_fingerprint`.
+    pub pins: Vec<String>,
+    pub backups: Vec<String>,
+}
+
+impl PinSet {
+    fn matches(&self, presented: &[String]) -> Option<(String, bool)> {
+        let hit = |pins: &[String]| presented.iter().find(|fp| pins.contains(fp)).cloned();
+        hit(&self.pins).map(|fp| (fp, false)).or_else(|| hit(&self.backups).map(|fp| (fp, true)))
+    }
+}
+
+/// Why a connection was rejected: everything needed to explain it to the user.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error(
+    "public key pin mismatch for {host} ({kind} pin): expected one of [{}], server presented [{}]",
+    .expected.join(", "),
+    .presented.join(", ")
+)]
+pub struct PinViolation {
+    pub host: String,
+    pub kind: PinSource,
+    /// Primary and backup pins that would have been accepted.
+    pub expected: Vec<String>,
+    /// SPKI fingerprints of the verified path, leaf first and trust anchor last.
+    pub presented: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PinVerdict {
+    /// No pins apply to this host.
+    Unpinned,
+    /// A pin matched. `backup` is true when it was a backup pin.
+    Matched { source: PinSource, pin: String, backup: bool },
+    /// First visit to a TOFU host: the leaf key is now pinned.
+    Learned(String),
+}
+
+#[derive(Debug, Default)]
+struct PinState {
+    static_pins: BTreeMap<String, PinSet>,
+    tofu_pins: BTreeMap<String, PinSet>,
+    tofu_domains: BTreeSet<String>,
+}
+
+impl PinState {
+    fn lookup(&self, host: &str) -> Option<&PinSet> {
+        self.static_pins.get(host).or_else(|| self.tofu_pins.get(host))
+    }
+
+    fn tofu_applies(&self, host: &str) -> bool {
+        self.tofu_domains.iter().any(|domain| host == domain || host.ends_with(&format!(".{}", domain)))
+    }
+}
+
+/// Shared handle to the pin store; clones see the same pins.
+#[derive(Debug, Clone, Default)]
+pub struct PinStore {
+    state: Arc<Mutex<PinState>>,
+    profile: Option<Profile>,
+}
+
+impl PinStore {
+    /// An in-memory store. Nothing is persisted.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads pins from `profile`. Every later change is written back to it.
+    pub fn load(profile: &Profile) -> io::Result<Self> {
+        let mut state = PinState::default();
+        if let Some(text) = profile.read_optional(PINS_FILE)? {
+            for (index, line) in text.lines().enumerate() {
+                parse_line(&mut state, line)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{} line {}: {}", PINS_FILE, index + 1, e)))?;
+            }
+        }
+        info!(
+            target: "net::pins",
+            "Pin store loaded: {} static, {} TOFU, {} TOFU domains",
+            state.static_pins.len(),
+            state.tofu_pins.len(),
+            state.tofu_domains.len()
+        );
+        Ok(Self { state: Arc::new(Mutex::new(state)), profile: Some(profile.clone()) })
+    }
+
+    /// Pins `host` to the given SPKI fingerprints. Replaces any earlier static entry.
+    pub fn add_static(&self, host: &str, pins: &[String], backups: &[String]) -> io::Result<()> {
+        if pins.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "a static pin set needs at least one pin"));
+        }
+        let set = PinSet {
+            host: normalize_host(host),
+            source: PinSource::Static,
+            pins: validate_pins(pins)?,
+            backups: validate_pins(backups)?,
+        };
+        self.update(|state| {
+            state.static_pins.insert(set.host.clone(), set);
+        })
+    }
+
+    /// Enables trust-on-first-use for `domain` and all of its subdomains.
+    pub fn enable_tofu(&self, domain: &str) -> io::Result<()> {
+        let domain = normalize_host(domain);
+        self.update(|state| {
+            state.tofu_domains.insert(domain);
+        })
+    }
+
+    /// Replaces the backup pins of an existing entry, ahead of a key rotation.
+    pub fn set_backups(&self, host: &str, backups: &[String]) -> io::Result<()> {
+        let host = normalize_host(host);
+        let backups = validate_pins(backups)?;
+        let mut found = false;
+        self.update(|state| {
+            let set = match state.static_pins.get_mut(&host) {
+                Some(set) => Some(set),
+                None => state.tofu_pins.get_mut(&host),
+            };
+            if let Some(set) = set {
+                set.backups = backups;
+                found = true;
+            }
+        })?;
+        if !found {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("no pins recorded for {}", host)));
+        }
+        Ok(())
+    }
+
+    /// Drops every pin for `host`. Returns whether anything was removed.
+    pub fn forget(&self, host: &str) -> io::Result<bool> {
+        let host = normalize_host(host);
+        let mut removed = false;
+        self.update(|state| {
+            removed |= state.static_pins.remove(&host).is_some();
+            removed |= state.tofu_pins.remove(&host).is_some();
+        })?;
+        Ok(removed)
+    }
+
+    /// The pins that apply to `host`, static taking precedence over TOFU.
+    pub fn pin_set(&self, host: &str) -> Option<PinSet> {
+        self.lock().lookup(&normalize_host(host)).cloned()
+    }
+
+    /// Every recorded pin set, static first.
+    pub fn entries(&self) -> Vec<PinSet> {
+        let state = self.lock();
+        state.static_pins.values().chain(state.tofu_pins.values()).cloned().collect()
+    }
+
+    pub fn tofu_domains(&self) -> Vec<String> {
+        self.lock().tofu_domains.iter().cloned().collect()
+    }
+
+    /// Checks SPKI fingerprints against the pins for `host`. `presented` must be the path chain
+    /// verification built, leaf first and trust anchor last, not whatever the server sent.
+    pub fn check(&self, host: &str, presented: &[String]) -> Result<PinVerdict, PinViolation> {
+        let host = normalize_host(host);
+        let mut state = self.lock();
+
+        let Some(set) = state.lookup(&host) else {
+            let Some(leaf) = presented.first().filter(|_| state.tofu_applies(&host)) else {
+                return Ok(PinVerdict::Unpinned);
+            };
+            let leaf = leaf.clone();
+            info!(target: "net::pins", "TOFU: pinning {} to {}", host, leaf);
+            let set = PinSet { host: host.clone(), source: PinSource::Tofu, pins: vec![leaf.clone()], backups: Vec::new() };
+            state.tofu_pins.insert(host, set);
+            self.persist(&state);
+            return Ok(PinVerdict::Learned(leaf));
+        };
+
+        let source = set.source;
+        let Some((pin, backup)) = set.matches(presented) else {
+            let violation = PinViolation {
+                host: host.clone(),
+                kind: source,
+                expected: set.pins.iter().chain(&set.backups).cloned().collect(),
+                presented: presented.to_vec(),
+            };
+            warn!(target: "net::pins", "{}", violation);
+            return Err(violation);
+        };
+
+        if backup {
+            warn!(target: "net::pins", "{} matched backup pin {}", host, pin);
+            if source == PinSource::Tofu {
+                // Rotation: the backup becomes the primary; the old key stops being accepted.
+                let set = state.tofu_pins.get_mut(&host).expect("looked up above");
+                set.backups.retain(|fp| fp != &pin);
+                set.pins = vec![pin.clone()];
+                info!(target: "net::pins", "Rotated TOFU pin for {} to {}", host, pin);
+                self.persist(&state);
+            }
+        }
+        Ok(PinVerdict::Matched { source, pin, backup })
+    }
+
+    fn update(&self, change: impl FnOnce(&mut PinState)) -> io::Result<()> {
+        let mut state = self.lock();
+        change(&mut state);
+        match &self.profile {
+            Some(profile) => profile.write_atomic(PINS_FILE, serialize(&state).as_bytes()),
+            None => Ok(()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, PinState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Saves after a change made during a handshake, where there is no caller to report to.
+    fn persist(&self, state: &PinState) {
+        if let Some(profile) = &self.profile {
+            if let Err(e) = profile.write_atomic(PINS_FILE, serialize(state).as_bytes()) {
+                warn!(target: "net::pins", "Failed to save {}: {}", PINS_FILE, e);
+            }
+        }
+    }
+}
+
+fn normalize_host(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn validate_pins(pins: &[String]) -> io::Result<Vec<String>> {
+    pins.iter()
+        .map(|pin| {
+            let pin = pin.to_ascii_lowercase();
+            if pin.len() == 64 && pin.bytes().all(|b| b.is_ascii_hexdigit()) {
+                Ok(pin)
+            } else {
+                Err(io::Error::new(io::ErrorKind::InvalidInput, format!("'{}' is not a hex SHA-256 fingerprint", pin)))
+            }
+        })
+        .collect()
+}
+
+fn parse_line(state: &mut PinState, line: &str) -> Result<(), String> {
+    let mut words = line.split_whitespace();
+    let (source, host) = match (words.next(), words.next()) {
+        (None, _) => return Ok(()),
+        (Some(word), _) if word.starts_with('#') => return Ok(()),
+        (Some("tofu-domain"), Some(domain)) => {
+            state.tofu_domains.insert(normalize_host(domain));
+            return Ok(());
+        }
+        (Some("static"), Some(host)) => (PinSource::Static, host),
+        (Some("tofu"), Some(host)) => (PinSource::Tofu, host),
+        _ => return Err(format!("unrecognised entry '{}'", line)),
+    };
+
+    let mut set = PinSet { host: normalize_host(host), source, pins: Vec::new(), backups: Vec::new() };
+    for word in words {
+        match word.split_once('=') {
+            Some(("pin", fp)) => set.pins.push(fp.to_string()),
+            Some(("backup", fp)) => set.backups.push(fp.to_string()),
+            _ => return Err(format!("unrecognised field '{}'", word)),
+        }
+    }
+    set.pins = validate_pins(&set.pins).map_err(|e| e.to_string())?;
+    set.backups = validate_pins(&set.backups).map_err(|e| e.to_string())?;
+
+    let map = match source {
+        PinSource::Static => &mut state.static_pins,
+        PinSource::Tofu => &mut state.tofu_pins,
+    };
+    map.insert(set.host.clone(), set);
+    Ok(())
+}
+
+fn serialize(state: &PinState) -> String {
+    let mut out = String::new();
+    for domain in &state.tofu_domains {
+        out.push_str(&format!("tofu-domain {}\n", domain));
+    }
+    for set in state.static_pins.values().chain(state.tofu_pins.values()) {
+        out.push_str(match set.source {
+            PinSource::Static => "static ",
+            PinSource::Tofu => "tofu ",
+        });
+        out.push_str(&set.host);
+        for pin in &set.pins {
+            out.push_str(&format!(" pin={}", pin));
+        }
+        for pin in &set.backups {
+            out.push_str(&format!(" backup={}", pin));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fp(byte: char) -> String {
+        byte.to_string().repeat(64)
+    }
+
+    #[test]
+    fn test_static_pins_and_backups() {
+        let store = PinStore::new();
+        store.add_static("Intranet.Corp", &[fp('a')], &[fp('b')]).unwrap();
+
+        assert_eq!(store.check("example.com", &[fp('c')]), Ok(PinVerdict::Unpinned));
+        assert_eq!(
+            store.check("intranet.corp", &[fp('c'), fp('a')]),
+            Ok(PinVerdict::Matched { source: PinSource::Static, pin: fp('a'), backup: false })
+        );
+        assert_eq!(
+            store.check("intranet.corp.", &[fp('b')]),
+            Ok(PinVerdict::Matched { source: PinSource::Static, pin: fp('b'), backup: true })
+        );
+
+        let violation = store.check("intranet.corp", &[fp('c')]).unwrap_err();
+        assert_eq!(violation.kind, PinSource::Static);
+        assert_eq!(violation.expected, vec![fp('a'), fp('b')]);
+        assert_eq!(violation.presented, vec![fp('c')]);
+        assert!(violation.to_string().contains("intranet.corp"));
+
+        assert!(store.add_static("x.corp", &["not-a-pin".to_string()], &[]).is_err());
+    }
+
+    #[test]
+    fn test_tofu_learns_rotates_and_persists() {
+        let dir = std::env::temp_dir().join(format!("yolofi_pins_{}", std::process::id()));
+        let profile = Profile::open(&dir).unwrap();
+
+        let store = PinStore::load(&profile).unwrap();
+        store.enable_tofu("corp.example").unwrap();
+        assert_eq!(store.check("other.example", &[fp('a')]), Ok(PinVerdict::Unpinned));
+        assert_eq!(store.check("wiki.corp.example", &[fp('a')]), Ok(PinVerdict::Learned(fp('a'))));
+        assert!(store.check("wiki.corp.example", &[fp('b')]).is_err());
+
+        // Announce the next key, then rotate to it.
+        store.set_backups("wiki.corp.example", &[fp('b')]).unwrap();
+        let reloaded = PinStore::load(&profile).unwrap();
+        assert!(matches!(reloaded.check("wiki.corp.example", &[fp('b')]), Ok(PinVerdict::Matched { backup: true, .. })));
+        assert!(reloaded.check("wiki.corp.example", &[fp('a')]).is_err());
+
+        let set = PinStore::load(&profile).unwrap().pin_set("wiki.corp.example").unwrap();
+        assert_eq!(set.pins, vec![fp('b')]);
+        assert!(set.backups.is_empty());
+
+        assert!(reloaded.forget("wiki.corp.example").unwrap());
+        assert_eq!(reloaded.check("wiki.corp.example", &[fp('c')]), Ok(PinVerdict::Learned(fp('c'))));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
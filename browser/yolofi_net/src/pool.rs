@@ -0,0 +1,389 @@
+// HTTP Connection Pool
+// Keeps finished keep-alive connections around so the next request to the same
+// origin skips DNS, TCP and TLS setup.
+
+use std::collections::HashMap;
+use std::io::{self, BufReader, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+use crate::http::{self, is_idempotent, HttpResponse};
+use crate::limits::Limits;
+use crate::transport::{Connector, Transport};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PoolKey {
+    pub scheme: String,
+    pub host: String,
+    pub port: u16,
+}
+
+impl PoolKey {
+    pub fn new(scheme: &str, host: &str, port: u16) -> Self {
+        Self { scheme: scheme.to_ascii_lowercase(), host: host.to_ascii_lowercase(), port }
+    }
+}
+
+/// Every connection slot for an origin is in use. As an `io::Error` it is `WouldBlock`.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("Connection limit ({limit}) reached for {host}:{port}")]
+pub struct PoolExhausted {
+    pub limit: usize,
+    pub host: String,
+    pub port: u16,
+}
+
+impl From<PoolExhausted> for io::Error {
+    fn from(e: PoolExhausted) -> Self {
+        io::Error::new(io::ErrorKind::WouldBlock, e)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    /// Idle connections older than this are closed instead of reused.
+    pub idle_timeout: Duration,
+    /// Upper bound on idle connections kept per origin.
+    pub max_idle_per_host: usize,
+    /// Upper bound on connections (idle + in use) per origin.
+    pub max_connections_per_host: usize,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self { idle_timeout: Duration::from_secs(90), max_idle_per_host: 4, max_connections_per_host: 6 }
+    }
+}
+
+struct IdleConnection<S> {
+    stream: BufReader<S>,
+    since: Instant,
+}
+
+struct PoolState<S> {
+    idle: HashMap<PoolKey, Vec<IdleConnection<S>>>,
+    in_use: HashMap<PoolKey, usize>,
+}
+
+pub struct ConnectionPool<C: Connector> {
+    connector: C,
+    config: PoolConfig,
+    state: Mutex<PoolState<C::Stream>>,
+}
+
+/// A checked-out connection. Returned to the pool by `release`, closed on drop.
+pub struct PooledConnection<'a, C: Connector> {
+    pool: &'a ConnectionPool<C>,
+    key: PoolKey,
+    stream: Option<BufReader<C::Stream>>,
+    reused: bool,
+    /// Whether any byte of a request went out on this connection.
+    written: bool,
+}
+
+impl<C: Connector> ConnectionPool<C> {
+    pub fn new(connector: C, config: PoolConfig) -> Self {
+        Self { connector, config, state: Mutex::new(PoolState { idle: HashMap::new(), in_use: HashMap::new() }) }
+    }
+
+    pub fn connector(&self) -> &C {
+        &self.connector
+    }
+
+    pub fn connector_mut(&mut self) -> &mut C {
+        &mut self.connector
+    }
+
+    /// Number of idle connections currently held for `key`.
+    pub fn idle_count(&self, key: &PoolKey) -> usize {
+        self.lock().idle.get(key).map_or(0, Vec::len)
+    }
+
+    pub fn checkout(&self, key: &PoolKey) -> io::Result<PooledConnection<'_, C>> {
+        self.checkout_with(key, || self.connector.connect(&key.host, key.port))
+    }
+
+    /// Like `checkout`, but a new connection comes from `open` rather than the connector,
+    /// so callers can layer TLS on top or keep their own error type.
+    pub fn checkout_with<E: From<PoolExhausted>>(
+        &self,
+        key: &PoolKey,
+        open: impl FnOnce() -> Result<C::Stream, E>,
+    ) -> Result<PooledConnection<'_, C>, E> {
+        {
+            let mut state = self.lock();
+            let timeout = self.config.idle_timeout;
+            let idle = state.idle.entry(key.clone()).or_default();
+            idle.retain(|conn| conn.since.elapsed() < timeout);
+
+            if let Some(conn) = idle.pop() {
+                *state.in_use.entry(key.clone()).or_default() += 1;
+                debug!(target: "net::pool", "Reusing connection to {}:{}", key.host, key.port);
+                let stream = Some(conn.stream);
+                return Ok(PooledConnection { pool: self, key: key.clone(), stream, reused: true, written: false });
+            }
+
+            let in_use = state.in_use.get(key).copied().unwrap_or(0);
+            if in_use >= self.config.max_connections_per_host {
+                return Err(PoolExhausted { limit: in_use, host: key.host.clone(), port: key.port }.into());
+            }
+            *state.in_use.entry(key.clone()).or_default() += 1;
+        }
+
+        info!(target: "net::pool", "Opening new {} connection to {}:{}", key.scheme, key.host, key.port);
+        match open() {
+            Ok(stream) => Ok(PooledConnection {
+                pool: self,
+                key: key.clone(),
+                stream: Some(BufReader::new(stream)),
+                reused: false,
+                written: false,
+            }),
+            Err(e) => {
+                self.finish(key, None);
+                Err(e)
+            }
+        }
+    }
+
+    /// Sends one request on a pooled connection and reads its response.
+    /// A stale reused connection is retried once on a fresh one, if that cannot repeat the request.
+    pub fn send(&self, key: &PoolKey, method: &str, request: &[u8]) -> io::Result<HttpResponse> {
+        self.send_with_limits(key, method, request, &Limits::default())
+    }
+
+    pub fn send_with_limits(
+        &self,
+        key: &PoolKey,
+        method: &str,
+        request: &[u8],
+        limits: &Limits,
+    ) -> io::Result<HttpResponse> {
+        let mut conn = self.checkout(key)?;
+        match conn.exchange_with_limits(method, request, limits) {
+            Ok(response) => Ok(response),
+            Err(e) if conn.may_retry(method) => {
+                debug!(target: "net::pool", "Reused connection failed ({}), retrying on a fresh one", e);
+                drop(conn);
+                let mut fresh = self.checkout_fresh_with(key, || self.connector.connect(&key.host, key.port))?;
+                fresh.exchange_with_limits(method, request, limits)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// A new connection for `key`, never an idle one.
+    pub fn checkout_fresh_with<E: From<PoolExhausted>>(
+        &self,
+        key: &PoolKey,
+        open: impl FnOnce() -> Result<C::Stream, E>,
+    ) -> Result<PooledConnection<'_, C>, E> {
+        // Anything else idle for this origin is probably just as stale.
+        self.lock().idle.remove(key);
+        self.checkout_with(key, open)
+    }
+
+    fn finish(&self, key: &PoolKey, stream: Option<BufReader<C::Stream>>) {
+        let mut state = self.lock();
+        if let Some(count) = state.in_use.get_mut(key) {
+            *count = count.saturating_sub(1);
+        }
+        if let Some(stream) = stream {
+            let idle = state.idle.entry(key.clone()).or_default();
+            if idle.len() >= self.config.max_idle_per_host {
+                idle.remove(0);
+            }
+            idle.push(IdleConnection { stream, since: Instant::now() });
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, PoolState<C::Stream>> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<C: Connector> PooledConnection<'_, C> {
+    pub fn stream(&mut self) -> &mut BufReader<C::Stream> {
+        self.stream.as_mut().expect("stream is present until release")
+    }
+
+    pub fn was_reused(&self) -> bool {
+        self.reused
+    }
+
+    /// Whether a failed request on this connection may be sent again on a fresh one.
+    /// Only a reused connection can be stale, and a request the server may have seen is
+    /// repeated only when its method is idempotent.
+    pub fn may_retry(&self, method: &str) -> bool {
+        self.reused && (!self.written || is_idempotent(method))
+    }
+
+    /// Writes the request, reads the response and returns the connection to the pool if allowed.
+    pub fn exchange(&mut self, method: &str, request: &[u8]) -> io::Result<HttpResponse> {
+        self.exchange_with_limits(method, request, &Limits::default())
+    }
+
+    pub fn exchange_with_limits(&mut self, method: &str, request: &[u8], limits: &Limits) -> io::Result<HttpResponse> {
+        let stream = self.stream.as_mut().expect("stream is present until release");
+        let mut rest = request;
+        while !rest.is_empty() {
+            match stream.get_mut().write(rest) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.written = true;
+                    rest = &rest[n..];
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        stream.get_mut().flush()?;
+        let (mut response, reusable) = http::read_response_with_limits(stream, method, limits)?;
+        response.security = stream.get_ref().security_info();
+
+        // Leftover bytes mean the server sent something we did not ask for.
+        if reusable && stream.buffer().is_empty() {
+            self.release();
+        }
+        Ok(response)
+    }
+
+    /// Takes the stream out of the pool for good, e.g. for a protocol the pool does not speak.
+    /// Anything already buffered from it is dropped.
+    pub fn detach(mut self) -> C::Stream {
+        let stream = self.stream.take().expect("stream is present until release");
+        self.pool.finish(&self.key, None);
+        stream.into_inner()
+    }
+
+    /// Hands the connection back for reuse.
+    pub fn release(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            debug!(target: "net::pool", "Returning connection to {} to the pool", stream.get_ref().peer());
+            self.pool.finish(&self.key, Some(stream));
+        }
+    }
+}
+
+impl<C: Connector> Drop for PooledConnection<'_, C> {
+    fn drop(&mut self) {
+        if self.stream.take().is_some() {
+            self.pool.finish(&self.key, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::MemoryConnector;
+    use std::io::{BufRead, Read};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Server that answers every request on a connection with a fixed-length body.
+    fn keep_alive_server(connections: Arc<AtomicUsize>) -> MemoryConnector {
+        MemoryConnector::new(move |_, _, server| {
+            connections.fetch_add(1, Ordering::SeqCst);
+            let mut reader = BufReader::new(server);
+            loop {
+                let mut line = String::new();
+                loop {
+                    line.clear();
+                    if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                        return;
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let _ = reader.get_mut().write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+            }
+        })
+    }
+
+    fn request() -> Vec<u8> {
+        http::build_get_request(&crate::Url::parse("http://example.test/").unwrap())
+    }
+
+    #[test]
+    fn test_keep_alive_connection_is_reused() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let pool = ConnectionPool::new(keep_alive_server(connections.clone()), PoolConfig::default());
+        let key = PoolKey::new("http", "example.test", 80);
+
+        for _ in 0..3 {
+            let response = pool.send(&key, "GET", &request()).unwrap();
+            assert_eq!(response.body, b"ok");
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+        assert_eq!(pool.idle_count(&key), 1);
+    }
+
+    #[test]
+    fn test_idle_timeout_and_host_limit() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let config = PoolConfig { idle_timeout: Duration::ZERO, max_idle_per_host: 1, max_connections_per_host: 1 };
+        let pool = ConnectionPool::new(keep_alive_server(connections.clone()), config);
+        let key = PoolKey::new("http", "example.test", 80);
+
+        pool.send(&key, "GET", &request()).unwrap();
+        pool.send(&key, "GET", &request()).unwrap();
+        assert_eq!(connections.load(Ordering::SeqCst), 2, "expired idle connection must not be reused");
+
+        let _held = pool.checkout(&key).unwrap();
+        let err = pool.checkout(&key).err().expect("second checkout exceeds the per-host limit");
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn test_post_on_stale_connection_is_not_retried() {
+        // The server answers the first request, then reads the next one and hangs up unanswered.
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let connector = MemoryConnector::new(move |_, _, server| {
+            let mut reader = BufReader::new(server);
+            for answered in [true, false] {
+                let mut line = String::new();
+                while line != "\r\n" {
+                    line.clear();
+                    if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                        return;
+                    }
+                }
+                counter.fetch_add(1, Ordering::SeqCst);
+                if answered {
+                    let _ = reader.get_mut().write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+                }
+            }
+        });
+        let pool = ConnectionPool::new(connector, PoolConfig::default());
+        let key = PoolKey::new("http", "example.test", 80);
+
+        pool.send(&key, "GET", &request()).unwrap();
+        let post = b"POST /submit HTTP/1.1\r\nHost: example.test\r\nContent-Length: 0\r\n\r\n";
+        assert!(pool.send(&key, "POST", post).is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 2, "the POST must reach the server only once");
+
+        pool.send(&key, "GET", &request()).unwrap();
+        let get_retry = pool.send(&key, "GET", &request()).unwrap();
+        assert_eq!(get_retry.body, b"ok");
+        assert_eq!(requests.load(Ordering::SeqCst), 5, "the stale GET is sent again on a fresh connection");
+    }
+
+    #[test]
+    fn test_close_delimited_response_is_not_pooled() {
+        let connector = MemoryConnector::new(|_, _, mut server| {
+            let mut buf = [0u8; 512];
+            let _ = server.read(&mut buf);
+            let _ = server.write_all(b"HTTP/1.1 200 OK\r\n\r\nuntil close");
+        });
+        let pool = ConnectionPool::new(connector, PoolConfig::default());
+        let key = PoolKey::new("http", "example.test", 80);
+
+        let response = pool.send(&key, "GET", &request()).unwrap();
+        assert_eq!(response.body, b"until close");
+        assert_eq!(pool.idle_count(&key), 0);
+    }
+}
@@ -0,0 +1,65 @@
+// Browser Profile Directory
+// Everything the net stack persists (trust decisions, pins, cookies, cache) lives here,
+// owned by the user and nowhere else.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+use yolofi_config::{PROFILE_DIR_NAME, PROFILE_ENV_VAR};
+
+#[derive(Debug, Clone)]
+pub struct Profile {
+    root: PathBuf,
+}
+
+impl Profile {
+    /// Opens (creating if needed) the profile at `root`.
+    pub fn open(root: impl AsRef<Path>) -> io::Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        std::fs::create_dir_all(&root)?;
+        debug!(target: "net::profile", "Using profile at {}", root.display());
+        Ok(Self { root })
+    }
+
+    /// Opens the profile at its default location.
+    pub fn open_default() -> io::Result<Self> {
+        Self::open(Self::default_location())
+    }
+
+    pub fn default_location() -> PathBuf {
+        if let Some(dir) = std::env::var_os(PROFILE_ENV_VAR) {
+            return PathBuf::from(dir);
+        }
+        match std::env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(PROFILE_DIR_NAME),
+            None => PathBuf::from(PROFILE_DIR_NAME),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Path of a file (or sub-directory) inside the profile.
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.root.join(name)
+    }
+
+    /// Reads a profile file, treating a missing file as empty.
+    pub fn read_optional(&self, name: &str) -> io::Result<Option<String>> {
+        match std::fs::read_to_string(self.path(name)) {
+            Ok(text) => Ok(Some(text)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Replaces a profile file atomically (write to a temp file, then rename).
+    pub fn write_atomic(&self, name: &str, contents: &[u8]) -> io::Result<()> {
+        let target = self.path(name);
+        let tmp = self.path(&format!("{}.tmp", name));
+        std::fs::write(&tmp, contents)?;
+        std::fs::rename(tmp, target)
+    }
+}
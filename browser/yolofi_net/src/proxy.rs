@@ -0,0 +1,469 @@
+// Proxy Connection Strategies
+// Routes connections through a SOCKS5 proxy (RFC 1928 / RFC 1929) or an HTTP CONNECT tunnel.
+// Hostnames are always handed to the proxy unresolved, so no DNS leaks past it (e.g. Tor).
+// Hosts matching a bypass rule connect directly, after the caller's own resolver looked them up.
+
+use base64::Engine;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use tracing::{debug, info};
+
+use crate::transport::Connector;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyKind {
+    Socks5,
+    HttpConnect,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProxyConfig {
+    pub kind: ProxyKind,
+    pub host: String,
+    pub port: u16,
+    pub credentials: Option<ProxyCredentials>,
+    /// Hosts reached directly. Entries: `*`, `example.com`, `.example.com`, `*.example.com`, `host:port`,
+    /// `[::1]:port`.
+    pub bypass: Vec<String>,
+}
+
+impl ProxyConfig {
+    pub fn new(kind: ProxyKind, host: &str, port: u16) -> Self {
+        Self { kind, host: host.to_string(), port, credentials: None, bypass: Vec::new() }
+    }
+
+    pub fn with_credentials(mut self, username: &str, password: &str) -> Self {
+        self.credentials = Some(ProxyCredentials { username: username.to_string(), password: password.to_string() });
+        self
+    }
+
+    /// Adds bypass rules from a comma separated list (the `NO_PROXY` format).
+    pub fn with_bypass_list(mut self, list: &str) -> Self {
+        self.bypass.extend(list.split(',').map(str::trim).filter(|r| !r.is_empty()).map(str::to_ascii_lowercase));
+        self
+    }
+
+    pub fn bypasses(&self, host: &str, port: u16) -> bool {
+        let host = host.trim_start_matches('[').trim_end_matches(']').to_ascii_lowercase();
+        self.bypass.iter().any(|rule| {
+            let (rule_host, rule_port) = split_rule(rule);
+            if rule_port.is_some_and(|p| p != port) {
+                return false;
+            }
+            if rule_host == "*" {
+                return true;
+            }
+            let suffix = rule_host.trim_start_matches('*').trim_start_matches('.');
+            host == suffix || host.ends_with(&format!(".{}", suffix))
+        })
+    }
+}
+
+/// Splits a bypass rule into host and optional port. A bare IPv6 address has no port;
+/// one with a port is bracketed, as in URLs.
+fn split_rule(rule: &str) -> (&str, Option<u16>) {
+    if let Some(rest) = rule.strip_prefix('[') {
+        if let Some((host, after)) = rest.split_once(']') {
+            return (host, after.strip_prefix(':').and_then(|p| p.parse().ok()));
+        }
+    }
+    match rule.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') => (host, port.parse().ok()),
+        _ => (rule, None),
+    }
+}
+
+/// Wraps another connector and tunnels every non-bypassed connection through the proxy.
+pub struct ProxyConnector<C: Connector> {
+    inner: C,
+    config: ProxyConfig,
+}
+
+impl<C: Connector> ProxyConnector<C> {
+    pub fn new(inner: C, config: ProxyConfig) -> Self {
+        Self { inner, config }
+    }
+
+    pub fn config(&self) -> &ProxyConfig {
+        &self.config
+    }
+
+    fn tunnel(&self, host: &str, port: u16) -> io::Result<C::Stream> {
+        let config = &self.config;
+        info!(
+            target: "net::proxy",
+            "Tunnelling {}:{} via {:?} proxy {}:{}", host, port, config.kind, config.host, config.port
+        );
+        let mut stream = self.inner.connect(&self.config.host, self.config.port)?;
+        match self.config.kind {
+            ProxyKind::Socks5 => socks5_handshake(&mut stream, host, port, self.config.credentials.as_ref())?,
+            ProxyKind::HttpConnect => http_connect(&mut stream, host, port, self.config.credentials.as_ref())?,
+        }
+        Ok(stream)
+    }
+}
+
+impl<C: Connector> Connector for ProxyConnector<C> {
+    type Stream = C::Stream;
+
+    fn connect(&self, host: &str, port: u16) -> io::Result<C::Stream> {
+        if self.config.bypasses(host, port) {
+            debug!(target: "net::proxy", "Bypassing proxy for {}:{}", host, port);
+            return self.inner.connect(host, port);
+        }
+        self.tunnel(host, port)
+    }
+
+    /// SOCKS5 and CONNECT both pass the name to the proxy; bypassed hosts are resolved by the caller.
+    fn resolves_remotely(&self, host: &str, port: u16) -> bool {
+        !self.config.bypasses(host, port)
+    }
+
+    fn connect_resolved(&self, host: &str, addr: &str, port: u16) -> io::Result<C::Stream> {
+        if self.config.bypasses(host, port) {
+            debug!(target: "net::proxy", "Bypassing proxy for {}:{} ({})", host, port, addr);
+            return self.inner.connect_resolved(host, addr, port);
+        }
+        self.tunnel(host, port)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// SOCKS5
+// ---------------------------------------------------------------------------
+
+const SOCKS_VERSION: u8 = 0x05;
+const AUTH_NONE: u8 = 0x00;
+const AUTH_USER_PASS: u8 = 0x02;
+const AUTH_NO_ACCEPTABLE: u8 = 0xFF;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+fn socks5_handshake<S: Read + Write>(
+    stream: &mut S,
+    host: &str,
+    port: u16,
+    credentials: Option<&ProxyCredentials>,
+) -> io::Result<()> {
+    // 1. Method negotiation
+    let greeting: &[u8] = match credentials {
+        Some(_) => &[SOCKS_VERSION, 2, AUTH_NONE, AUTH_USER_PASS],
+        None => &[SOCKS_VERSION, 1, AUTH_NONE],
+    };
+    stream.write_all(greeting)?;
+
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice)?;
+    if choice[0] != SOCKS_VERSION {
+        let message = format!("SOCKS proxy answered with version {}", choice[0]);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    }
+
+    match (choice[1], credentials) {
+        (AUTH_NONE, _) => {}
+        (AUTH_USER_PASS, Some(creds)) => socks5_authenticate(stream, creds)?,
+        (AUTH_NO_ACCEPTABLE, _) | (AUTH_USER_PASS, None) => {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "SOCKS proxy requires authentication"))
+        }
+        (other, _) => {
+            let message = format!("SOCKS proxy chose unsupported method {:#04x}", other);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+    }
+
+    // 2. CONNECT request. Hostnames go as ATYP_DOMAIN so the proxy resolves them.
+    let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0x00];
+    let bare_host = host.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(v4) = bare_host.parse::<Ipv4Addr>() {
+        request.push(ATYP_IPV4);
+        request.extend_from_slice(&v4.octets());
+    } else if let Ok(v6) = bare_host.parse::<Ipv6Addr>() {
+        request.push(ATYP_IPV6);
+        request.extend_from_slice(&v6.octets());
+    } else {
+        let len = u8::try_from(host.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Hostname too long for SOCKS5"))?;
+        request.push(ATYP_DOMAIN);
+        request.push(len);
+        request.extend_from_slice(host.as_bytes());
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request)?;
+
+    // 3. Reply: VER REP RSV ATYP BND.ADDR BND.PORT
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply)?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("SOCKS reply has version {}", reply[0])));
+    }
+    if reply[1] != 0x00 {
+        return Err(io::Error::new(io::ErrorKind::ConnectionRefused, socks5_reply_message(reply[1])));
+    }
+    let bound_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        other => {
+            let message = format!("SOCKS reply has unknown address type {}", other);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+    };
+    let mut bound = vec![0u8; bound_len + 2];
+    stream.read_exact(&mut bound)?;
+
+    debug!(target: "net::proxy", "SOCKS5 tunnel to {}:{} established", host, port);
+    Ok(())
+}
+
+fn socks5_authenticate<S: Read + Write>(stream: &mut S, creds: &ProxyCredentials) -> io::Result<()> {
+    let user = creds.username.as_bytes();
+    let pass = creds.password.as_bytes();
+    if user.len() > 255 || pass.len() > 255 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "SOCKS credentials longer than 255 bytes"));
+    }
+
+    let mut request = vec![0x01, user.len() as u8];
+    request.extend_from_slice(user);
+    request.push(pass.len() as u8);
+    request.extend_from_slice(pass);
+    stream.write_all(&request)?;
+
+    let mut status = [0u8; 2];
+    stream.read_exact(&mut status)?;
+    if status[0] != 0x01 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("SOCKS auth reply has version {}", status[0])));
+    }
+    if status[1] != 0x00 {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "SOCKS proxy rejected the credentials"));
+    }
+    Ok(())
+}
+
+fn socks5_reply_message(code: u8) -> &'static str {
+    match code {
+        0x01 => "SOCKS proxy: general server failure",
+        0x02 => "SOCKS proxy: connection not allowed by ruleset",
+        0x03 => "SOCKS proxy: network unreachable",
+        0x04 => "SOCKS proxy: host unreachable",
+        0x05 => "SOCKS proxy: connection refused",
+        0x06 => "SOCKS proxy: TTL expired",
+        0x07 => "SOCKS proxy: command not supported",
+        0x08 => "SOCKS proxy: address type not supported",
+        _ => "SOCKS proxy: unknown failure",
+    }
+}
+
+// ---------------------------------------------------------------------------
+// HTTP CONNECT
+// ---------------------------------------------------------------------------
+
+fn http_connect<S: Read + Write>(
+    stream: &mut S,
+    host: &str,
+    port: u16,
+    credentials: Option<&ProxyCredentials>,
+) -> io::Result<()> {
+    let authority = if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    };
+
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+    if let Some(creds) = credentials {
+        let token = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", creds.username, creds.password));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes())?;
+
+    // Read the head one byte at a time: anything after it already belongs to the tunnel.
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if stream.read(&mut byte)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Proxy closed the connection during CONNECT"));
+        }
+        head.push(byte[0]);
+        if head.len() > 16 * 1024 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "CONNECT response head too large"));
+        }
+    }
+
+    let status_line = String::from_utf8_lossy(&head);
+    let status = status_line.split_whitespace().nth(1).and_then(|s| s.parse::<u16>().ok()).unwrap_or(0);
+    match status {
+        200..=299 => {
+            debug!(target: "net::proxy", "CONNECT tunnel to {} established", authority);
+            Ok(())
+        }
+        407 => Err(io::Error::new(io::ErrorKind::PermissionDenied, "HTTP proxy requires authentication (407)")),
+        other => {
+            let message = format!("HTTP proxy refused CONNECT with status {}", other);
+            Err(io::Error::new(io::ErrorKind::ConnectionRefused, message))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipe::PipeStream;
+    use crate::transport::MemoryConnector;
+
+    /// Minimal SOCKS5 stand-in: checks credentials, records the target, then echoes.
+    fn socks5_stand_in(mut s: PipeStream) {
+        let mut head = [0u8; 2];
+        s.read_exact(&mut head).unwrap();
+        let mut methods = vec![0u8; head[1] as usize];
+        s.read_exact(&mut methods).unwrap();
+        assert!(methods.contains(&AUTH_USER_PASS));
+        s.write_all(&[SOCKS_VERSION, AUTH_USER_PASS]).unwrap();
+
+        let mut ver_ulen = [0u8; 2];
+        s.read_exact(&mut ver_ulen).unwrap();
+        let mut user = vec![0u8; ver_ulen[1] as usize];
+        s.read_exact(&mut user).unwrap();
+        let mut plen = [0u8; 1];
+        s.read_exact(&mut plen).unwrap();
+        let mut pass = vec![0u8; plen[0] as usize];
+        s.read_exact(&mut pass).unwrap();
+        let ok = user == b"alice" && pass == b"s3cret";
+        s.write_all(&[0x01, if ok { 0x00 } else { 0x01 }]).unwrap();
+        if !ok {
+            return;
+        }
+
+        let mut req = [0u8; 4];
+        s.read_exact(&mut req).unwrap();
+        assert_eq!(req[3], ATYP_DOMAIN, "hostname must be resolved by the proxy");
+        let mut len = [0u8; 1];
+        s.read_exact(&mut len).unwrap();
+        let mut target = vec![0u8; len[0] as usize + 2];
+        s.read_exact(&mut target).unwrap();
+        assert_eq!(&target[..len[0] as usize], b"hidden.onion");
+        assert_eq!(u16::from_be_bytes([target[target.len() - 2], target[target.len() - 1]]), 80);
+
+        s.write_all(&[SOCKS_VERSION, 0x00, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0]).unwrap();
+        echo(s);
+    }
+
+    fn connect_stand_in(mut s: PipeStream) {
+        let mut head = Vec::new();
+        let mut byte = [0u8; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            s.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8(head).unwrap();
+        assert!(head.starts_with("CONNECT internal.corp:443 HTTP/1.1\r\n"));
+        // "bob:hunter2" in base64
+        if !head.contains("Proxy-Authorization: Basic Ym9iOmh1bnRlcjI=\r\n") {
+            s.write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n").unwrap();
+            return;
+        }
+        s.write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n").unwrap();
+        echo(s);
+    }
+
+    fn echo(mut s: PipeStream) {
+        let mut buf = [0u8; 64];
+        while let Ok(n) = s.read(&mut buf) {
+            if n == 0 || s.write_all(&buf[..n]).is_err() {
+                break;
+            }
+        }
+    }
+
+    fn round_trip<S: Read + Write>(stream: &mut S) -> Vec<u8> {
+        stream.write_all(b"tunnelled").unwrap();
+        let mut buf = [0u8; 9];
+        stream.read_exact(&mut buf).unwrap();
+        buf.to_vec()
+    }
+
+    #[test]
+    fn test_socks5_with_auth_and_remote_dns() {
+        let upstream = MemoryConnector::new(|host, port, s| {
+            assert_eq!((host, port), ("127.0.0.1", 9050));
+            socks5_stand_in(s);
+        });
+        let config = ProxyConfig::new(ProxyKind::Socks5, "127.0.0.1", 9050).with_credentials("alice", "s3cret");
+        let mut stream = ProxyConnector::new(upstream, config).connect("hidden.onion", 80).unwrap();
+        assert_eq!(round_trip(&mut stream), b"tunnelled");
+
+        let upstream = MemoryConnector::new(|_, _, s| socks5_stand_in(s));
+        let config = ProxyConfig::new(ProxyKind::Socks5, "127.0.0.1", 9050).with_credentials("alice", "wrong");
+        let err = ProxyConnector::new(upstream, config).connect("hidden.onion", 80).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+
+        // A reply that is not SOCKS5 is refused even when its status byte says success.
+        let upstream = MemoryConnector::new(|_, _, mut s| {
+            let mut greeting = [0u8; 3];
+            s.read_exact(&mut greeting).unwrap();
+            s.write_all(&[SOCKS_VERSION, AUTH_NONE]).unwrap();
+            let mut request = [0u8; 4 + 1 + 12 + 2];
+            s.read_exact(&mut request).unwrap();
+            s.write_all(&[0x04, 0x00, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0]).unwrap();
+        });
+        let config = ProxyConfig::new(ProxyKind::Socks5, "127.0.0.1", 9050);
+        let err = ProxyConnector::new(upstream, config).connect("hidden.onion", 80).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_http_connect_with_basic_auth() {
+        let upstream = MemoryConnector::new(|_, _, s| connect_stand_in(s));
+        let config = ProxyConfig::new(ProxyKind::HttpConnect, "proxy.corp", 3128).with_credentials("bob", "hunter2");
+        let mut stream = ProxyConnector::new(upstream, config).connect("internal.corp", 443).unwrap();
+        assert_eq!(round_trip(&mut stream), b"tunnelled");
+
+        let upstream = MemoryConnector::new(|_, _, s| connect_stand_in(s));
+        let config = ProxyConfig::new(ProxyKind::HttpConnect, "proxy.corp", 3128);
+        let err = ProxyConnector::new(upstream, config).connect("internal.corp", 443).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn test_bypass_rules() {
+        let config = ProxyConfig::new(ProxyKind::HttpConnect, "proxy.corp", 3128)
+            .with_bypass_list("localhost, .internal.corp, 10.0.0.7, intranet:8080");
+        assert!(config.bypasses("localhost", 80));
+        assert!(config.bypasses("wiki.internal.corp", 443));
+        assert!(config.bypasses("internal.corp", 443));
+        assert!(config.bypasses("10.0.0.7", 22));
+        assert!(config.bypasses("intranet", 8080));
+        assert!(!config.bypasses("intranet", 80));
+        assert!(!config.bypasses("notinternal.corp", 443));
+        assert!(!config.bypasses("example.com", 443));
+        // Only bypassed hosts are left to the caller's resolver.
+        let proxy = ProxyConnector::new(MemoryConnector::new(|_, _, _| {}), config.clone());
+        assert!(proxy.resolves_remotely("example.com", 443));
+        assert!(!proxy.resolves_remotely("wiki.internal.corp", 443));
+
+        let v6 = ProxyConfig::new(ProxyKind::Socks5, "proxy", 1080).with_bypass_list("[::1]:8080, [fe80::1], ::2");
+        assert!(v6.bypasses("[::1]", 8080));
+        assert!(!v6.bypasses("::1", 80));
+        assert!(v6.bypasses("fe80::1", 443));
+        assert!(v6.bypasses("[::2]", 22));
+
+        let direct = MemoryConnector::new(|host, _, s| {
+            assert_eq!(host, "localhost");
+            echo(s);
+        });
+        let mut stream = ProxyConnector::new(direct, config).connect("localhost", 80).unwrap();
+        assert_eq!(round_trip(&mut stream), b"tunnelled");
+    }
+}
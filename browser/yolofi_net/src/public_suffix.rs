@@ -0,0 +1,203 @@
+// Public Suffix List
+// Which domains are "public" (com, co.uk, github.io) so cookies cannot be scoped to
+// them and sites can be compared by registrable domain (eTLD+1).
+//
+// A built-in subset of the list ships with the browser. Dropping the full
+// `public_suffix_list.dat` from publicsuffix.org into the profile replaces it.
+// Unlisted TLDs fall back to the implicit "*" rule: the last label is the suffix.
+
+use std::collections::HashSet;
+use std::io;
+use std::sync::OnceLock;
+use tracing::info;
+
+use crate::profile::Profile;
+
+const PSL_FILE: &str = "public_suffix_list.dat";
+
+const BUILTIN_RULES: &str = "
+// ICANN
+com
+net
+org
+edu
+gov
+mil
+int
+info
+biz
+io
+dev
+app
+xyz
+uk
+co.uk
+org.uk
+ac.uk
+gov.uk
+ltd.uk
+plc.uk
+jp
+co.jp
+ne.jp
+or.jp
+ac.jp
+*.kawasaki.jp
+!city.kawasaki.jp
+au
+com.au
+net.au
+org.au
+edu.au
+de
+fr
+nl
+in
+co.in
+br
+com.br
+cn
+com.cn
+*.ck
+!www.ck
+// Private
+github.io
+gitlab.io
+herokuapp.com
+blogspot.com
+appspot.com
+pages.dev
+workers.dev
+vercel.app
+netlify.app
+s3.amazonaws.com
+cloudfront.net
+";
+
+#[derive(Debug, Default, Clone)]
+pub struct PublicSuffixList {
+    rules: HashSet<String>,
+    /// "*.ck" is stored as "ck".
+    wildcards: HashSet<String>,
+    /// "!www.ck" is stored as "www.ck".
+    exceptions: HashSet<String>,
+}
+
+impl PublicSuffixList {
+    /// Parses the publicsuffix.org format: one rule per line, `//` comments.
+    pub fn parse(text: &str) -> Self {
+        let mut list = Self::default();
+        for line in text.lines() {
+            let rule = line.split_whitespace().next().unwrap_or("");
+            if rule.is_empty() || rule.starts_with("//") {
+                continue;
+            }
+            let rule = rule.to_lowercase();
+            if let Some(exception) = rule.strip_prefix('!') {
+                list.exceptions.insert(to_ascii(exception));
+            } else if let Some(parent) = rule.strip_prefix("*.") {
+                list.wildcards.insert(to_ascii(parent));
+            } else {
+                list.rules.insert(to_ascii(&rule));
+            }
+        }
+        list
+    }
+
+    /// The built-in subset.
+    pub fn builtin() -> &'static PublicSuffixList {
+        static BUILTIN: OnceLock<PublicSuffixList> = OnceLock::new();
+        BUILTIN.get_or_init(|| PublicSuffixList::parse(BUILTIN_RULES))
+    }
+
+    /// The profile's copy of the full list if there is one, else the built-in subset.
+    pub fn load(profile: &Profile) -> io::Result<Self> {
+        match profile.read_optional(PSL_FILE)? {
+            Some(text) => {
+                let list = Self::parse(&text);
+                info!(target: "net::cookies", "Loaded {} public suffix rules from profile", list.len());
+                Ok(list)
+            }
+            None => Ok(Self::builtin().clone()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len() + self.wildcards.len() + self.exceptions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The public suffix of an ASCII (already IDNA-encoded), lowercase domain.
+    pub fn public_suffix<'a>(&self, domain: &'a str) -> &'a str {
+        let domain = domain.trim_end_matches('.');
+        let labels: Vec<&str> = domain.split('.').collect();
+        // Longest matching rule wins; exceptions beat everything and drop their first label.
+        let mut suffix_labels = 1;
+        for start in (0..labels.len()).rev() {
+            let candidate = labels[start..].join(".");
+            if self.exceptions.contains(&candidate) {
+                suffix_labels = labels.len() - start - 1;
+                break;
+            }
+            if self.rules.contains(&candidate) {
+                suffix_labels = suffix_labels.max(labels.len() - start);
+            }
+            if self.wildcards.contains(&candidate) && start > 0 {
+                suffix_labels = suffix_labels.max(labels.len() - start + 1);
+            }
+        }
+        let skip: usize = labels[..labels.len() - suffix_labels].iter().map(|l| l.len() + 1).sum();
+        &domain[skip..]
+    }
+
+    pub fn is_public_suffix(&self, domain: &str) -> bool {
+        let domain = domain.trim_end_matches('.');
+        self.public_suffix(domain) == domain
+    }
+
+    /// The public suffix plus one label ("example.co.uk"). `None` for a public suffix itself.
+    pub fn registrable_domain<'a>(&self, domain: &'a str) -> Option<&'a str> {
+        let domain = domain.trim_end_matches('.');
+        let suffix = self.public_suffix(domain);
+        if suffix.len() == domain.len() {
+            return None;
+        }
+        let rest = &domain[..domain.len() - suffix.len() - 1];
+        let start = rest.rfind('.').map_or(0, |i| i + 1);
+        Some(&domain[start..])
+    }
+}
+
+fn to_ascii(rule: &str) -> String {
+    yolofi_url::idna::domain_to_ascii(rule).unwrap_or_else(|_| rule.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suffix_rules_wildcards_and_exceptions() {
+        let list = PublicSuffixList::builtin();
+        assert_eq!(list.public_suffix("www.example.co.uk"), "co.uk");
+        assert_eq!(list.registrable_domain("www.example.co.uk"), Some("example.co.uk"));
+        assert_eq!(list.registrable_domain("alice.github.io"), Some("alice.github.io"));
+        assert!(list.is_public_suffix("github.io"));
+        assert!(list.is_public_suffix("co.uk"));
+
+        // *.ck makes every second-level name public, except www.ck.
+        assert!(list.is_public_suffix("foo.ck"));
+        assert_eq!(list.registrable_domain("a.foo.ck"), Some("a.foo.ck"));
+        assert_eq!(list.registrable_domain("www.ck"), Some("www.ck"));
+
+        // Unlisted TLD: the implicit "*" rule.
+        assert_eq!(list.registrable_domain("shop.example.internal"), Some("example.internal"));
+        assert_eq!(list.registrable_domain("localhost"), None);
+
+        let custom = PublicSuffixList::parse("// comment\nexample\n*.corp.example\n");
+        assert_eq!(custom.registrable_domain("a.b.team.corp.example"), Some("b.team.corp.example"));
+    }
+}
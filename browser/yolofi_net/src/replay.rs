@@ -0,0 +1,267 @@
+// Replay Transport
+// Serves a capture journal back to the pipeline without touching the network.
+// Any difference between what the pipeline does and what was recorded is a hard error.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing::{error, info};
+
+use crate::journal::{read_journal, Direction, JournalRecord};
+
+struct RecordedConnection {
+    conn_id: u32,
+    peer: String,
+    events: VecDeque<(Direction, Vec<u8>)>,
+}
+
+/// A loaded journal. Connections are handed out in the order they were captured.
+#[derive(Clone)]
+pub struct ReplayJournal {
+    pending: Arc<Mutex<VecDeque<RecordedConnection>>>,
+}
+
+impl ReplayJournal {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let records = read_journal(path.as_ref())?;
+        let journal = Self::from_records(records)?;
+        info!(target: "net::replay", "Loaded journal {} for replay", path.as_ref().display());
+        Ok(journal)
+    }
+
+    pub fn from_records(records: Vec<JournalRecord>) -> io::Result<Self> {
+        let mut connections: Vec<RecordedConnection> = Vec::new();
+
+        for record in records {
+            match record {
+                JournalRecord::Open { conn_id, peer, .. } => {
+                    connections.push(RecordedConnection { conn_id, peer, events: VecDeque::new() });
+                }
+                JournalRecord::Data { conn_id, direction, bytes, .. } => {
+                    let conn = connections.iter_mut().find(|c| c.conn_id == conn_id).ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Journal data for connection #{} before it was opened", conn_id),
+                        )
+                    })?;
+                    conn.events.push_back((direction, bytes));
+                }
+            }
+        }
+
+        Ok(Self { pending: Arc::new(Mutex::new(connections.into())) })
+    }
+
+    /// Number of recorded connections that have not been replayed yet.
+    pub fn remaining_connections(&self) -> usize {
+        self.lock().len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<RecordedConnection>> {
+        self.pending.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Stream that plays back one recorded connection.
+pub struct ReplayStream {
+    conn_id: u32,
+    peer: String,
+    events: VecDeque<(Direction, Vec<u8>)>,
+    /// Bytes of the front event already consumed.
+    offset: usize,
+}
+
+impl ReplayStream {
+    /// Takes the next recorded connection. The address must match the capture exactly.
+    pub fn connect(journal: &ReplayJournal, addr: &str) -> io::Result<Self> {
+        let next = journal.lock().pop_front();
+        match next {
+            Some(conn) if conn.peer == addr => {
+                info!(target: "net::replay", "Replaying connection #{} to {}", conn.conn_id, addr);
+                Ok(Self { conn_id: conn.conn_id, peer: conn.peer, events: conn.events, offset: 0 })
+            }
+            Some(conn) => Err(divergence(
+                conn.conn_id,
+                format!("pipeline connected to {} but the journal recorded {}", addr, conn.peer),
+            )),
+            None => Err(divergence(u32::MAX, format!("pipeline connected to {} after the journal ended", addr))),
+        }
+    }
+
+    pub fn peer_addr(&self) -> &str {
+        &self.peer
+    }
+
+    /// Fails if the pipeline stopped before consuming everything that was recorded.
+    pub fn finish(self) -> io::Result<()> {
+        // A trailing end-of-stream marker is allowed to stay unread.
+        let leftover = self.events.iter().map(|(_, b)| b.len()).sum::<usize>() - self.offset;
+        if leftover > 0 {
+            return Err(divergence(
+                self.conn_id,
+                format!("{} recorded bytes were never exchanged", leftover),
+            ));
+        }
+        Ok(())
+    }
+
+    fn advance(&mut self, n: usize) {
+        self.offset += n;
+        if let Some((_, bytes)) = self.events.front() {
+            if self.offset == bytes.len() {
+                self.events.pop_front();
+                self.offset = 0;
+            }
+        }
+    }
+}
+
+impl Read for ReplayStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.events.front() {
+            Some((Direction::Read, bytes)) if bytes.is_empty() => {
+                // Recorded end of stream. Keep returning EOF like a real socket would.
+                Ok(0)
+            }
+            Some((Direction::Read, bytes)) => {
+                let available = &bytes[self.offset..];
+                let n = available.len().min(buf.len());
+                buf[..n].copy_from_slice(&available[..n]);
+                self.advance(n);
+                Ok(n)
+            }
+            Some((Direction::Write, bytes)) => {
+                let pending = bytes.len() - self.offset;
+                Err(divergence(
+                    self.conn_id,
+                    format!("pipeline read but the journal expects {} more written bytes first", pending),
+                ))
+            }
+            None => Err(divergence(self.conn_id, "pipeline read past the end of the recording".to_string())),
+        }
+    }
+}
+
+impl Write for ReplayStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut written = 0;
+
+        // A single write may span several recorded write records (or vice versa).
+        while written < buf.len() {
+            let expected = match self.events.front() {
+                Some((Direction::Write, bytes)) => &bytes[self.offset..],
+                Some((Direction::Read, _)) if written > 0 => break,
+                Some((Direction::Read, _)) => {
+                    return Err(divergence(
+                        self.conn_id,
+                        format!("pipeline wrote {} bytes but the journal expects a read", buf.len()),
+                    ))
+                }
+                None => {
+                    return Err(divergence(
+                        self.conn_id,
+                        format!("pipeline wrote {} bytes past the end of the recording", buf.len() - written),
+                    ))
+                }
+            };
+
+            let n = expected.len().min(buf.len() - written);
+            if let Some(i) = (0..n).find(|&i| expected[i] != buf[written + i]) {
+                return Err(divergence(
+                    self.conn_id,
+                    format!(
+                        "written byte {:#04x} does not match recorded byte {:#04x}",
+                        buf[written + i],
+                        expected[i]
+                    ),
+                ));
+            }
+            self.advance(n);
+            written += n;
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn divergence(conn_id: u32, detail: String) -> io::Error {
+    error!(target: "net::replay", "REPLAY DIVERGENCE on connection #{}: {}", conn_id, detail);
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("replay divergence on connection #{}: {}", conn_id, detail),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::CaptureJournal;
+    use crate::tcp::TracedTcpStream;
+    use std::net::TcpListener;
+
+    fn capture_exchange(path: &Path) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 4];
+            sock.read_exact(&mut buf).unwrap();
+            sock.write_all(b"pong\xff\x00").unwrap();
+        });
+
+        let journal = CaptureJournal::open(path).unwrap();
+        let mut stream = TracedTcpStream::connect_captured(&addr, &journal).unwrap();
+        stream.write_all(b"ping").unwrap();
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).unwrap();
+        assert_eq!(reply, b"pong\xff\x00");
+        server.join().unwrap();
+        addr
+    }
+
+    #[test]
+    fn test_replay_serves_captured_bytes() {
+        let path = std::env::temp_dir().join(format!("yolofi_replay_ok_{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let addr = capture_exchange(&path);
+
+        let journal = ReplayJournal::load(&path).unwrap();
+        let mut stream = ReplayStream::connect(&journal, &addr).unwrap();
+        stream.write_all(b"pi").unwrap();
+        stream.write_all(b"ng").unwrap();
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).unwrap();
+        assert_eq!(reply, b"pong\xff\x00");
+        stream.finish().unwrap();
+        assert_eq!(journal.remaining_connections(), 0);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_replay_divergence_fails() {
+        let path = std::env::temp_dir().join(format!("yolofi_replay_bad_{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let addr = capture_exchange(&path);
+
+        let journal = ReplayJournal::load(&path).unwrap();
+        assert!(ReplayStream::connect(&journal.clone(), "10.0.0.1:80").is_err());
+
+        let journal = ReplayJournal::load(&path).unwrap();
+        let mut stream = ReplayStream::connect(&journal, &addr).unwrap();
+        let err = stream.write_all(b"pang").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let journal = ReplayJournal::load(&path).unwrap();
+        let mut stream = ReplayStream::connect(&journal, &addr).unwrap();
+        let mut buf = [0u8; 8];
+        assert!(stream.read(&mut buf).is_err(), "reading before the recorded write must fail");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,386 @@
+// Request Builder
+// Every outgoing request is assembled here: method, URL, fields and body, with
+// Host, Content-Type and Content-Length decided in one place so callers cannot get
+// the framing wrong. `build` validates names and values (no CR/LF smuggling) and
+// yields the `HttpRequest` that HTTP/1.1 serializes and HTTP/2 encodes.
+//
+// Bodies: raw bytes, application/x-www-form-urlencoded pairs, or multipart/form-data
+// (RFC 7578) with text fields and files.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use yolofi_url::percent;
+
+use crate::headers::HeaderMap;
+use crate::http::HttpRequest;
+use crate::identity::ReferrerPolicy;
+use crate::Url;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum RequestError {
+    #[error("invalid method {0:?}")]
+    InvalidMethod(String),
+    #[error("invalid header name {0:?}")]
+    InvalidHeaderName(String),
+    #[error("invalid value for header {0}")]
+    InvalidHeaderValue(String),
+}
+
+/// Fields that describe the body; dropped along with it when a redirect turns a request into GET.
+const BODY_HEADERS: [&str; 4] = ["Content-Type", "Content-Encoding", "Content-Language", "Content-Location"];
+
+#[derive(Debug, Clone)]
+pub struct RequestBuilder {
+    method: String,
+    url: Url,
+    headers: HeaderMap,
+    body: Vec<u8>,
+    content_type: Option<String>,
+    referrer: Option<Url>,
+    referrer_policy: Option<ReferrerPolicy>,
+    top_level: Option<Url>,
+}
+
+impl RequestBuilder {
+    /// The standard methods are uppercased, as the Fetch standard normalizes them;
+    /// any other method is sent as given.
+    pub fn new(method: &str, url: &Url) -> Self {
+        let upper = method.to_ascii_uppercase();
+        let method = match upper.as_str() {
+            "DELETE" | "GET" | "HEAD" | "OPTIONS" | "POST" | "PUT" => upper,
+            _ => method.to_string(),
+        };
+        Self {
+            method,
+            url: url.clone(),
+            headers: HeaderMap::new(),
+            body: Vec::new(),
+            content_type: None,
+            referrer: None,
+            referrer_policy: None,
+            top_level: None,
+        }
+    }
+
+    pub fn get(url: &Url) -> Self {
+        Self::new("GET", url)
+    }
+
+    pub fn post(url: &Url) -> Self {
+        Self::new("POST", url)
+    }
+
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        &mut self.headers
+    }
+
+    /// The page the request comes from. `Identity::apply` turns it into a Referer, or not.
+    pub fn referrer(&self) -> Option<&Url> {
+        self.referrer.as_ref()
+    }
+
+    pub fn referrer_policy(&self) -> Option<ReferrerPolicy> {
+        self.referrer_policy
+    }
+
+    pub fn with_referrer(mut self, referrer: &Url) -> Self {
+        self.referrer = Some(referrer.clone());
+        self
+    }
+
+    /// Overrides the identity's default policy for this request.
+    pub fn with_referrer_policy(mut self, policy: ReferrerPolicy) -> Self {
+        self.referrer_policy = Some(policy);
+        self
+    }
+
+    /// The page loading this request as a subresource. `None` for navigations.
+    pub fn top_level(&self) -> Option<&Url> {
+        self.top_level.as_ref()
+    }
+
+    /// Marks the request as a subresource (image, script, fetch()) of the page at `page`,
+    /// which decides whether its cookies are third-party.
+    pub fn with_top_level(mut self, page: &Url) -> Self {
+        self.top_level = Some(page.clone());
+        self
+    }
+
+    /// Adds a field. Host, Content-Length and Transfer-Encoding are managed by `build`.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    /// Adds a field unless the request already has one by that name.
+    pub fn default_header(mut self, name: &str, value: &str) -> Self {
+        if !self.headers.contains(name) {
+            self.headers.append(name, value);
+        }
+        self
+    }
+
+    /// A raw body. Content-Type is left to the caller.
+    pub fn bytes(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self.content_type = None;
+        self
+    }
+
+    pub fn bytes_with_type(mut self, body: impl Into<Vec<u8>>, content_type: &str) -> Self {
+        self.body = body.into();
+        self.content_type = Some(content_type.to_string());
+        self
+    }
+
+    /// An application/x-www-form-urlencoded body.
+    pub fn form<'a>(self, pairs: impl IntoIterator<Item = (&'a str, &'a str)>) -> Self {
+        let encoded: Vec<String> =
+            pairs.into_iter().map(|(n, v)| format!("{}={}", percent::form_encode(n), percent::form_encode(v))).collect();
+        self.bytes_with_type(encoded.join("&"), "application/x-www-form-urlencoded")
+    }
+
+    /// Fails when a part's Content-Type would break out of its field.
+    pub fn multipart(self, form: Multipart) -> Result<Self, RequestError> {
+        let content_type = form.content_type();
+        Ok(self.bytes_with_type(form.encode()?, &content_type))
+    }
+
+    pub fn set_url(&mut self, url: Url) {
+        self.url = url;
+    }
+
+    pub fn set_referrer_policy(&mut self, policy: ReferrerPolicy) {
+        self.referrer_policy = Some(policy);
+    }
+
+    pub fn set_method(&mut self, method: &str) {
+        self.method = Self::new(method, &self.url).method;
+    }
+
+    pub fn remove_header(&mut self, name: &str) {
+        self.headers.remove(name);
+    }
+
+    /// Drops the body and the fields describing it.
+    pub fn clear_body(&mut self) {
+        self.body.clear();
+        self.content_type = None;
+        for name in BODY_HEADERS {
+            self.headers.remove(name);
+        }
+    }
+
+    /// Host first, then the caller's fields in order, then Content-Type and Content-Length.
+    pub fn build(&self) -> Result<HttpRequest, RequestError> {
+        if !is_token(&self.method) {
+            return Err(RequestError::InvalidMethod(self.method.clone()));
+        }
+        let mut headers = HeaderMap::new();
+        headers.append("Host", self.headers.get("Host").map_or_else(|| self.url.authority(), str::to_string));
+        for (name, value) in self.headers.iter() {
+            if !is_token(name) {
+                return Err(RequestError::InvalidHeaderName(name.to_string()));
+            }
+            if !is_field_value(value) {
+                return Err(RequestError::InvalidHeaderValue(name.to_string()));
+            }
+            let managed = ["Host", "Content-Length", "Transfer-Encoding"].iter().any(|m| m.eq_ignore_ascii_case(name));
+            if !managed {
+                headers.append(name, value);
+            }
+        }
+        if let Some(content_type) = self.content_type.as_deref().filter(|_| !headers.contains("Content-Type")) {
+            if !is_field_value(content_type) {
+                return Err(RequestError::InvalidHeaderValue("Content-Type".to_string()));
+            }
+            headers.append("Content-Type", content_type);
+        }
+        // Methods that normally carry a body say so even when it is empty (RFC 9110 section 8.6).
+        if !self.body.is_empty() || matches!(self.method.as_str(), "POST" | "PUT" | "PATCH") {
+            headers.append("Content-Length", self.body.len().to_string());
+        }
+        Ok(HttpRequest {
+            method: self.method.clone(),
+            path: self.url.request_target(),
+            headers,
+            body: self.body.clone(),
+        })
+    }
+}
+
+/// tchar from RFC 9110 section 5.6.2.
+fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// No CR, LF or NUL, which would end the field early (RFC 9110 section 5.5).
+fn is_field_value(value: &str) -> bool {
+    !value.bytes().any(|b| matches!(b, b'\r' | b'\n' | b'\0'))
+}
+
+/// A multipart/form-data body under construction.
+#[derive(Debug, Clone)]
+pub struct Multipart {
+    boundary: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone)]
+struct Part {
+    name: String,
+    filename: Option<String>,
+    content_type: Option<String>,
+    data: Vec<u8>,
+}
+
+impl Default for Multipart {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Multipart {
+    /// A form with a random boundary.
+    pub fn new() -> Self {
+        let random = || RandomState::new().build_hasher().finish();
+        let boundary = format!("----YolofiFormBoundary{:016x}{:016x}", random(), random());
+        Self::with_boundary(&boundary)
+    }
+
+    pub fn with_boundary(boundary: &str) -> Self {
+        Self { boundary: boundary.to_string(), parts: Vec::new() }
+    }
+
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    pub fn text(mut self, name: &str, value: &str) -> Self {
+        self.parts.push(Part { name: name.to_string(), filename: None, content_type: None, data: value.as_bytes().to_vec() });
+        self
+    }
+
+    pub fn file(mut self, name: &str, filename: &str, content_type: &str, data: impl Into<Vec<u8>>) -> Self {
+        self.parts.push(Part {
+            name: name.to_string(),
+            filename: Some(filename.to_string()),
+            content_type: Some(content_type.to_string()),
+            data: data.into(),
+        });
+        self
+    }
+
+    pub fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+
+    /// Part Content-Types get the same CR/LF check as request fields.
+    pub fn encode(&self) -> Result<Vec<u8>, RequestError> {
+        let mut out = Vec::new();
+        for part in &self.parts {
+            let mut head = format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"", self.boundary, escape(&part.name));
+            if let Some(filename) = &part.filename {
+                head.push_str(&format!("; filename=\"{}\"", escape(filename)));
+            }
+            head.push_str("\r\n");
+            if let Some(content_type) = &part.content_type {
+                if !is_field_value(content_type) {
+                    return Err(RequestError::InvalidHeaderValue("Content-Type".to_string()));
+                }
+                head.push_str(&format!("Content-Type: {}\r\n", content_type));
+            }
+            head.push_str("\r\n");
+            out.extend_from_slice(head.as_bytes());
+            out.extend_from_slice(&part.data);
+            out.extend_from_slice(b"\r\n");
+        }
+        out.extend_from_slice(format!("--{}--\r\n", self.boundary).as_bytes());
+        Ok(out)
+    }
+}
+
+/// Quotes in names and filenames are percent-encoded, as HTML form submission does.
+fn escape(value: &str) -> String {
+    value.replace('"', "%22").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn test_bodies_and_framing() {
+        let request = RequestBuilder::post(&url("http://a.test:8080/submit?x=1"))
+            .header("Accept", "text/html")
+            .header("Content-Length", "999")
+            .form([("q", "rust lang"), ("tag", "a&b")])
+            .build()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(request.serialize()).unwrap(),
+            "POST /submit?x=1 HTTP/1.1\r\nHost: a.test:8080\r\nAccept: text/html\r\n\
+Content-Type: application/x-www-form-urlencoded\r\nContent-Length: 21\r\n\r\nq=rust+lang&tag=a%26b"
+        );
+
+        let form = Multipart::with_boundary("XyZ").text("title", "Hi").file("doc", "a\"b.txt", "text/plain", "data");
+        let request = RequestBuilder::new("put", &url("https://a.test/up")).multipart(form).unwrap().build().unwrap();
+        assert_eq!(request.method, "PUT");
+        assert_eq!(request.headers.content_type(), Some("multipart/form-data; boundary=XyZ"));
+        let expected = "--XyZ\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nHi\r\n\
+--XyZ\r\nContent-Disposition: form-data; name=\"doc\"; filename=\"a%22b.txt\"\r\nContent-Type: text/plain\r\n\r\ndata\r\n\
+--XyZ--\r\n";
+        assert_eq!(String::from_utf8(request.body.clone()).unwrap(), expected);
+        assert_eq!(request.headers.content_length(), Some(expected.len() as u64));
+        assert_ne!(Multipart::new().boundary(), Multipart::new().boundary());
+
+        // No body: GET sends no Content-Length, POST says 0.
+        assert!(!RequestBuilder::get(&url("http://a.test/")).build().unwrap().headers.contains("Content-Length"));
+        let empty_post = RequestBuilder::post(&url("http://a.test/")).build().unwrap();
+        assert_eq!(empty_post.headers.content_length(), Some(0));
+
+        let mut redirected = RequestBuilder::post(&url("http://a.test/")).header("Content-Type", "text/plain").bytes("x");
+        redirected.clear_body();
+        redirected.set_method("get");
+        let request = redirected.build().unwrap();
+        assert_eq!((request.method.as_str(), request.headers.len()), ("GET", 1));
+    }
+
+    #[test]
+    fn test_rejects_injection_and_bad_tokens() {
+        let base = url("http://a.test/");
+        let injected = RequestBuilder::get(&base).header("X-Note", "a\r\nEvil: 1").build();
+        assert_eq!(injected.unwrap_err(), RequestError::InvalidHeaderValue("X-Note".into()));
+        let bad_name = RequestBuilder::get(&base).header("Bad Name", "1").build();
+        assert_eq!(bad_name.unwrap_err(), RequestError::InvalidHeaderName("Bad Name".into()));
+        assert!(matches!(RequestBuilder::new("GE T", &base).build(), Err(RequestError::InvalidMethod(_))));
+        let smuggled = Multipart::new().file("doc", "a.txt", "text/plain\r\n\r\n--x", "data");
+        let err = RequestError::InvalidHeaderValue("Content-Type".into());
+        assert_eq!(RequestBuilder::post(&base).multipart(smuggled).unwrap_err(), err);
+        let bad_boundary = RequestBuilder::post(&base).multipart(Multipart::with_boundary("x\r\nEvil: 1")).unwrap();
+        assert_eq!(bad_boundary.build().unwrap_err(), err);
+        // Extension methods keep their case.
+        assert_eq!(RequestBuilder::new("patch", &base).method(), "patch");
+        assert_eq!(RequestBuilder::new("PROPFIND", &base).build().unwrap().method, "PROPFIND");
+    }
+}
@@ -0,0 +1,103 @@
+// Connection Security Info
+// What a TLS handshake established, kept alongside every response so the
+// browser can show why a connection is trusted and tests can assert on it.
+
+use std::net::IpAddr;
+
+use rustls::pki_types::{CertificateDer, TrustAnchor};
+use rustls::{CipherSuite, ClientConnection, ProtocolVersion};
+use x509_parser::extensions::GeneralName;
+
+use crate::pin_store::PinVerdict;
+use crate::trust_store::{anchor_fingerprint, der_sequence, name_to_string, spki_fingerprint};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionSecurityInfo {
+    pub protocol_version: Option<ProtocolVersion>,
+    pub cipher_suite: Option<CipherSuite>,
+    /// Negotiated ALPN protocol, e.g. "h2".
+    pub alpn: Option<String>,
+    /// The path chain verification built, leaf first and trust anchor last.
+    /// Extra certificates the server sent that are not on the path are not listed.
+    pub chain: Vec<CertificateInfo>,
+    /// `None` when the connection was not subject to pinning.
+    pub pin_status: Option<PinVerdict>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateInfo {
+    pub subject: String,
+    pub issuer: String,
+    /// DNS names and IP addresses from the subjectAltName extension.
+    pub subject_alt_names: Vec<String>,
+    /// Validity window, in seconds since the Unix epoch.
+    pub not_before: i64,
+    pub not_after: i64,
+    /// Hex SHA-256 of the SubjectPublicKeyInfo, the value pins are made of.
+    pub spki_sha256: String,
+}
+
+impl ConnectionSecurityInfo {
+    pub(crate) fn from_connection(
+        conn: &ClientConnection,
+        chain: Vec<CertificateInfo>,
+        pin_status: Option<PinVerdict>,
+    ) -> Self {
+        Self {
+            protocol_version: conn.protocol_version(),
+            cipher_suite: conn.negotiated_cipher_suite().map(|suite| suite.suite()),
+            alpn: conn.alpn_protocol().map(|p| String::from_utf8_lossy(p).into_owned()),
+            chain,
+            pin_status,
+        }
+    }
+
+    /// The server's own certificate.
+    pub fn leaf(&self) -> Option<&CertificateInfo> {
+        self.chain.first()
+    }
+}
+
+impl CertificateInfo {
+    /// Returns `None` for certificates that cannot be parsed (they would not have verified).
+    pub fn parse(cert: &CertificateDer<'_>) -> Option<Self> {
+        let (_, parsed) = x509_parser::parse_x509_certificate(cert.as_ref()).ok()?;
+        let subject_alt_names = match parsed.subject_alternative_name() {
+            Ok(Some(san)) => san.value.general_names.iter().map(general_name_to_string).collect(),
+            _ => Vec::new(),
+        };
+        Some(Self {
+            subject: parsed.subject().to_string(),
+            issuer: parsed.issuer().to_string(),
+            subject_alt_names,
+            not_before: parsed.validity().not_before.timestamp(),
+            not_after: parsed.validity().not_after.timestamp(),
+            spki_sha256: spki_fingerprint(parsed.tbs_certificate.subject_pki.raw),
+        })
+    }
+
+    /// A root known only as a trust anchor. Anchors carry no validity window, so it is left unbounded.
+    pub(crate) fn from_anchor(anchor: &TrustAnchor<'_>) -> Self {
+        let subject = name_to_string(&der_sequence(anchor.subject.as_ref()));
+        Self {
+            issuer: subject.clone(),
+            subject,
+            subject_alt_names: Vec::new(),
+            not_before: i64::MIN,
+            not_after: i64::MAX,
+            spki_sha256: anchor_fingerprint(anchor),
+        }
+    }
+}
+
+fn general_name_to_string(name: &GeneralName<'_>) -> String {
+    match name {
+        GeneralName::DNSName(dns) => dns.to_string(),
+        GeneralName::IPAddress(bytes) => match bytes.len() {
+            4 => IpAddr::from(<[u8; 4]>::try_from(*bytes).expect("length checked")).to_string(),
+            16 => IpAddr::from(<[u8; 16]>::try_from(*bytes).expect("length checked")).to_string(),
+            _ => format!("{}", name),
+        },
+        other => format!("{}", other),
+    }
+}
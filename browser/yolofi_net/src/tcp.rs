@@ -2,9 +2,14 @@ use std::io::{self, Read, Write};
 use std::net::TcpStream;
 use tracing::info;
 
+use crate::journal::{CaptureJournal, Direction};
+use crate::proxy::{ProxyConfig, ProxyConnector};
+use crate::transport::{Connector, TcpConnector};
+
 pub struct TracedTcpStream {
     inner: TcpStream,
     peer_addr: String,
+    capture: Option<(CaptureJournal, u32)>,
 }
 
 impl TracedTcpStream {
@@ -12,7 +17,25 @@ impl TracedTcpStream {
         info!(target: "net::tcp", "Connecting to {}...", addr);
         let stream = TcpStream::connect(addr)?;
         info!(target: "net::tcp", "Connected.");
-        Ok(Self { inner: stream, peer_addr: addr.to_string() })
+        Ok(Self { inner: stream, peer_addr: addr.to_string(), capture: None })
+    }
+
+    /// Connects like `connect`, additionally writing every byte to `journal`.
+    pub fn connect_captured(addr: &str, journal: &CaptureJournal) -> io::Result<Self> {
+        let mut stream = Self::connect(addr)?;
+        let conn_id = journal.open_connection(addr)?;
+        info!(target: "net::tcp", "Capturing connection #{} to {}", conn_id, journal.path().display());
+        stream.capture = Some((journal.clone(), conn_id));
+        Ok(stream)
+    }
+
+    /// Connects to `host:port` through `proxy`, or directly if a bypass rule matches.
+    pub fn connect_via(proxy: &ProxyConfig, host: &str, port: u16) -> io::Result<Self> {
+        ProxyConnector::new(TcpConnector::new(), proxy.clone()).connect(host, port)
+    }
+
+    pub fn peer_addr(&self) -> &str {
+        &self.peer_addr
     }
 }
 
@@ -20,6 +43,11 @@ impl Read for TracedTcpStream {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         let n = self.inner.read(buf)?;
         info!(target: "net::tcp", "Read {} bytes from {}", n, self.peer_addr);
+        // Zero-length reads are recorded too: they mark end of stream. A read into an
+        // empty buffer also returns 0, but says nothing about the stream.
+        if let Some((journal, conn_id)) = self.capture.as_ref().filter(|_| !buf.is_empty()) {
+            journal.record(*conn_id, Direction::Read, &buf[..n])?;
+        }
         Ok(n)
     }
 }
@@ -28,6 +56,9 @@ impl Write for TracedTcpStream {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let n = self.inner.write(buf)?;
         info!(target: "net::tcp", "Wrote {} bytes to {}", n, self.peer_addr);
+        if let Some((journal, conn_id)) = &self.capture {
+            journal.record(*conn_id, Direction::Write, &buf[..n])?;
+        }
         Ok(n)
     }
 
@@ -0,0 +1,107 @@
+// Test PKI
+// A throwaway CA and server certificate plus an in-process TLS server,
+// so TLS code can be tested without the network or the OS trust store.
+
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair, KeyUsagePurpose};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+use crate::pipe::PipeStream;
+
+pub struct TestPki {
+    pub ca: CertificateDer<'static>,
+    pub leaf: CertificateDer<'static>,
+    leaf_key: Vec<u8>,
+}
+
+impl TestPki {
+    /// Creates a CA and a leaf certificate for `host`, signed by that CA.
+    pub fn new(host: &str) -> Self {
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params.distinguished_name.push(DnType::CommonName, "YoloFi Test Root CA");
+        ca_params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+        let ca_key = KeyPair::generate().unwrap();
+        let ca_cert = ca_params.self_signed(&ca_key).unwrap();
+
+        let mut leaf_params = CertificateParams::new(vec![host.to_string()]).unwrap();
+        leaf_params.distinguished_name.push(DnType::CommonName, host);
+        let leaf_key = KeyPair::generate().unwrap();
+        let leaf = leaf_params.signed_by(&leaf_key, &ca_cert, &ca_key).unwrap();
+
+        Self { ca: ca_cert.der().clone(), leaf: leaf.der().clone(), leaf_key: leaf_key.serialize_der() }
+    }
+
+    pub fn ca_der(&self) -> CertificateDer<'static> {
+        self.ca.clone()
+    }
+
+    pub fn server_config(&self, alpn: &[&[u8]]) -> Arc<ServerConfig> {
+        self.server_config_with_extra(alpn, &[])
+    }
+
+    /// Like `server_config`, but the server also sends `extra` after its real chain.
+    pub fn server_config_with_extra(&self, alpn: &[&[u8]], extra: &[CertificateDer<'static>]) -> Arc<ServerConfig> {
+        self.build_config(alpn, extra, rustls::DEFAULT_VERSIONS)
+    }
+
+    /// A server that has not moved to TLS 1.3.
+    pub fn tls12_server_config(&self, alpn: &[&[u8]]) -> Arc<ServerConfig> {
+        self.build_config(alpn, &[], &[&rustls::version::TLS12])
+    }
+
+    fn build_config(
+        &self,
+        alpn: &[&[u8]],
+        extra: &[CertificateDer<'static>],
+        versions: &[&'static rustls::SupportedProtocolVersion],
+    ) -> Arc<ServerConfig> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(self.leaf_key.clone()));
+        let chain = [self.leaf.clone(), self.ca.clone()].into_iter().chain(extra.iter().cloned()).collect();
+        let mut config = ServerConfig::builder_with_provider(provider)
+            .with_protocol_versions(versions)
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(chain, key)
+            .unwrap();
+        config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+        Arc::new(config)
+    }
+
+    /// Accepts one TLS session on `pipe` and echoes application data until the client leaves.
+    pub fn serve_echo<S: Read + Write>(config: Arc<ServerConfig>, stream: S) {
+        let conn = ServerConnection::new(config).unwrap();
+        let mut tls = StreamOwned::new(conn, stream);
+        let mut buf = [0u8; 1024];
+        while let Ok(n) = tls.read(&mut buf) {
+            if n == 0 || tls.write_all(&buf[..n]).is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Wraps the server end of `pipe` in a TLS session; the handshake runs on first use.
+    pub fn accept(config: Arc<ServerConfig>, pipe: PipeStream) -> StreamOwned<ServerConnection, PipeStream> {
+        StreamOwned::new(ServerConnection::new(config).unwrap(), pipe)
+    }
+
+    /// Accepts one TLS session, waits for a request head and answers with `response`.
+    pub fn serve_response(config: Arc<ServerConfig>, pipe: PipeStream, response: &[u8]) {
+        let conn = ServerConnection::new(config).unwrap();
+        let mut tls = StreamOwned::new(conn, pipe);
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !request.ends_with(b"\r\n\r\n") {
+            match tls.read(&mut buf) {
+                Ok(0) | Err(_) => return,
+                Ok(n) => request.extend_from_slice(&buf[..n]),
+            }
+        }
+        let _ = tls.write_all(response);
+        let _ = tls.flush();
+    }
+}
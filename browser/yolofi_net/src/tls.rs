@@ -1,4 +1,562 @@
-// TLS Handshake placeholder
-pub fn handshake() {
-    todo!("Implement TLS 1.3 handshake");
+// TLS Client
+// rustls does the cryptography (pure Rust, auditable, per the Phase 1 spec).
+// We own everything around it: which roots are trusted, which versions and
+// ALPN protocols are offered, and how failures are reported.
+
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::WebPkiSupportedAlgorithms;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{CertificateError, ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, StreamOwned};
+
+use crate::pin_store::{PinStore, PinVerdict, PinViolation};
+use crate::security::{CertificateInfo, ConnectionSecurityInfo};
+use crate::transport::{Connector, Transport};
+use crate::trust_store::{anchor_fingerprint, TrustStore, TrustStoreError};
+
+pub const ALPN_HTTP11: &[u8] = b"http/1.1";
+pub const ALPN_H2: &[u8] = b"h2";
+
+#[derive(Debug, thiserror::Error)]
+pub enum TlsError {
+    #[error("'{0}' is not a valid TLS server name")]
+    InvalidServerName(String),
+    #[error("server certificate rejected: {0:?}")]
+    Certificate(rustls::CertificateError),
+    #[error(transparent)]
+    PinMismatch(PinViolation),
+    #[error("server sent alert: {0:?}")]
+    AlertReceived(rustls::AlertDescription),
+    #[error("server does not support any offered ALPN protocol")]
+    NoApplicationProtocol,
+    #[error("server is incompatible: {0:?}")]
+    PeerIncompatible(rustls::PeerIncompatible),
+    #[error("server misbehaved: {0:?}")]
+    PeerMisbehaved(rustls::PeerMisbehaved),
+    #[error("TLS error: {0}")]
+    Protocol(rustls::Error),
+    #[error("connection closed during handshake")]
+    ClosedDuringHandshake,
+    #[error("I/O error during handshake: {0}")]
+    Io(io::Error),
+}
+
+impl From<rustls::Error> for TlsError {
+    fn from(e: rustls::Error) -> Self {
+        match e {
+            rustls::Error::InvalidCertificate(CertificateError::Other(other))
+                if other.0.downcast_ref::<PinViolation>().is_some() =>
+            {
+                TlsError::PinMismatch(other.0.downcast_ref::<PinViolation>().expect("checked above").clone())
+            }
+            rustls::Error::InvalidCertificate(cert) => TlsError::Certificate(cert),
+            rustls::Error::AlertReceived(alert) => TlsError::AlertReceived(alert),
+            rustls::Error::NoApplicationProtocol => TlsError::NoApplicationProtocol,
+            rustls::Error::PeerIncompatible(why) => TlsError::PeerIncompatible(why),
+            rustls::Error::PeerMisbehaved(why) => TlsError::PeerMisbehaved(why),
+            other => TlsError::Protocol(other),
+        }
+    }
+}
+
+impl From<io::Error> for TlsError {
+    fn from(e: io::Error) -> Self {
+        // rustls reports its own failures through io::Error(InvalidData); unwrap those.
+        if e.get_ref().and_then(|inner| inner.downcast_ref::<rustls::Error>()).is_some() {
+            let inner = e.into_inner().expect("checked above");
+            return (*inner.downcast::<rustls::Error>().expect("checked above")).into();
+        }
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof => TlsError::ClosedDuringHandshake,
+            _ => TlsError::Io(e),
+        }
+    }
+}
+
+impl From<TlsError> for io::Error {
+    fn from(e: TlsError) -> Self {
+        match e {
+            TlsError::Io(inner) => inner,
+            other => io::Error::new(io::ErrorKind::InvalidData, other),
+        }
+    }
+}
+
+/// What the client offers and whom it trusts.
+/// Chains are verified only against roots from a `TrustStore`, never the OS store.
+#[derive(Clone)]
+pub struct TlsConfig {
+    pub roots: Arc<RootCertStore>,
+    /// ALPN protocols in preference order.
+    pub alpn: Vec<Vec<u8>>,
+    /// TLS 1.3 is always offered; this additionally allows falling back to 1.2.
+    /// Off unless a caller opts in with `with_tls12`, so a downgrade needs a deliberate choice.
+    pub allow_tls12: bool,
+    /// SPKI pins enforced after chain verification.
+    pub pins: Option<PinStore>,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self::from_trust_store(&TrustStore::bundled())
+    }
+}
+
+impl TlsConfig {
+    pub fn from_trust_store(store: &TrustStore) -> Self {
+        Self { roots: store.root_cert_store(), alpn: vec![ALPN_HTTP11.to_vec()], allow_tls12: false, pins: None }
+    }
+
+    /// Trusts exactly the given root certificates and nothing else.
+    pub fn with_root_certificates(certs: &[CertificateDer<'_>]) -> Result<Self, TrustStoreError> {
+        let mut store = TrustStore::empty();
+        for cert in certs {
+            store.add_root(cert.clone().into_owned())?;
+        }
+        Ok(Self::from_trust_store(&store))
+    }
+
+    pub fn with_alpn(mut self, protocols: &[&[u8]]) -> Self {
+        self.alpn = protocols.iter().map(|p| p.to_vec()).collect();
+        self
+    }
+
+    /// Also offers TLS 1.2, for servers that do not speak 1.3 yet.
+    pub fn with_tls12(mut self, allowed: bool) -> Self {
+        self.allow_tls12 = allowed;
+        self
+    }
+
+    pub fn with_pins(mut self, pins: PinStore) -> Self {
+        self.pins = Some(pins);
+        self
+    }
+
+    /// `verification` receives the verified path and, when pinning is enabled, the pin check result.
+    fn client_config(&self, verification: &VerificationSlot) -> Result<Arc<ClientConfig>, TlsError> {
+        let versions: &[&rustls::SupportedProtocolVersion] = if self.allow_tls12 {
+            &[&rustls::version::TLS13, &rustls::version::TLS12]
+        } else {
+            &[&rustls::version::TLS13]
+        };
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider.clone()).with_protocol_versions(versions)?;
+        let algorithms = provider.signature_verification_algorithms;
+        let chain = WebPkiServerVerifier::builder_with_provider(self.roots.clone(), provider)
+            .build()
+            .map_err(|e| TlsError::Protocol(rustls::Error::General(e.to_string())))?;
+        let verifier = PathVerifier {
+            chain,
+            roots: self.roots.clone(),
+            algorithms,
+            pins: self.pins.clone(),
+            verification: verification.clone(),
+        };
+        let mut config = builder.dangerous().with_custom_certificate_verifier(Arc::new(verifier)).with_no_client_auth();
+        config.alpn_protocols = self.alpn.clone();
+        Ok(Arc::new(config))
+    }
+}
+
+/// What the verifier learned during a handshake.
+#[derive(Debug, Default)]
+struct Verification {
+    path: Vec<CertificateInfo>,
+    pin_status: Option<PinVerdict>,
+}
+
+type VerificationSlot = Arc<Mutex<Option<Verification>>>;
+
+/// Normal WebPKI chain verification that records the verified path, followed by the host's SPKI pins.
+#[derive(Debug)]
+struct PathVerifier {
+    chain: Arc<WebPkiServerVerifier>,
+    roots: Arc<RootCertStore>,
+    algorithms: WebPkiSupportedAlgorithms,
+    pins: Option<PinStore>,
+    verification: VerificationSlot,
+}
+
+impl PathVerifier {
+    /// The path that verifies, leaf first and trust anchor last.
+    /// Certificates the server sent that are not on the path are left out, so they cannot satisfy a pin.
+    fn verified_path(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
+    ) -> Result<Vec<CertificateInfo>, webpki::Error> {
+        let cert = webpki::EndEntityCert::try_from(end_entity)?;
+        let usage = webpki::KeyUsage::server_auth();
+        let roots = &self.roots.roots;
+        let path = cert.verify_for_usage(self.algorithms.all, roots, intermediates, now, usage, None, None)?;
+        let mut certs: Vec<CertificateInfo> = std::iter::once(path.end_entity().der())
+            .chain(path.intermediate_certificates().map(|cert| cert.der()))
+            .filter_map(|der| CertificateInfo::parse(&der))
+            .collect();
+
+        // Prefer the anchor's full certificate when the server sent it along.
+        let anchor = anchor_fingerprint(path.anchor());
+        let sent = intermediates.iter().filter_map(CertificateInfo::parse).find(|cert| cert.spki_sha256 == anchor);
+        certs.push(sent.unwrap_or_else(|| CertificateInfo::from_anchor(path.anchor())));
+        Ok(certs)
+    }
+}
+
+impl ServerCertVerifier for PathVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.chain.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+
+        let path = self
+            .verified_path(end_entity, intermediates, now)
+            .map_err(|e| rustls::Error::InvalidCertificate(CertificateError::Other(rustls::OtherError(Arc::new(e)))))?;
+        let pin_status = match &self.pins {
+            None => None,
+            Some(pins) => {
+                let presented: Vec<String> = path.iter().map(|cert| cert.spki_sha256.clone()).collect();
+                let verdict = pins.check(&server_name.to_str(), &presented).map_err(|violation| {
+                    rustls::Error::InvalidCertificate(CertificateError::Other(rustls::OtherError(Arc::new(violation))))
+                })?;
+                Some(verdict)
+            }
+        };
+        *self.verification.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) =
+            Some(Verification { path, pin_status });
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.chain.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.chain.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.chain.supported_verify_schemes()
+    }
+}
+
+/// An established TLS session over any transport.
+pub struct TlsStream<S: Transport> {
+    inner: StreamOwned<ClientConnection, S>,
+    security: ConnectionSecurityInfo,
+}
+
+impl<S: Transport> TlsStream<S> {
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.inner.conn.alpn_protocol()
+    }
+
+    pub fn protocol_version(&self) -> Option<rustls::ProtocolVersion> {
+        self.inner.conn.protocol_version()
+    }
+
+    /// Version, cipher suite, ALPN, certificate chain and pin status of this session.
+    pub fn security(&self) -> &ConnectionSecurityInfo {
+        &self.security
+    }
+
+    pub fn connection(&self) -> &ClientConnection {
+        &self.inner.conn
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner.sock
+    }
+}
+
+/// Runs a TLS handshake for `server_name` over `stream`.
+pub fn handshake<S: Transport>(mut stream: S, server_name: &str, config: &TlsConfig) -> Result<TlsStream<S>, TlsError> {
+    let name = ServerName::try_from(server_name.trim_start_matches('[').trim_end_matches(']').to_string())
+        .map_err(|_| TlsError::InvalidServerName(server_name.to_string()))?;
+
+    info!(target: "net::tls", "Starting TLS handshake with {} (SNI {})", stream.peer(), server_name);
+    let verification = VerificationSlot::default();
+    let mut conn = ClientConnection::new(config.client_config(&verification)?, name)?;
+
+    while conn.is_handshaking() {
+        if let Err(e) = conn.complete_io(&mut stream) {
+            let error = TlsError::from(e);
+            warn!(target: "net::tls", "Handshake with {} failed: {}", server_name, error);
+            return Err(error);
+        }
+    }
+
+    info!(
+        target: "net::tls",
+        "Handshake complete: {:?}, {:?}, ALPN {:?}",
+        conn.protocol_version(),
+        conn.negotiated_cipher_suite().map(|s| s.suite()),
+        conn.alpn_protocol().map(String::from_utf8_lossy)
+    );
+    let Verification { path, pin_status } =
+        verification.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take().unwrap_or_default();
+    let security = ConnectionSecurityInfo::from_connection(&conn, path, pin_status);
+    Ok(TlsStream { inner: StreamOwned::new(conn, stream), security })
+}
+
+impl<S: Transport> Read for TlsStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<S: Transport> Write for TlsStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: Transport> Transport for TlsStream<S> {
+    fn peer(&self) -> &str {
+        self.inner.sock.peer()
+    }
+
+    fn security_info(&self) -> Option<ConnectionSecurityInfo> {
+        Some(self.security.clone())
+    }
+}
+
+/// Wraps another connector and speaks TLS on every connection, using the host as SNI.
+pub struct TlsConnector<C: Connector> {
+    inner: C,
+    config: TlsConfig,
+}
+
+impl<C: Connector> TlsConnector<C> {
+    pub fn new(inner: C, config: TlsConfig) -> Self {
+        Self { inner, config }
+    }
+}
+
+impl<C: Connector> Connector for TlsConnector<C> {
+    type Stream = TlsStream<C::Stream>;
+
+    fn connect(&self, host: &str, port: u16) -> io::Result<Self::Stream> {
+        let stream = self.inner.connect(host, port)?;
+        Ok(handshake(stream, host, &self.config)?)
+    }
+
+    fn resolves_remotely(&self, host: &str, port: u16) -> bool {
+        self.inner.resolves_remotely(host, port)
+    }
+
+    fn connect_resolved(&self, host: &str, addr: &str, port: u16) -> io::Result<Self::Stream> {
+        let stream = self.inner.connect_resolved(host, addr, port)?;
+        Ok(handshake(stream, host, &self.config)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_pki::TestPki;
+    use crate::transport::{MemoryConnector, TcpConnector};
+    use crate::trust_store::describe_certificate;
+    use std::net::TcpListener;
+
+    fn fixture_connector(pki: &TestPki) -> MemoryConnector {
+        let server_config = pki.server_config(&[ALPN_H2, ALPN_HTTP11]);
+        MemoryConnector::new(move |_, _, pipe| TestPki::serve_echo(server_config.clone(), pipe))
+    }
+
+    #[test]
+    fn test_handshake_with_test_ca() {
+        let pki = TestPki::new("localhost");
+        let config = TlsConfig::with_root_certificates(&[pki.ca_der()]).unwrap().with_alpn(&[ALPN_H2]);
+        let connector = TlsConnector::new(fixture_connector(&pki), config);
+
+        let mut stream = connector.connect("localhost", 443).unwrap();
+        assert_eq!(stream.alpn_protocol(), Some(ALPN_H2));
+        assert_eq!(stream.protocol_version(), Some(rustls::ProtocolVersion::TLSv1_3));
+
+        stream.write_all(b"encrypted hello").unwrap();
+        let mut buf = [0u8; 15];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"encrypted hello");
+    }
+
+    #[test]
+    fn test_tls12_only_when_opted_in_over_tcp() {
+        // A real local server: one TLS 1.3 session, then two from a server stuck on 1.2.
+        let pki = TestPki::new("localhost");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let modern = pki.server_config(&[ALPN_HTTP11]);
+        let legacy = pki.tls12_server_config(&[ALPN_HTTP11]);
+        let server = std::thread::spawn(move || {
+            for config in [modern, legacy.clone(), legacy] {
+                TestPki::serve_echo(config, listener.accept().unwrap().0);
+            }
+        });
+        let config = TlsConfig::with_root_certificates(&[pki.ca_der()]).unwrap();
+        assert!(!config.allow_tls12);
+        let connect = |config: &TlsConfig| {
+            handshake(TcpConnector::new().connect("127.0.0.1", port).unwrap(), "localhost", config)
+        };
+
+        let mut stream = connect(&config).unwrap();
+        assert_eq!(stream.protocol_version(), Some(rustls::ProtocolVersion::TLSv1_3));
+        stream.write_all(b"over tcp").unwrap();
+        let mut buf = [0u8; 8];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"over tcp");
+        drop(stream);
+
+        let err = connect(&config).err().unwrap();
+        assert!(matches!(err, TlsError::AlertReceived(_) | TlsError::PeerIncompatible(_)), "{:?}", err);
+        let stream = connect(&config.with_tls12(true)).unwrap();
+        assert_eq!(stream.protocol_version(), Some(rustls::ProtocolVersion::TLSv1_2));
+        drop(stream);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_handshake_errors_are_typed() {
+        let pki = TestPki::new("localhost");
+
+        // Certificate is for "localhost", not for the name we asked for.
+        let config = TlsConfig::with_root_certificates(&[pki.ca_der()]).unwrap();
+        let stream = fixture_connector(&pki).connect("wrong.test", 443).unwrap();
+        let err = handshake(stream, "wrong.test", &config).err().unwrap();
+        assert!(
+            matches!(
+                err,
+                TlsError::Certificate(
+                    rustls::CertificateError::NotValidForName | rustls::CertificateError::NotValidForNameContext { .. }
+                )
+            ),
+            "{:?}",
+            err
+        );
+
+        // Default roots do not include our test CA.
+        let stream = fixture_connector(&pki).connect("localhost", 443).unwrap();
+        let err = handshake(stream, "localhost", &TlsConfig::default()).err().unwrap();
+        assert!(matches!(err, TlsError::Certificate(rustls::CertificateError::UnknownIssuer)), "{:?}", err);
+
+        let stream = fixture_connector(&pki).connect("localhost", 443).unwrap();
+        let err = handshake(stream, "bad name!", &config).err().unwrap();
+        assert!(matches!(err, TlsError::InvalidServerName(_)));
+    }
+
+    #[test]
+    fn test_pinned_handshake() {
+        let pki = TestPki::new("localhost");
+        let (leaf_pin, _) = describe_certificate(&pki.leaf).unwrap();
+        let pins = PinStore::new();
+        let config = TlsConfig::with_root_certificates(&[pki.ca_der()]).unwrap().with_pins(pins.clone());
+
+        pins.add_static("localhost", &["ab".repeat(32)], &[]).unwrap();
+        let stream = fixture_connector(&pki).connect("localhost", 443).unwrap();
+        match handshake(stream, "localhost", &config).err().unwrap() {
+            TlsError::PinMismatch(violation) => {
+                assert_eq!(violation.expected, vec!["ab".repeat(32)]);
+                assert_eq!(violation.presented[0], leaf_pin);
+            }
+            other => panic!("expected a pin mismatch, got {:?}", other),
+        }
+
+        pins.add_static("localhost", &["ab".repeat(32)], &[leaf_pin]).unwrap();
+        let stream = fixture_connector(&pki).connect("localhost", 443).unwrap();
+        assert!(handshake(stream, "localhost", &config).is_ok());
+    }
+
+    #[test]
+    fn test_pins_only_match_the_verified_path() {
+        let pki = TestPki::new("localhost");
+        let other = TestPki::new("localhost");
+        let (other_pin, _) = describe_certificate(&other.ca).unwrap();
+        let (root_pin, _) = describe_certificate(&pki.ca).unwrap();
+        let server_config = pki.server_config_with_extra(&[ALPN_HTTP11], &[other.ca_der()]);
+        let connector = MemoryConnector::new(move |_, _, pipe| TestPki::serve_echo(server_config.clone(), pipe));
+        let pins = PinStore::new();
+        let config = TlsConfig::with_root_certificates(&[pki.ca_der()]).unwrap().with_pins(pins.clone());
+
+        // The pinned certificate is sent, but nothing chains through it.
+        pins.add_static("localhost", &[other_pin], &[]).unwrap();
+        match handshake(connector.connect("localhost", 443).unwrap(), "localhost", &config).err().unwrap() {
+            TlsError::PinMismatch(violation) => assert_eq!(violation.presented.len(), 2, "{:?}", violation.presented),
+            other => panic!("expected a pin mismatch, got {:?}", other),
+        }
+
+        pins.add_static("localhost", &[root_pin], &[]).unwrap();
+        assert!(handshake(connector.connect("localhost", 443).unwrap(), "localhost", &config).is_ok());
+    }
+
+    #[test]
+    fn test_security_info_reaches_response() {
+        let pki = TestPki::new("localhost");
+        let (leaf_pin, _) = describe_certificate(&pki.leaf).unwrap();
+        let server_config = pki.server_config(&[ALPN_HTTP11]);
+        let memory = MemoryConnector::new(move |_, _, pipe| {
+            TestPki::serve_response(server_config.clone(), pipe, b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+        });
+        let pins = PinStore::new();
+        pins.enable_tofu("localhost").unwrap();
+        let config = TlsConfig::with_root_certificates(&[pki.ca_der()]).unwrap().with_pins(pins);
+
+        let response = crate::http::get(&TlsConnector::new(memory, config), &crate::Url::parse("https://localhost/").unwrap()).unwrap();
+        assert_eq!(response.body, b"ok");
+
+        let security = response.security.expect("response came over TLS");
+        assert_eq!(security.protocol_version, Some(rustls::ProtocolVersion::TLSv1_3));
+        assert!(security.cipher_suite.is_some());
+        assert_eq!(security.alpn.as_deref(), Some("http/1.1"));
+        assert_eq!(security.pin_status, Some(PinVerdict::Learned(leaf_pin.clone())));
+
+        assert_eq!(security.chain.len(), 2);
+        let leaf = security.leaf().unwrap();
+        assert_eq!(leaf.subject_alt_names, vec!["localhost".to_string()]);
+        assert_eq!(leaf.spki_sha256, leaf_pin);
+        assert!(leaf.not_before < leaf.not_after);
+        assert!(security.chain[1].subject.contains("YoloFi Test Root CA"));
+        assert_eq!(leaf.issuer, security.chain[1].subject);
+    }
+
+    #[test]
+    fn test_security_chain_is_the_verified_path() {
+        let pki = TestPki::new("localhost");
+        let other = TestPki::new("localhost");
+        let (root_pin, _) = describe_certificate(&pki.ca).unwrap();
+        let server_config = pki.server_config_with_extra(&[ALPN_HTTP11], &[other.ca_der()]);
+        let connector = MemoryConnector::new(move |_, _, pipe| TestPki::serve_echo(server_config.clone(), pipe));
+        let config = TlsConfig::with_root_certificates(&[pki.ca_der()]).unwrap();
+
+        // The server sends three certificates; only two of them chain.
+        let stream = handshake(connector.connect("localhost", 443).unwrap(), "localhost", &config).unwrap();
+        assert_eq!(stream.connection().peer_certificates().unwrap().len(), 3);
+        let security = stream.security();
+        assert_eq!(security.chain.len(), 2, "{:?}", security.chain);
+        assert_eq!(security.chain[1].spki_sha256, root_pin);
+        assert_eq!(security.pin_status, None);
+    }
 }
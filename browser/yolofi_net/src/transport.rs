@@ -0,0 +1,241 @@
+// Transport Abstraction
+// Everything above this line (DNS, HTTP, TLS) talks to these traits instead of `std::net`,
+// so the same code runs over real sockets, in-memory pipes, or a recorded journal.
+
+use std::io::{self, Read, Write};
+use std::net::UdpSocket;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::info;
+
+use crate::journal::{CaptureJournal, Direction};
+use crate::pipe::{self, PipeStream};
+use crate::replay::{ReplayJournal, ReplayStream};
+use crate::security::ConnectionSecurityInfo;
+use crate::tcp::TracedTcpStream;
+
+/// A connected, bidirectional byte stream.
+pub trait Transport: Read + Write {
+    /// The address this stream is connected to, as passed to the connector.
+    fn peer(&self) -> &str;
+
+    /// What the TLS layer established, if this stream is (or wraps) a TLS session.
+    fn security_info(&self) -> Option<ConnectionSecurityInfo> {
+        None
+    }
+}
+
+/// Opens stream transports.
+pub trait Connector {
+    type Stream: Transport;
+
+    fn connect(&self, host: &str, port: u16) -> io::Result<Self::Stream>;
+
+    /// True when `host` is handed on unresolved (a proxy does the lookup), so callers
+    /// must not resolve it locally first.
+    fn resolves_remotely(&self, _host: &str, _port: u16) -> bool {
+        false
+    }
+
+    /// Connects to `host` at `addr`, which the caller resolved. Connectors that route by
+    /// name (proxy bypass rules, SNI) still see `host`; the socket goes to `addr`.
+    fn connect_resolved(&self, _host: &str, addr: &str, port: u16) -> io::Result<Self::Stream> {
+        self.connect(addr, port)
+    }
+}
+
+/// Request/response datagram exchange (used by DNS).
+pub trait DatagramTransport {
+    /// Sends `query` to `server` and returns the first reply.
+    fn exchange(&self, server: &str, query: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+impl Transport for TracedTcpStream {
+    fn peer(&self) -> &str {
+        self.peer_addr()
+    }
+}
+
+impl Transport for ReplayStream {
+    fn peer(&self) -> &str {
+        self.peer_addr()
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn peer(&self) -> &str {
+        (**self).peer()
+    }
+
+    fn security_info(&self) -> Option<ConnectionSecurityInfo> {
+        (**self).security_info()
+    }
+}
+
+impl<C: Connector + ?Sized> Connector for &C {
+    type Stream = C::Stream;
+
+    fn connect(&self, host: &str, port: u16) -> io::Result<Self::Stream> {
+        (**self).connect(host, port)
+    }
+
+    fn resolves_remotely(&self, host: &str, port: u16) -> bool {
+        (**self).resolves_remotely(host, port)
+    }
+
+    fn connect_resolved(&self, host: &str, addr: &str, port: u16) -> io::Result<Self::Stream> {
+        (**self).connect_resolved(host, addr, port)
+    }
+}
+
+fn join_addr(host: &str, port: u16) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Real network
+// ---------------------------------------------------------------------------
+
+/// Connects over real TCP, optionally capturing the traffic.
+#[derive(Clone, Default)]
+pub struct TcpConnector {
+    capture: Option<CaptureJournal>,
+}
+
+impl TcpConnector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capture(journal: CaptureJournal) -> Self {
+        Self { capture: Some(journal) }
+    }
+}
+
+impl Connector for TcpConnector {
+    type Stream = TracedTcpStream;
+
+    fn connect(&self, host: &str, port: u16) -> io::Result<TracedTcpStream> {
+        let addr = join_addr(host, port);
+        match &self.capture {
+            Some(journal) => TracedTcpStream::connect_captured(&addr, journal),
+            None => TracedTcpStream::connect(&addr),
+        }
+    }
+}
+
+/// Plain UDP, optionally capturing each exchange as its own journal connection.
+#[derive(Clone)]
+pub struct UdpTransport {
+    timeout: Duration,
+    capture: Option<CaptureJournal>,
+}
+
+impl UdpTransport {
+    pub fn new(timeout: Duration) -> Self {
+        Self { timeout, capture: None }
+    }
+
+    pub fn with_capture(timeout: Duration, journal: CaptureJournal) -> Self {
+        Self { timeout, capture: Some(journal) }
+    }
+}
+
+impl DatagramTransport for UdpTransport {
+    fn exchange(&self, server: &str, query: &[u8]) -> io::Result<Vec<u8>> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.set_read_timeout(Some(self.timeout))?;
+        socket.send_to(query, server)?;
+
+        let mut buffer = [0u8; 512];
+        let (amt, _src) = socket.recv_from(&mut buffer)?;
+
+        if let Some(journal) = &self.capture {
+            let conn_id = journal.open_connection(&format!("udp://{}", server))?;
+            journal.record(conn_id, Direction::Write, query)?;
+            journal.record(conn_id, Direction::Read, &buffer[..amt])?;
+            journal.record(conn_id, Direction::Read, &[])?;
+        }
+        Ok(buffer[..amt].to_vec())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// In-memory
+// ---------------------------------------------------------------------------
+
+type PipeHandler = dyn Fn(&str, u16, PipeStream) + Send + Sync;
+
+/// Hands every connection to an in-process server running on its own thread.
+#[derive(Clone)]
+pub struct MemoryConnector {
+    handler: Arc<PipeHandler>,
+}
+
+impl MemoryConnector {
+    /// `handler` receives the target host, port and the server end of the pipe.
+    pub fn new(handler: impl Fn(&str, u16, PipeStream) + Send + Sync + 'static) -> Self {
+        Self { handler: Arc::new(handler) }
+    }
+}
+
+impl Connector for MemoryConnector {
+    type Stream = PipeStream;
+
+    fn connect(&self, host: &str, port: u16) -> io::Result<PipeStream> {
+        let (client, server) = pipe::duplex(&join_addr(host, port));
+        let handler = self.handler.clone();
+        let host = host.to_string();
+        std::thread::spawn(move || handler(&host, port, server));
+        Ok(client)
+    }
+}
+
+impl<F> DatagramTransport for F
+where
+    F: Fn(&str, &[u8]) -> io::Result<Vec<u8>>,
+{
+    fn exchange(&self, server: &str, query: &[u8]) -> io::Result<Vec<u8>> {
+        self(server, query)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Recorded replay
+// ---------------------------------------------------------------------------
+
+/// Serves connections from a capture journal. Never touches the network.
+#[derive(Clone)]
+pub struct ReplayConnector {
+    journal: ReplayJournal,
+}
+
+impl ReplayConnector {
+    pub fn new(journal: ReplayJournal) -> Self {
+        info!(target: "net::replay", "Network is in replay mode ({} connections recorded)", journal.remaining_connections());
+        Self { journal }
+    }
+}
+
+impl Connector for ReplayConnector {
+    type Stream = ReplayStream;
+
+    fn connect(&self, host: &str, port: u16) -> io::Result<ReplayStream> {
+        ReplayStream::connect(&self.journal, &join_addr(host, port))
+    }
+}
+
+impl DatagramTransport for ReplayJournal {
+    fn exchange(&self, server: &str, query: &[u8]) -> io::Result<Vec<u8>> {
+        let mut stream = ReplayStream::connect(self, &format!("udp://{}", server))?;
+        stream.write_all(query)?;
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply)?;
+        stream.finish()?;
+        Ok(reply)
+    }
+}
@@ -0,0 +1,317 @@
+// Browser Trust Store
+// Phase 1: certificates are verified "against our own Trust Store, not the OS store".
+// Roots come from three places:
+//   1. the bundled set shipped with the browser,
+//   2. roots the user imported,
+//   3. minus any root the user distrusted.
+// Roots are identified by the SHA-256 of their SubjectPublicKeyInfo (hex).
+
+use std::collections::BTreeSet;
+use std::io;
+use std::sync::Arc;
+use tracing::info;
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, TrustAnchor};
+use rustls::RootCertStore;
+use sha2::{Digest, Sha256};
+use x509_parser::prelude::{FromDer, X509Name};
+
+use crate::profile::Profile;
+
+const USER_ROOTS_FILE: &str = "trust_roots.pem";
+const DISTRUSTED_FILE: &str = "trust_distrusted.txt";
+
+#[derive(Debug, thiserror::Error)]
+pub enum TrustStoreError {
+    #[error("invalid PEM: {0}")]
+    Pem(String),
+    #[error("not a usable root certificate: {0}")]
+    InvalidCertificate(String),
+    #[error("no root with fingerprint {0}")]
+    UnknownRoot(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RootOrigin {
+    Bundled,
+    User,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RootInfo {
+    pub fingerprint: String,
+    pub subject: String,
+    pub origin: RootOrigin,
+    pub distrusted: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct TrustStore {
+    bundled: Vec<TrustAnchor<'static>>,
+    user_roots: Vec<CertificateDer<'static>>,
+    distrusted: BTreeSet<String>,
+}
+
+impl Default for TrustStore {
+    fn default() -> Self {
+        Self::bundled()
+    }
+}
+
+impl TrustStore {
+    /// The roots shipped with the browser.
+    pub fn bundled() -> Self {
+        Self { bundled: webpki_roots::TLS_SERVER_ROOTS.to_vec(), user_roots: Vec::new(), distrusted: BTreeSet::new() }
+    }
+
+    /// A store that trusts nothing until roots are added.
+    pub fn empty() -> Self {
+        Self { bundled: Vec::new(), user_roots: Vec::new(), distrusted: BTreeSet::new() }
+    }
+
+    /// Bundled roots plus the user's additions and distrust list from `profile`.
+    pub fn load(profile: &Profile) -> Result<Self, TrustStoreError> {
+        let mut store = Self::bundled();
+        if let Some(pem) = profile.read_optional(USER_ROOTS_FILE)?.filter(|pem| !pem.trim().is_empty()) {
+            store.import_pem(pem.as_bytes())?;
+        }
+        if let Some(list) = profile.read_optional(DISTRUSTED_FILE)? {
+            store.distrusted = list.lines().map(str::trim).filter(|l| !l.is_empty()).map(str::to_string).collect();
+        }
+        info!(
+            target: "net::trust",
+            "Trust store loaded: {} bundled, {} user, {} distrusted",
+            store.bundled.len(),
+            store.user_roots.len(),
+            store.distrusted.len()
+        );
+        Ok(store)
+    }
+
+    pub fn save(&self, profile: &Profile) -> Result<(), TrustStoreError> {
+        profile.write_atomic(USER_ROOTS_FILE, self.export_pem().as_bytes())?;
+        let mut list = String::new();
+        for fingerprint in &self.distrusted {
+            list.push_str(fingerprint);
+            list.push('\n');
+        }
+        profile.write_atomic(DISTRUSTED_FILE, list.as_bytes())?;
+        Ok(())
+    }
+
+    /// Adds every certificate in `pem` as a user root. Returns their fingerprints.
+    pub fn import_pem(&mut self, pem: &[u8]) -> Result<Vec<String>, TrustStoreError> {
+        let certs = CertificateDer::pem_slice_iter(pem)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| TrustStoreError::Pem(format!("{:?}", e)))?;
+        if certs.is_empty() {
+            return Err(TrustStoreError::Pem("no CERTIFICATE blocks found".to_string()));
+        }
+        certs.into_iter().map(|cert| self.add_root(cert)).collect()
+    }
+
+    pub fn add_root(&mut self, cert: CertificateDer<'static>) -> Result<String, TrustStoreError> {
+        let (fingerprint, subject) = describe_certificate(&cert)?;
+
+        // Reject anything rustls itself would refuse as an anchor.
+        RootCertStore::empty()
+            .add(cert.clone())
+            .map_err(|e| TrustStoreError::InvalidCertificate(e.to_string()))?;
+
+        if !self.user_roots.iter().any(|c| c == &cert) {
+            info!(target: "net::trust", "Added user root {} ({})", subject, fingerprint);
+            self.user_roots.push(cert);
+        }
+        Ok(fingerprint)
+    }
+
+    /// Deletes a user root, along with any distrust entry for it unless a bundled root shares the key.
+    pub fn remove_root(&mut self, fingerprint: &str) -> Result<(), TrustStoreError> {
+        let before = self.user_roots.len();
+        self.user_roots
+            .retain(|cert| describe_certificate(cert).map(|(fp, _)| fp != fingerprint).unwrap_or(true));
+        if self.user_roots.len() == before {
+            return Err(TrustStoreError::UnknownRoot(fingerprint.to_string()));
+        }
+        if !self.bundled.iter().any(|anchor| anchor_fingerprint(anchor) == fingerprint) {
+            self.distrusted.remove(fingerprint);
+        }
+        Ok(())
+    }
+
+    /// Stops trusting a root (bundled or user) without deleting it.
+    pub fn distrust(&mut self, fingerprint: &str) -> Result<(), TrustStoreError> {
+        if !self.roots().iter().any(|r| r.fingerprint == fingerprint) {
+            return Err(TrustStoreError::UnknownRoot(fingerprint.to_string()));
+        }
+        info!(target: "net::trust", "Distrusting root {}", fingerprint);
+        self.distrusted.insert(fingerprint.to_string());
+        Ok(())
+    }
+
+    pub fn restore(&mut self, fingerprint: &str) -> Result<(), TrustStoreError> {
+        if !self.distrusted.remove(fingerprint) {
+            return Err(TrustStoreError::UnknownRoot(fingerprint.to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn is_distrusted(&self, fingerprint: &str) -> bool {
+        self.distrusted.contains(fingerprint)
+    }
+
+    /// Every known root with its status, bundled first.
+    pub fn roots(&self) -> Vec<RootInfo> {
+        let bundled = self.bundled.iter().map(|anchor| {
+            let fingerprint = anchor_fingerprint(anchor);
+            let subject = name_to_string(&der_sequence(anchor.subject.as_ref()));
+            (fingerprint, subject, RootOrigin::Bundled)
+        });
+        let user = self.user_roots.iter().filter_map(|cert| {
+            describe_certificate(cert).ok().map(|(fingerprint, subject)| (fingerprint, subject, RootOrigin::User))
+        });
+
+        bundled
+            .chain(user)
+            .map(|(fingerprint, subject, origin)| RootInfo {
+                distrusted: self.distrusted.contains(&fingerprint),
+                fingerprint,
+                subject,
+                origin,
+            })
+            .collect()
+    }
+
+    /// User-added roots as PEM. Bundled roots ship with the binary and are not exported.
+    pub fn export_pem(&self) -> String {
+        use base64::Engine;
+        let mut out = String::new();
+        for cert in &self.user_roots {
+            let encoded = base64::engine::general_purpose::STANDARD.encode(cert.as_ref());
+            out.push_str("-----BEGIN CERTIFICATE-----\n");
+            for line in encoded.as_bytes().chunks(64) {
+                out.push_str(std::str::from_utf8(line).expect("base64 is ASCII"));
+                out.push('\n');
+            }
+            out.push_str("-----END CERTIFICATE-----\n");
+        }
+        out
+    }
+
+    /// The anchors chain verification may use: everything known, minus the distrusted.
+    pub fn root_cert_store(&self) -> Arc<RootCertStore> {
+        let mut store = RootCertStore::empty();
+        for anchor in &self.bundled {
+            let fingerprint = anchor_fingerprint(anchor);
+            if !self.distrusted.contains(&fingerprint) {
+                store.roots.push(anchor.clone());
+            }
+        }
+        for cert in &self.user_roots {
+            let trusted = describe_certificate(cert).map(|(fp, _)| !self.distrusted.contains(&fp)).unwrap_or(false);
+            if trusted {
+                // Validated in add_root.
+                let _ = store.add(cert.clone());
+            }
+        }
+        Arc::new(store)
+    }
+}
+
+/// Hex SHA-256 of a DER-encoded SubjectPublicKeyInfo.
+pub fn spki_fingerprint(spki_der: &[u8]) -> String {
+    Sha256::digest(spki_der).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Returns (SPKI fingerprint, subject) for a certificate.
+pub(crate) fn describe_certificate(cert: &CertificateDer<'_>) -> Result<(String, String), TrustStoreError> {
+    let (_, parsed) = x509_parser::parse_x509_certificate(cert.as_ref())
+        .map_err(|e| TrustStoreError::InvalidCertificate(e.to_string()))?;
+    Ok((spki_fingerprint(parsed.tbs_certificate.subject_pki.raw), parsed.subject().to_string()))
+}
+
+pub(crate) fn name_to_string(name_der: &[u8]) -> String {
+    X509Name::from_der(name_der).map(|(_, name)| name.to_string()).unwrap_or_else(|_| "<unparsable name>".to_string())
+}
+
+/// SPKI fingerprint of a trust anchor, comparable with `describe_certificate`'s.
+pub(crate) fn anchor_fingerprint(anchor: &TrustAnchor<'_>) -> String {
+    spki_fingerprint(&der_sequence(anchor.subject_public_key_info.as_ref()))
+}
+
+/// Trust anchors store SPKI and subject without their outer SEQUENCE; put it back.
+pub(crate) fn der_sequence(contents: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x30];
+    let len = contents.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes: Vec<u8> = len.to_be_bytes().iter().copied().skip_while(|&b| b == 0).collect();
+        out.push(0x80 | bytes.len() as u8);
+        out.extend_from_slice(&bytes);
+    }
+    out.extend_from_slice(contents);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_pki::TestPki;
+
+    #[test]
+    fn test_import_export_and_distrust() {
+        let pki = TestPki::new("localhost");
+        let mut store = TrustStore::empty();
+        let original = TrustStore { user_roots: vec![pki.ca_der()], ..TrustStore::empty() }.export_pem();
+
+        let fingerprints = store.import_pem(original.as_bytes()).unwrap();
+        assert_eq!(fingerprints.len(), 1);
+        assert_eq!(store.export_pem(), original);
+        assert_eq!(store.root_cert_store().roots.len(), 1);
+
+        let info = &store.roots()[0];
+        assert_eq!(info.origin, RootOrigin::User);
+        assert!(info.subject.contains("YoloFi Test Root CA"));
+
+        store.distrust(&fingerprints[0]).unwrap();
+        assert!(store.root_cert_store().roots.is_empty());
+        store.restore(&fingerprints[0]).unwrap();
+        assert_eq!(store.root_cert_store().roots.len(), 1);
+
+        assert!(matches!(store.distrust("00ff"), Err(TrustStoreError::UnknownRoot(_))));
+
+        // Removing a distrusted root takes its distrust entry along, so re-adding it trusts it again.
+        store.distrust(&fingerprints[0]).unwrap();
+        store.remove_root(&fingerprints[0]).unwrap();
+        assert!(!store.is_distrusted(&fingerprints[0]));
+        store.import_pem(original.as_bytes()).unwrap();
+        assert_eq!(store.root_cert_store().roots.len(), 1);
+        assert!(matches!(store.remove_root("00ff"), Err(TrustStoreError::UnknownRoot(_))));
+        assert!(matches!(store.import_pem(b"not pem"), Err(TrustStoreError::Pem(_))));
+    }
+
+    #[test]
+    fn test_bundled_roots_can_be_distrusted_and_persisted() {
+        let dir = std::env::temp_dir().join(format!("yolofi_trust_{}", std::process::id()));
+        let profile = Profile::open(&dir).unwrap();
+
+        let mut store = TrustStore::bundled();
+        let bundled_count = store.root_cert_store().roots.len();
+        let victim = store.roots()[0].fingerprint.clone();
+        assert_eq!(victim.len(), 64);
+        store.distrust(&victim).unwrap();
+        let extra = TrustStore { user_roots: vec![TestPki::new("a.test").ca_der()], ..TrustStore::empty() };
+        store.import_pem(extra.export_pem().as_bytes()).unwrap();
+        store.save(&profile).unwrap();
+
+        let reloaded = TrustStore::load(&profile).unwrap();
+        assert!(reloaded.is_distrusted(&victim));
+        assert_eq!(reloaded.root_cert_store().roots.len(), bundled_count - 1 + 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
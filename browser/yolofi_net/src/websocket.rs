@@ -0,0 +1,879 @@
+// WebSocket Client (RFC 6455)
+// The opening handshake is an HTTP/1.1 GET with Upgrade: websocket; after the 101
+// the same stream carries frames. `connect` dials ws:// the way a `FetchClient` does
+// (its DNS resolver and proxy) and wss:// over TLS with the client's trust settings
+// (offering only http/1.1, since WebSockets over HTTP/2 are not supported);
+// `WebSocket::handshake` works on any stream that is already connected.
+//
+//   - every client frame is masked with a fresh key from the system CSPRNG
+//   - fragmented messages are reassembled; control frames may arrive in between
+//   - pings are answered with a pong and also handed to the caller
+//   - close codes are validated, and a close from the peer is echoed
+//   - permessage-deflate (RFC 7692) is offered and used when the server agrees
+//
+// Anything the peer gets wrong closes the connection with the matching close code
+// (1002, 1007 or 1009) and returns a typed error.
+
+use base64::Engine;
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+use rustls::crypto::SecureRandom;
+use sha1::{Digest, Sha1};
+use std::collections::VecDeque;
+use std::io::{self, BufReader, Read};
+use tracing::{debug, info, warn};
+
+use crate::fetch::{FetchClient, FetchError};
+use crate::headers::HeaderMap;
+use crate::http;
+use crate::http_parser::{ParseLimits, DEFAULT_MAX_BODY_SIZE, DEFAULT_MAX_CHUNK_SIZE};
+use crate::identity::Identity;
+use crate::request::{RequestBuilder, RequestError};
+use crate::tls::{self, TlsError, ALPN_HTTP11};
+use crate::transport::{Connector, DatagramTransport, Transport};
+use crate::Url;
+
+/// Appended to the key before hashing (section 1.3).
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// What a sync flush leaves at the end of a compressed message (RFC 7692 section 7.2.1).
+const DEFLATE_TAIL: [u8; 4] = [0x00, 0x00, 0xFF, 0xFF];
+
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// The 101 carries a handful of fields; anything much bigger is not a WebSocket server.
+const HANDSHAKE_LIMITS: ParseLimits = ParseLimits {
+    max_chunk_size: DEFAULT_MAX_CHUNK_SIZE,
+    max_body_size: DEFAULT_MAX_BODY_SIZE,
+    max_status_line: 1024,
+    max_headers: 64,
+    max_header_bytes: 16 * 1024,
+};
+
+pub const CLOSE_NORMAL: u16 = 1000;
+pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+pub const CLOSE_INVALID_DATA: u16 = 1007;
+pub const CLOSE_TOO_BIG: u16 = 1009;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+const FIN: u8 = 0x80;
+const RSV1: u8 = 0x40;
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebSocketError {
+    #[error("unsupported URL scheme {0:?}")]
+    UnsupportedScheme(String),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Connect(#[from] FetchError),
+    #[error("TLS error: {0}")]
+    Tls(#[from] TlsError),
+    #[error("invalid handshake request: {0}")]
+    InvalidRequest(#[from] RequestError),
+    #[error("handshake rejected: {0}")]
+    Handshake(String),
+    #[error("protocol error: {0}")]
+    Protocol(String),
+    #[error("text message is not valid UTF-8")]
+    InvalidUtf8,
+    #[error("message exceeds the {limit} byte limit")]
+    MessageTooLarge { limit: usize },
+    #[error("the connection is closed")]
+    Closed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// The peer's close frame; `None` if it carried no status code.
+    Close(Option<CloseFrame>),
+}
+
+/// Codes an endpoint may put in a close frame (section 7.4).
+pub fn is_valid_close_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999)
+}
+
+#[derive(Debug, Clone)]
+pub struct WebSocketConfig {
+    /// Subprotocols offered in Sec-WebSocket-Protocol, in preference order.
+    pub protocols: Vec<String>,
+    /// Offers permessage-deflate.
+    pub deflate: bool,
+    /// Largest message accepted, after reassembly and decompression.
+    pub max_message_size: usize,
+    /// Outgoing messages longer than this are sent in fragments.
+    pub max_frame_size: usize,
+    /// User-Agent and the other fields every request presents.
+    pub identity: Identity,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        Self {
+            protocols: Vec::new(),
+            deflate: true,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            identity: Identity::default(),
+        }
+    }
+}
+
+/// An open WebSocket connection.
+pub struct WebSocket<S: Transport> {
+    stream: BufReader<S>,
+    random: &'static dyn SecureRandom,
+    protocol: Option<String>,
+    deflate: Option<Deflate>,
+    max_message_size: usize,
+    max_frame_size: usize,
+    /// A fragmented data message in progress: opcode, compressed flag and payload so far.
+    partial: Option<(u8, bool, Vec<u8>)>,
+    /// Messages that arrived while `close` waited for the peer, returned by later reads.
+    pending: VecDeque<Message>,
+    sent_close: bool,
+    received_close: bool,
+}
+
+/// Opens a ws:// or wss:// URL through `client`'s connector, resolver and TLS settings,
+/// and performs the handshake.
+pub fn connect<C, D>(
+    client: &FetchClient<C, D>,
+    url: &Url,
+    config: &WebSocketConfig,
+) -> Result<WebSocket<Box<dyn Transport>>, WebSocketError>
+where
+    C: Connector,
+    C::Stream: 'static,
+    D: DatagramTransport,
+{
+    let secure = match url.scheme() {
+        "ws" => false,
+        "wss" => true,
+        other => return Err(WebSocketError::UnsupportedScheme(other.to_string())),
+    };
+    let host = url.host().ok_or_else(|| WebSocketError::Handshake("URL has no host".to_string()))?;
+    let port = url.port_or_default().expect("ws(s) has a default port");
+    let stream = client.dialer().dial(host, port)?;
+    let stream: Box<dyn Transport> = if secure {
+        let tls = client.tls().clone().with_alpn(&[ALPN_HTTP11]);
+        Box::new(tls::handshake(stream, &host.to_socket_host(), &tls)?)
+    } else {
+        Box::new(stream)
+    };
+    WebSocket::handshake(stream, url, config)
+}
+
+impl<S: Transport> WebSocket<S> {
+    /// Sends the upgrade request on `stream` and checks the server's 101 (section 4.1).
+    pub fn handshake(mut stream: S, url: &Url, config: &WebSocketConfig) -> Result<Self, WebSocketError> {
+        let random = rustls::crypto::ring::default_provider().secure_random;
+        let mut nonce = [0u8; 16];
+        random.fill(&mut nonce).map_err(|_| io::Error::other("no system randomness"))?;
+        let key = base64::engine::general_purpose::STANDARD.encode(nonce);
+
+        let mut request = RequestBuilder::get(url)
+            .header("Upgrade", "websocket")
+            .header("Connection", "Upgrade")
+            .header("Sec-WebSocket-Key", &key)
+            .header("Sec-WebSocket-Version", "13");
+        if !config.protocols.is_empty() {
+            request = request.header("Sec-WebSocket-Protocol", &config.protocols.join(", "));
+        }
+        if config.deflate {
+            request = request.header("Sec-WebSocket-Extensions", "permessage-deflate");
+        }
+        let request = config.identity.apply(request).build()?;
+        info!(target: "net::websocket", "Opening WebSocket to {}", url);
+        stream.write_all(&request.serialize())?;
+        stream.flush()?;
+
+        // Frames may follow the 101 in the same read; they stay in the BufReader.
+        let mut stream = BufReader::new(stream);
+        let response = http::read_response_head(&mut stream, "GET", HANDSHAKE_LIMITS)?;
+        if response.status != 101 {
+            return Err(WebSocketError::Handshake(format!("status {} instead of 101", response.status)));
+        }
+        let headers = &response.headers;
+        if !headers.get("Upgrade").is_some_and(|v| v.trim().eq_ignore_ascii_case("websocket")) {
+            return Err(WebSocketError::Handshake("missing Upgrade: websocket".to_string()));
+        }
+        let connection = headers.get_combined("Connection").unwrap_or_default();
+        if !connection.split(',').any(|token| token.trim().eq_ignore_ascii_case("upgrade")) {
+            return Err(WebSocketError::Handshake("missing Connection: Upgrade".to_string()));
+        }
+        if headers.get("Sec-WebSocket-Accept") != Some(accept_key(&key).as_str()) {
+            return Err(WebSocketError::Handshake("wrong Sec-WebSocket-Accept".to_string()));
+        }
+        let protocol = headers.get("Sec-WebSocket-Protocol").map(str::to_string);
+        if let Some(protocol) = protocol.as_ref().filter(|p| !config.protocols.contains(p)) {
+            return Err(WebSocketError::Handshake(format!("subprotocol {:?} was not offered", protocol)));
+        }
+        let deflate = negotiated_deflate(headers, config.deflate)?;
+        debug!(target: "net::websocket", "Handshake done: protocol={:?} deflate={}", protocol, deflate.is_some());
+
+        Ok(Self {
+            stream,
+            random,
+            protocol,
+            deflate,
+            max_message_size: config.max_message_size,
+            max_frame_size: config.max_frame_size.max(1),
+            partial: None,
+            pending: VecDeque::new(),
+            sent_close: false,
+            received_close: false,
+        })
+    }
+
+    /// The subprotocol the server picked, if any.
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    /// Whether messages are compressed with permessage-deflate.
+    pub fn is_compressed(&self) -> bool {
+        self.deflate.is_some()
+    }
+
+    pub fn get_ref(&self) -> &S {
+        self.stream.get_ref()
+    }
+
+    pub fn send_text(&mut self, text: &str) -> Result<(), WebSocketError> {
+        self.send(Message::Text(text.to_string()))
+    }
+
+    pub fn send_binary(&mut self, data: &[u8]) -> Result<(), WebSocketError> {
+        self.send(Message::Binary(data.to_vec()))
+    }
+
+    pub fn ping(&mut self, payload: &[u8]) -> Result<(), WebSocketError> {
+        self.send(Message::Ping(payload.to_vec()))
+    }
+
+    pub fn send(&mut self, message: Message) -> Result<(), WebSocketError> {
+        if self.sent_close {
+            return Err(WebSocketError::Closed);
+        }
+        let (opcode, payload) = match message {
+            Message::Text(text) => (OP_TEXT, text.into_bytes()),
+            Message::Binary(data) => (OP_BINARY, data),
+            Message::Ping(data) => return self.write_control(OP_PING, &data),
+            Message::Pong(data) => return self.write_control(OP_PONG, &data),
+            Message::Close(frame) => {
+                let frame = frame.unwrap_or(CloseFrame { code: CLOSE_NORMAL, reason: String::new() });
+                return self.close(frame.code, &frame.reason);
+            }
+        };
+        let (payload, compressed) = match &mut self.deflate {
+            Some(deflate) => (deflate.compress(&payload)?, true),
+            None => (payload, false),
+        };
+        let mut chunks = payload.chunks(self.max_frame_size).peekable();
+        let mut first = true;
+        // An empty message is still one frame.
+        if chunks.peek().is_none() {
+            return self.write_frame(FIN | opcode | if compressed { RSV1 } else { 0 }, &[]);
+        }
+        while let Some(chunk) = chunks.next() {
+            let mut head = if first { opcode } else { OP_CONTINUATION };
+            if first && compressed {
+                head |= RSV1;
+            }
+            if chunks.peek().is_none() {
+                head |= FIN;
+            }
+            self.write_frame(head, chunk)?;
+            first = false;
+        }
+        Ok(())
+    }
+
+    /// Reads the next message. Pings are answered before they are returned.
+    pub fn read(&mut self) -> Result<Message, WebSocketError> {
+        match self.pending.pop_front() {
+            Some(message) => Ok(message),
+            None => self.read_message(),
+        }
+    }
+
+    fn read_message(&mut self) -> Result<Message, WebSocketError> {
+        loop {
+            if self.received_close {
+                return Err(WebSocketError::Closed);
+            }
+            let (head, payload) = match self.read_frame() {
+                Ok(frame) => frame,
+                Err(err) => return Err(self.fail(err)),
+            };
+            let opcode = head & 0x0F;
+            match opcode {
+                OP_PING => {
+                    if !self.sent_close {
+                        self.write_control(OP_PONG, &payload)?;
+                    }
+                    return Ok(Message::Ping(payload));
+                }
+                OP_PONG => return Ok(Message::Pong(payload)),
+                OP_CLOSE => {
+                    self.received_close = true;
+                    let frame = match parse_close(&payload) {
+                        Ok(frame) => frame,
+                        Err(err) => return Err(self.fail(err)),
+                    };
+                    if !self.sent_close {
+                        // Echo the status code to complete the closing handshake.
+                        let echo = frame.as_ref().map(|f| f.code.to_be_bytes().to_vec()).unwrap_or_default();
+                        self.sent_close = true;
+                        self.write_control(OP_CLOSE, &echo)?;
+                    }
+                    info!(target: "net::websocket", "Closed by peer: {:?}", frame);
+                    return Ok(Message::Close(frame));
+                }
+                _ => {}
+            }
+            match self.on_data_frame(head, payload) {
+                Ok(Some(message)) => return Ok(message),
+                Ok(None) => continue,
+                Err(err) => return Err(self.fail(err)),
+            }
+        }
+    }
+
+    /// Starts the closing handshake and waits for the peer's close frame.
+    /// Messages that arrive in the meantime are kept for `read`; pings are still answered.
+    pub fn close(&mut self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        if !is_valid_close_code(code) {
+            return Err(WebSocketError::Protocol(format!("close code {} may not be sent", code)));
+        }
+        if !self.sent_close {
+            let mut payload = code.to_be_bytes().to_vec();
+            payload.extend_from_slice(reason.as_bytes());
+            self.write_control(OP_CLOSE, &payload)?;
+            self.sent_close = true;
+        }
+        while !self.received_close {
+            match self.read_message() {
+                Ok(message @ (Message::Text(_) | Message::Binary(_))) => self.pending.push_back(message),
+                Ok(_) => {}
+                // The peer may simply drop the connection once it has our close.
+                Err(WebSocketError::Io(err)) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds a data frame to the message in progress; returns the message once it is complete.
+    fn on_data_frame(&mut self, head: u8, payload: Vec<u8>) -> Result<Option<Message>, WebSocketError> {
+        let opcode = head & 0x0F;
+        let (opcode, compressed, mut data) = match (opcode, self.partial.take()) {
+            (OP_CONTINUATION, Some((opcode, compressed, data))) => (opcode, compressed, data),
+            (OP_CONTINUATION, None) => {
+                return Err(WebSocketError::Protocol("continuation frame without a message".to_string()));
+            }
+            (_, Some(_)) => return Err(WebSocketError::Protocol("new message inside a fragmented one".to_string())),
+            (opcode, None) => (opcode, head & RSV1 != 0, Vec::new()),
+        };
+        if data.len() + payload.len() > self.max_message_size {
+            return Err(WebSocketError::MessageTooLarge { limit: self.max_message_size });
+        }
+        data.extend_from_slice(&payload);
+        if head & FIN == 0 {
+            self.partial = Some((opcode, compressed, data));
+            return Ok(None);
+        }
+        if compressed {
+            let deflate = self.deflate.as_mut().expect("RSV1 is rejected without permessage-deflate");
+            data = deflate.decompress(&data, self.max_message_size)?;
+        }
+        Ok(Some(match opcode {
+            OP_TEXT => Message::Text(String::from_utf8(data).map_err(|_| WebSocketError::InvalidUtf8)?),
+            _ => Message::Binary(data),
+        }))
+    }
+
+    /// Reads one frame and checks everything that does not depend on earlier frames.
+    fn read_frame(&mut self) -> Result<(u8, Vec<u8>), WebSocketError> {
+        let mut head = [0u8; 2];
+        self.stream.read_exact(&mut head)?;
+        let opcode = head[0] & 0x0F;
+        let control = opcode & 0x08 != 0;
+        if !matches!(opcode, OP_CONTINUATION | OP_TEXT | OP_BINARY | OP_CLOSE | OP_PING | OP_PONG) {
+            return Err(WebSocketError::Protocol(format!("unknown opcode {:#x}", opcode)));
+        }
+        let rsv = head[0] & 0x70;
+        let compressible = self.deflate.is_some() && matches!(opcode, OP_TEXT | OP_BINARY);
+        if rsv != 0 && !(rsv == RSV1 && compressible) {
+            return Err(WebSocketError::Protocol(format!("reserved bits {:#x} set", rsv)));
+        }
+        if head[1] & 0x80 != 0 {
+            return Err(WebSocketError::Protocol("server frames must not be masked".to_string()));
+        }
+        let length = match head[1] & 0x7F {
+            126 => {
+                let mut bytes = [0u8; 2];
+                self.stream.read_exact(&mut bytes)?;
+                u16::from_be_bytes(bytes) as u64
+            }
+            127 => {
+                let mut bytes = [0u8; 8];
+                self.stream.read_exact(&mut bytes)?;
+                u64::from_be_bytes(bytes)
+            }
+            short => short as u64,
+        };
+        if control && (length > 125 || head[0] & FIN == 0) {
+            return Err(WebSocketError::Protocol("control frames must be short and unfragmented".to_string()));
+        }
+        // Checked before allocating, so a huge declared length costs nothing.
+        if length > self.max_message_size as u64 {
+            return Err(WebSocketError::MessageTooLarge { limit: self.max_message_size });
+        }
+        let mut payload = vec![0u8; length as usize];
+        self.stream.read_exact(&mut payload)?;
+        Ok((head[0], payload))
+    }
+
+    fn write_control(&mut self, opcode: u8, payload: &[u8]) -> Result<(), WebSocketError> {
+        if payload.len() > 125 {
+            return Err(WebSocketError::Protocol("control payloads are limited to 125 bytes".to_string()));
+        }
+        self.write_frame(FIN | opcode, payload)
+    }
+
+    /// Writes one masked frame (section 5.2).
+    fn write_frame(&mut self, head: u8, payload: &[u8]) -> Result<(), WebSocketError> {
+        let mut frame = Vec::with_capacity(payload.len() + 14);
+        frame.push(head);
+        match payload.len() {
+            len @ 0..=125 => frame.push(0x80 | len as u8),
+            len @ 126..=0xFFFF => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        let mut mask = [0u8; 4];
+        self.random.fill(&mut mask).map_err(|_| io::Error::other("no system randomness"))?;
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m));
+        let stream = self.stream.get_mut();
+        stream.write_all(&frame)?;
+        stream.flush()?;
+        Ok(())
+    }
+
+    /// Closes with the code that matches `err`, as far as the connection still allows, and returns it.
+    fn fail(&mut self, err: WebSocketError) -> WebSocketError {
+        let code = match err {
+            WebSocketError::Protocol(_) => CLOSE_PROTOCOL_ERROR,
+            WebSocketError::InvalidUtf8 => CLOSE_INVALID_DATA,
+            WebSocketError::MessageTooLarge { .. } => CLOSE_TOO_BIG,
+            _ => return err,
+        };
+        warn!(target: "net::websocket", "Closing with {}: {}", code, err);
+        if !self.sent_close {
+            self.sent_close = true;
+            let _ = self.write_control(OP_CLOSE, &code.to_be_bytes());
+        }
+        self.received_close = true;
+        err
+    }
+}
+
+/// Sec-WebSocket-Accept for `key`: base64(SHA-1(key + GUID)).
+pub fn accept_key(key: &str) -> String {
+    let digest = Sha1::digest(format!("{}{}", key, ACCEPT_GUID).as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(digest)
+}
+
+fn parse_close(payload: &[u8]) -> Result<Option<CloseFrame>, WebSocketError> {
+    match payload {
+        [] => Ok(None),
+        [_] => Err(WebSocketError::Protocol("one-byte close payload".to_string())),
+        [high, low, reason @ ..] => {
+            let code = u16::from_be_bytes([*high, *low]);
+            if !is_valid_close_code(code) {
+                return Err(WebSocketError::Protocol(format!("invalid close code {}", code)));
+            }
+            let reason = std::str::from_utf8(reason).map_err(|_| WebSocketError::InvalidUtf8)?;
+            Ok(Some(CloseFrame { code, reason: reason.to_string() }))
+        }
+    }
+}
+
+/// The permessage-deflate parameters the server accepted, if it accepted any (RFC 7692 section 7.1).
+fn negotiated_deflate(headers: &HeaderMap, offered: bool) -> Result<Option<Deflate>, WebSocketError> {
+    let Some(extensions) = headers.get_combined("Sec-WebSocket-Extensions") else {
+        return Ok(None);
+    };
+    let mut deflate = None;
+    for extension in extensions.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let mut params = extension.split(';').map(str::trim);
+        let name = params.next().unwrap_or_default();
+        if !offered || !name.eq_ignore_ascii_case("permessage-deflate") || deflate.is_some() {
+            return Err(WebSocketError::Handshake(format!("extension {:?} was not offered", extension)));
+        }
+        let mut negotiated = Deflate::new();
+        for param in params {
+            let (key, value) = param.split_once('=').map_or((param, None), |(k, v)| (k.trim(), Some(v.trim())));
+            match (key, value) {
+                ("server_no_context_takeover", None) => negotiated.server_no_context_takeover = true,
+                ("client_no_context_takeover", None) => negotiated.client_no_context_takeover = true,
+                // A smaller server window only means shorter back-references; any window decodes it.
+                ("server_max_window_bits", Some(bits))
+                    if bits.trim_matches('"').parse::<u8>().is_ok_and(|b| (8..=15).contains(&b)) => {}
+                // Not offered, because the compressor always uses a 32 KiB window.
+                _ => return Err(WebSocketError::Handshake(format!("unsupported deflate parameter {:?}", param))),
+            }
+        }
+        deflate = Some(negotiated);
+    }
+    Ok(deflate)
+}
+
+/// permessage-deflate state for one connection: raw DEFLATE, sync-flushed per message.
+struct Deflate {
+    compress: Compress,
+    decompress: Decompress,
+    client_no_context_takeover: bool,
+    server_no_context_takeover: bool,
+}
+
+impl Deflate {
+    fn new() -> Self {
+        Self {
+            compress: Compress::new(Compression::default(), false),
+            decompress: Decompress::new(false),
+            client_no_context_takeover: false,
+            server_no_context_takeover: false,
+        }
+    }
+
+    fn compress(&mut self, data: &[u8]) -> Result<Vec<u8>, WebSocketError> {
+        let start = self.compress.total_in();
+        let mut out = Vec::with_capacity(data.len() / 2 + 64);
+        loop {
+            let consumed = (self.compress.total_in() - start) as usize;
+            self.compress
+                .compress_vec(&data[consumed..], &mut out, FlushCompress::Sync)
+                .map_err(|e| io::Error::other(e.to_string()))?;
+            // The flush is complete once all input is in and the output did not fill up.
+            if (self.compress.total_in() - start) as usize == data.len() && out.len() < out.capacity() {
+                break;
+            }
+            out.reserve(out.capacity());
+        }
+        if out.ends_with(&DEFLATE_TAIL) {
+            out.truncate(out.len() - DEFLATE_TAIL.len());
+        }
+        if self.client_no_context_takeover {
+            self.compress.reset();
+        }
+        Ok(out)
+    }
+
+    fn decompress(&mut self, data: &[u8], limit: usize) -> Result<Vec<u8>, WebSocketError> {
+        let input = [data, &DEFLATE_TAIL].concat();
+        let start = self.decompress.total_in();
+        let mut out = Vec::with_capacity((data.len() * 4).clamp(64, limit + 1));
+        loop {
+            let before = (self.decompress.total_in(), self.decompress.total_out());
+            let consumed = (before.0 - start) as usize;
+            self.decompress
+                .decompress_vec(&input[consumed..], &mut out, FlushDecompress::Sync)
+                .map_err(|e| WebSocketError::Protocol(format!("corrupt compressed message: {}", e)))?;
+            if out.len() > limit {
+                return Err(WebSocketError::MessageTooLarge { limit });
+            }
+            let done = (self.decompress.total_in() - start) as usize == input.len();
+            if done && out.len() < out.capacity() {
+                break;
+            }
+            let stalled = (self.decompress.total_in(), self.decompress.total_out()) == before;
+            if stalled && out.len() < out.capacity() {
+                return Err(WebSocketError::Protocol("truncated compressed message".to_string()));
+            }
+            // Grow towards the limit, never past it: one byte over is enough to know.
+            out.reserve(out.capacity().min(limit + 1 - out.len()).max(1));
+        }
+        if self.server_no_context_takeover {
+            self.decompress.reset(false);
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::DnsResolver;
+    use crate::pipe::PipeStream;
+    use crate::test_pki::TestPki;
+    use crate::tls::TlsConfig;
+    use crate::transport::{MemoryConnector, TcpConnector, UdpTransport};
+    use std::io::{BufRead, Write};
+    use std::net::TcpListener;
+
+    /// A minimal server: answers the handshake, then echoes every frame back unmasked
+    /// (compressed ones as they are) until it has echoed a close.
+    fn serve_echo<S: Read + Write>(stream: S, extensions: Option<&str>) {
+        let mut stream = BufReader::new(stream);
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            if stream.read_line(&mut head).unwrap() == 0 {
+                return;
+            }
+        }
+        let key = head.lines().find_map(|l| l.strip_prefix("Sec-WebSocket-Key: ")).unwrap();
+        let mut response = format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+Sec-WebSocket-Accept: {}\r\n",
+            accept_key(key)
+        );
+        if let Some(extensions) = extensions {
+            response.push_str(&format!("Sec-WebSocket-Extensions: {}\r\n", extensions));
+        }
+        stream.get_mut().write_all(format!("{}\r\n", response).as_bytes()).unwrap();
+        loop {
+            let mut head = [0u8; 2];
+            if stream.read_exact(&mut head).is_err() {
+                return;
+            }
+            assert_eq!(head[1] & 0x80, 0x80, "client frames are masked");
+            if extensions.is_some() && matches!(head[0] & 0x0F, OP_TEXT | OP_BINARY) {
+                assert_eq!(head[0] & RSV1, RSV1, "messages are compressed");
+            }
+            let length = match head[1] & 0x7F {
+                126 => {
+                    let mut bytes = [0u8; 2];
+                    stream.read_exact(&mut bytes).unwrap();
+                    u16::from_be_bytes(bytes) as usize
+                }
+                127 => {
+                    let mut bytes = [0u8; 8];
+                    stream.read_exact(&mut bytes).unwrap();
+                    u64::from_be_bytes(bytes) as usize
+                }
+                short => short as usize,
+            };
+            let mut mask = [0u8; 4];
+            stream.read_exact(&mut mask).unwrap();
+            let mut payload = vec![0u8; length];
+            stream.read_exact(&mut payload).unwrap();
+            payload.iter_mut().zip(mask.iter().cycle()).for_each(|(b, m)| *b ^= m);
+
+            let mut frame = vec![head[0]];
+            match length {
+                0..=125 => frame.push(length as u8),
+                _ => {
+                    frame.push(127);
+                    frame.extend_from_slice(&(length as u64).to_be_bytes());
+                }
+            }
+            frame.extend_from_slice(&payload);
+            stream.get_mut().write_all(&frame).unwrap();
+            if head[0] & 0x0F == OP_CLOSE {
+                return;
+            }
+        }
+    }
+
+    #[test]
+    fn test_echo_with_fragmentation_and_deflate() {
+        // A real local echo server over TCP.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("ws://{}/chat", listener.local_addr().unwrap())).unwrap();
+        let server = std::thread::spawn(move || serve_echo(listener.accept().unwrap().0, Some("permessage-deflate")));
+        let config = WebSocketConfig { max_frame_size: 100, ..WebSocketConfig::default() };
+        let client = FetchClient::<_, UdpTransport>::with_parts(TcpConnector::new(), None);
+        let mut socket = connect(&client, &url, &config).unwrap();
+        assert!(socket.is_compressed());
+
+        // The server checks that every message arrives compressed, and fragmented at 100 bytes.
+        let text = "realtime dashboard update; ".repeat(200);
+        socket.send_text(&text).unwrap();
+        assert_eq!(socket.read().unwrap(), Message::Text(text.clone()));
+        // The second message leans on the first through the shared window.
+        socket.send_text(&text).unwrap();
+        assert_eq!(socket.read().unwrap(), Message::Text(text));
+
+        let binary: Vec<u8> = (0..5000u32).map(|i| (i * 7919 % 251) as u8).collect();
+        socket.send_binary(&binary).unwrap();
+        assert_eq!(socket.read().unwrap(), Message::Binary(binary));
+        socket.send_binary(&[]).unwrap();
+        assert_eq!(socket.read().unwrap(), Message::Binary(Vec::new()));
+
+        socket.ping(b"are you there").unwrap();
+        assert_eq!(socket.read().unwrap(), Message::Ping(b"are you there".to_vec()));
+        socket.close(CLOSE_NORMAL, "bye").unwrap();
+        assert!(matches!(socket.read(), Err(WebSocketError::Closed)));
+        assert!(matches!(socket.send_text("late"), Err(WebSocketError::Closed)));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_echo_over_tls() {
+        let pki = TestPki::new("localhost");
+        let server_config = pki.server_config(&[ALPN_HTTP11]);
+        // Only the address our own resolver returned answers.
+        let memory = MemoryConnector::new(move |addr, _, pipe| {
+            if addr == "10.0.0.7" {
+                serve_echo(TestPki::accept(server_config.clone(), pipe), None)
+            }
+        });
+        let dns = DnsResolver::with_transport(|_: &str, query: &[u8]| {
+            let mut reply = query.to_vec();
+            reply[2..4].copy_from_slice(&[0x81, 0x80]);
+            reply[7] = 1; // ANCOUNT
+            reply.extend_from_slice(&[0xC0, 0x0C, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 10, 0, 0, 7]);
+            Ok(reply)
+        });
+        let tls = TlsConfig::with_root_certificates(&[pki.ca_der()]).unwrap();
+        let client = FetchClient::with_parts(memory, Some(dns)).with_tls(tls);
+        let url = Url::parse("wss://localhost/feed").unwrap();
+        let mut socket = connect(&client, &url, &WebSocketConfig::default()).unwrap();
+        assert!(!socket.is_compressed());
+        assert!(socket.get_ref().security_info().is_some());
+
+        socket.send_text("über TLS").unwrap();
+        assert_eq!(socket.read().unwrap(), Message::Text("über TLS".to_string()));
+        socket.close(CLOSE_NORMAL, "").unwrap();
+
+        let err = connect(&client, &Url::parse("https://localhost/").unwrap(), &WebSocketConfig::default());
+        assert!(matches!(err, Err(WebSocketError::UnsupportedScheme(_))));
+    }
+
+    #[test]
+    fn test_peer_mistakes_are_typed_and_closed() {
+        // A server that answers with `accept`, sends `frames`, and returns the close code the client sent back.
+        let run = |accept: Option<&str>, extensions: &str, frames: &'static [u8]| {
+            let (client, mut server) = crate::pipe::duplex("echo.test:80");
+            let (accept, extensions) = (accept.map(str::to_string), extensions.to_string());
+            let server = std::thread::spawn(move || {
+                let mut head = Vec::new();
+                let mut byte = [0u8; 1];
+                while !head.ends_with(b"\r\n\r\n") {
+                    server.read_exact(&mut byte).unwrap();
+                    head.push(byte[0]);
+                }
+                let head = String::from_utf8(head).unwrap();
+                let key = head.lines().find_map(|l| l.strip_prefix("Sec-WebSocket-Key: ")).unwrap();
+                let response = format!(
+                    "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: upgrade\r\n\
+Sec-WebSocket-Accept: {}\r\nSec-WebSocket-Extensions: {}\r\n\r\n",
+                    accept.unwrap_or_else(|| accept_key(key)),
+                    extensions
+                );
+                server.write_all(response.as_bytes()).unwrap();
+                server.write_all(frames).unwrap();
+                // FIN|close, masked length 2, mask, masked code.
+                let mut reply = [0u8; 8];
+                server.read_exact(&mut reply).ok()?;
+                assert_eq!(&reply[..2], b"\x88\x82");
+                Some(u16::from_be_bytes([reply[6] ^ reply[2], reply[7] ^ reply[3]]))
+            });
+            let url = Url::parse("ws://echo.test/").unwrap();
+            let result = WebSocket::<PipeStream>::handshake(client, &url, &WebSocketConfig::default())
+                .and_then(|mut socket| socket.read());
+            (result, server.join().unwrap())
+        };
+
+        let (result, sent) = run(Some("bm90IHRoZSBhY2NlcHQ="), "permessage-deflate", b"");
+        assert!(matches!(result, Err(WebSocketError::Handshake(_))) && sent.is_none(), "{:?}", result);
+        let (result, _) = run(None, "permessage-deflate; client_max_window_bits=10", b"");
+        assert!(matches!(result, Err(WebSocketError::Handshake(_))), "{:?}", result);
+
+        let cases: &[(&'static [u8], u16)] = &[
+            // A masked server frame.
+            (b"\x81\x81abcd\x00", CLOSE_PROTOCOL_ERROR),
+            // A fragmented ping.
+            (b"\x09\x00", CLOSE_PROTOCOL_ERROR),
+            // A continuation with nothing to continue.
+            (b"\x80\x01x", CLOSE_PROTOCOL_ERROR),
+            // RSV2 set.
+            (b"\xA1\x01x", CLOSE_PROTOCOL_ERROR),
+            // Invalid UTF-8 in a text message split over two frames.
+            (b"\x01\x01\xC3\x80\x01\x28", CLOSE_INVALID_DATA),
+            // Close code 1005 may never appear on the wire.
+            (b"\x88\x02\x03\xED", CLOSE_PROTOCOL_ERROR),
+            // A declared length of 2^62 is refused before anything is allocated.
+            (b"\x82\x7F\x40\x00\x00\x00\x00\x00\x00\x00", CLOSE_TOO_BIG),
+        ];
+        for &(frames, code) in cases {
+            let (result, sent) = run(None, "permessage-deflate", frames);
+            assert!(result.is_err(), "{:?}", frames);
+            assert_eq!(sent, Some(code), "{:?} for {:?}", result, frames);
+        }
+    }
+
+    #[test]
+    fn test_close_keeps_late_messages_and_caps_the_handshake() {
+        // Answers the upgrade with `head` after the status line, then runs `then`.
+        let serve = |head: String, then: fn(PipeStream)| {
+            let (client, server) = crate::pipe::duplex("echo.test:80");
+            let server = std::thread::spawn(move || {
+                let mut server = BufReader::new(server);
+                let mut request = String::new();
+                while !request.ends_with("\r\n\r\n") {
+                    server.read_line(&mut request).unwrap();
+                }
+                let key = request.lines().find_map(|l| l.strip_prefix("Sec-WebSocket-Key: ")).unwrap();
+                let head = head.replace("{accept}", &accept_key(key));
+                let mut server = server.into_inner();
+                server.write_all(head.as_bytes()).unwrap();
+                then(server);
+            });
+            let config = WebSocketConfig { deflate: false, ..WebSocketConfig::default() };
+            let socket = WebSocket::handshake(client, &Url::parse("ws://echo.test/").unwrap(), &config);
+            (socket, server)
+        };
+        let upgrade = "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+Sec-WebSocket-Accept: {accept}\r\n\r\n";
+
+        // A message the server sent before it saw our close is still delivered.
+        let (socket, server) = serve(upgrade.to_string(), |mut server| {
+            let mut head = [0u8; 2];
+            server.read_exact(&mut head).unwrap();
+            assert_eq!(head[0], 0x88);
+            let mut rest = vec![0u8; 4 + usize::from(head[1] & 0x7F)];
+            server.read_exact(&mut rest).unwrap();
+            server.write_all(b"\x81\x04late\x88\x02\x03\xE8").unwrap();
+        });
+        let mut socket = socket.unwrap();
+        socket.close(CLOSE_NORMAL, "").unwrap();
+        assert_eq!(socket.read().unwrap(), Message::Text("late".to_string()));
+        assert!(matches!(socket.read(), Err(WebSocketError::Closed)));
+        server.join().unwrap();
+
+        // An oversized head is refused, and a non-101 is rejected without waiting for its body.
+        let huge = format!("HTTP/1.1 101 Switching Protocols\r\nX-Padding: {}\r\n\r\n", "x".repeat(20_000));
+        let (socket, server) = serve(huge, |_| {});
+        assert!(matches!(socket, Err(WebSocketError::Io(_))));
+        server.join().unwrap();
+        let (socket, server) = serve("HTTP/1.1 200 OK\r\nContent-Length: 1000000\r\n\r\n".to_string(), |_| {});
+        assert!(matches!(socket, Err(WebSocketError::Handshake(_))));
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,300 @@
+// Host Parsing
+// A URL host is a domain (ASCII, after IDNA), an IPv4 or IPv6 address, an opaque
+// host (non-special schemes keep theirs percent-encoded but otherwise untouched),
+// or empty (file: URLs).
+
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::idna;
+use crate::percent::{self, EncodeSet};
+use crate::ParseError;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Host {
+    Domain(String),
+    Ipv4(Ipv4Addr),
+    Ipv6(Ipv6Addr),
+    Opaque(String),
+    Empty,
+}
+
+pub(crate) fn is_forbidden_host_code_point(c: char) -> bool {
+    matches!(
+        c,
+        '\0' | '\t' | '\n' | '\r' | ' ' | '#' | '/' | ':' | '<' | '>' | '?' | '@' | '[' | '\\' | ']' | '^' | '|'
+    )
+}
+
+impl Host {
+    /// `opaque` is set for non-special schemes.
+    pub fn parse(input: &str, opaque: bool) -> Result<Self, ParseError> {
+        if let Some(inner) = input.strip_prefix('[') {
+            let inner = inner.strip_suffix(']').ok_or_else(|| ParseError::InvalidIpv6Address(input.to_string()))?;
+            return parse_ipv6(inner).map(Host::Ipv6).ok_or_else(|| ParseError::InvalidIpv6Address(input.to_string()));
+        }
+        if opaque {
+            if input.chars().any(is_forbidden_host_code_point) {
+                return Err(ParseError::InvalidDomain(input.to_string()));
+            }
+            return Ok(if input.is_empty() {
+                Host::Empty
+            } else {
+                Host::Opaque(percent::encode(input, EncodeSet::C0Control))
+            });
+        }
+
+        let domain = percent::decode_utf8(input);
+        let ascii = idna::domain_to_ascii(&domain)?;
+        if ends_in_a_number(&ascii) {
+            return parse_ipv4(&ascii).map(Host::Ipv4).ok_or(ParseError::InvalidIpv4Address(ascii));
+        }
+        Ok(Host::Domain(ascii))
+    }
+
+    /// The name to resolve or present in SNI: domains only.
+    pub fn domain(&self) -> Option<&str> {
+        match self {
+            Host::Domain(domain) => Some(domain),
+            _ => None,
+        }
+    }
+
+    /// The host without IPv6 brackets, as a socket address wants it.
+    pub fn to_socket_host(&self) -> String {
+        match self {
+            Host::Ipv6(addr) => addr.to_string(),
+            other => other.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Host {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Host::Domain(domain) | Host::Opaque(domain) => f.write_str(domain),
+            Host::Ipv4(addr) => write!(f, "{}", addr),
+            Host::Ipv6(addr) => write!(f, "[{}]", serialize_ipv6(addr)),
+            Host::Empty => Ok(()),
+        }
+    }
+}
+
+/// Labels like "0x7f" or "1" make the whole host an IPv4 address.
+fn ends_in_a_number(host: &str) -> bool {
+    let mut parts: Vec<&str> = host.split('.').collect();
+    if parts.len() > 1 && parts.last() == Some(&"") {
+        parts.pop();
+    }
+    let last = parts.last().copied().unwrap_or("");
+    (!last.is_empty() && last.bytes().all(|b| b.is_ascii_digit())) || parse_ipv4_number(last).is_some()
+}
+
+/// Decimal, 0x-prefixed hex or 0-prefixed octal.
+fn parse_ipv4_number(input: &str) -> Option<u64> {
+    if input.is_empty() {
+        return None;
+    }
+    let (digits, radix) = if let Some(hex) = input.strip_prefix("0x").or_else(|| input.strip_prefix("0X")) {
+        (hex, 16)
+    } else if input.len() > 1 && input.starts_with('0') {
+        (&input[1..], 8)
+    } else {
+        (input, 10)
+    };
+    if digits.is_empty() {
+        return Some(0);
+    }
+    if !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    // Anything this long is out of range anyway; saturate instead of overflowing.
+    Some(u64::from_str_radix(digits, radix).unwrap_or(u64::MAX))
+}
+
+fn parse_ipv4(input: &str) -> Option<Ipv4Addr> {
+    let mut parts: Vec<&str> = input.split('.').collect();
+    if parts.len() > 1 && parts.last() == Some(&"") {
+        parts.pop();
+    }
+    if parts.len() > 4 {
+        return None;
+    }
+    let numbers: Vec<u64> = parts.iter().map(|p| parse_ipv4_number(p)).collect::<Option<_>>()?;
+    let (last, rest) = numbers.split_last()?;
+    if rest.iter().any(|&n| n > 255) || *last >= 256u64.pow(5 - numbers.len() as u32) {
+        return None;
+    }
+    let mut address = *last;
+    for (i, &n) in rest.iter().enumerate() {
+        address += n << (8 * (3 - i));
+    }
+    Some(Ipv4Addr::from(address as u32))
+}
+
+fn parse_ipv6(input: &str) -> Option<Ipv6Addr> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut pieces = [0u16; 8];
+    let mut index = 0;
+    let mut compress: Option<usize> = None;
+    let mut p = 0;
+
+    if chars.first() == Some(&':') {
+        if chars.get(1) != Some(&':') {
+            return None;
+        }
+        p = 2;
+        index = 1;
+        compress = Some(1);
+    }
+    while p < chars.len() {
+        if index == 8 {
+            return None;
+        }
+        if chars[p] == ':' {
+            if compress.is_some() {
+                return None;
+            }
+            p += 1;
+            index += 1;
+            compress = Some(index);
+            continue;
+        }
+
+        let mut value = 0u16;
+        let mut length = 0;
+        while length < 4 && p < chars.len() && chars[p].is_ascii_hexdigit() {
+            value = value * 0x10 + chars[p].to_digit(16)? as u16;
+            p += 1;
+            length += 1;
+        }
+        if chars.get(p) == Some(&'.') {
+            // Embedded IPv4: the last 32 bits, in dotted decimal.
+            if length == 0 || index > 6 {
+                return None;
+            }
+            p -= length;
+            let mut numbers_seen = 0;
+            while p < chars.len() {
+                let mut piece: Option<u16> = None;
+                if numbers_seen > 0 {
+                    if chars[p] == '.' && numbers_seen < 4 {
+                        p += 1;
+                    } else {
+                        return None;
+                    }
+                }
+                if !chars.get(p)?.is_ascii_digit() {
+                    return None;
+                }
+                while let Some(d) = chars.get(p).and_then(|c| c.to_digit(10)) {
+                    piece = match piece {
+                        None => Some(d as u16),
+                        Some(0) => return None,
+                        Some(n) => Some(n * 10 + d as u16),
+                    };
+                    if piece? > 255 {
+                        return None;
+                    }
+                    p += 1;
+                }
+                pieces[index] = pieces[index] * 0x100 + piece?;
+                numbers_seen += 1;
+                if numbers_seen == 2 || numbers_seen == 4 {
+                    index += 1;
+                }
+            }
+            if numbers_seen != 4 {
+                return None;
+            }
+            break;
+        }
+        match chars.get(p) {
+            Some(':') => {
+                p += 1;
+                if p == chars.len() {
+                    return None;
+                }
+            }
+            Some(_) => return None,
+            None => {}
+        }
+        pieces[index] = value;
+        index += 1;
+    }
+
+    match compress {
+        Some(start) => {
+            let moved = index - start;
+            for i in 0..moved {
+                pieces[7 - i] = pieces[index - 1 - i];
+                pieces[index - 1 - i] = 0;
+            }
+        }
+        None if index != 8 => return None,
+        None => {}
+    }
+    Some(Ipv6Addr::from(pieces))
+}
+
+/// Compresses the first longest run of two or more zero pieces; no IPv4 suffix form.
+fn serialize_ipv6(addr: &Ipv6Addr) -> String {
+    let pieces = addr.segments();
+    let (mut best, mut best_len) = (None, 1);
+    let mut i = 0;
+    while i < 8 {
+        let run = pieces[i..].iter().take_while(|&&p| p == 0).count();
+        if run > best_len {
+            best = Some(i);
+            best_len = run;
+        }
+        i += run.max(1);
+    }
+
+    let mut out = String::new();
+    let mut i = 0;
+    while i < 8 {
+        if best == Some(i) {
+            out.push_str(if i == 0 { "::" } else { ":" });
+            i += best_len;
+            continue;
+        }
+        out.push_str(&format!("{:x}", pieces[i]));
+        if i < 7 {
+            out.push(':');
+        }
+        i += 1;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(input: &str) -> String {
+        Host::parse(input, false).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_special_hosts() {
+        assert_eq!(host("EXAMPLE.com"), "example.com");
+        assert_eq!(host("0x7f.1"), "127.0.0.1");
+        assert_eq!(host("3232235777"), "192.168.1.1");
+        assert_eq!(host("0300.0250.01.01"), "192.168.1.1");
+        assert_eq!(host("ex%41mple.org"), "example.org");
+        assert!(Host::parse("1.2.3.256", false).is_err());
+        assert!(Host::parse("1.2.3.4.5", false).is_err());
+
+        assert_eq!(host("[2001:DB8:0:0:1:0:0:1]"), "[2001:db8::1:0:0:1]");
+        assert_eq!(host("[::ffff:192.168.0.1]"), "[::ffff:c0a8:1]");
+        assert_eq!(host("[::]"), "[::]");
+        assert_eq!(host("[1::]"), "[1::]");
+        assert!(Host::parse("[1:2:3:4:5:6:7:8:9]", false).is_err());
+        assert!(Host::parse("[::1::]", false).is_err());
+        assert_eq!(Host::parse("[::1]", false).unwrap().to_socket_host(), "::1");
+
+        assert_eq!(Host::parse("Opaque%20Host", true).unwrap(), Host::Opaque("Opaque%20Host".into()));
+        assert!(Host::parse("a b", true).is_err());
+    }
+}
@@ -0,0 +1,196 @@
+// IDNA
+// Internationalized domain names go on the wire as ASCII: each non-ASCII label
+// becomes "xn--" + Punycode (RFC 3492).
+//
+// Mapping is a subset of UTS #46: case folding and the ideographic full stops.
+// Labels are not NFC-normalized, so "é" spelled as e + U+0301 encodes differently
+// from the precomposed form.
+
+use crate::ParseError;
+
+const BASE: u32 = 36;
+const T_MIN: u32 = 1;
+const T_MAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 0x80;
+
+fn adapt(mut delta: u32, points: u32, first: bool) -> u32 {
+    delta /= if first { DAMP } else { 2 };
+    delta += delta / points;
+    let mut k = 0;
+    while delta > ((BASE - T_MIN) * T_MAX) / 2 {
+        delta /= BASE - T_MIN;
+        k += BASE;
+    }
+    k + (BASE - T_MIN + 1) * delta / (delta + SKEW)
+}
+
+fn threshold(k: u32, bias: u32) -> u32 {
+    k.saturating_sub(bias).clamp(T_MIN, T_MAX)
+}
+
+fn digit(d: u32) -> char {
+    (if d < 26 { b'a' + d as u8 } else { b'0' + (d - 26) as u8 }) as char
+}
+
+/// Punycode-encodes one label (without the "xn--" prefix). `None` on overflow.
+pub fn punycode_encode(label: &str) -> Option<String> {
+    let input: Vec<u32> = label.chars().map(u32::from).collect();
+    let mut output: String = label.chars().filter(char::is_ascii).collect();
+    let basic = output.len() as u32;
+    let mut handled = basic;
+    if basic > 0 {
+        output.push('-');
+    }
+
+    let (mut n, mut delta, mut bias) = (INITIAL_N, 0u32, INITIAL_BIAS);
+    while (handled as usize) < input.len() {
+        let m = input.iter().copied().filter(|&c| c >= n).min()?;
+        delta = delta.checked_add((m - n).checked_mul(handled + 1)?)?;
+        n = m;
+        for &c in &input {
+            if c < n {
+                delta = delta.checked_add(1)?;
+            }
+            if c == n {
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = threshold(k, bias);
+                    if q < t {
+                        break;
+                    }
+                    output.push(digit(t + (q - t) % (BASE - t)));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                output.push(digit(q));
+                bias = adapt(delta, handled + 1, handled == basic);
+                delta = 0;
+                handled += 1;
+            }
+        }
+        delta += 1;
+        n += 1;
+    }
+    Some(output)
+}
+
+/// Decodes one Punycode label (without the "xn--" prefix).
+pub fn punycode_decode(label: &str) -> Option<String> {
+    let (basic, encoded) = match label.rfind('-') {
+        Some(i) => (&label[..i], &label[i + 1..]),
+        None => ("", label),
+    };
+    if !basic.is_ascii() {
+        return None;
+    }
+    let mut output: Vec<char> = basic.chars().collect();
+    let (mut n, mut i, mut bias) = (INITIAL_N, 0u32, INITIAL_BIAS);
+    let mut digits = encoded.bytes().peekable();
+    while digits.peek().is_some() {
+        let old_i = i;
+        let mut w = 1u32;
+        let mut k = BASE;
+        loop {
+            let d = match digits.next()? {
+                b @ b'a'..=b'z' => b - b'a',
+                b @ b'A'..=b'Z' => b - b'A',
+                b @ b'0'..=b'9' => b - b'0' + 26,
+                _ => return None,
+            } as u32;
+            i = i.checked_add(d.checked_mul(w)?)?;
+            let t = threshold(k, bias);
+            if d < t {
+                break;
+            }
+            w = w.checked_mul(BASE - t)?;
+            k += BASE;
+        }
+        let len = output.len() as u32 + 1;
+        bias = adapt(i - old_i, len, old_i == 0);
+        n = n.checked_add(i / len)?;
+        i %= len;
+        output.insert(i as usize, char::from_u32(n)?);
+        i += 1;
+    }
+    Some(output.into_iter().collect())
+}
+
+/// Code points that may not appear in a domain after mapping.
+pub(crate) fn is_forbidden_domain_code_point(c: char) -> bool {
+    crate::host::is_forbidden_host_code_point(c) || c <= '\u{1F}' || c == '%' || c == '\u{7F}'
+}
+
+/// UTS #46 ToASCII with the URL standard's flags (no hyphen or DNS length checks).
+pub fn domain_to_ascii(domain: &str) -> Result<String, ParseError> {
+    let mapped: String = domain
+        .chars()
+        .map(|c| match c {
+            '\u{3002}' | '\u{FF0E}' | '\u{FF61}' => '.',
+            other => other,
+        })
+        .flat_map(char::to_lowercase)
+        .filter(|&c| c != '\u{AD}')
+        .collect();
+
+    let mut labels = Vec::new();
+    for label in mapped.split('.') {
+        if let Some(encoded) = label.strip_prefix("xn--") {
+            match punycode_decode(encoded) {
+                Some(decoded) if !decoded.is_ascii() && decoded.chars().all(|c| !is_forbidden_domain_code_point(c)) => {}
+                _ => return Err(ParseError::InvalidDomain(domain.to_string())),
+            }
+            labels.push(label.to_string());
+        } else if label.is_ascii() {
+            labels.push(label.to_string());
+        } else {
+            let encoded = punycode_encode(label).ok_or_else(|| ParseError::InvalidDomain(domain.to_string()))?;
+            labels.push(format!("xn--{}", encoded));
+        }
+    }
+    let ascii = labels.join(".");
+    if ascii.is_empty() || ascii.chars().any(is_forbidden_domain_code_point) {
+        return Err(ParseError::InvalidDomain(domain.to_string()));
+    }
+    Ok(ascii)
+}
+
+/// Decodes "xn--" labels for display; labels that fail to decode are left alone.
+pub fn domain_to_unicode(domain: &str) -> String {
+    domain
+        .split('.')
+        .map(|label| match label.strip_prefix("xn--").and_then(punycode_decode) {
+            Some(decoded) => decoded,
+            None => label.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_punycode_matches_rfc_3492_samples() {
+        let samples = [
+            ("bücher", "bcher-kva"),
+            ("münchen", "mnchen-3ya"),
+            ("他们为什么不说中文", "ihqwcrb4cv8a8dqg056pqjye"),
+            ("пример", "e1afmkfd"),
+        ];
+        for (unicode, encoded) in samples {
+            assert_eq!(punycode_encode(unicode).as_deref(), Some(encoded));
+            assert_eq!(punycode_decode(encoded).as_deref(), Some(unicode));
+        }
+
+        assert_eq!(domain_to_ascii("Bücher.Example").unwrap(), "xn--bcher-kva.example");
+        assert_eq!(domain_to_ascii("例え。テスト").unwrap(), "xn--r8jz45g.xn--zckzah");
+        assert_eq!(domain_to_unicode("xn--mnchen-3ya.de"), "münchen.de");
+        assert!(domain_to_ascii("xn--a_b.com").is_err());
+        assert!(domain_to_ascii("exa%mple.com").is_err());
+    }
+}
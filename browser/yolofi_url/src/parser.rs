@@ -0,0 +1,556 @@
+// Basic URL Parser
+// The state machine from the URL standard, section 4.4, minus state overrides
+// (setters go through `Url` methods instead) and minus validation-error reporting.
+// Input is walked as chars; `p` may step back one, which the spec writes as
+// "decrease pointer by 1".
+
+use crate::host::Host;
+use crate::percent::{self, EncodeSet};
+use crate::{default_port, is_special_scheme, ParseError, Path, Url};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    SchemeStart,
+    Scheme,
+    NoScheme,
+    SpecialRelativeOrAuthority,
+    PathOrAuthority,
+    Relative,
+    RelativeSlash,
+    SpecialAuthoritySlashes,
+    SpecialAuthorityIgnoreSlashes,
+    Authority,
+    Host,
+    Port,
+    File,
+    FileSlash,
+    FileHost,
+    PathStart,
+    Path,
+    OpaquePath,
+    Query,
+    Fragment,
+}
+
+/// "C:" or "c|".
+fn is_windows_drive_letter(s: &[char]) -> bool {
+    s.len() == 2 && s[0].is_ascii_alphabetic() && (s[1] == ':' || s[1] == '|')
+}
+
+fn is_normalized_windows_drive_letter(s: &str) -> bool {
+    let chars: Vec<char> = s.chars().collect();
+    is_windows_drive_letter(&chars) && chars[1] == ':'
+}
+
+/// A drive letter followed by the end of input or one of / \ ? #.
+fn starts_with_windows_drive_letter(rest: &[char]) -> bool {
+    rest.len() >= 2 && is_windows_drive_letter(&rest[..2]) && matches!(rest.get(2), None | Some('/' | '\\' | '?' | '#'))
+}
+
+fn is_single_dot(segment: &str) -> bool {
+    segment == "." || segment.eq_ignore_ascii_case("%2e")
+}
+
+fn is_double_dot(segment: &str) -> bool {
+    matches!(segment.to_ascii_lowercase().as_str(), ".." | ".%2e" | "%2e." | "%2e%2e")
+}
+
+struct Parser<'a> {
+    input: Vec<char>,
+    original: &'a str,
+    base: Option<&'a Url>,
+    url: Url,
+    segments: Vec<String>,
+    opaque_path: Option<String>,
+}
+
+impl Parser<'_> {
+    fn special(&self) -> bool {
+        is_special_scheme(&self.url.scheme)
+    }
+
+    fn shorten_path(&mut self) {
+        if self.url.scheme == "file" && self.segments.len() == 1 && is_normalized_windows_drive_letter(&self.segments[0]) {
+            return;
+        }
+        self.segments.pop();
+    }
+
+    fn copy_authority_from(&mut self, base: &Url) {
+        self.url.username = base.username.clone();
+        self.url.password = base.password.clone();
+        self.url.host = base.host.clone();
+        self.url.port = base.port;
+    }
+
+    fn copy_path_from(&mut self, base: &Url) {
+        match &base.path {
+            Path::Segments(segments) => self.segments = segments.clone(),
+            Path::Opaque(path) => self.opaque_path = Some(path.clone()),
+        }
+    }
+
+    fn parse_host(&self, buffer: &str) -> Result<Host, ParseError> {
+        Host::parse(buffer, !self.special())
+    }
+
+    fn run(mut self) -> Result<Url, ParseError> {
+        let input = std::mem::take(&mut self.input);
+        let len = input.len() as isize;
+        let mut state = State::SchemeStart;
+        let mut buffer = String::new();
+        let (mut at_sign_seen, mut inside_brackets, mut password_token_seen) = (false, false, false);
+        let mut p: isize = 0;
+
+        loop {
+            // `None` is the spec's EOF code point.
+            let c = (p < len).then(|| input[p as usize]);
+            let rest = |p: isize| &input[((p + 1).min(len)) as usize..];
+
+            match state {
+                State::SchemeStart => match c {
+                    Some(c) if c.is_ascii_alphabetic() => {
+                        buffer.push(c.to_ascii_lowercase());
+                        state = State::Scheme;
+                    }
+                    _ => {
+                        state = State::NoScheme;
+                        p -= 1;
+                    }
+                },
+                State::Scheme => match c {
+                    Some(c) if c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.') => {
+                        buffer.push(c.to_ascii_lowercase());
+                    }
+                    Some(':') => {
+                        self.url.scheme = std::mem::take(&mut buffer);
+                        if self.url.scheme == "file" {
+                            state = State::File;
+                        } else if self.special() && self.base.is_some_and(|b| b.scheme == self.url.scheme) {
+                            state = State::SpecialRelativeOrAuthority;
+                        } else if self.special() {
+                            state = State::SpecialAuthoritySlashes;
+                        } else if rest(p).first() == Some(&'/') {
+                            state = State::PathOrAuthority;
+                            p += 1;
+                        } else {
+                            self.opaque_path = Some(String::new());
+                            state = State::OpaquePath;
+                        }
+                    }
+                    _ => {
+                        buffer.clear();
+                        state = State::NoScheme;
+                        p = -1;
+                    }
+                },
+                State::NoScheme => {
+                    let base = match self.base {
+                        Some(base) if !(base.has_opaque_path() && c != Some('#')) => base,
+                        _ => return Err(ParseError::RelativeWithoutBase(self.original.to_string())),
+                    };
+                    if base.has_opaque_path() {
+                        self.url.scheme = base.scheme.clone();
+                        self.copy_path_from(base);
+                        self.url.query = base.query.clone();
+                        self.url.fragment = Some(String::new());
+                        state = State::Fragment;
+                    } else if base.scheme != "file" {
+                        state = State::Relative;
+                        p -= 1;
+                    } else {
+                        state = State::File;
+                        p -= 1;
+                    }
+                }
+                State::SpecialRelativeOrAuthority => {
+                    if c == Some('/') && rest(p).first() == Some(&'/') {
+                        state = State::SpecialAuthorityIgnoreSlashes;
+                        p += 1;
+                    } else {
+                        state = State::Relative;
+                        p -= 1;
+                    }
+                }
+                State::PathOrAuthority => {
+                    if c == Some('/') {
+                        state = State::Authority;
+                    } else {
+                        state = State::Path;
+                        p -= 1;
+                    }
+                }
+                State::Relative => {
+                    let base = self.base.expect("relative state needs a base");
+                    self.url.scheme = base.scheme.clone();
+                    if c == Some('/') || (self.special() && c == Some('\\')) {
+                        state = State::RelativeSlash;
+                    } else {
+                        self.copy_authority_from(base);
+                        self.copy_path_from(base);
+                        self.url.query = base.query.clone();
+                        match c {
+                            Some('?') => {
+                                self.url.query = Some(String::new());
+                                state = State::Query;
+                            }
+                            Some('#') => {
+                                self.url.fragment = Some(String::new());
+                                state = State::Fragment;
+                            }
+                            Some(_) => {
+                                self.url.query = None;
+                                self.shorten_path();
+                                state = State::Path;
+                                p -= 1;
+                            }
+                            None => {}
+                        }
+                    }
+                }
+                State::RelativeSlash => {
+                    if self.special() && matches!(c, Some('/' | '\\')) {
+                        state = State::SpecialAuthorityIgnoreSlashes;
+                    } else if c == Some('/') {
+                        state = State::Authority;
+                    } else {
+                        let base = self.base.expect("relative state needs a base");
+                        self.copy_authority_from(base);
+                        state = State::Path;
+                        p -= 1;
+                    }
+                }
+                State::SpecialAuthoritySlashes => {
+                    if c == Some('/') && rest(p).first() == Some(&'/') {
+                        p += 1;
+                    } else {
+                        p -= 1;
+                    }
+                    state = State::SpecialAuthorityIgnoreSlashes;
+                }
+                State::SpecialAuthorityIgnoreSlashes => {
+                    if !matches!(c, Some('/' | '\\')) {
+                        state = State::Authority;
+                        p -= 1;
+                    }
+                }
+                State::Authority => match c {
+                    Some('@') => {
+                        if at_sign_seen {
+                            buffer.insert_str(0, "%40");
+                        }
+                        at_sign_seen = true;
+                        for ch in std::mem::take(&mut buffer).chars() {
+                            if ch == ':' && !password_token_seen {
+                                password_token_seen = true;
+                                continue;
+                            }
+                            let target = if password_token_seen { &mut self.url.password } else { &mut self.url.username };
+                            percent::encode_into(target, ch.encode_utf8(&mut [0; 4]), EncodeSet::Userinfo);
+                        }
+                    }
+                    None | Some('/' | '?' | '#') => {
+                        if at_sign_seen && buffer.is_empty() {
+                            return Err(ParseError::MissingHost(self.original.to_string()));
+                        }
+                        p -= buffer.chars().count() as isize + 1;
+                        buffer.clear();
+                        state = State::Host;
+                    }
+                    Some('\\') if self.special() => {
+                        if at_sign_seen && buffer.is_empty() {
+                            return Err(ParseError::MissingHost(self.original.to_string()));
+                        }
+                        p -= buffer.chars().count() as isize + 1;
+                        buffer.clear();
+                        state = State::Host;
+                    }
+                    Some(c) => buffer.push(c),
+                },
+                State::Host => {
+                    let end = c.is_none() || matches!(c, Some('/' | '?' | '#')) || (self.special() && c == Some('\\'));
+                    if c == Some(':') && !inside_brackets {
+                        if buffer.is_empty() {
+                            return Err(ParseError::MissingHost(self.original.to_string()));
+                        }
+                        self.url.host = Some(self.parse_host(&buffer)?);
+                        buffer.clear();
+                        state = State::Port;
+                    } else if end {
+                        p -= 1;
+                        if self.special() && buffer.is_empty() {
+                            return Err(ParseError::MissingHost(self.original.to_string()));
+                        }
+                        self.url.host = Some(self.parse_host(&buffer)?);
+                        buffer.clear();
+                        state = State::PathStart;
+                    } else if let Some(c) = c {
+                        match c {
+                            '[' => inside_brackets = true,
+                            ']' => inside_brackets = false,
+                            _ => {}
+                        }
+                        buffer.push(c);
+                    }
+                }
+                State::Port => match c {
+                    Some(d) if d.is_ascii_digit() => buffer.push(d),
+                    None | Some('/' | '?' | '#') => {
+                        self.finish_port(&mut buffer)?;
+                        state = State::PathStart;
+                        p -= 1;
+                    }
+                    Some('\\') if self.special() => {
+                        self.finish_port(&mut buffer)?;
+                        state = State::PathStart;
+                        p -= 1;
+                    }
+                    Some(_) => return Err(ParseError::InvalidPort(self.original.to_string())),
+                },
+                State::File => {
+                    self.url.scheme = "file".to_string();
+                    self.url.host = Some(Host::Empty);
+                    if matches!(c, Some('/' | '\\')) {
+                        state = State::FileSlash;
+                    } else if let Some(base) = self.base.filter(|b| b.scheme == "file") {
+                        self.url.host = base.host.clone();
+                        self.copy_path_from(base);
+                        self.url.query = base.query.clone();
+                        match c {
+                            Some('?') => {
+                                self.url.query = Some(String::new());
+                                state = State::Query;
+                            }
+                            Some('#') => {
+                                self.url.fragment = Some(String::new());
+                                state = State::Fragment;
+                            }
+                            Some(_) => {
+                                self.url.query = None;
+                                if !starts_with_windows_drive_letter(&input[p as usize..]) {
+                                    self.shorten_path();
+                                } else {
+                                    self.segments.clear();
+                                }
+                                state = State::Path;
+                                p -= 1;
+                            }
+                            None => {}
+                        }
+                    } else {
+                        state = State::Path;
+                        p -= 1;
+                    }
+                }
+                State::FileSlash => {
+                    if matches!(c, Some('/' | '\\')) {
+                        state = State::FileHost;
+                    } else {
+                        if let Some(base) = self.base.filter(|b| b.scheme == "file") {
+                            self.url.host = base.host.clone();
+                            let rest_here = &input[p.clamp(0, len) as usize..];
+                            if !starts_with_windows_drive_letter(rest_here) {
+                                if let Some(first) = base.path_segments().and_then(|s| s.first()) {
+                                    if is_normalized_windows_drive_letter(first) {
+                                        self.segments.push(first.clone());
+                                    }
+                                }
+                            }
+                        }
+                        state = State::Path;
+                        p -= 1;
+                    }
+                }
+                State::FileHost => match c {
+                    None | Some('/' | '\\' | '?' | '#') => {
+                        p -= 1;
+                        let chars: Vec<char> = buffer.chars().collect();
+                        if is_windows_drive_letter(&chars) {
+                            // "file://C:/" — the drive letter is the path, not a host.
+                            state = State::Path;
+                        } else if buffer.is_empty() {
+                            self.url.host = Some(Host::Empty);
+                            state = State::PathStart;
+                        } else {
+                            let host = self.parse_host(&buffer)?;
+                            self.url.host = Some(if host == Host::Domain("localhost".into()) { Host::Empty } else { host });
+                            buffer.clear();
+                            state = State::PathStart;
+                        }
+                    }
+                    Some(c) => buffer.push(c),
+                },
+                State::PathStart => {
+                    if self.special() {
+                        state = State::Path;
+                        if !matches!(c, Some('/' | '\\')) {
+                            p -= 1;
+                        }
+                    } else if c == Some('?') {
+                        self.url.query = Some(String::new());
+                        state = State::Query;
+                    } else if c == Some('#') {
+                        self.url.fragment = Some(String::new());
+                        state = State::Fragment;
+                    } else if c.is_some() {
+                        state = State::Path;
+                        if c != Some('/') {
+                            p -= 1;
+                        }
+                    }
+                }
+                State::Path => {
+                    let slash = c == Some('/') || (self.special() && c == Some('\\'));
+                    if c.is_none() || slash || matches!(c, Some('?' | '#')) {
+                        if is_double_dot(&buffer) {
+                            self.shorten_path();
+                            if !slash {
+                                self.segments.push(String::new());
+                            }
+                        } else if is_single_dot(&buffer) && !slash {
+                            self.segments.push(String::new());
+                        } else if !is_single_dot(&buffer) {
+                            let chars: Vec<char> = buffer.chars().collect();
+                            if self.url.scheme == "file" && self.segments.is_empty() && is_windows_drive_letter(&chars) {
+                                buffer = format!("{}:", chars[0]);
+                            }
+                            self.segments.push(buffer.clone());
+                        }
+                        buffer.clear();
+                        match c {
+                            Some('?') => {
+                                self.url.query = Some(String::new());
+                                state = State::Query;
+                            }
+                            Some('#') => {
+                                self.url.fragment = Some(String::new());
+                                state = State::Fragment;
+                            }
+                            _ => {}
+                        }
+                    } else if let Some(c) = c {
+                        percent::encode_into(&mut buffer, c.encode_utf8(&mut [0; 4]), EncodeSet::Path);
+                    }
+                }
+                State::OpaquePath => {
+                    let path = self.opaque_path.get_or_insert_with(String::new);
+                    match c {
+                        Some('?') => {
+                            self.url.query = Some(String::new());
+                            state = State::Query;
+                        }
+                        Some('#') => {
+                            self.url.fragment = Some(String::new());
+                            state = State::Fragment;
+                        }
+                        Some(' ') if matches!(rest(p).first(), Some('?' | '#')) => path.push_str("%20"),
+                        Some(c) => percent::encode_into(path, c.encode_utf8(&mut [0; 4]), EncodeSet::C0Control),
+                        None => {}
+                    }
+                }
+                State::Query => match c {
+                    None | Some('#') => {
+                        let set = if self.special() { EncodeSet::SpecialQuery } else { EncodeSet::Query };
+                        percent::encode_into(self.url.query.get_or_insert_with(String::new), &buffer, set);
+                        buffer.clear();
+                        if c == Some('#') {
+                            self.url.fragment = Some(String::new());
+                            state = State::Fragment;
+                        }
+                    }
+                    Some(c) => buffer.push(c),
+                },
+                State::Fragment => {
+                    if let Some(c) = c {
+                        let fragment = self.url.fragment.get_or_insert_with(String::new);
+                        percent::encode_into(fragment, c.encode_utf8(&mut [0; 4]), EncodeSet::Fragment);
+                    }
+                }
+            }
+
+            if c.is_none() && p >= len {
+                break;
+            }
+            p += 1;
+        }
+
+        self.url.path = match self.opaque_path {
+            Some(path) => Path::Opaque(path),
+            None => Path::Segments(self.segments),
+        };
+        Ok(self.url)
+    }
+
+    fn finish_port(&mut self, buffer: &mut String) -> Result<(), ParseError> {
+        if !buffer.is_empty() {
+            let port: u16 = buffer.parse().map_err(|_| ParseError::InvalidPort(self.original.to_string()))?;
+            self.url.port = (default_port(&self.url.scheme) != Some(port)).then_some(port);
+            buffer.clear();
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn parse(input: &str, base: Option<&Url>) -> Result<Url, ParseError> {
+    let trimmed = input.trim_matches(|c: char| c <= ' ');
+    let chars: Vec<char> = trimmed.chars().filter(|&c| !matches!(c, '\t' | '\n' | '\r')).collect();
+    let parser = Parser {
+        input: chars,
+        original: input,
+        base,
+        url: Url {
+            scheme: String::new(),
+            username: String::new(),
+            password: String::new(),
+            host: None,
+            port: None,
+            path: Path::Segments(Vec::new()),
+            query: None,
+            fragment: None,
+        },
+        segments: Vec::new(),
+        opaque_path: None,
+    };
+    parser.run()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Url;
+
+    /// Cases from the web-platform-tests urltestdata.json, relative to the base.
+    #[test]
+    fn test_relative_resolution() {
+        let base = Url::parse("http://example.org/foo/bar?q#f").unwrap();
+        let cases = [
+            ("", "http://example.org/foo/bar?q"),
+            ("baz", "http://example.org/foo/baz"),
+            ("../baz", "http://example.org/baz"),
+            ("./baz/", "http://example.org/foo/baz/"),
+            ("/../../x", "http://example.org/x"),
+            ("?x=1", "http://example.org/foo/bar?x=1"),
+            ("#frag", "http://example.org/foo/bar?q#frag"),
+            ("//other.test:80/p", "http://other.test/p"),
+            ("\\\\other.test\\a\\b", "http://other.test/a/b"),
+            ("https://[::1]:8443/%2e%2E/x", "https://[::1]:8443/x"),
+            ("  http://a.test/\tb\nc  ", "http://a.test/bc"),
+            ("http:/a.test", "http://example.org/a.test"),
+            ("https:/a.test", "https://a.test/"),
+            ("/a b/é?c d#e f", "http://example.org/a%20b/%C3%A9?c%20d#e%20f"),
+            ("HTTP://User@München.DE:0080", "http://User@xn--mnchen-3ya.de/"),
+            ("file:///C|/dir/../x", "file:///C:/x"),
+            ("mailto:a@b.test?subject=hi", "mailto:a@b.test?subject=hi"),
+            ("web+demo://h/p/../q", "web+demo://h/q"),
+            ("web+demo:/.//p", "web+demo:/.//p"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(base.join(input).map(|u| u.to_string()).as_deref(), Ok(expected), "{:?}", input);
+        }
+
+        let failures = ["http://", "http://a.test:65536/", "http://a b.test/", "http://[::1/", "http://@/x", "//x"];
+        for input in failures {
+            assert!(Url::parse(input).is_err(), "{:?}", input);
+        }
+        assert!(Url::parse("mailto:x").unwrap().join("y").is_err());
+    }
+}
@@ -0,0 +1,109 @@
+// Percent-Encoding
+// The encode sets of the URL standard, each a superset of the one before it.
+// Non-ASCII input is always encoded, byte by byte, from its UTF-8 form.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodeSet {
+    /// C0 controls and everything above '~'.
+    C0Control,
+    Fragment,
+    Query,
+    /// Query, plus `'` (http, https, ws, wss, ftp, file).
+    SpecialQuery,
+    Path,
+    /// Path, plus '/' and '%': a single segment pushed through the API.
+    PathSegment,
+    Userinfo,
+    /// Userinfo, plus `$ % & + ,`: what encodeURIComponent() leaves alone.
+    Component,
+    /// application/x-www-form-urlencoded; space is handled separately (as '+').
+    Form,
+}
+
+impl EncodeSet {
+    pub fn contains(self, byte: u8) -> bool {
+        if !(0x20..0x7F).contains(&byte) {
+            return true;
+        }
+        let b = byte;
+        match self {
+            EncodeSet::C0Control => false,
+            EncodeSet::Fragment => matches!(b, b' ' | b'"' | b'<' | b'>' | b'`'),
+            EncodeSet::Query => matches!(b, b' ' | b'"' | b'#' | b'<' | b'>'),
+            EncodeSet::SpecialQuery => EncodeSet::Query.contains(b) || b == b'\'',
+            EncodeSet::Path => EncodeSet::Query.contains(b) || matches!(b, b'?' | b'^' | b'`' | b'{' | b'}'),
+            EncodeSet::PathSegment => EncodeSet::Path.contains(b) || matches!(b, b'/' | b'%'),
+            EncodeSet::Userinfo => {
+                EncodeSet::Path.contains(b) || matches!(b, b'/' | b':' | b';' | b'=' | b'@' | b'[' | b'\\' | b']' | b'|')
+            }
+            EncodeSet::Component => EncodeSet::Userinfo.contains(b) || matches!(b, b'$' | b'%' | b'&' | b'+' | b','),
+            EncodeSet::Form => EncodeSet::Component.contains(b) || matches!(b, b'!' | b'\'' | b'(' | b')' | b'~'),
+        }
+    }
+}
+
+/// Appends `input` to `out`, encoding every byte in `set` as `%XX`.
+pub fn encode_into(out: &mut String, input: &str, set: EncodeSet) {
+    for &byte in input.as_bytes() {
+        if set.contains(byte) {
+            out.push_str(&format!("%{:02X}", byte));
+        } else {
+            out.push(byte as char);
+        }
+    }
+}
+
+pub fn encode(input: &str, set: EncodeSet) -> String {
+    let mut out = String::with_capacity(input.len());
+    encode_into(&mut out, input, set);
+    out
+}
+
+/// application/x-www-form-urlencoded serialization of one name or value.
+pub fn form_encode(input: &str) -> String {
+    input.split(' ').map(|part| encode(part, EncodeSet::Form)).collect::<Vec<_>>().join("+")
+}
+
+/// Decodes `%XX` sequences; malformed ones are kept as-is.
+pub fn decode(input: &str) -> Vec<u8> {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = |b: u8| (b as char).to_digit(16);
+        match (bytes[i], bytes.get(i + 1).copied().and_then(hex), bytes.get(i + 2).copied().and_then(hex)) {
+            (b'%', Some(high), Some(low)) => {
+                out.push((high * 16 + low) as u8);
+                i += 3;
+            }
+            (byte, _, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Decodes to a string, replacing invalid UTF-8.
+pub fn decode_utf8(input: &str) -> String {
+    String::from_utf8_lossy(&decode(input)).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_sets_and_round_trip() {
+        assert_eq!(encode("a b\"#<>?`{}", EncodeSet::Path), "a%20b%22%23%3C%3E%3F%60%7B%7D");
+        assert_eq!(encode("a b#'", EncodeSet::SpecialQuery), "a%20b%23%27");
+        assert_eq!(encode("user:p@ss", EncodeSet::Userinfo), "user%3Ap%40ss");
+        assert_eq!(encode("a/b%", EncodeSet::PathSegment), "a%2Fb%25");
+        assert_eq!(encode("é", EncodeSet::C0Control), "%C3%A9");
+        assert_eq!(form_encode("new york & co~"), "new+york+%26+co%7E");
+
+        assert_eq!(decode_utf8("caf%C3%A9%2"), "café%2");
+        assert_eq!(decode_utf8(&encode("x/ü?", EncodeSet::Component)), "x/ü?");
+    }
+}